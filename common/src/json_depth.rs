@@ -0,0 +1,154 @@
+/*!
+JSON parsing with a nesting-depth limit.
+
+The text is scanned iteratively for its maximum nesting depth before serde
+sees it, so an adversarially deep payload is rejected in linear time without
+recursing at all. Only payloads within the limit reach `serde_json`, whose
+own fixed recursion limit is lifted so the configured limit is the one that
+applies.
+
+The server and the test runner both use this, so both sides enforce the
+same rule.
+*/
+
+use serde_json::Value as JsonValue;
+use std::fmt;
+
+/// Default for `--max-json-depth`
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+#[derive(Debug)]
+pub enum DepthError {
+    /// Nesting exceeded the limit
+    TooDeep { limit: usize },
+    /// Within the limit but not valid JSON
+    Invalid(serde_json::Error),
+}
+
+impl fmt::Display for DepthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepthError::TooDeep { limit } => {
+                write!(f, "JSON nesting exceeds the maximum depth of {}", limit)
+            }
+            DepthError::Invalid(e) => write!(f, "Invalid JSON: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DepthError {}
+
+/// Parse `text`, rejecting arrays/objects nested deeper than `max_depth`
+pub fn from_str(text: &str, max_depth: usize) -> Result<JsonValue, DepthError> {
+    if nesting_depth(text) > max_depth {
+        return Err(DepthError::TooDeep { limit: max_depth });
+    }
+    let mut deserializer = serde_json::Deserializer::from_str(text);
+    deserializer.disable_recursion_limit();
+    let value = serde::Deserialize::deserialize(&mut deserializer).map_err(DepthError::Invalid)?;
+    deserializer.end().map_err(DepthError::Invalid)?;
+    Ok(value)
+}
+
+/// Deepest array/object nesting in `text`, ignoring brackets inside strings.
+/// Malformed input is scanned as far as it goes; serde reports the error.
+fn nesting_depth(text: &str) -> usize {
+    let mut depth = 0usize;
+    let mut max = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for byte in text.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `depth` arrays nested in each other around `1`
+    fn nested(depth: usize) -> String {
+        format!("{}1{}", "[".repeat(depth), "]".repeat(depth))
+    }
+
+    #[test]
+    fn accepts_up_to_the_limit() {
+        for depth in [0, 1, DEFAULT_MAX_DEPTH - 1, DEFAULT_MAX_DEPTH] {
+            let value = from_str(&nested(depth), DEFAULT_MAX_DEPTH).unwrap();
+            let mut inner = &value;
+            for _ in 0..depth {
+                inner = &inner[0];
+            }
+            assert_eq!(*inner, JsonValue::from(1));
+        }
+    }
+
+    #[test]
+    fn rejects_just_past_the_limit() {
+        let error = from_str(&nested(DEFAULT_MAX_DEPTH + 1), DEFAULT_MAX_DEPTH).unwrap_err();
+        assert!(matches!(
+            error,
+            DepthError::TooDeep {
+                limit: DEFAULT_MAX_DEPTH
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "JSON nesting exceeds the maximum depth of 128"
+        );
+
+        let objects = r#"{"a": {"b": [{"c": 1}]}}"#;
+        assert!(from_str(objects, 4).is_ok());
+        assert!(matches!(
+            from_str(objects, 3),
+            Err(DepthError::TooDeep { limit: 3 })
+        ));
+    }
+
+    #[test]
+    fn rejects_very_deep_payloads_without_recursing() {
+        let error = from_str(&nested(1_000_000), DEFAULT_MAX_DEPTH).unwrap_err();
+        assert!(matches!(error, DepthError::TooDeep { .. }));
+        // Unclosed brackets count too
+        let unclosed = "[".repeat(DEFAULT_MAX_DEPTH + 1);
+        assert!(matches!(
+            from_str(&unclosed, DEFAULT_MAX_DEPTH),
+            Err(DepthError::TooDeep { .. })
+        ));
+    }
+
+    #[test]
+    fn brackets_in_strings_do_not_count() {
+        let text = r#"["[[[{{{", "\"[[[", {"]]]": "{{{"}]"#;
+        assert_eq!(nesting_depth(text), 2);
+        assert!(from_str(text, 2).is_ok());
+    }
+
+    #[test]
+    fn invalid_json_within_the_limit() {
+        for text in ["[1,", "[1] 2", "{'a': 1}", ""] {
+            let error = from_str(text, DEFAULT_MAX_DEPTH).unwrap_err();
+            assert!(matches!(error, DepthError::Invalid(_)), "{}", text);
+            assert!(error.to_string().starts_with("Invalid JSON: "));
+        }
+    }
+}
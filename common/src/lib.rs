@@ -0,0 +1,9 @@
+/*!
+Code the Rust server and the test runner share, so both sides of the wire
+agree: the protocol revision, and JSON and MessagePack decoding under the
+same nesting limit.
+*/
+
+pub mod json_depth;
+pub mod msgpack;
+pub mod protocol;
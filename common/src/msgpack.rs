@@ -0,0 +1,143 @@
+/*!
+MessagePack payloads, for calls made with `encoding: MSGPACK`.
+
+Arguments and results decode into the same `serde_json::Value` as JSON
+ones, so everything after decoding is shared, and the nesting limit of
+`--max-json-depth` applies to them too. Encoding and decoding skip the text
+round trip, which is most of the cost of a large payload, and integers
+travel as 64-bit values rather than through a float.
+*/
+
+use serde_json::Value as JsonValue;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// Nesting exceeded the limit
+    TooDeep { limit: usize },
+    /// Not MessagePack that a JSON value can hold
+    Invalid(rmp_serde::decode::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooDeep { limit } => {
+                write!(
+                    f,
+                    "MessagePack nesting exceeds the maximum depth of {}",
+                    limit
+                )
+            }
+            DecodeError::Invalid(e) => write!(f, "Invalid MessagePack: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decode `bytes`, rejecting arrays/maps nested deeper than `max_depth`
+pub fn from_slice(bytes: &[u8], max_depth: usize) -> Result<JsonValue, DecodeError> {
+    let mut deserializer = rmp_serde::Deserializer::from_read_ref(bytes);
+    // rmp_serde fails on reaching its limit rather than on passing it
+    deserializer.set_max_depth(max_depth.saturating_add(1));
+    serde::Deserialize::deserialize(&mut deserializer).map_err(|e| match e {
+        rmp_serde::decode::Error::DepthLimitExceeded => DecodeError::TooDeep { limit: max_depth },
+        e => DecodeError::Invalid(e),
+    })
+}
+
+/// Encode `value`, with maps as MessagePack maps
+pub fn to_vec(value: &JsonValue) -> Vec<u8> {
+    // A JSON value always has a MessagePack encoding
+    rmp_serde::to_vec(value).expect("JSON values encode as MessagePack")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+
+    /// Array of about 10 MB of MessagePack: integers past 2^53, which a
+    /// float would round, and floats, which are costly to print and parse
+    fn large_array() -> JsonValue {
+        let items = (0..1_200_000u64)
+            .map(|i| match i % 3 {
+                0 => json!(u64::MAX - i),
+                1 => json!(-(1i64 << 60) - i as i64),
+                _ => json!(i as f64 / 7.0),
+            })
+            .collect();
+        JsonValue::Array(items)
+    }
+
+    /// Fastest of a few runs of `f`, so one slow run doesn't decide a comparison
+    fn fastest(mut f: impl FnMut()) -> Duration {
+        (0..3)
+            .map(|_| {
+                let started = Instant::now();
+                f();
+                started.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn large_array_round_trips_exactly() {
+        let value = large_array();
+        let bytes = to_vec(&value);
+        assert!(bytes.len() >= 10_000_000, "only {} bytes", bytes.len());
+        let decoded = from_slice(&bytes, 128).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(decoded[0].as_u64(), Some(u64::MAX));
+        assert_eq!(decoded[1].as_i64(), Some(-(1i64 << 60) - 1));
+    }
+
+    #[test]
+    fn large_array_is_faster_than_json_text() {
+        let value = large_array();
+        let msgpack = fastest(|| {
+            let bytes = to_vec(&value);
+            from_slice(&bytes, 128).unwrap();
+        });
+        let json = fastest(|| {
+            let text = serde_json::to_string(&value).unwrap();
+            serde_json::from_str::<JsonValue>(&text).unwrap();
+        });
+        assert!(
+            msgpack < json,
+            "MessagePack took {:?}, JSON text {:?}",
+            msgpack,
+            json
+        );
+    }
+
+    #[test]
+    fn nesting_past_the_limit_is_rejected() {
+        // The same limit as JSON text: as deep as the limit is accepted
+        let nested = json!([[[1]]]);
+        assert_eq!(from_slice(&to_vec(&nested), 3).unwrap(), nested);
+        let error = from_slice(&to_vec(&nested), 2).unwrap_err();
+        assert!(matches!(error, DecodeError::TooDeep { limit: 2 }));
+        assert_eq!(
+            error.to_string(),
+            "MessagePack nesting exceeds the maximum depth of 2"
+        );
+    }
+
+    #[test]
+    fn maps_keep_their_keys() {
+        let value = json!({"name": "x", "nested": {"list": [1, null, true]}});
+        assert_eq!(from_slice(&to_vec(&value), 128).unwrap(), value);
+    }
+
+    #[test]
+    fn truncated_payloads_are_invalid() {
+        let bytes = to_vec(&json!(["abc", 1]));
+        let error = from_slice(&bytes[..bytes.len() - 2], 128).unwrap_err();
+        assert!(matches!(error, DecodeError::Invalid(_)));
+        assert!(error.to_string().starts_with("Invalid MessagePack: "));
+    }
+}
@@ -0,0 +1,11 @@
+/*!
+Revision of the wire protocol in `proto/transpile_test.proto`.
+
+Bump `PROTOCOL_VERSION` whenever a proto change would make an older runner
+and a newer server (or the reverse) misread each other. Servers report it
+in `GetServerInfo` and `runner bootstrap` compares it with the one it was
+built with; `python/server.py` keeps a constant in step.
+*/
+
+/// Servers built before versioning existed report 0
+pub const PROTOCOL_VERSION: u32 = 1;
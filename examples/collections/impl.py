@@ -0,0 +1,211 @@
+"""
+Example implementation: Strings, collections and a stateful stack
+
+Functions over text, floats and nested JSON, where implementations tend to
+drift apart: unicode, float formatting, ordering. Each one mirrors the Rust
+version in impl.rs, down to the error messages the suite checks.
+"""
+
+import json
+import sys
+sys.path.append('../../python')
+from server import transpile_test
+
+
+@transpile_test(
+    name="reverse_string",
+    description="Reverse a string",
+    is_stateful=False,
+    parameter_types=["str"],
+    return_type="str",
+)
+def reverse_string(context, s):
+    """Reverse a string by code point."""
+    if not isinstance(s, str):
+        raise ValueError("Missing or invalid 's'")
+    return s[::-1]
+
+
+@transpile_test(
+    name="word_count",
+    description="Count the occurrences of each word in a text",
+    is_stateful=False,
+    parameter_types=["str"],
+    return_type="dict",
+)
+def word_count(context, text):
+    """Count whitespace-separated words, case-sensitively."""
+    if not isinstance(text, str):
+        raise ValueError("Missing or invalid 'text'")
+    counts = {}
+    for word in text.split():
+        counts[word] = counts.get(word, 0) + 1
+    return counts
+
+
+@transpile_test(
+    name="sort_numbers",
+    description="Sort a list of numbers in ascending order",
+    is_stateful=False,
+    parameter_types=["list"],
+    return_type="list",
+)
+def sort_numbers(context, numbers):
+    """Sort numbers ascending; sorted() is stable and keeps ints as ints."""
+    if not isinstance(numbers, list):
+        raise ValueError("Missing or invalid 'numbers'")
+    for index, number in enumerate(numbers):
+        if isinstance(number, bool) or not isinstance(number, (int, float)):
+            raise ValueError(f"'numbers' item {index} is not a number")
+    return sorted(numbers)
+
+
+@transpile_test(
+    name="merge_dicts",
+    description="Deep-merge two dicts, the second's values taking precedence",
+    is_stateful=False,
+    parameter_types=["dict", "dict"],
+    return_type="dict",
+)
+def merge_dicts(context, a, b):
+    """Merge b into a copy of a, recursing into dicts both have."""
+    if not isinstance(a, dict):
+        raise ValueError("Missing or invalid 'a'")
+    if not isinstance(b, dict):
+        raise ValueError("Missing or invalid 'b'")
+    merged = dict(a)
+    for key, value in b.items():
+        if isinstance(merged.get(key), dict) and isinstance(value, dict):
+            value = merge_dicts(context, merged[key], value)
+        merged[key] = value
+    return merged
+
+
+@transpile_test(
+    name="unique",
+    description="Remove duplicates from a list, preserving first-occurrence order",
+    is_stateful=False,
+    parameter_types=["list"],
+    return_type="list",
+)
+def unique(context, items):
+    """Drop repeated items, keeping the first of each.
+
+    Items are keyed by their JSON, since 1 == 1.0 == True in Python but they
+    are different values to the Rust version.
+    """
+    if not isinstance(items, list):
+        raise ValueError("Missing or invalid 'items'")
+    seen = set()
+    result = []
+    for item in items:
+        key = json.dumps(item, sort_keys=True)
+        if key not in seen:
+            seen.add(key)
+            result.append(item)
+    return result
+
+
+def _stack(context):
+    """The context's stack, empty if it has none yet."""
+    stack = context.state.get("stack", [])
+    if not isinstance(stack, list):
+        raise ValueError("'stack' in state is not a list")
+    return stack
+
+
+@transpile_test(
+    name="stack_push",
+    description="Push a value onto the stack, returning its new size (stateful)",
+    is_stateful=True,
+    parameter_types=["any"],
+    return_type="int",
+)
+def stack_push(context, value):
+    """Push onto the stack held in the context."""
+    stack = _stack(context) + [value]
+    context.update_state("stack", stack)
+    return len(stack)
+
+
+@transpile_test(
+    name="stack_pop",
+    description="Remove and return the top of the stack (stateful)",
+    is_stateful=True,
+    parameter_types=[],
+    return_type="any",
+)
+def stack_pop(context):
+    """Pop from the stack held in the context."""
+    stack = _stack(context)
+    if not stack:
+        raise IndexError("pop from empty stack")
+    context.update_state("stack", stack[:-1])
+    return stack[-1]
+
+
+@transpile_test(
+    name="stack_peek",
+    description="Return the top of the stack without removing it (stateful)",
+    is_stateful=True,
+    parameter_types=[],
+    return_type="any",
+)
+def stack_peek(context):
+    """Look at the top of the stack held in the context."""
+    stack = _stack(context)
+    if not stack:
+        raise IndexError("peek at empty stack")
+    return stack[-1]
+
+
+@transpile_test(
+    name="moving_average",
+    description="Add a value and return the mean of the last window values (stateful)",
+    is_stateful=True,
+    parameter_types=["float", "int"],
+    return_type="float",
+)
+def moving_average(context, value, window):
+    """Mean of the last window values seen on the context."""
+    if isinstance(value, bool) or not isinstance(value, (int, float)):
+        raise ValueError("Missing or invalid 'value'")
+    if isinstance(window, bool) or not isinstance(window, int):
+        raise ValueError("Missing or invalid 'window'")
+    if window < 1:
+        raise ValueError("'window' must be at least 1")
+
+    samples = context.state.get("samples", []) + [float(value)]
+    samples = samples[-window:]
+
+    # Summed left to right like the Rust version; sum() compensates for
+    # rounding on Python 3.12+, which gives different last digits
+    total = 0.0
+    for sample in samples:
+        total += sample
+    context.update_state("samples", samples)
+    return total / len(samples)
+
+
+@transpile_test(
+    name="type_name",
+    description="Name the type of a value as Python does",
+    is_stateful=False,
+    parameter_types=["any"],
+    return_type="str",
+)
+def type_name(context, value):
+    """type(value).__name__, e.g. int, float, str, list, dict."""
+    return type(value).__name__
+
+
+@transpile_test(
+    name="same_type",
+    description="Whether two values have the same type",
+    is_stateful=False,
+    parameter_types=["any", "any"],
+    return_type="bool",
+)
+def same_type(context, a, b):
+    """Whether a and b are of the same type, so 1 and 1.0 aren't."""
+    return type(a) is type(b)
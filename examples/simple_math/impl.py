@@ -6,6 +6,7 @@ via the transpilation testing infrastructure.
 """
 
 import sys
+import time
 sys.path.append('../../python')
 from server import transpile_test
 
@@ -64,6 +65,7 @@ def counter_increment(context):
     current = context.state.get("counter", 0)
     new_value = current + 1
     context.update_state("counter", new_value)
+    print(f"counter: {current} -> {new_value}")
     return new_value
 
 
@@ -113,3 +115,85 @@ def is_prime(context, n):
         if n % i == 0:
             return False
     return True
+
+
+@transpile_test(
+    name="element_at",
+    description="Get the element of a list at an index",
+    is_stateful=False,
+    parameter_types=["list", "int"],
+    return_type="any",
+)
+def element_at(context, items, index):
+    """Get items[index]; an index past the end raises IndexError."""
+    if index < 0:
+        raise ValueError("'index' must not be negative")
+    return items[index]
+
+
+@transpile_test(
+    name="sum_vec",
+    description="Sum the integers below n, held in a vector",
+    is_stateful=False,
+    parameter_types=["int"],
+    return_type="int",
+)
+def sum_vec(context, n):
+    """Sum the integers below n, building the list first."""
+    if n < 0:
+        raise ValueError("'n' must not be negative")
+    values = list(range(n))
+    return sum(values)
+
+
+@transpile_test(
+    name="delayed_add",
+    description="Add two numbers after sleeping for delay_ms milliseconds",
+    is_stateful=False,
+    parameter_types=["int", "int", "int"],
+    return_type="int",
+)
+def delayed_add(context, a, b, delay_ms):
+    """Add two numbers after a delay, standing in for slow I/O."""
+    time.sleep(delay_ms / 1000)
+    return a + b
+
+
+@transpile_test(
+    name="busy_loop",
+    description="Keep the CPU busy for ms milliseconds, then return ms",
+    is_stateful=False,
+    parameter_types=["int"],
+    return_type="int",
+)
+def busy_loop(context, ms):
+    """Spin for ms milliseconds without sleeping, for exercising deadlines."""
+    until = time.perf_counter() + ms / 1000
+    while time.perf_counter() < until:
+        pass
+    return ms
+
+
+@transpile_test(
+    name="sequence",
+    description="Return the n consecutive integers from start",
+    is_stateful=False,
+    parameter_types=["int", "int"],
+    return_type="list",
+)
+def sequence(context, start, n):
+    """n consecutive integers from start, for large payloads and big integers."""
+    return list(range(start, start + n))
+
+
+@transpile_test(
+    name="range_squares",
+    description="Yield the squares of the integers below n",
+    is_stateful=False,
+    parameter_types=["int"],
+    return_type="int",
+)
+def range_squares(context, n):
+    """Yield the squares of 0..n one at a time."""
+    for i in range(n):
+        yield i * i
@@ -7,45 +7,51 @@ via the transpilation testing infrastructure.
 This should be integrated into the Rust server for testing.
 */
 
+use serde::Deserialize;
 use serde_json::{json, Value as JsonValue};
 
+/// Arguments of `add` and `multiply`
+#[derive(Deserialize)]
+struct Operands {
+    a: i64,
+    b: i64,
+}
+
+/// Arguments of `fibonacci`
+#[derive(Deserialize)]
+struct Position {
+    n: i64,
+}
+
 pub fn register_functions(server: &crate::TranspileTestServer) {
     // Add
-    server.register_function(
+    server.register_typed_function(
         "add",
-        |_ctx, args| {
-            let a = args["a"].as_i64().ok_or("Missing or invalid 'a'")?;
-            let b = args["b"].as_i64().ok_or("Missing or invalid 'b'")?;
-            Ok(json!(a + b))
-        },
+        |_ctx, Operands { a, b }| Ok(a + b),
         "Add two numbers",
         false,
         vec!["int".to_string(), "int".to_string()],
         "int",
     );
+    server.declare_parameters("add", &[("a", "int"), ("b", "int")]);
 
     // Multiply
-    server.register_function(
+    server.register_typed_function(
         "multiply",
-        |_ctx, args| {
-            let a = args["a"].as_i64().ok_or("Missing or invalid 'a'")?;
-            let b = args["b"].as_i64().ok_or("Missing or invalid 'b'")?;
-            Ok(json!(a * b))
-        },
+        |_ctx, Operands { a, b }| Ok(a * b),
         "Multiply two numbers",
         false,
         vec!["int".to_string(), "int".to_string()],
         "int",
     );
+    server.declare_parameters("multiply", &[("a", "int"), ("b", "int")]);
 
     // Fibonacci
-    server.register_function(
+    server.register_typed_function(
         "fibonacci",
-        |_ctx, args| {
-            let n = args["n"].as_i64().ok_or("Missing or invalid 'n'")?;
-
+        |_ctx, Position { n }| {
             if n <= 1 {
-                return Ok(json!(n));
+                return Ok(n);
             }
 
             let mut a = 0i64;
@@ -56,13 +62,14 @@ pub fn register_functions(server: &crate::TranspileTestServer) {
                 b = temp;
             }
 
-            Ok(json!(b))
+            Ok(b)
         },
         "Calculate the nth Fibonacci number",
         false,
         vec!["int".to_string()],
         "int",
     );
+    server.declare_parameters("fibonacci", &[("n", "int")]);
 
     // Counter increment (stateful)
     server.register_function(
@@ -75,6 +82,7 @@ pub fn register_functions(server: &crate::TranspileTestServer) {
 
             let new_value = current + 1;
             ctx.set_state("counter".to_string(), json!(new_value));
+            ctx.print(&format!("counter: {} -> {}\n", current, new_value));
 
             Ok(json!(new_value))
         },
@@ -122,6 +130,7 @@ pub fn register_functions(server: &crate::TranspileTestServer) {
         vec!["int".to_string()],
         "int",
     );
+    server.declare_parameters("factorial", &[("n", "int")]);
 
     // Is prime
     server.register_function(
@@ -153,4 +162,105 @@ pub fn register_functions(server: &crate::TranspileTestServer) {
         vec!["int".to_string()],
         "bool",
     );
+    server.declare_parameters("is_prime", &[("n", "int")]);
+
+    // Element at an index, left unchecked: an index past the end panics,
+    // which the server reports as a failed call
+    server.register_function(
+        "element_at",
+        |_ctx, args| {
+            let items = args["items"].as_array().ok_or("Missing or invalid 'items'")?;
+            let index = args["index"].as_u64().ok_or("Missing or invalid 'index'")?;
+            Ok(items[index as usize].clone())
+        },
+        "Get the element of a list at an index",
+        false,
+        vec!["list".to_string(), "int".to_string()],
+        "any",
+    );
+    server.declare_parameters("element_at", &[("items", "list"), ("index", "int")]);
+
+    // Sum of a vector, which allocates n integers so memory_bytes is visible
+    server.register_function(
+        "sum_vec",
+        |_ctx, args| {
+            let n = args["n"].as_i64().ok_or("Missing or invalid 'n'")?;
+            let n = usize::try_from(n).map_err(|_| "'n' must not be negative")?;
+
+            let values: Vec<i64> = (0..n as i64).collect();
+            Ok(json!(values.iter().sum::<i64>()))
+        },
+        "Sum the integers below n, held in a vector",
+        false,
+        vec!["int".to_string()],
+        "int",
+    );
+    server.declare_parameters("sum_vec", &[("n", "int")]);
+
+    // Sum after a delay, awaited rather than blocking the worker thread
+    server.register_async_function(
+        "delayed_add",
+        |_ctx, args| async move {
+            let a = args["a"].as_i64().ok_or("Missing or invalid 'a'")?;
+            let b = args["b"].as_i64().ok_or("Missing or invalid 'b'")?;
+            let delay_ms = args["delay_ms"].as_u64().ok_or("Missing or invalid 'delay_ms'")?;
+            tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+            Ok(json!(a + b))
+        },
+        "Add two numbers after sleeping for delay_ms milliseconds",
+        false,
+        vec!["int".to_string(), "int".to_string(), "int".to_string()],
+        "int",
+    );
+    server.declare_parameters("delayed_add", &[("a", "int"), ("b", "int"), ("delay_ms", "int")]);
+
+    // Spin for ms milliseconds without yielding, for exercising deadlines
+    server.register_function(
+        "busy_loop",
+        |_ctx, args| {
+            let ms = args["ms"].as_u64().ok_or("Missing or invalid 'ms'")?;
+            let until = std::time::Instant::now() + std::time::Duration::from_millis(ms);
+            while std::time::Instant::now() < until {
+                std::hint::spin_loop();
+            }
+            Ok(json!(ms))
+        },
+        "Keep the CPU busy for ms milliseconds, then return ms",
+        false,
+        vec!["int".to_string()],
+        "int",
+    );
+    server.declare_parameters("busy_loop", &[("ms", "int")]);
+
+    // n consecutive integers from start, for large payloads and integers
+    // past 2^53
+    server.register_function(
+        "sequence",
+        |_ctx, args| {
+            let start = args["start"].as_i64().ok_or("Missing or invalid 'start'")?;
+            let n = args["n"].as_i64().ok_or("Missing or invalid 'n'")?;
+            let end = start.checked_add(n).ok_or("'start' + 'n' overflows")?;
+            Ok(json!((start..end).collect::<Vec<i64>>()))
+        },
+        "Return the n consecutive integers from start",
+        false,
+        vec!["int".to_string(), "int".to_string()],
+        "list",
+    );
+    server.declare_parameters("sequence", &[("start", "int"), ("n", "int")]);
+
+    // Squares of 0..n, streamed one at a time rather than returned as a list
+    server.register_streaming_function(
+        "range_squares",
+        |_ctx, args| {
+            let n = args["n"].as_i64().ok_or("Missing or invalid 'n'")?;
+            let squares = (0..n).map(|i| Ok(json!(i * i)));
+            Ok(Box::new(squares) as Box<dyn Iterator<Item = _> + Send>)
+        },
+        "Yield the squares of the integers below n",
+        false,
+        vec!["int".to_string()],
+        "int",
+    );
+    server.declare_parameters("range_squares", &[("n", "int")]);
 }
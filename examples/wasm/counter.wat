@@ -0,0 +1,16 @@
+;; A guest that counts its calls in memory, to show that it can't: every
+;; call gets a fresh instance, so `wasm_calls` always answers 1.
+;;
+;; The output `{"ok":0}` sits at the start of memory, and each call bumps
+;; the digit before returning it. `alloc` hands out the bytes after it.
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "{\"ok\":0}")
+
+  (func (export "alloc") (param $len i32) (result i32)
+    i32.const 8)
+
+  (func (export "wasm_calls") (param $ptr i32) (param $len i32) (result i64)
+    (i32.store8 (i32.const 6) (i32.add (i32.load8_u (i32.const 6)) (i32.const 1)))
+    ;; Output at 0, 8 bytes long
+    i64.const 8))
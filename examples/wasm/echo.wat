@@ -0,0 +1,19 @@
+;; A guest for `--wasm-dir`, written by hand to show the ABI.
+;;
+;; `{"ok":` sits at the start of memory and `alloc` hands out the bytes
+;; right after it, so `wasm_echo` only has to append `}` to the arguments
+;; the host wrote there to produce `{"ok": <arguments>}`.
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "{\"ok\":")
+
+  (func (export "alloc") (param $len i32) (result i32)
+    i32.const 6)
+
+  (func (export "wasm_echo") (param $ptr i32) (param $len i32) (result i64)
+    (i32.store8 (i32.add (local.get $ptr) (local.get $len)) (i32.const 125))
+    ;; Output at 0, 6 + len + 1 bytes long
+    (i64.extend_i32_u (i32.add (local.get $len) (i32.const 7))))
+
+  (func (export "wasm_trap") (param $ptr i32) (param $len i32) (result i64)
+    unreachable))
@@ -9,19 +9,30 @@ This server allows executing Python functions over gRPC with support for:
 """
 
 import argparse
+import copy
 import importlib.util
+import inspect
+import io
 import json
 import logging
+import subprocess
 import sys
+import threading
 import time
 import uuid
 from concurrent import futures
 from pathlib import Path
-from typing import Any, Callable, Dict, Optional
+from typing import Any, Callable, Dict, Optional, Sequence
 
 import grpc
 from grpc_reflection.v1alpha import reflection
 
+# MessagePack encoding is offered only when the package is installed
+try:
+    import msgpack
+except ImportError:
+    msgpack = None
+
 # Generated proto imports (will be generated from .proto file)
 # For now, we'll add placeholder imports
 try:
@@ -32,6 +43,146 @@ except ImportError:
     sys.exit(1)
 
 
+SERVER_VERSION = "0.1.0"
+
+# Wire protocol revision, kept in step with common/src/protocol.rs
+PROTOCOL_VERSION = 1
+
+# Prefix of method names only built-in diagnostics may use
+RESERVED_PREFIX = "__"
+
+# Largest gRPC message sent or accepted, matching the Rust server
+MAX_MESSAGE_BYTES = 64 * 1024 * 1024
+
+# Default for --max-json-depth, matching the Rust server
+DEFAULT_MAX_JSON_DEPTH = 128
+
+
+def _nesting_depth(text: str) -> int:
+    """Deepest array/object nesting in JSON text, without parsing it."""
+    depth = deepest = 0
+    in_string = escaped = False
+    for char in text:
+        if in_string:
+            if escaped:
+                escaped = False
+            elif char == "\\":
+                escaped = True
+            elif char == '"':
+                in_string = False
+        elif char == '"':
+            in_string = True
+        elif char in "[{":
+            depth += 1
+            deepest = max(deepest, depth)
+        elif char in "]}":
+            depth = max(depth - 1, 0)
+    return deepest
+
+
+def _call_with_timeout(call: Callable, timeout: float):
+    """Run call() on a daemon thread for at most timeout seconds.
+
+    Returns (True, result), or (False, None) if it didn't finish in time;
+    an exception it raised is raised again here.
+    """
+    outcome = {}
+
+    def target():
+        try:
+            outcome["result"] = call()
+        except BaseException as e:
+            outcome["error"] = e
+
+    thread = threading.Thread(target=target, daemon=True)
+    thread.start()
+    thread.join(timeout)
+    if thread.is_alive():
+        return False, None
+    if "error" in outcome:
+        raise outcome["error"]
+    return True, outcome["result"]
+
+
+def _spread(args) -> tuple:
+    """Positional and keyword arguments for a call given args: an object
+    passes its keys by name, a list passes its items in order, and any other
+    value is the only argument."""
+    if isinstance(args, dict):
+        return (), args
+    if isinstance(args, list):
+        return tuple(args), {}
+    return (args,), {}
+
+
+def _failed(code: int, error: str, output: Optional[Dict[str, str]] = None):
+    """Response to an invocation that failed with error code `code`."""
+    return transpile_test_pb2.InvokeMethodResponse(
+        success=False, error=error, error_code=code, **(output or {})
+    )
+
+
+class _ThreadCapture(io.TextIOBase):
+    """Stand-in for sys.stdout or sys.stderr that collects what a thread
+    writes while it's capturing, and passes everything else through.
+
+    Calls run on a thread pool, so swapping sys.stdout per call would mix
+    the output of concurrent calls.
+    """
+
+    def __init__(self, stream):
+        self._stream = stream
+        self._local = threading.local()
+
+    def write(self, text):
+        buffer = getattr(self._local, "buffer", None)
+        return (buffer if buffer is not None else self._stream).write(text)
+
+    def flush(self):
+        self._stream.flush()
+
+    def start(self):
+        self._local.buffer = io.StringIO()
+
+    def stop(self) -> str:
+        buffer, self._local.buffer = self._local.buffer, None
+        return buffer.getvalue()
+
+
+def _capturing(call: Callable, output: Dict[str, str]) -> Callable:
+    """call, with what it prints in the thread that runs it put in output.
+
+    Only sys.stdout/sys.stderr as installed by serve() are captured.
+    """
+    def captured():
+        streams = [s for s in (sys.stdout, sys.stderr) if isinstance(s, _ThreadCapture)]
+        for stream in streams:
+            stream.start()
+        try:
+            return call()
+        finally:
+            for stream in streams:
+                name = "stdout" if stream is sys.stdout else "stderr"
+                output[name] = stream.stop()
+
+    return captured
+
+
+def _git_hash() -> str:
+    """Short hash of the commit the server is running from."""
+    try:
+        output = subprocess.run(
+            ["git", "rev-parse", "--short", "HEAD"],
+            cwd=Path(__file__).parent,
+            capture_output=True,
+            text=True,
+            check=True,
+        )
+        return output.stdout.strip()
+    except (OSError, subprocess.CalledProcessError):
+        return "unknown"
+
+
 class ExecutionContext:
     """Manages state for stateful function execution."""
 
@@ -43,6 +194,21 @@ class ExecutionContext:
                 self.state = json.loads(initial_state)
             except json.JSONDecodeError:
                 logging.warning(f"Invalid initial state JSON for context {context_id}")
+        self.initial_state: Dict[str, Any] = copy.deepcopy(self.state)
+        self.created_at = time.time()
+        self.last_used = self.created_at
+
+    def touch(self):
+        """Record a use, for ListContexts."""
+        self.last_used = time.time()
+
+    def info(self):
+        return transpile_test_pb2.ContextInfo(
+            context_id=self.context_id,
+            created_at_ms=int(self.created_at * 1000),
+            last_used_at_ms=int(self.last_used * 1000),
+            state_keys=len(self.state),
+        )
 
     def get_state(self) -> str:
         return json.dumps(self.state, default=str)
@@ -50,14 +216,24 @@ class ExecutionContext:
     def update_state(self, key: str, value: Any):
         self.state[key] = value
 
+    def reset(self):
+        self.state = copy.deepcopy(self.initial_state)
+
 
 class TranspileTestServiceImpl(transpile_test_pb2_grpc.TranspileTestServiceServicer):
     """Implementation of the TranspileTestService."""
 
-    def __init__(self):
+    def __init__(
+        self, max_json_depth: int = DEFAULT_MAX_JSON_DEPTH, default_timeout_ms: int = 0
+    ):
+        self.max_json_depth = max_json_depth
+        # Deadline of calls that don't set timeout_ms (0: none)
+        self.default_timeout_ms = default_timeout_ms
         self.contexts: Dict[str, ExecutionContext] = {}
         self.methods: Dict[str, Callable] = {}
         self.method_metadata: Dict[str, dict] = {}
+        self.git_hash = _git_hash()
+        self._register_builtins()
         logging.info("Python gRPC server initialized")
 
     def register_function(
@@ -69,16 +245,81 @@ class TranspileTestServiceImpl(transpile_test_pb2_grpc.TranspileTestServiceServi
         parameter_types: Optional[list] = None,
         return_type: str = "Any",
     ):
-        """Register a function that can be invoked via gRPC."""
+        """Register a function that can be invoked via gRPC.
+
+        Names in the reserved ``__`` namespace belong to the built-in
+        diagnostics and are refused.
+        """
+        if name.startswith(RESERVED_PREFIX):
+            logging.error(
+                f"Refusing to register {name}: names starting with "
+                f"'{RESERVED_PREFIX}' are reserved"
+            )
+            return
+        self._insert_function(
+            name, func, description, is_stateful, parameter_types, return_type
+        )
+
+    def _insert_function(
+        self,
+        name: str,
+        func: Callable,
+        description: str = "",
+        is_stateful: bool = False,
+        parameter_types: Optional[list] = None,
+        return_type: str = "Any",
+    ):
+        """Register without the reserved-name check, for built-ins."""
         self.methods[name] = func
         self.method_metadata[name] = {
             "description": description,
             "is_stateful": is_stateful,
             "parameter_types": parameter_types or [],
             "return_type": return_type,
+            # Generators stream their items through InvokeMethodStream
+            "is_streaming": inspect.isgeneratorfunction(func),
         }
         logging.info(f"Registered function: {name}")
 
+    def _register_builtins(self):
+        """Reserved diagnostic methods, mirroring the Rust server's built-ins."""
+
+        def echo(**kwargs):
+            return kwargs
+
+        def sleep_ms(ms):
+            time.sleep(ms / 1000)
+            return None
+
+        def fail(message="__fail called"):
+            raise RuntimeError(message)
+
+        def server_info():
+            info = self._server_info()
+            return {
+                "runtime": info.runtime,
+                "version": info.version,
+                "git_hash": info.git_hash,
+                "registration_complete": info.registration_complete,
+                "registration_error": info.registration_error,
+                "protocol_version": info.protocol_version,
+                "rejected_invocations": info.rejected_invocations,
+            }
+
+        self._insert_function("__echo", echo, "Return the arguments unchanged", False, ["any"], "any")
+        self._insert_function(
+            "__sleep_ms", sleep_ms, "Sleep for the given number of milliseconds", False, ["int"], "None"
+        )
+        self._insert_function("__fail", fail, "Fail with the given message", False, ["str"], "None")
+        self._insert_function(
+            "__server_info",
+            server_info,
+            "Describe this server build, like the GetServerInfo RPC",
+            False,
+            [],
+            "dict",
+        )
+
     def load_module(self, module_path: str):
         """Dynamically load a Python module and register its functions."""
         path = Path(module_path)
@@ -106,8 +347,17 @@ class TranspileTestServiceImpl(transpile_test_pb2_grpc.TranspileTestServiceServi
                     )
             logging.info(f"Loaded module: {module_path}")
 
+    def _check_depth(self, text: str, field: str, context):
+        """Abort with INVALID_ARGUMENT if `text` nests deeper than allowed."""
+        if _nesting_depth(text) > self.max_json_depth:
+            context.abort(
+                grpc.StatusCode.INVALID_ARGUMENT,
+                f"{field}: JSON nesting exceeds the maximum depth of {self.max_json_depth}",
+            )
+
     def CreateContext(self, request, context):
         """Create a new execution context."""
+        self._check_depth(request.initial_state, "initial_state", context)
         context_id = str(uuid.uuid4())
         exec_context = ExecutionContext(context_id, request.initial_state)
         self.contexts[context_id] = exec_context
@@ -120,48 +370,98 @@ class TranspileTestServiceImpl(transpile_test_pb2_grpc.TranspileTestServiceServi
     def InvokeMethod(self, request, context):
         """Invoke a registered method."""
         start_time = time.perf_counter()
+        # Checked before parsing so deep payloads never reach the recursive parser
+        if _nesting_depth(request.arguments) > self.max_json_depth:
+            return _failed(
+                transpile_test_pb2.INVALID_ARGUMENTS,
+                f"arguments: JSON nesting exceeds the maximum depth of {self.max_json_depth}",
+            )
 
         try:
             # Get the function
             if request.method_name not in self.methods:
-                return transpile_test_pb2.InvokeMethodResponse(
-                    success=False,
-                    error=f"Method not found: {request.method_name}",
+                return _failed(
+                    transpile_test_pb2.METHOD_NOT_FOUND,
+                    f"Method not found: {request.method_name}",
+                )
+
+            if self.method_metadata[request.method_name].get("is_streaming"):
+                return _failed(
+                    transpile_test_pb2.METHOD_NOT_FOUND,
+                    f"{request.method_name} streams its results; "
+                    "call it with InvokeMethodStream",
                 )
 
             func = self.methods[request.method_name]
 
             # Parse arguments
+            msgpack_encoded = request.encoding == transpile_test_pb2.MSGPACK
+            if msgpack_encoded and msgpack is None:
+                return _failed(
+                    transpile_test_pb2.INVALID_ARGUMENTS,
+                    "MessagePack encoding needs the msgpack package",
+                )
             try:
-                args = json.loads(request.arguments) if request.arguments else {}
-            except json.JSONDecodeError as e:
-                return transpile_test_pb2.InvokeMethodResponse(
-                    success=False, error=f"Invalid JSON arguments: {e}"
+                if msgpack_encoded:
+                    args = msgpack.unpackb(request.payload) if request.payload else {}
+                else:
+                    args = json.loads(request.arguments) if request.arguments else {}
+            except ValueError as e:
+                encoding = "MessagePack" if msgpack_encoded else "JSON"
+                return _failed(
+                    transpile_test_pb2.INVALID_ARGUMENTS, f"Invalid {encoding} arguments: {e}"
                 )
 
             # Get context if needed
             exec_context = None
             if request.context_id:
                 if request.context_id not in self.contexts:
-                    return transpile_test_pb2.InvokeMethodResponse(
-                        success=False, error=f"Context not found: {request.context_id}"
+                    return _failed(
+                        transpile_test_pb2.CONTEXT_NOT_FOUND,
+                        f"Context not found: {request.context_id}",
                     )
                 exec_context = self.contexts[request.context_id]
+                exec_context.touch()
 
             # Execute function
+            positional, named = _spread(args)
             if exec_context and self.method_metadata[request.method_name]["is_stateful"]:
                 # Pass context to stateful functions
-                result = func(exec_context, **args)
+                def call():
+                    return func(exec_context, *positional, **named)
             else:
                 # Call stateless functions normally
-                result = func(**args)
+                def call():
+                    return func(*positional, **named)
+
+            # Reported whether or not the call succeeds
+            output = {}
+            call = _capturing(call, output)
+
+            timeout_ms = request.timeout_ms or self.default_timeout_ms
+            try:
+                if timeout_ms:
+                    finished, result = _call_with_timeout(call, timeout_ms / 1000)
+                    if not finished:
+                        # Threads can't be killed, so the call runs on unobserved
+                        logging.warning(
+                            f"Abandoned {request.method_name} after {timeout_ms}ms; "
+                            "it keeps running in the background"
+                        )
+                        return _failed(
+                            transpile_test_pb2.TIMEOUT,
+                            f"TimeoutError: exceeded {timeout_ms}ms",
+                            output,
+                        )
+                else:
+                    result = call()
+            except Exception as e:
+                logging.error(f"Error executing {request.method_name}: {e}", exc_info=True)
+                return _failed(transpile_test_pb2.EXECUTION_ERROR, str(e), output)
 
             # Calculate execution time
             execution_time_us = int((time.perf_counter() - start_time) * 1_000_000)
 
-            # Serialize result
-            result_json = json.dumps(result, default=str)
-
             metadata = transpile_test_pb2.ExecutionMetadata(
                 execution_time_us=execution_time_us,
                 memory_bytes=0,  # TODO: Implement memory tracking
@@ -169,16 +469,83 @@ class TranspileTestServiceImpl(transpile_test_pb2_grpc.TranspileTestServiceServi
             )
 
             logging.debug(f"Executed {request.method_name} in {execution_time_us}us")
+
+            # Serialize result the way the arguments came
+            if msgpack_encoded:
+                return transpile_test_pb2.InvokeMethodResponse(
+                    success=True,
+                    encoding=transpile_test_pb2.MSGPACK,
+                    payload=msgpack.packb(result, default=str),
+                    metadata=metadata,
+                    **output,
+                )
             return transpile_test_pb2.InvokeMethodResponse(
-                success=True, result=result_json, error="", metadata=metadata
+                success=True,
+                result=json.dumps(result, default=str),
+                error="",
+                metadata=metadata,
+                **output,
             )
 
         except Exception as e:
-            logging.error(f"Error executing {request.method_name}: {e}", exc_info=True)
-            return transpile_test_pb2.InvokeMethodResponse(
-                success=False, error=str(e)
+            logging.error(f"Failed to invoke {request.method_name}: {e}", exc_info=True)
+            return _failed(transpile_test_pb2.INTERNAL, str(e))
+
+    def InvokeMethodStream(self, request, context):
+        """Invoke a generator method, streaming each item it yields."""
+        start_time = time.perf_counter()
+        self._check_depth(request.arguments, "arguments", context)
+
+        def end(count, error=""):
+            metadata = transpile_test_pb2.ExecutionMetadata(
+                execution_time_us=int((time.perf_counter() - start_time) * 1_000_000),
+                memory_bytes=0,
+                runtime="python",
+            )
+            return transpile_test_pb2.InvokeStreamChunk(
+                sequence=count, done=True, error=error, metadata=metadata
             )
 
+        name = request.method_name
+        if name not in self.methods:
+            yield end(0, f"Method not found: {name}")
+            return
+        if not self.method_metadata[name].get("is_streaming"):
+            yield end(0, f"{name} doesn't stream its results; call it with InvokeMethod")
+            return
+        try:
+            args = json.loads(request.arguments) if request.arguments else {}
+        except json.JSONDecodeError as e:
+            yield end(0, f"Invalid JSON arguments: {e}")
+            return
+        exec_context = None
+        if request.context_id:
+            if request.context_id not in self.contexts:
+                yield end(0, f"Context not found: {request.context_id}")
+                return
+            exec_context = self.contexts[request.context_id]
+            exec_context.touch()
+
+        func = self.methods[name]
+        count = 0
+        try:
+            positional, named = _spread(args)
+            if exec_context and self.method_metadata[name]["is_stateful"]:
+                items = func(exec_context, *positional, **named)
+            else:
+                items = func(*positional, **named)
+            for item in items:
+                yield transpile_test_pb2.InvokeStreamChunk(
+                    sequence=count, item=json.dumps(item, default=str)
+                )
+                count += 1
+        except Exception as e:
+            logging.error(f"Error streaming {name} at item {count}: {e}", exc_info=True)
+            yield end(count, str(e))
+            return
+        logging.debug(f"Streamed {count} items of {name}")
+        yield end(count)
+
     def InspectState(self, request, context):
         """Inspect the state of a context."""
         if request.context_id not in self.contexts:
@@ -187,10 +554,46 @@ class TranspileTestServiceImpl(transpile_test_pb2_grpc.TranspileTestServiceServi
             )
 
         exec_context = self.contexts[request.context_id]
+        exec_context.touch()
         return transpile_test_pb2.InspectStateResponse(
             success=True, state=exec_context.get_state(), error=""
         )
 
+    def ExportContext(self, request, context):
+        """Export the full state of a context."""
+        if request.context_id not in self.contexts:
+            return transpile_test_pb2.ExportContextResponse(
+                success=False,
+                context_id=request.context_id,
+                error=f"Context not found: {request.context_id}",
+            )
+
+        exec_context = self.contexts[request.context_id]
+        exec_context.touch()
+        return transpile_test_pb2.ExportContextResponse(
+            success=True, context_id=request.context_id, state=exec_context.get_state()
+        )
+
+    def ImportContext(self, request, context):
+        """Create a context holding previously exported state."""
+        self._check_depth(request.state, "state", context)
+        try:
+            state = json.loads(request.state)
+        except json.JSONDecodeError as e:
+            error = f"Invalid state JSON: {e}"
+        else:
+            error = None
+            if not isinstance(state, dict):
+                error = f"state must be a JSON object, got {type(state).__name__}"
+        if error:
+            logging.warning(f"Rejected context import: {error}")
+            return transpile_test_pb2.ImportContextResponse(success=False, error=error)
+
+        context_id = str(uuid.uuid4())
+        self.contexts[context_id] = ExecutionContext(context_id, request.state)
+        logging.info(f"Imported context: {context_id} ({len(state)} keys)")
+        return transpile_test_pb2.ImportContextResponse(context_id=context_id, success=True)
+
     def DestroyContext(self, request, context):
         """Destroy an execution context."""
         if request.context_id in self.contexts:
@@ -202,6 +605,31 @@ class TranspileTestServiceImpl(transpile_test_pb2_grpc.TranspileTestServiceServi
                 success=False, error=f"Context not found: {request.context_id}"
             )
 
+    def ResetContext(self, request, context):
+        """Reset a context to the state it was created with."""
+        if request.context_id not in self.contexts:
+            return transpile_test_pb2.ResetContextResponse(
+                success=False, error=f"Context not found: {request.context_id}"
+            )
+
+        exec_context = self.contexts[request.context_id]
+        exec_context.touch()
+        exec_context.reset()
+        logging.debug(f"Reset context: {request.context_id}")
+        return transpile_test_pb2.ResetContextResponse(success=True, error="")
+
+    def ListContexts(self, request, context):
+        """List the live contexts, oldest first."""
+        contexts = sorted(self.contexts.values(), key=lambda c: c.created_at)
+        return transpile_test_pb2.ListContextsResponse(contexts=[c.info() for c in contexts])
+
+    def DestroyAllContexts(self, request, context):
+        """Destroy every context."""
+        destroyed = len(self.contexts)
+        self.contexts.clear()
+        logging.info(f"Destroyed all {destroyed} contexts")
+        return transpile_test_pb2.DestroyAllContextsResponse(destroyed=destroyed)
+
     def ListMethods(self, request, context):
         """List available methods."""
         methods = []
@@ -215,11 +643,28 @@ class TranspileTestServiceImpl(transpile_test_pb2_grpc.TranspileTestServiceServi
                 is_stateful=metadata["is_stateful"],
                 parameter_types=metadata["parameter_types"],
                 return_type=metadata["return_type"],
+                is_streaming=metadata.get("is_streaming", False),
             )
             methods.append(method_info)
 
         return transpile_test_pb2.ListMethodsResponse(methods=methods)
 
+    def GetServerInfo(self, request, context):
+        """Describe this server build."""
+        return self._server_info()
+
+    def _server_info(self):
+        # Modules are loaded before the server starts accepting requests
+        return transpile_test_pb2.GetServerInfoResponse(
+            runtime="python",
+            version=SERVER_VERSION,
+            git_hash=self.git_hash,
+            registration_complete=True,
+            protocol_version=PROTOCOL_VERSION,
+            encodings=[transpile_test_pb2.JSON]
+            + ([transpile_test_pb2.MSGPACK] if msgpack is not None else []),
+        )
+
 
 def transpile_test(**metadata):
     """Decorator to mark functions for transpilation testing."""
@@ -231,12 +676,29 @@ def transpile_test(**metadata):
     return decorator
 
 
-def serve(port: int, module_path: Optional[str] = None):
+def serve(
+    port: int,
+    module_paths: Sequence[str] = (),
+    max_json_depth: int = DEFAULT_MAX_JSON_DEPTH,
+    default_timeout_ms: int = 0,
+):
     """Start the gRPC server."""
-    server = grpc.server(futures.ThreadPoolExecutor(max_workers=10))
-    service = TranspileTestServiceImpl()
+    # Lets each call capture what it prints; logging keeps the real stderr
+    sys.stdout = _ThreadCapture(sys.stdout)
+    sys.stderr = _ThreadCapture(sys.stderr)
+    server = grpc.server(
+        futures.ThreadPoolExecutor(max_workers=10),
+        options=[
+            ("grpc.max_send_message_length", MAX_MESSAGE_BYTES),
+            ("grpc.max_receive_message_length", MAX_MESSAGE_BYTES),
+        ],
+    )
+    service = TranspileTestServiceImpl(
+        max_json_depth=max_json_depth, default_timeout_ms=default_timeout_ms
+    )
 
-    if module_path:
+    # In order, so a later module's functions replace an earlier one's
+    for module_path in module_paths:
         service.load_module(module_path)
 
     transpile_test_pb2_grpc.add_TranspileTestServiceServicer_to_server(service, server)
@@ -264,7 +726,25 @@ def serve(port: int, module_path: Optional[str] = None):
 def main():
     parser = argparse.ArgumentParser(description="Python gRPC Test Server")
     parser.add_argument("--port", type=int, default=50051, help="Server port")
-    parser.add_argument("--module", type=str, help="Python module to load")
+    parser.add_argument(
+        "--module",
+        type=str,
+        action="append",
+        default=[],
+        help="Python module to load (repeatable)",
+    )
+    parser.add_argument(
+        "--max-json-depth",
+        type=int,
+        default=DEFAULT_MAX_JSON_DEPTH,
+        help="Deepest JSON nesting accepted in arguments and initial state",
+    )
+    parser.add_argument(
+        "--default-timeout-ms",
+        type=int,
+        default=0,
+        help="Deadline of calls that don't set timeout_ms (0: none)",
+    )
     parser.add_argument("--verbose", action="store_true", help="Enable debug logging")
 
     args = parser.parse_args()
@@ -274,7 +754,7 @@ def main():
         format="%(asctime)s - %(name)s - %(levelname)s - %(message)s",
     )
 
-    serve(args.port, args.module)
+    serve(args.port, args.module, args.max_json_depth, args.default_timeout_ms)
 
 
 if __name__ == "__main__":
@@ -1,8 +1,22 @@
+use std::process::Command;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
         .build_server(true)
-        .build_client(false)
-        .compile(&["../proto/transpile_test.proto"], &["../proto"])?;
+        .build_client(true)
+        .compile_protos(&["../proto/transpile_test.proto"], &["../proto"])?;
+
+    // Embed the git commit so GetServerInfo can identify the build
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=../.git/HEAD");
 
     Ok(())
 }
@@ -0,0 +1,259 @@
+/*!
+Checking invocation arguments against declared parameters.
+
+A function whose parameters are declared with
+`TranspileTestServer::declare_parameters` has its arguments checked before
+it runs: they must be a JSON object with every declared parameter, each of
+its declared type, and nothing else. A failure is returned as the call's
+error, prefixed `ArgumentError:` so clients can tell it from an error the
+function raised. Functions registered without names aren't checked.
+
+Once the parameters are named, by `declare_parameters` or
+`name_parameters`, arguments may also be given by position: a JSON array is
+mapped onto the names in order before anything else sees it, and any other
+value that isn't an object is taken as the only argument.
+
+Types are matched loosely by name: `int`, `float` (any number), `str`,
+`bool`, `list` and `dict`, with common aliases; `any` or a type not listed
+accepts every value. An optional type, written `int?`, `Optional[int]` or
+`Option<int>`, also accepts `null`, and its parameter may be left out.
+*/
+
+use serde_json::Value as JsonValue;
+
+/// Start of every argument validation error
+pub const ERROR_PREFIX: &str = "ArgumentError:";
+
+/// A named, typed parameter
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub name: String,
+    pub type_name: String,
+}
+
+impl Parameter {
+    pub fn new(name: impl Into<String>, type_name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            type_name: type_name.into(),
+        }
+    }
+}
+
+/// Map positional `args` onto `names`, leaving an object as it is
+pub fn by_position(names: &[String], args: JsonValue) -> Result<JsonValue, String> {
+    let values = match args {
+        JsonValue::Object(_) => return Ok(args),
+        JsonValue::Array(values) => values,
+        value => vec![value],
+    };
+    if values.len() != names.len() {
+        return Err(format!(
+            "{} expected {} argument{}, got {}",
+            ERROR_PREFIX,
+            names.len(),
+            if names.len() == 1 { "" } else { "s" },
+            values.len()
+        ));
+    }
+    Ok(JsonValue::Object(
+        names.iter().cloned().zip(values).collect(),
+    ))
+}
+
+/// Check `args` against `parameters`, describing the first problem found
+pub fn validate(parameters: &[Parameter], args: &JsonValue) -> Result<(), String> {
+    let Some(args) = args.as_object() else {
+        return Err(format!(
+            "{} arguments must be a JSON object, got {}",
+            ERROR_PREFIX,
+            json_type(args)
+        ));
+    };
+    for parameter in parameters {
+        match args.get(&parameter.name) {
+            None if optional(&parameter.type_name).is_some() => {}
+            None => {
+                return Err(format!(
+                    "{} missing parameter '{}' ({})",
+                    ERROR_PREFIX, parameter.name, parameter.type_name
+                ));
+            }
+            Some(value) if !matches_type(&parameter.type_name, value) => {
+                let shown = match value {
+                    JsonValue::Null | JsonValue::Array(_) | JsonValue::Object(_) => String::new(),
+                    scalar => format!(" {}", scalar),
+                };
+                return Err(format!(
+                    "{} parameter '{}' must be {}, got {}{}",
+                    ERROR_PREFIX,
+                    parameter.name,
+                    parameter.type_name,
+                    json_type(value),
+                    shown
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+    if let Some(extra) = args
+        .keys()
+        .find(|key| !parameters.iter().any(|p| &p.name == *key))
+    {
+        return Err(format!("{} unexpected parameter '{}'", ERROR_PREFIX, extra));
+    }
+    Ok(())
+}
+
+/// The type an optional type wraps
+fn optional(type_name: &str) -> Option<&str> {
+    let type_name = type_name.trim();
+    type_name
+        .strip_suffix('?')
+        .or_else(|| type_name.strip_prefix("Optional[")?.strip_suffix(']'))
+        .or_else(|| type_name.strip_prefix("Option<")?.strip_suffix('>'))
+}
+
+fn matches_type(type_name: &str, value: &JsonValue) -> bool {
+    if let Some(inner) = optional(type_name) {
+        return value.is_null() || matches_type(inner, value);
+    }
+    match type_name.trim().to_ascii_lowercase().as_str() {
+        "int" | "integer" | "i32" | "i64" | "u32" | "u64" | "long" => {
+            value.is_i64() || value.is_u64()
+        }
+        "float" | "double" | "f32" | "f64" | "number" => value.is_number(),
+        "str" | "string" => value.is_string(),
+        "bool" | "boolean" => value.is_boolean(),
+        "list" | "array" | "vec" => value.is_array(),
+        "dict" | "object" | "map" => value.is_object(),
+        _ => true,
+    }
+}
+
+/// Name of the JSON type of `value`, in the same terms as parameter types
+pub fn json_type(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "bool",
+        JsonValue::Number(n) if n.is_f64() => "float",
+        JsonValue::Number(_) => "int",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "list",
+        JsonValue::Object(_) => "dict",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn add() -> Vec<Parameter> {
+        vec![Parameter::new("a", "int"), Parameter::new("b", "int")]
+    }
+
+    fn names(parameters: &[Parameter]) -> Vec<String> {
+        parameters.iter().map(|p| p.name.clone()).collect()
+    }
+
+    #[test]
+    fn matching_arguments_pass() {
+        assert_eq!(validate(&add(), &json!({"a": 1, "b": 2})), Ok(()));
+        assert_eq!(validate(&[], &json!({})), Ok(()));
+    }
+
+    #[test]
+    fn a_missing_parameter_is_named_with_its_type() {
+        assert_eq!(
+            validate(&add(), &json!({"a": 1})).unwrap_err(),
+            "ArgumentError: missing parameter 'b' (int)"
+        );
+    }
+
+    #[test]
+    fn a_wrong_type_names_the_parameter_and_what_was_given() {
+        let cases = [
+            (
+                json!({"a": "2", "b": 2}),
+                "parameter 'a' must be int, got string \"2\"",
+            ),
+            (
+                json!({"a": 1, "b": 2.5}),
+                "parameter 'b' must be int, got float 2.5",
+            ),
+            (
+                json!({"a": 1, "b": null}),
+                "parameter 'b' must be int, got null",
+            ),
+            (
+                json!({"a": [1], "b": 2}),
+                "parameter 'a' must be int, got list",
+            ),
+            (
+                json!({"a": {"x": 1}, "b": 2}),
+                "parameter 'a' must be int, got dict",
+            ),
+        ];
+        for (args, error) in cases {
+            let found = validate(&add(), &args).unwrap_err();
+            assert_eq!(found, format!("{} {}", ERROR_PREFIX, error), "{}", args);
+        }
+    }
+
+    #[test]
+    fn an_unexpected_parameter_is_named() {
+        assert_eq!(
+            validate(&add(), &json!({"a": 1, "b": 2, "c": 3})).unwrap_err(),
+            "ArgumentError: unexpected parameter 'c'"
+        );
+        assert_eq!(
+            validate(&add(), &json!([1, 2])).unwrap_err(),
+            "ArgumentError: arguments must be a JSON object, got list"
+        );
+    }
+
+    #[test]
+    fn positional_arguments_bind_in_order() {
+        let names = names(&add());
+        let bound = by_position(&names, json!([1, 2])).unwrap();
+        assert_eq!(bound, json!({"a": 1, "b": 2}));
+        assert_eq!(validate(&add(), &bound), Ok(()));
+
+        let object = json!({"b": 2, "a": 1});
+        assert_eq!(by_position(&names, object.clone()).unwrap(), object);
+        let single = ["n".to_string()];
+        assert_eq!(by_position(&single, json!(5)).unwrap(), json!({"n": 5}));
+        assert_eq!(
+            by_position(&single, json!([[5]])).unwrap(),
+            json!({"n": [5]})
+        );
+        assert_eq!(
+            by_position(&names, json!([1])).unwrap_err(),
+            "ArgumentError: expected 2 arguments, got 1"
+        );
+    }
+
+    #[test]
+    fn optional_parameters_accept_null_or_nothing() {
+        for type_name in ["int?", "Optional[int]", "Option<int>"] {
+            let parameters = [
+                Parameter::new("a", "int"),
+                Parameter::new("limit", type_name),
+            ];
+            assert_eq!(
+                validate(&parameters, &json!({"a": 1, "limit": null})),
+                Ok(())
+            );
+            assert_eq!(validate(&parameters, &json!({"a": 1, "limit": 10})), Ok(()));
+            assert_eq!(validate(&parameters, &json!({"a": 1})), Ok(()));
+            assert_eq!(
+                validate(&parameters, &json!({"a": 1, "limit": "ten"})).unwrap_err(),
+                format!(
+                    "ArgumentError: parameter 'limit' must be {}, got string \"ten\"",
+                    type_name
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,80 @@
+/*!
+Token-based roles for restricting method invocation.
+
+The roles file is a JSON object mapping bearer tokens to role names:
+
+```json
+{"s3cr3t-token": "admin", "ci-token": "ci"}
+```
+
+Callers identify themselves with an `authorization: Bearer <token>` header.
+Methods registered without allowed roles stay callable by everyone; those
+registered with `register_restricted_function` only by their roles and
+admin. Every call that invokes, lists or touches contexts rejects a token
+the file doesn't know, while a call without one goes ahead anonymously.
+*/
+
+use std::collections::HashMap;
+use std::path::Path;
+use tonic::metadata::MetadataMap;
+
+/// Role that may invoke and list every method
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Default)]
+pub struct Roles {
+    tokens: HashMap<String, String>,
+}
+
+impl Roles {
+    pub fn new(tokens: HashMap<String, String>) -> Self {
+        Self { tokens }
+    }
+
+    /// Load a token → role map from a JSON file
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read roles file {}: {}", path.display(), e))?;
+        let tokens: HashMap<String, String> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid roles file {}: {}", path.display(), e))?;
+        Ok(Self::new(tokens))
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Role of the caller, or `None` if no token was sent.
+    ///
+    /// A token that isn't in the roles file is rejected outright rather than
+    /// treated as anonymous, so typos in client configuration are visible.
+    /// The error is meant for `Status::unauthenticated`.
+    pub fn caller_role(&self, metadata: &MetadataMap) -> Result<Option<String>, &'static str> {
+        let header = match metadata.get("authorization") {
+            Some(value) => value
+                .to_str()
+                .map_err(|_| "Malformed authorization header")?,
+            None => return Ok(None),
+        };
+
+        let token = header
+            .strip_prefix("Bearer ")
+            .ok_or("Expected a Bearer token")?;
+
+        match self.tokens.get(token) {
+            Some(role) => Ok(Some(role.clone())),
+            None => Err("Unknown auth token"),
+        }
+    }
+}
+
+/// Whether a caller with `role` may invoke a method restricted to `allowed_roles`
+pub fn is_allowed(allowed_roles: &[String], role: Option<&str>) -> bool {
+    if allowed_roles.is_empty() {
+        return true;
+    }
+    match role {
+        Some(role) => role == ADMIN_ROLE || allowed_roles.iter().any(|r| r == role),
+        None => false,
+    }
+}
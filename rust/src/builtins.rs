@@ -0,0 +1,161 @@
+/*!
+Reserved diagnostic methods available on every server.
+
+These live in the `__` namespace, which `register_function` refuses, so a
+known-good method exists for transport debugging no matter which example
+modules or plugins are loaded:
+
+- `__echo`: returns its arguments verbatim
+- `__sleep_ms {ms}`: sleeps, then returns `null`
+- `__fail {message}`: always fails with `message`
+- `__server_info`: the `GetServerInfo` payload as JSON
+*/
+
+use crate::{server_info, ExecutionContext, TranspileTestServer};
+use serde_json::{json, Value as JsonValue};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// Prefix of method names only built-ins may use
+pub const RESERVED_PREFIX: &str = "__";
+
+pub fn is_reserved(name: &str) -> bool {
+    name.starts_with(RESERVED_PREFIX)
+}
+
+pub fn register(server: &TranspileTestServer) {
+    server.insert_function(
+        "__echo",
+        |_ctx: &ExecutionContext, args: JsonValue| Ok(args),
+        "Return the arguments unchanged",
+        false,
+        vec!["any".to_string()],
+        "any",
+    );
+
+    server.insert_async_function(
+        "__sleep_ms",
+        |_ctx: &ExecutionContext, args: JsonValue| async move {
+            let ms = args["ms"].as_u64().ok_or("Missing or invalid 'ms'")?;
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            Ok(JsonValue::Null)
+        },
+        "Sleep for the given number of milliseconds",
+        false,
+        vec!["int".to_string()],
+        "None",
+    );
+
+    server.insert_function(
+        "__fail",
+        |_ctx: &ExecutionContext, args: JsonValue| {
+            let message = args["message"].as_str().unwrap_or("__fail called");
+            Err(message.to_string())
+        },
+        "Fail with the given message",
+        false,
+        vec!["str".to_string()],
+        "None",
+    );
+
+    let registration = server.registration().clone();
+    let rejected = server.rejected_invocations.clone();
+    server.insert_function(
+        "__server_info",
+        move |_ctx: &ExecutionContext, _args: JsonValue| {
+            let info = server_info(&registration, rejected.load(Ordering::Relaxed));
+            Ok(json!({
+                "runtime": info.runtime,
+                "version": info.version,
+                "git_hash": info.git_hash,
+                "registration_complete": info.registration_complete,
+                "registration_error": info.registration_error,
+                "protocol_version": info.protocol_version,
+                "rejected_invocations": info.rejected_invocations,
+            }))
+        },
+        "Describe this server build, like the GetServerInfo RPC",
+        false,
+        Vec::new(),
+        "dict",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transpile_test::transpile_test_service_server::TranspileTestService;
+    use crate::transpile_test::InvokeMethodRequest;
+    use tonic::Request;
+
+    fn replacement(_ctx: &ExecutionContext, _args: JsonValue) -> Result<JsonValue, String> {
+        Ok(json!("replaced"))
+    }
+
+    async fn call(server: &TranspileTestServer, method: &str) -> String {
+        let request = InvokeMethodRequest {
+            method_name: method.to_string(),
+            arguments: r#"{"x": 1}"#.to_string(),
+            ..Default::default()
+        };
+        let response = server.invoke_method(Request::new(request)).await.unwrap();
+        response.into_inner().result
+    }
+
+    #[test]
+    fn reserved_names() {
+        assert!(is_reserved("__echo"));
+        assert!(is_reserved("__"));
+        assert!(!is_reserved("_echo"));
+        assert!(!is_reserved("echo__"));
+        assert!(!is_reserved("a__b"));
+    }
+
+    #[tokio::test]
+    async fn every_registration_refuses_reserved_names() {
+        let server = TranspileTestServer::new();
+        let types = Vec::new;
+        server.register_function("__echo", replacement, "", false, types(), "any");
+        server.register_typed_function(
+            "__echo",
+            |_ctx, args: JsonValue| Ok(args),
+            "",
+            false,
+            types(),
+            "any",
+        );
+        server.register_async_function(
+            "__echo",
+            |_ctx, _args| async { Ok(json!("replaced")) },
+            "",
+            false,
+            types(),
+            "any",
+        );
+        server.register_restricted_function("__echo", &[], replacement, "", false, types(), "any");
+        server.register_streaming_function(
+            "__echo",
+            |_ctx, _args| Ok(Box::new(std::iter::empty()) as crate::streaming::Items),
+            "",
+            false,
+            types(),
+            "any",
+        );
+        server.register_function("__new", replacement, "", false, types(), "any");
+
+        // The built-in is untouched, and nothing new joined the namespace
+        assert_eq!(call(&server, "__echo").await, r#"{"x":1}"#);
+        assert!(server.streams.read().is_empty());
+        assert!(!server.methods.read().contains_key("__new"));
+        assert!(!server.metadata.read().contains_key("__new"));
+    }
+
+    #[tokio::test]
+    async fn names_outside_the_namespace_are_accepted() {
+        let server = TranspileTestServer::new();
+        for name in ["_echo", "echo__", "a__b"] {
+            server.register_function(name, replacement, "", false, Vec::new(), "any");
+            assert_eq!(call(&server, name).await, r#""replaced""#);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+/*!
+Server-side deadlines for invocations.
+
+A call with a timeout, from `InvokeMethodRequest.timeout_ms` or
+`--default-timeout-ms`, runs on a blocking thread of its own while the
+request waits for it at most that long. Registered functions are mostly
+synchronous and can't be interrupted, so a call that misses its deadline
+is abandoned rather than stopped: a synchronous function runs on to the end
+with nobody waiting for it, while an async one is dropped at its next
+await. Either way the worker threads stay free, so other invocations are
+unaffected.
+
+The call is measured and its panics caught on that thread just as for an
+untimed call.
+*/
+
+use crate::{catch_unwind, memory, FunctionFuture};
+use serde_json::Value as JsonValue;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+
+/// What a call returned, or what it panicked with, and its peak memory
+pub type Outcome = (std::thread::Result<Result<JsonValue, String>>, i64);
+
+/// Start of the error of a call that missed its deadline
+pub const ERROR_PREFIX: &str = "TimeoutError:";
+
+/// Run `call` for at most `timeout`; None if the deadline passed first
+pub async fn run(call: FunctionFuture, timeout: Duration) -> Option<Outcome> {
+    let runtime = Handle::current();
+    let (abandon, abandoned) = oneshot::channel::<()>();
+    let task = tokio::task::spawn_blocking(move || {
+        runtime.block_on(async {
+            tokio::select! {
+                outcome = memory::measure_future(catch_unwind(call)) => Some(outcome),
+                _ = abandoned => None,
+            }
+        })
+    });
+
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => Some((Ok(Err(format!("Call failed: {}", e))), 0)),
+        Err(_) => {
+            let _ = abandon.send(());
+            None
+        }
+    }
+}
+
+/// The error of a call that missed a deadline of `timeout`
+pub fn error(timeout: Duration) -> String {
+    format!("{} exceeded {}ms", ERROR_PREFIX, timeout.as_millis())
+}
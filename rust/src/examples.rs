@@ -2,45 +2,53 @@
 Example implementations for testing
 */
 
-use serde_json::{json, Value as JsonValue};
+use serde::Deserialize;
+use serde_json::{json, Map, Value as JsonValue};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// Arguments of `add` and `multiply`
+#[derive(Deserialize)]
+struct Operands {
+    a: i64,
+    b: i64,
+}
+
+/// Arguments of `fibonacci`
+#[derive(Deserialize)]
+struct Position {
+    n: i64,
+}
 
 pub fn register_simple_math(server: &crate::TranspileTestServer) {
     // Add
-    server.register_function(
+    server.register_typed_function(
         "add",
-        |_ctx, args| {
-            let a = args["a"].as_i64().ok_or("Missing or invalid 'a'")?;
-            let b = args["b"].as_i64().ok_or("Missing or invalid 'b'")?;
-            Ok(json!(a + b))
-        },
+        |_ctx, Operands { a, b }| Ok(a + b),
         "Add two numbers",
         false,
         vec!["int".to_string(), "int".to_string()],
         "int",
     );
+    server.declare_parameters("add", &[("a", "int"), ("b", "int")]);
 
     // Multiply
-    server.register_function(
+    server.register_typed_function(
         "multiply",
-        |_ctx, args| {
-            let a = args["a"].as_i64().ok_or("Missing or invalid 'a'")?;
-            let b = args["b"].as_i64().ok_or("Missing or invalid 'b'")?;
-            Ok(json!(a * b))
-        },
+        |_ctx, Operands { a, b }| Ok(a * b),
         "Multiply two numbers",
         false,
         vec!["int".to_string(), "int".to_string()],
         "int",
     );
+    server.declare_parameters("multiply", &[("a", "int"), ("b", "int")]);
 
     // Fibonacci
-    server.register_function(
+    server.register_typed_function(
         "fibonacci",
-        |_ctx, args| {
-            let n = args["n"].as_i64().ok_or("Missing or invalid 'n'")?;
-
+        |_ctx, Position { n }| {
             if n <= 1 {
-                return Ok(json!(n));
+                return Ok(n);
             }
 
             let mut a = 0i64;
@@ -51,13 +59,14 @@ pub fn register_simple_math(server: &crate::TranspileTestServer) {
                 b = temp;
             }
 
-            Ok(json!(b))
+            Ok(b)
         },
         "Calculate the nth Fibonacci number",
         false,
         vec!["int".to_string()],
         "int",
     );
+    server.declare_parameters("fibonacci", &[("n", "int")]);
 
     // Counter increment (stateful)
     server.register_function(
@@ -70,6 +79,7 @@ pub fn register_simple_math(server: &crate::TranspileTestServer) {
 
             let new_value = current + 1;
             ctx.set_state("counter".to_string(), json!(new_value));
+            ctx.print(&format!("counter: {} -> {}\n", current, new_value));
 
             Ok(json!(new_value))
         },
@@ -117,6 +127,7 @@ pub fn register_simple_math(server: &crate::TranspileTestServer) {
         vec!["int".to_string()],
         "int",
     );
+    server.declare_parameters("factorial", &[("n", "int")]);
 
     // Is prime
     server.register_function(
@@ -148,4 +159,397 @@ pub fn register_simple_math(server: &crate::TranspileTestServer) {
         vec!["int".to_string()],
         "bool",
     );
+    server.declare_parameters("is_prime", &[("n", "int")]);
+
+    // Element at an index, left unchecked: an index past the end panics,
+    // which the server reports as a failed call
+    server.register_function(
+        "element_at",
+        |_ctx, args| {
+            let items = args["items"]
+                .as_array()
+                .ok_or("Missing or invalid 'items'")?;
+            let index = args["index"].as_u64().ok_or("Missing or invalid 'index'")?;
+            Ok(items[index as usize].clone())
+        },
+        "Get the element of a list at an index",
+        false,
+        vec!["list".to_string(), "int".to_string()],
+        "any",
+    );
+    server.declare_parameters("element_at", &[("items", "list"), ("index", "int")]);
+
+    // Sum of a vector, which allocates n integers so memory_bytes is visible
+    server.register_function(
+        "sum_vec",
+        |_ctx, args| {
+            let n = args["n"].as_i64().ok_or("Missing or invalid 'n'")?;
+            let n = usize::try_from(n).map_err(|_| "'n' must not be negative")?;
+
+            let values: Vec<i64> = (0..n as i64).collect();
+            Ok(json!(values.iter().sum::<i64>()))
+        },
+        "Sum the integers below n, held in a vector",
+        false,
+        vec!["int".to_string()],
+        "int",
+    );
+    server.declare_parameters("sum_vec", &[("n", "int")]);
+
+    // Sum after a delay, awaited rather than blocking the worker thread
+    server.register_async_function(
+        "delayed_add",
+        |_ctx, args| async move {
+            let a = args["a"].as_i64().ok_or("Missing or invalid 'a'")?;
+            let b = args["b"].as_i64().ok_or("Missing or invalid 'b'")?;
+            let delay_ms = args["delay_ms"]
+                .as_u64()
+                .ok_or("Missing or invalid 'delay_ms'")?;
+            tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+            Ok(json!(a + b))
+        },
+        "Add two numbers after sleeping for delay_ms milliseconds",
+        false,
+        vec!["int".to_string(), "int".to_string(), "int".to_string()],
+        "int",
+    );
+    server.declare_parameters(
+        "delayed_add",
+        &[("a", "int"), ("b", "int"), ("delay_ms", "int")],
+    );
+
+    // Spin for ms milliseconds without yielding, for exercising deadlines
+    server.register_function(
+        "busy_loop",
+        |_ctx, args| {
+            let ms = args["ms"].as_u64().ok_or("Missing or invalid 'ms'")?;
+            let until = std::time::Instant::now() + std::time::Duration::from_millis(ms);
+            while std::time::Instant::now() < until {
+                std::hint::spin_loop();
+            }
+            Ok(json!(ms))
+        },
+        "Keep the CPU busy for ms milliseconds, then return ms",
+        false,
+        vec!["int".to_string()],
+        "int",
+    );
+    server.declare_parameters("busy_loop", &[("ms", "int")]);
+
+    // n consecutive integers from start, for large payloads and integers
+    // past 2^53
+    server.register_function(
+        "sequence",
+        |_ctx, args| {
+            let start = args["start"].as_i64().ok_or("Missing or invalid 'start'")?;
+            let n = args["n"].as_i64().ok_or("Missing or invalid 'n'")?;
+            let end = start.checked_add(n).ok_or("'start' + 'n' overflows")?;
+            Ok(json!((start..end).collect::<Vec<i64>>()))
+        },
+        "Return the n consecutive integers from start",
+        false,
+        vec!["int".to_string(), "int".to_string()],
+        "list",
+    );
+    server.declare_parameters("sequence", &[("start", "int"), ("n", "int")]);
+
+    // Squares of 0..n, streamed one at a time rather than returned as a list
+    server.register_streaming_function(
+        "range_squares",
+        |_ctx, args| {
+            let n = args["n"].as_i64().ok_or("Missing or invalid 'n'")?;
+            let squares = (0..n).map(|i| Ok(json!(i * i)));
+            Ok(Box::new(squares) as Box<dyn Iterator<Item = _> + Send>)
+        },
+        "Yield the squares of the integers below n",
+        false,
+        vec!["int".to_string()],
+        "int",
+    );
+    server.declare_parameters("range_squares", &[("n", "int")]);
+}
+
+pub fn register_collections(server: &crate::TranspileTestServer) {
+    // Reverse a string by code point, as Python's s[::-1] does
+    server.register_function(
+        "reverse_string",
+        |_ctx, args| {
+            let s = args["s"].as_str().ok_or("Missing or invalid 's'")?;
+            Ok(json!(s.chars().rev().collect::<String>()))
+        },
+        "Reverse a string",
+        false,
+        vec!["str".to_string()],
+        "str",
+    );
+    server.declare_parameters("reverse_string", &[("s", "str")]);
+
+    // Count whitespace-separated words, case-sensitively
+    server.register_function(
+        "word_count",
+        |_ctx, args| {
+            let text = args["text"].as_str().ok_or("Missing or invalid 'text'")?;
+            let mut counts = Map::new();
+            for word in text.split_whitespace() {
+                let count = counts.entry(word).or_insert(json!(0));
+                *count = json!(count.as_i64().unwrap_or(0) + 1);
+            }
+            Ok(JsonValue::Object(counts))
+        },
+        "Count the occurrences of each word in a text",
+        false,
+        vec!["str".to_string()],
+        "dict",
+    );
+    server.declare_parameters("word_count", &[("text", "str")]);
+
+    // Sort numbers ascending, keeping equal ones in order and ints as ints
+    server.register_function(
+        "sort_numbers",
+        |_ctx, args| {
+            let numbers = args["numbers"]
+                .as_array()
+                .ok_or("Missing or invalid 'numbers'")?;
+            let mut keyed = Vec::with_capacity(numbers.len());
+            for (index, number) in numbers.iter().enumerate() {
+                let key = number
+                    .as_f64()
+                    .ok_or_else(|| format!("'numbers' item {} is not a number", index))?;
+                keyed.push((key, number.clone()));
+            }
+            // Stable, so 1 and 1.0 keep their order, as do 0.0 and -0.0
+            keyed.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            Ok(JsonValue::Array(
+                keyed.into_iter().map(|(_, number)| number).collect(),
+            ))
+        },
+        "Sort a list of numbers in ascending order",
+        false,
+        vec!["list".to_string()],
+        "list",
+    );
+    server.declare_parameters("sort_numbers", &[("numbers", "list")]);
+
+    // Merge two objects, b winning, recursing into objects both have
+    server.register_function(
+        "merge_dicts",
+        |_ctx, args| {
+            let a = args["a"].as_object().ok_or("Missing or invalid 'a'")?;
+            let b = args["b"].as_object().ok_or("Missing or invalid 'b'")?;
+
+            fn merge(a: &Map<String, JsonValue>, b: &Map<String, JsonValue>) -> JsonValue {
+                let mut merged = a.clone();
+                for (key, value) in b {
+                    let value = match (merged.get(key), value) {
+                        (Some(JsonValue::Object(old)), JsonValue::Object(new)) => merge(old, new),
+                        _ => value.clone(),
+                    };
+                    merged.insert(key.clone(), value);
+                }
+                JsonValue::Object(merged)
+            }
+
+            Ok(merge(a, b))
+        },
+        "Deep-merge two dicts, the second's values taking precedence",
+        false,
+        vec!["dict".to_string(), "dict".to_string()],
+        "dict",
+    );
+    server.declare_parameters("merge_dicts", &[("a", "dict"), ("b", "dict")]);
+
+    // Drop repeated items, keeping the first of each
+    server.register_function(
+        "unique",
+        |_ctx, args| {
+            let items = args["items"]
+                .as_array()
+                .ok_or("Missing or invalid 'items'")?;
+            // Keyed by JSON text, so 1, 1.0 and true stay distinct as in Python
+            let mut seen = HashSet::new();
+            let unique: Vec<JsonValue> = items
+                .iter()
+                .filter(|item| seen.insert(item.to_string()))
+                .cloned()
+                .collect();
+            Ok(json!(unique))
+        },
+        "Remove duplicates from a list, preserving first-occurrence order",
+        false,
+        vec!["list".to_string()],
+        "list",
+    );
+    server.declare_parameters("unique", &[("items", "list")]);
+
+    // Push onto the stack held in the context (stateful)
+    server.register_function(
+        "stack_push",
+        |ctx, args| {
+            let value = args.get("value").ok_or("Missing 'value'")?.clone();
+            let mut stack = stack(ctx)?;
+            stack.push(value);
+            let size = stack.len();
+            ctx.set_state("stack".to_string(), json!(stack));
+            Ok(json!(size))
+        },
+        "Push a value onto the stack, returning its new size (stateful)",
+        true,
+        vec!["any".to_string()],
+        "int",
+    );
+    server.declare_parameters("stack_push", &[("value", "any")]);
+
+    // Pop from the stack held in the context (stateful)
+    server.register_function(
+        "stack_pop",
+        |ctx, _args| {
+            let mut stack = stack(ctx)?;
+            let top = stack.pop().ok_or("pop from empty stack")?;
+            ctx.set_state("stack".to_string(), json!(stack));
+            Ok(top)
+        },
+        "Remove and return the top of the stack (stateful)",
+        true,
+        vec![],
+        "any",
+    );
+    server.declare_parameters("stack_pop", &[]);
+
+    // Look at the top of the stack held in the context (stateful)
+    server.register_function(
+        "stack_peek",
+        |ctx, _args| {
+            let stack = stack(ctx)?;
+            stack
+                .last()
+                .cloned()
+                .ok_or_else(|| "peek at empty stack".to_string())
+        },
+        "Return the top of the stack without removing it (stateful)",
+        true,
+        vec![],
+        "any",
+    );
+    server.declare_parameters("stack_peek", &[]);
+
+    // Mean of the last `window` values seen on the context (stateful)
+    server.register_function(
+        "moving_average",
+        |ctx, args| {
+            let value = args["value"].as_f64().ok_or("Missing or invalid 'value'")?;
+            let window = args["window"]
+                .as_i64()
+                .ok_or("Missing or invalid 'window'")?;
+            let window = usize::try_from(window)
+                .ok()
+                .filter(|&window| window > 0)
+                .ok_or("'window' must be at least 1")?;
+
+            let mut samples: Vec<f64> = match ctx.get_state("samples") {
+                Some(samples) => serde_json::from_value(samples)
+                    .map_err(|_| "'samples' in state is not a list of numbers")?,
+                None => Vec::new(),
+            };
+            samples.push(value);
+            let excess = samples.len().saturating_sub(window);
+            samples.drain(..excess);
+
+            // Summed left to right, like the Python version's loop
+            let total = samples.iter().fold(0.0, |total, sample| total + sample);
+            let average = total / samples.len() as f64;
+            ctx.set_state("samples".to_string(), json!(samples));
+            Ok(json!(average))
+        },
+        "Add a value and return the mean of the last window values (stateful)",
+        true,
+        vec!["float".to_string(), "int".to_string()],
+        "float",
+    );
+    server.declare_parameters("moving_average", &[("value", "float"), ("window", "int")]);
+
+    // Python's name for the type of any value
+    server.register_function(
+        "type_name",
+        |_ctx, args| {
+            let value = args.get("value").ok_or("Missing 'value'")?;
+            Ok(json!(python_type_name(value)))
+        },
+        "Name the type of a value as Python does",
+        false,
+        vec!["any".to_string()],
+        "str",
+    );
+    // Any value will do, so the parameters are only named, for positional calls
+    server.name_parameters("type_name", &["value"]);
+
+    // Whether two values have the same type, so 1 and 1.0 don't
+    server.register_function(
+        "same_type",
+        |_ctx, args| {
+            let a = args.get("a").ok_or("Missing 'a'")?;
+            let b = args.get("b").ok_or("Missing 'b'")?;
+            Ok(json!(python_type_name(a) == python_type_name(b)))
+        },
+        "Whether two values have the same type",
+        false,
+        vec!["any".to_string(), "any".to_string()],
+        "bool",
+    );
+    server.name_parameters("same_type", &["a", "b"]);
+}
+
+/// The context's stack, empty if it has none yet
+fn stack(ctx: &crate::ExecutionContext) -> Result<Vec<JsonValue>, String> {
+    match ctx.get_state("stack") {
+        Some(JsonValue::Array(stack)) => Ok(stack),
+        Some(_) => Err("'stack' in state is not a list".to_string()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// What Python's `type(value).__name__` is for `value` once decoded
+fn python_type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "NoneType",
+        JsonValue::Bool(_) => "bool",
+        JsonValue::Number(n) if n.is_f64() => "float",
+        JsonValue::Number(_) => "int",
+        JsonValue::String(_) => "str",
+        JsonValue::Array(_) => "list",
+        JsonValue::Object(_) => "dict",
+    }
+}
+
+/// Methods only some callers may invoke, given a `--roles-file`
+pub fn register_restricted(server: &crate::TranspileTestServer) {
+    // Put a context back as it was created, discarding every test's changes
+    server.register_restricted_function(
+        "admin_reset_state",
+        &[crate::auth::ADMIN_ROLE],
+        |ctx, _args| {
+            ctx.reset();
+            Ok(JsonValue::Null)
+        },
+        "Restore the context's initial state (admin only)",
+        true,
+        vec![],
+        "None",
+    );
+
+    // Contents of a file on the server's machine
+    server.register_restricted_function(
+        "read_text_file",
+        &["filesystem"],
+        |_ctx, args| {
+            let path = args["path"].as_str().ok_or("Missing or invalid 'path'")?;
+            std::fs::read_to_string(path)
+                .map(JsonValue::String)
+                .map_err(|e| format!("Cannot read {}: {}", path, e))
+        },
+        "Read a UTF-8 text file on the server (filesystem role)",
+        false,
+        vec!["str".to_string()],
+        "str",
+    );
+    server.declare_parameters("read_text_file", &[("path", "str")]);
 }
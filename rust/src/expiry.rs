@@ -0,0 +1,49 @@
+/*!
+Context expiry (`--context-ttl-secs`).
+
+A context that goes unused for the TTL is removed, so contexts a crashed or
+careless client never destroyed don't pile up over a long session. Every
+call that names a context touches it. A background task sweeps expired
+contexts periodically and logs what it removed; between sweeps, lookups
+treat an expired context as already gone, so it gets the usual "Context not
+found" either way. A TTL of zero keeps contexts until they're destroyed.
+*/
+
+use crate::ExecutionContext;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+/// Longest wait between sweeps, however long the TTL
+const MAX_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Remove expired contexts every half TTL (at most every minute) for as
+/// long as the server runs
+pub fn spawn_sweeper(contexts: Arc<RwLock<HashMap<String, ExecutionContext>>>, ttl: Duration) {
+    let interval = (ttl / 2).clamp(Duration::from_millis(100), MAX_SWEEP_INTERVAL);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            sweep(&contexts, ttl);
+        }
+    });
+}
+
+fn sweep(contexts: &RwLock<HashMap<String, ExecutionContext>>, ttl: Duration) {
+    let mut contexts = contexts.write();
+    contexts.retain(|id, context| {
+        if !context.expired(ttl) {
+            return true;
+        }
+        info!(
+            "Expired context: {} (created {}s ago, unused for {}s)",
+            id,
+            context.created_at.elapsed().as_secs(),
+            context.idle().as_secs()
+        );
+        false
+    });
+}
@@ -0,0 +1,121 @@
+/*!
+Memory used by a method call, for `ExecutionMetadata.memory_bytes`.
+
+With the `memory-tracking` feature (on by default) the server's global
+allocator counts the bytes each thread allocates and frees. `measure` runs a
+function with the counters reset and returns the peak of live bytes it
+allocated above where it started. A poll runs on one thread, so a call
+isn't charged for what other threads allocate meanwhile, and two calls of
+the same method with the same arguments report the same peak.
+
+An async call may move threads between polls, so `measure_future` measures
+each poll on its own and reports the largest peak. A synchronous function
+runs within a single poll, so it's measured in full.
+
+Without the feature the system allocator is used untouched and `measure`
+reports 0.
+*/
+
+#[cfg(feature = "memory-tracking")]
+mod tracking {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// The system allocator, counting per thread while a measurement runs
+    pub struct TrackingAllocator;
+
+    thread_local! {
+        // Const-initialized without destructors, so reading them never
+        // allocates, which an allocator can't afford
+        static MEASURING: Cell<bool> = const { Cell::new(false) };
+        /// Bytes allocated minus bytes freed since the measurement began
+        static LIVE: Cell<isize> = const { Cell::new(0) };
+        static PEAK: Cell<isize> = const { Cell::new(0) };
+    }
+
+    fn record(delta: isize) {
+        // `try_with` because allocation continues while thread-locals are
+        // being torn down
+        let _ = MEASURING.try_with(|measuring| {
+            if measuring.get() {
+                let live = LIVE.with(|live| {
+                    live.set(live.get() + delta);
+                    live.get()
+                });
+                PEAK.with(|peak| peak.set(peak.get().max(live)));
+            }
+        });
+    }
+
+    unsafe impl GlobalAlloc for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                record(layout.size() as isize);
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc_zeroed(layout);
+            if !ptr.is_null() {
+                record(layout.size() as isize);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            record(-(layout.size() as isize));
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                record(new_size as isize - layout.size() as isize);
+            }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+    pub fn measure<T>(f: impl FnOnce() -> T) -> (T, i64) {
+        LIVE.with(|live| live.set(0));
+        PEAK.with(|peak| peak.set(0));
+        MEASURING.with(|measuring| measuring.set(true));
+        let value = f();
+        MEASURING.with(|measuring| measuring.set(false));
+        (value, PEAK.with(Cell::get) as i64)
+    }
+}
+
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+
+/// Run `f`, returning its value and the peak bytes it had allocated
+#[cfg(feature = "memory-tracking")]
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, i64) {
+    tracking::measure(f)
+}
+
+/// Run `f`; memory isn't tracked in this build
+#[cfg(not(feature = "memory-tracking"))]
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, i64) {
+    (f(), 0)
+}
+
+/// Await `future`, returning its output and the most bytes any one poll had
+/// allocated
+pub async fn measure_future<F: Future>(future: F) -> (F::Output, i64) {
+    let mut future = pin!(future);
+    let mut peak = 0;
+    let output = poll_fn(|cx| {
+        let (poll, bytes) = measure(|| future.as_mut().poll(cx));
+        peak = peak.max(bytes);
+        poll
+    })
+    .await;
+    (output, peak)
+}
@@ -0,0 +1,194 @@
+/*!
+Registration readiness tracking.
+
+Each source of registered functions (an examples module, a plugin directory)
+is announced with `begin` and marked done when its guard is finished or
+dropped. `GetServerInfo` reports `registration_complete` only once every
+announced source is done, so clients don't race a partially populated method
+table. If sources are still pending when the registration timeout expires,
+the barrier opens anyway and the stragglers are reported as an error, as is
+a source that fails. Slow sources, like the modules of `--wasm-dir`, load
+after the server starts answering, so the barrier is what clients wait on.
+*/
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+#[derive(Clone, Default)]
+pub struct RegistrationTracker {
+    inner: Arc<Mutex<TrackerState>>,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    pending: Vec<String>,
+    error: Option<String>,
+}
+
+impl RegistrationTracker {
+    /// Announce a registration source; it stays pending until the guard finishes
+    pub fn begin(&self, source: impl Into<String>) -> PendingSource {
+        let source = source.into();
+        self.inner.lock().pending.push(source.clone());
+        PendingSource {
+            tracker: self.clone(),
+            source,
+        }
+    }
+
+    /// Whether clients can rely on the method table being fully populated
+    pub fn is_complete(&self) -> bool {
+        let state = self.inner.lock();
+        state.pending.is_empty() || state.error.is_some()
+    }
+
+    /// Error describing sources that missed the registration timeout
+    pub fn error(&self) -> Option<String> {
+        self.inner.lock().error.clone()
+    }
+
+    /// Open the barrier after `timeout` even if some sources never finished
+    pub fn spawn_timeout(&self, timeout: Duration) {
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            let pending = tracker.inner.lock().pending.join(", ");
+            if !pending.is_empty() {
+                tracker.report(format!(
+                    "Registration timed out after {:?} waiting for: {}",
+                    timeout, pending
+                ));
+            }
+        });
+    }
+
+    /// Record `message` in the error, after any reported before it
+    fn report(&self, message: String) {
+        error!("{}", message);
+        let mut state = self.inner.lock();
+        state.error = Some(match state.error.take() {
+            Some(earlier) => format!("{}; {}", earlier, message),
+            None => message,
+        });
+    }
+
+    fn finish(&self, source: &str) {
+        let mut state = self.inner.lock();
+        if let Some(index) = state.pending.iter().position(|s| s == source) {
+            state.pending.remove(index);
+        }
+        if state.pending.is_empty() {
+            info!("All registration sources finished");
+        }
+    }
+}
+
+/// A registration source that hasn't finished yet
+pub struct PendingSource {
+    tracker: RegistrationTracker,
+    source: String,
+}
+
+impl PendingSource {
+    pub fn finish(self) {
+        // Dropping does the bookkeeping
+    }
+
+    /// Give up on the source, reporting why its functions are missing
+    pub fn fail(self, reason: impl std::fmt::Display) {
+        let message = format!("Registration of {} failed: {}", self.source, reason);
+        self.tracker.report(message);
+    }
+}
+
+impl Drop for PendingSource {
+    fn drop(&mut self) {
+        info!("Registration source done: {}", self.source);
+        self.tracker.finish(&self.source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpile_test::transpile_test_service_server::TranspileTestService;
+    use crate::transpile_test::{GetServerInfoRequest, GetServerInfoResponse};
+    use crate::TranspileTestServer;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tonic::Request;
+
+    async fn info(server: &TranspileTestServer) -> GetServerInfoResponse {
+        let response = server
+            .get_server_info(Request::new(GetServerInfoRequest {}))
+            .await
+            .unwrap();
+        response.into_inner()
+    }
+
+    #[tokio::test]
+    async fn a_slow_source_holds_the_barrier_until_it_registers() {
+        let server = Arc::new(TranspileTestServer::new());
+        let source = server.registration().begin("slow_plugin");
+        let plugin = {
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                server.register_function(
+                    "slow_answer",
+                    |_ctx, _args| Ok(json!(42)),
+                    "Registered late",
+                    false,
+                    Vec::new(),
+                    "int",
+                );
+                source.finish();
+            })
+        };
+
+        let pending = info(&server).await;
+        assert!(!pending.registration_complete);
+        assert!(pending.registration_error.is_empty());
+        assert!(!server.metadata.read().contains_key("slow_answer"));
+
+        plugin.await.unwrap();
+        let done = info(&server).await;
+        assert!(done.registration_complete);
+        assert!(done.registration_error.is_empty());
+        assert!(server.metadata.read().contains_key("slow_answer"));
+    }
+
+    #[tokio::test]
+    async fn stragglers_are_reported_when_the_timeout_opens_the_barrier() {
+        let server = TranspileTestServer::new();
+        server
+            .registration()
+            .spawn_timeout(Duration::from_millis(50));
+        server.registration().begin("quick_plugin").finish();
+        let _stuck = server.registration().begin("stuck_plugin");
+        assert!(!info(&server).await.registration_complete);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let info = info(&server).await;
+        assert!(info.registration_complete);
+        assert_eq!(
+            info.registration_error,
+            "Registration timed out after 50ms waiting for: stuck_plugin"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_source_is_reported() {
+        let server = TranspileTestServer::new();
+        let source = server.registration().begin("wasm:missing");
+        source.fail("No such file or directory");
+        let info = info(&server).await;
+        assert!(info.registration_complete);
+        assert_eq!(
+            info.registration_error,
+            "Registration of wasm:missing failed: No such file or directory"
+        );
+    }
+}
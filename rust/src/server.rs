@@ -8,13 +8,25 @@ This server allows executing Rust functions over gRPC with support for:
 */
 
 use clap::Parser;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::Value as JsonValue;
+use std::any::Any;
 use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{transport::Server, Request, Response, Status};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+use transpile_common::{json_depth, msgpack, protocol};
 use uuid::Uuid;
 
 // Generated proto code
@@ -27,33 +39,150 @@ use transpile_test::transpile_test_service_server::{
 };
 use transpile_test::*;
 
+mod arguments;
+mod auth;
+mod builtins;
+mod deadline;
 mod examples;
+mod expiry;
+mod memory;
+mod registration;
+mod shutdown;
+mod streaming;
+mod wasm;
 
-/// Type alias for registered functions
-type RegisteredFunction =
-    Arc<dyn Fn(&ExecutionContext, JsonValue) -> Result<JsonValue, String> + Send + Sync>;
+use arguments::Parameter;
+use auth::Roles;
+use json_depth::DepthError;
+use registration::RegistrationTracker;
+use shutdown::{InFlight, Shutdown};
+
+/// Largest gRPC message sent or accepted, well past tonic's 4MiB default so
+/// large payloads fit in either encoding
+const MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+
+/// The result of a registered function, once awaited
+pub type FunctionFuture = Pin<Box<dyn Future<Output = Result<JsonValue, String>> + Send>>;
+
+/// Type alias for registered functions; synchronous ones are wrapped in a
+/// future when they're registered
+type RegisteredFunction = Arc<dyn Fn(&ExecutionContext, JsonValue) -> FunctionFuture + Send + Sync>;
+
+/// Streaming functions, registered separately from ordinary ones
+type StreamingFunction =
+    Arc<dyn Fn(&ExecutionContext, JsonValue) -> Result<streaming::Items, String> + Send + Sync>;
+
+/// Arguments and context to call a method with, or why the call fails
+type Prepared = Result<(JsonValue, ExecutionContext), (ErrorCode, String)>;
+
+/// Response to an invocation that failed with `code`
+fn failed(code: ErrorCode, error: String) -> InvokeMethodResponse {
+    InvokeMethodResponse {
+        success: false,
+        result: String::new(),
+        error,
+        metadata: None,
+        error_code: code as i32,
+        encoding: Encoding::Json as i32,
+        payload: Vec::new(),
+        stdout: String::new(),
+        stderr: String::new(),
+    }
+}
+
+/// What a function printed during one call
+#[derive(Default)]
+struct Output {
+    stdout: String,
+    stderr: String,
+}
 
 /// Execution context for stateful function calls
 #[derive(Clone)]
 pub struct ExecutionContext {
     context_id: String,
     state: Arc<RwLock<HashMap<String, JsonValue>>>,
+    initial_state: Arc<HashMap<String, JsonValue>>,
+    created_at: Instant,
+    last_used: Arc<Mutex<Instant>>,
+    /// Output of the current call; each call gets its own
+    output: Arc<Mutex<Output>>,
+    /// Held by a stateful call for as long as it runs, so a function's
+    /// read-then-write of the state can't interleave with another call's
+    turn: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl ExecutionContext {
-    fn new(context_id: String, initial_state: Option<String>) -> Self {
-        let state = if let Some(init) = initial_state {
-            serde_json::from_str(&init).unwrap_or_default()
-        } else {
-            HashMap::new()
-        };
-
+    fn new(context_id: String, state: HashMap<String, JsonValue>) -> Self {
+        let now = Instant::now();
         Self {
             context_id,
-            state: Arc::new(RwLock::new(state)),
+            state: Arc::new(RwLock::new(state.clone())),
+            initial_state: Arc::new(state),
+            created_at: now,
+            last_used: Arc::new(Mutex::new(now)),
+            output: Arc::default(),
+            turn: Arc::default(),
+        }
+    }
+
+    /// `call`, run once no other stateful call on the context is running.
+    /// The turn is held until the call finishes, even if it's abandoned.
+    fn exclusive(&self, call: FunctionFuture) -> FunctionFuture {
+        let turn = self.take_turn();
+        Box::pin(async move {
+            let _turn = turn.await;
+            call.await
+        })
+    }
+
+    /// Wait for the context's turn, keeping it until the guard is dropped
+    fn take_turn(&self) -> impl Future<Output = tokio::sync::OwnedMutexGuard<()>> {
+        Arc::clone(&self.turn).lock_owned()
+    }
+
+    /// The context as seen by one call, with output of its own
+    fn for_call(&self) -> Self {
+        Self {
+            output: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Take what the call has printed so far
+    fn take_output(&self) -> Output {
+        std::mem::take(&mut *self.output.lock())
+    }
+
+    /// Record a use, restarting the TTL
+    fn touch(&self) {
+        *self.last_used.lock() = Instant::now();
+    }
+
+    /// Time since the context was last used
+    fn idle(&self) -> Duration {
+        self.last_used.lock().elapsed()
+    }
+
+    fn expired(&self, ttl: Duration) -> bool {
+        self.idle() >= ttl
+    }
+
+    /// What `ListContexts` reports about the context
+    fn info(&self) -> ContextInfo {
+        ContextInfo {
+            context_id: self.context_id.clone(),
+            created_at_ms: unix_ms(self.created_at),
+            last_used_at_ms: unix_ms(*self.last_used.lock()),
+            state_keys: self.state.read().len() as u32,
         }
     }
 
+    /// Restore the state the context was created with
+    fn reset(&self) {
+        *self.state.write() = (*self.initial_state).clone();
+    }
+
     pub fn get_state(&self, key: &str) -> Option<JsonValue> {
         self.state.read().get(key).cloned()
     }
@@ -65,6 +194,23 @@ impl ExecutionContext {
     pub fn get_all_state(&self) -> HashMap<String, JsonValue> {
         self.state.read().clone()
     }
+
+    /// Write `text` to the call's captured stdout, as `print!` would
+    pub fn print(&self, text: &str) {
+        self.output.lock().stdout.push_str(text);
+    }
+
+    /// Write `text` to the call's captured stderr, as `eprint!` would
+    pub fn eprint(&self, text: &str) {
+        self.output.lock().stderr.push_str(text);
+    }
+}
+
+/// Wall-clock time of `instant` in milliseconds since the Unix epoch
+fn unix_ms(instant: Instant) -> i64 {
+    let time = SystemTime::now() - instant.elapsed();
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
 }
 
 /// Metadata about a registered function
@@ -74,94 +220,239 @@ struct FunctionMetadata {
     is_stateful: bool,
     parameter_types: Vec<String>,
     return_type: String,
+    /// Roles allowed to invoke the method (empty means unrestricted)
+    allowed_roles: Vec<String>,
+    /// Results are streamed by `InvokeMethodStream` instead of returned
+    is_streaming: bool,
+    /// Named parameters arguments are checked against (None: unchecked)
+    parameters: Option<Vec<Parameter>>,
+    /// Parameter names in order, for arguments given by position
+    parameter_names: Option<Vec<String>>,
+}
+
+impl FunctionMetadata {
+    fn new(
+        description: String,
+        is_stateful: bool,
+        parameter_types: Vec<String>,
+        return_type: String,
+    ) -> Self {
+        Self {
+            description,
+            is_stateful,
+            parameter_types,
+            return_type,
+            allowed_roles: Vec::new(),
+            is_streaming: false,
+            parameters: None,
+            parameter_names: None,
+        }
+    }
 }
 
 /// Service implementation
 pub struct TranspileTestServer {
     contexts: Arc<RwLock<HashMap<String, ExecutionContext>>>,
     methods: Arc<RwLock<HashMap<String, RegisteredFunction>>>,
+    streams: Arc<RwLock<HashMap<String, StreamingFunction>>>,
     metadata: Arc<RwLock<HashMap<String, FunctionMetadata>>>,
+    roles: Roles,
+    /// Calls refused by `allowed_roles`, reported in `GetServerInfo`
+    rejected_invocations: Arc<AtomicU64>,
+    registration: RegistrationTracker,
+    /// Deepest nesting accepted in arguments and initial state
+    max_json_depth: usize,
+    /// How long a context may go unused before it's removed (None: forever)
+    context_ttl: Option<Duration>,
+    /// Deadline of calls that don't set their own (None: unbounded)
+    default_timeout: Option<Duration>,
+    /// Calls running now, waited for at shutdown
+    in_flight: InFlight,
 }
 
 impl TranspileTestServer {
     pub fn new() -> Self {
         info!("Initializing Rust gRPC server");
-        Self {
+        let server = Self {
             contexts: Arc::new(RwLock::new(HashMap::new())),
             methods: Arc::new(RwLock::new(HashMap::new())),
+            streams: Arc::new(RwLock::new(HashMap::new())),
             metadata: Arc::new(RwLock::new(HashMap::new())),
-        }
+            roles: Roles::default(),
+            rejected_invocations: Arc::new(AtomicU64::new(0)),
+            registration: RegistrationTracker::default(),
+            max_json_depth: json_depth::DEFAULT_MAX_DEPTH,
+            context_ttl: None,
+            default_timeout: None,
+            in_flight: InFlight::default(),
+        };
+        builtins::register(&server);
+        server
     }
 
-    /// Register a function that can be invoked via gRPC
-    pub fn register_function<F>(
-        &self,
-        name: impl Into<String>,
-        func: F,
-        description: impl Into<String>,
-        is_stateful: bool,
-        parameter_types: Vec<String>,
-        return_type: impl Into<String>,
-    ) where
-        F: Fn(&ExecutionContext, JsonValue) -> Result<JsonValue, String> + Send + Sync + 'static,
-    {
-        let name = name.into();
-        let description = description.into();
-        let return_type = return_type.into();
-
-        self.methods.write().insert(name.clone(), Arc::new(func));
-        self.metadata.write().insert(
-            name.clone(),
-            FunctionMetadata {
-                description,
-                is_stateful,
-                parameter_types,
-                return_type,
-            },
-        );
+    /// Readiness of the sources that register functions on this server
+    pub fn registration(&self) -> &RegistrationTracker {
+        &self.registration
+    }
 
-        info!("Registered function: {}", name);
+    /// Resolve caller roles from bearer tokens using this map
+    pub fn with_roles(mut self, roles: Roles) -> Self {
+        self.roles = roles;
+        self
     }
-}
 
-impl Default for TranspileTestServer {
-    fn default() -> Self {
-        Self::new()
+    /// Reject arguments and initial state nested deeper than `depth`
+    pub fn with_max_json_depth(mut self, depth: usize) -> Self {
+        self.max_json_depth = depth;
+        self
     }
-}
 
-#[tonic::async_trait]
-impl TranspileTestService for TranspileTestServer {
-    async fn create_context(
-        &self,
-        request: Request<CreateContextRequest>,
-    ) -> Result<Response<CreateContextResponse>, Status> {
-        let req = request.into_inner();
+    /// Remove contexts unused for `secs` seconds; 0 keeps them until destroyed
+    pub fn with_context_ttl(mut self, secs: u64) -> Self {
+        self.context_ttl = (secs > 0).then(|| Duration::from_secs(secs));
+        self
+    }
+
+    /// Give up on calls that don't set `timeout_ms` after `ms`
+    /// milliseconds; 0 lets them run as long as they take
+    pub fn with_default_timeout(mut self, ms: u64) -> Self {
+        self.default_timeout = (ms > 0).then(|| Duration::from_millis(ms));
+        self
+    }
+
+    /// Start sweeping expired contexts, if contexts expire
+    pub fn spawn_context_sweeper(&self) {
+        if let Some(ttl) = self.context_ttl {
+            expiry::spawn_sweeper(self.contexts.clone(), ttl);
+        }
+    }
+
+    /// The context named `context_id`, touched, unless it's gone or expired
+    fn live_context(&self, context_id: &str) -> Option<ExecutionContext> {
+        let context = self.contexts.read().get(context_id).cloned()?;
+        if self.context_ttl.is_some_and(|ttl| context.expired(ttl)) {
+            // The sweeper will log it
+            return None;
+        }
+        context.touch();
+        Some(context)
+    }
+
+    /// Add a new context holding `state`, returning its id
+    fn insert_context(&self, state: HashMap<String, JsonValue>) -> String {
         let context_id = Uuid::new_v4().to_string();
+        let context = ExecutionContext::new(context_id.clone(), state);
+        self.contexts.write().insert(context_id.clone(), context);
+        context_id
+    }
 
-        let initial_state = if req.initial_state.is_empty() {
-            None
-        } else {
-            Some(req.initial_state)
+    /// Check a call of a registered method may go ahead: the caller is
+    /// allowed, the arguments parse and fit the declared parameters, and the
+    /// context exists. The arguments and context to call it with, or why
+    /// the call fails; the outer error is a call refused outright.
+    fn prepare(
+        &self,
+        role: Option<&str>,
+        req: &InvokeMethodRequest,
+    ) -> Result<Prepared, Box<Status>> {
+        // Check the caller may invoke it
+        let allowed = self
+            .metadata
+            .read()
+            .get(&req.method_name)
+            .is_none_or(|meta| auth::is_allowed(&meta.allowed_roles, role));
+        if !allowed {
+            let rejected = self.rejected_invocations.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!(
+                "Rejected {} for role {:?} ({} rejected invocations)",
+                req.method_name, role, rejected
+            );
+            return Err(Box::new(Status::permission_denied(format!(
+                "Method {} is not permitted for this caller",
+                req.method_name
+            ))));
+        }
+
+        // Parse arguments
+        let args: JsonValue = match req.encoding() {
+            Encoding::Json => match json_depth::from_str(&req.arguments, self.max_json_depth) {
+                Ok(v) => v,
+                Err(e @ DepthError::TooDeep { .. }) => {
+                    return Ok(Err((
+                        ErrorCode::InvalidArguments,
+                        format!("arguments: {}", e),
+                    )));
+                }
+                Err(DepthError::Invalid(e)) => {
+                    let error = format!("Invalid JSON arguments: {}", e);
+                    return Ok(Err((ErrorCode::InvalidArguments, error)));
+                }
+            },
+            Encoding::Msgpack => match msgpack::from_slice(&req.payload, self.max_json_depth) {
+                Ok(v) => v,
+                Err(e @ msgpack::DecodeError::TooDeep { .. }) => {
+                    return Ok(Err((
+                        ErrorCode::InvalidArguments,
+                        format!("arguments: {}", e),
+                    )));
+                }
+                Err(msgpack::DecodeError::Invalid(e)) => {
+                    let error = format!("Invalid MessagePack arguments: {}", e);
+                    return Ok(Err((ErrorCode::InvalidArguments, error)));
+                }
+            },
         };
 
-        let context = ExecutionContext::new(context_id.clone(), initial_state);
-        self.contexts.write().insert(context_id.clone(), context);
+        // Name positional arguments, then check them against the declared
+        // parameters, if any
+        let (names, parameters) = self
+            .metadata
+            .read()
+            .get(&req.method_name)
+            .map(|meta| (meta.parameter_names.clone(), meta.parameters.clone()))
+            .unwrap_or_default();
+        let args = match names {
+            Some(names) => match arguments::by_position(&names, args) {
+                Ok(args) => args,
+                Err(e) => {
+                    debug!("Rejected arguments of {}: {}", req.method_name, e);
+                    return Ok(Err((ErrorCode::InvalidArguments, e)));
+                }
+            },
+            None => args,
+        };
+        if let Some(parameters) = parameters {
+            if let Err(e) = arguments::validate(&parameters, &args) {
+                debug!("Rejected arguments of {}: {}", req.method_name, e);
+                return Ok(Err((ErrorCode::InvalidArguments, e)));
+            }
+        }
 
-        info!("Created context: {}", context_id);
+        // Get or create context
+        let context = if req.context_id.is_empty() {
+            // Create temporary context for stateless calls
+            ExecutionContext::new(Uuid::new_v4().to_string(), HashMap::new())
+        } else {
+            match self.live_context(&req.context_id) {
+                Some(ctx) => ctx,
+                None => {
+                    let error = format!("Context not found: {}", req.context_id);
+                    return Ok(Err((ErrorCode::ContextNotFound, error)));
+                }
+            }
+        };
 
-        Ok(Response::new(CreateContextResponse {
-            context_id,
-            success: true,
-            error: String::new(),
-        }))
+        Ok(Ok((args, context)))
     }
 
-    async fn invoke_method(
+    /// Run one invocation for `InvokeMethod` or an `InvokeBatch` item; the
+    /// error is a call InvokeMethod refuses outright. The caller counts the
+    /// call in flight.
+    async fn invoke(
         &self,
-        request: Request<InvokeMethodRequest>,
-    ) -> Result<Response<InvokeMethodResponse>, Status> {
-        let req = request.into_inner();
+        role: Option<&str>,
+        req: InvokeMethodRequest,
+    ) -> Result<InvokeMethodResponse, Box<Status>> {
         let start = Instant::now();
 
         // Get the function
@@ -170,164 +461,862 @@ impl TranspileTestService for TranspileTestServer {
             match methods.get(&req.method_name) {
                 Some(f) => Arc::clone(f),
                 None => {
-                    return Ok(Response::new(InvokeMethodResponse {
-                        success: false,
-                        result: String::new(),
-                        error: format!("Method not found: {}", req.method_name),
-                        metadata: None,
-                    }));
+                    let error = if self.streams.read().contains_key(&req.method_name) {
+                        format!(
+                            "{} streams its results; call it with InvokeMethodStream",
+                            req.method_name
+                        )
+                    } else {
+                        format!("Method not found: {}", req.method_name)
+                    };
+                    return Ok(failed(ErrorCode::MethodNotFound, error));
                 }
             }
         };
 
-        // Parse arguments
-        let args: JsonValue = match serde_json::from_str(&req.arguments) {
-            Ok(v) => v,
-            Err(e) => {
-                return Ok(Response::new(InvokeMethodResponse {
-                    success: false,
-                    result: String::new(),
-                    error: format!("Invalid JSON arguments: {}", e),
-                    metadata: None,
-                }));
-            }
+        let (args, context) = match self.prepare(role, &req)? {
+            Ok(prepared) => prepared,
+            Err((code, error)) => return Ok(failed(code, error)),
         };
+        // Output is per call, even on a shared context
+        let context = context.for_call();
+        let is_stateful = self
+            .metadata
+            .read()
+            .get(&req.method_name)
+            .is_some_and(|meta| meta.is_stateful);
 
-        // Get or create context
-        let context = if req.context_id.is_empty() {
-            // Create temporary context for stateless calls
-            ExecutionContext::new(Uuid::new_v4().to_string(), None)
-        } else {
-            let contexts = self.contexts.read();
-            match contexts.get(&req.context_id) {
-                Some(ctx) => ctx.clone(),
-                None => {
-                    return Ok(Response::new(InvokeMethodResponse {
-                        success: false,
-                        result: String::new(),
-                        error: format!("Context not found: {}", req.context_id),
-                        metadata: None,
-                    }));
+        let timeout = match req.timeout_ms {
+            0 => self.default_timeout,
+            ms => Some(Duration::from_millis(ms)),
+        };
+        // Execute the function, awaiting it if it's async. The future is
+        // made outside the measurement, so its box isn't charged to the call.
+        // A panicking function fails its call rather than the RPC; contexts
+        // are parking_lot locks, which a panic leaves usable.
+        let outcome = match panic::catch_unwind(AssertUnwindSafe(|| func(&context, args))) {
+            Ok(future) => {
+                // Stateful functions take turns on their context, waiting
+                // within the deadline; stateless ones run concurrently.
+                let future = if is_stateful {
+                    context.exclusive(future)
+                } else {
+                    future
+                };
+                match timeout {
+                    None => Some(memory::measure_future(catch_unwind(future)).await),
+                    Some(timeout) => deadline::run(future, timeout).await,
                 }
             }
+            Err(payload) => Some((Err(payload), 0)),
         };
-
-        // Execute the function
-        let result = match func(&context, args) {
-            Ok(res) => res,
-            Err(e) => {
+        let output = context.take_output();
+        let printed = |response| InvokeMethodResponse {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            ..response
+        };
+        let Some((result, memory_bytes)) = outcome else {
+            // Only a missed deadline leaves no outcome
+            let timeout = timeout.unwrap_or_default();
+            warn!(
+                "Abandoned {} after {}ms; a synchronous function keeps running in the background",
+                req.method_name,
+                timeout.as_millis()
+            );
+            return Ok(printed(failed(
+                ErrorCode::Timeout,
+                deadline::error(timeout),
+            )));
+        };
+        let result = match result {
+            Ok(Ok(res)) => res,
+            Ok(Err(e)) => {
                 error!("Error executing {}: {}", req.method_name, e);
-                return Ok(Response::new(InvokeMethodResponse {
-                    success: false,
-                    result: String::new(),
-                    error: e,
-                    metadata: None,
-                }));
+                return Ok(printed(failed(ErrorCode::ExecutionError, e)));
+            }
+            Err(payload) => {
+                let message = panic_message(payload.as_ref());
+                error!("{} panicked: {}", req.method_name, message);
+                let error = format!("panicked: {}", message);
+                return Ok(printed(failed(ErrorCode::ExecutionError, error)));
             }
         };
 
         // Calculate execution time
         let execution_time_us = start.elapsed().as_micros() as i64;
 
-        let result_json = serde_json::to_string(&result).unwrap_or_else(|_| "null".to_string());
+        // Encoded the way the arguments were
+        let (result_json, payload) = match req.encoding() {
+            Encoding::Json => (
+                serde_json::to_string(&result).unwrap_or_else(|_| "null".to_string()),
+                Vec::new(),
+            ),
+            Encoding::Msgpack => (String::new(), msgpack::to_vec(&result)),
+        };
 
         debug!(
-            "Executed {} in {}μs",
-            req.method_name, execution_time_us
+            "Executed {} (context {}) in {}μs, peak {} bytes",
+            req.method_name, context.context_id, execution_time_us, memory_bytes
         );
 
-        Ok(Response::new(InvokeMethodResponse {
+        Ok(printed(InvokeMethodResponse {
             success: true,
             result: result_json,
             error: String::new(),
             metadata: Some(ExecutionMetadata {
                 execution_time_us,
-                memory_bytes: 0, // TODO: Implement memory tracking
+                memory_bytes,
                 runtime: "rust".to_string(),
             }),
+            error_code: ErrorCode::Unspecified as i32,
+            encoding: req.encoding,
+            payload,
+            ..Default::default()
         }))
     }
 
-    async fn inspect_state(
+    /// Register a function that can be invoked via gRPC.
+    ///
+    /// Names in the reserved `__` namespace belong to the built-in
+    /// diagnostics and are refused.
+    pub fn register_function<F>(
         &self,
-        request: Request<InspectStateRequest>,
-    ) -> Result<Response<InspectStateResponse>, Status> {
-        let req = request.into_inner();
-
-        let contexts = self.contexts.read();
-        match contexts.get(&req.context_id) {
-            Some(context) => {
-                let state = context.get_all_state();
-                let state_json =
-                    serde_json::to_string(&state).unwrap_or_else(|_| "{}".to_string());
-
-                Ok(Response::new(InspectStateResponse {
-                    success: true,
-                    state: state_json,
-                    error: String::new(),
-                }))
-            }
-            None => Ok(Response::new(InspectStateResponse {
-                success: false,
-                state: String::new(),
-                error: format!("Context not found: {}", req.context_id),
-            })),
+        name: impl Into<String>,
+        func: F,
+        description: impl Into<String>,
+        is_stateful: bool,
+        parameter_types: Vec<String>,
+        return_type: impl Into<String>,
+    ) where
+        F: Fn(&ExecutionContext, JsonValue) -> Result<JsonValue, String> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        if let Err(e) = check_name(&name) {
+            error!("Refusing to register {}: {}", name, e);
+            return;
         }
+        self.insert_function(
+            name,
+            func,
+            description,
+            is_stateful,
+            parameter_types,
+            return_type,
+        );
     }
 
-    async fn destroy_context(
+    /// Register a function only callers holding one of `allowed_roles` may
+    /// invoke or list; the admin role may always. It is restricted from the
+    /// moment it is registered, so no call slips in unchecked.
+    #[allow(clippy::too_many_arguments)]
+    pub fn register_restricted_function<F>(
         &self,
-        request: Request<DestroyContextRequest>,
-    ) -> Result<Response<DestroyContextResponse>, Status> {
-        let req = request.into_inner();
-
-        let removed = self.contexts.write().remove(&req.context_id).is_some();
-
-        if removed {
-            info!("Destroyed context: {}", req.context_id);
-            Ok(Response::new(DestroyContextResponse {
-                success: true,
-                error: String::new(),
-            }))
-        } else {
-            Ok(Response::new(DestroyContextResponse {
-                success: false,
-                error: format!("Context not found: {}", req.context_id),
-            }))
+        name: impl Into<String>,
+        allowed_roles: &[&str],
+        func: F,
+        description: impl Into<String>,
+        is_stateful: bool,
+        parameter_types: Vec<String>,
+        return_type: impl Into<String>,
+    ) where
+        F: Fn(&ExecutionContext, JsonValue) -> Result<JsonValue, String> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        if let Err(e) = check_name(&name) {
+            error!("Refusing to register {}: {}", name, e);
+            return;
         }
+        let mut meta = FunctionMetadata::new(
+            description.into(),
+            is_stateful,
+            parameter_types,
+            return_type.into(),
+        );
+        meta.allowed_roles = allowed_roles.iter().map(|role| role.to_string()).collect();
+        info!("Restricting {} to roles {:?}", name, meta.allowed_roles);
+        self.insert_registered(name, blocking(func), meta);
     }
 
-    async fn list_methods(
+    /// Register a function taking its arguments as `Args` and returning `Ret`.
+    ///
+    /// The arguments are deserialized before the call, failing it with the
+    /// path of the field that didn't fit, and the result is serialized after.
+    pub fn register_typed_function<Args, Ret, F>(
         &self,
-        request: Request<ListMethodsRequest>,
-    ) -> Result<Response<ListMethodsResponse>, Status> {
-        let req = request.into_inner();
-        let metadata = self.metadata.read();
+        name: impl Into<String>,
+        func: F,
+        description: impl Into<String>,
+        is_stateful: bool,
+        parameter_types: Vec<String>,
+        return_type: impl Into<String>,
+    ) where
+        Args: DeserializeOwned,
+        Ret: Serialize,
+        F: Fn(&ExecutionContext, Args) -> Result<Ret, String> + Send + Sync + 'static,
+    {
+        self.register_function(
+            name,
+            move |ctx, args| {
+                let args: Args = serde_path_to_error::deserialize(args)
+                    .map_err(|e| format!("Invalid arguments: {}", e))?;
+                let result = func(ctx, args)?;
+                serde_json::to_value(result).map_err(|e| format!("Cannot serialize result: {}", e))
+            },
+            description,
+            is_stateful,
+            parameter_types,
+            return_type,
+        );
+    }
 
-        let methods: Vec<MethodInfo> = metadata
-            .iter()
-            .filter(|(name, _)| req.prefix.is_empty() || name.starts_with(&req.prefix))
-            .map(|(name, meta)| MethodInfo {
-                name: name.clone(),
-                description: meta.description.clone(),
-                is_stateful: meta.is_stateful,
-                parameter_types: meta.parameter_types.clone(),
-                return_type: meta.return_type.clone(),
-            })
-            .collect();
+    /// Register an async function, for methods that await: I/O through
+    /// tokio, calls to other services, simulated latency. The future must
+    /// own what it uses, so clone the context into it if it needs state.
+    ///
+    /// Execution time covers the whole await.
+    pub fn register_async_function<F, Fut>(
+        &self,
+        name: impl Into<String>,
+        func: F,
+        description: impl Into<String>,
+        is_stateful: bool,
+        parameter_types: Vec<String>,
+        return_type: impl Into<String>,
+    ) where
+        F: Fn(&ExecutionContext, JsonValue) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<JsonValue, String>> + Send + 'static,
+    {
+        let name = name.into();
+        if let Err(e) = check_name(&name) {
+            error!("Refusing to register {}: {}", name, e);
+            return;
+        }
+        self.insert_async_function(
+            name,
+            func,
+            description,
+            is_stateful,
+            parameter_types,
+            return_type,
+        );
+    }
 
-        Ok(Response::new(ListMethodsResponse { methods }))
+    /// Register without the reserved-name check, for built-ins
+    fn insert_function<F>(
+        &self,
+        name: impl Into<String>,
+        func: F,
+        description: impl Into<String>,
+        is_stateful: bool,
+        parameter_types: Vec<String>,
+        return_type: impl Into<String>,
+    ) where
+        F: Fn(&ExecutionContext, JsonValue) -> Result<JsonValue, String> + Send + Sync + 'static,
+    {
+        let meta = FunctionMetadata::new(
+            description.into(),
+            is_stateful,
+            parameter_types,
+            return_type.into(),
+        );
+        self.insert_registered(name.into(), blocking(func), meta);
     }
-}
 
-#[derive(Parser)]
-#[command(name = "transpile-test-server")]
+    /// Register an async function without the reserved-name check
+    fn insert_async_function<F, Fut>(
+        &self,
+        name: impl Into<String>,
+        func: F,
+        description: impl Into<String>,
+        is_stateful: bool,
+        parameter_types: Vec<String>,
+        return_type: impl Into<String>,
+    ) where
+        F: Fn(&ExecutionContext, JsonValue) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<JsonValue, String>> + Send + 'static,
+    {
+        let wrapped = move |ctx: &ExecutionContext, args: JsonValue| -> FunctionFuture {
+            Box::pin(func(ctx, args))
+        };
+        let meta = FunctionMetadata::new(
+            description.into(),
+            is_stateful,
+            parameter_types,
+            return_type.into(),
+        );
+        self.insert_registered(name.into(), Arc::new(wrapped), meta);
+    }
+
+    fn insert_registered(&self, name: String, func: RegisteredFunction, meta: FunctionMetadata) {
+        self.streams.write().remove(&name);
+        // Metadata first: a restricted function must never be callable
+        // without its roles
+        self.metadata.write().insert(name.clone(), meta);
+        self.methods.write().insert(name.clone(), func);
+
+        info!("Registered function: {}", name);
+    }
+
+    /// Register a function whose results are streamed item by item by
+    /// `InvokeMethodStream`, like a generator. `return_type` is the type of
+    /// each item.
+    pub fn register_streaming_function<F>(
+        &self,
+        name: impl Into<String>,
+        func: F,
+        description: impl Into<String>,
+        is_stateful: bool,
+        parameter_types: Vec<String>,
+        return_type: impl Into<String>,
+    ) where
+        F: Fn(&ExecutionContext, JsonValue) -> Result<streaming::Items, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let name = name.into();
+        if let Err(e) = check_name(&name) {
+            error!("Refusing to register {}: {}", name, e);
+            return;
+        }
+
+        let mut meta = FunctionMetadata::new(
+            description.into(),
+            is_stateful,
+            parameter_types,
+            return_type.into(),
+        );
+        meta.is_streaming = true;
+        self.methods.write().remove(&name);
+        self.streams.write().insert(name.clone(), Arc::new(func));
+        self.metadata.write().insert(name.clone(), meta);
+
+        info!("Registered streaming function: {}", name);
+    }
+
+    /// Name a registered function's parameters, in order, with their types.
+    ///
+    /// Arguments are then checked against them before each call, and the
+    /// types replace the ones given at registration.
+    pub fn declare_parameters(&self, name: &str, parameters: &[(&str, &str)]) {
+        match self.metadata.write().get_mut(name) {
+            Some(meta) => {
+                let parameters: Vec<Parameter> = parameters
+                    .iter()
+                    .map(|&(name, type_name)| Parameter::new(name, type_name))
+                    .collect();
+                meta.parameter_types = parameters.iter().map(|p| p.type_name.clone()).collect();
+                meta.parameter_names = Some(parameters.iter().map(|p| p.name.clone()).collect());
+                meta.parameters = Some(parameters);
+            }
+            None => warn!(
+                "Cannot declare parameters of unregistered function: {}",
+                name
+            ),
+        }
+    }
+
+    /// Name a registered function's parameters, in order, so it can be
+    /// called with positional arguments. Unlike `declare_parameters`,
+    /// nothing about the arguments is checked.
+    pub fn name_parameters(&self, name: &str, names: &[&str]) {
+        match self.metadata.write().get_mut(name) {
+            Some(meta) => {
+                meta.parameter_names = Some(names.iter().map(|name| name.to_string()).collect());
+            }
+            None => warn!("Cannot name parameters of unregistered function: {}", name),
+        }
+    }
+}
+
+impl Default for TranspileTestServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[tonic::async_trait]
+impl TranspileTestService for TranspileTestServer {
+    async fn create_context(
+        &self,
+        request: Request<CreateContextRequest>,
+    ) -> Result<Response<CreateContextResponse>, Status> {
+        self.roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let req = request.into_inner();
+
+        // Unparseable initial state starts the context empty, but over-deep
+        // state is rejected before it can be parsed at all
+        let state = if req.initial_state.is_empty() {
+            HashMap::new()
+        } else {
+            match json_depth::from_str(&req.initial_state, self.max_json_depth) {
+                Ok(value) => serde_json::from_value(value).unwrap_or_default(),
+                Err(e @ DepthError::TooDeep { .. }) => {
+                    return Err(Status::invalid_argument(format!("initial_state: {}", e)));
+                }
+                Err(DepthError::Invalid(_)) => HashMap::new(),
+            }
+        };
+
+        let context_id = self.insert_context(state);
+        info!("Created context: {}", context_id);
+
+        Ok(Response::new(CreateContextResponse {
+            context_id,
+            success: true,
+            error: String::new(),
+            ttl_secs: self.context_ttl.map_or(0, |ttl| ttl.as_secs()),
+        }))
+    }
+
+    async fn invoke_method(
+        &self,
+        request: Request<InvokeMethodRequest>,
+    ) -> Result<Response<InvokeMethodResponse>, Status> {
+        let role = self
+            .roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let req = request.into_inner();
+        let _call = self.in_flight.enter();
+        self.invoke(role.as_deref(), req)
+            .await
+            .map(Response::new)
+            .map_err(|status| *status)
+    }
+
+    async fn invoke_batch(
+        &self,
+        request: Request<InvokeBatchRequest>,
+    ) -> Result<Response<InvokeBatchResponse>, Status> {
+        let role = self
+            .roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let requests = request.into_inner().requests;
+        debug!("Invoking a batch of {}", requests.len());
+        // The whole batch is one call in flight
+        let _call = self.in_flight.enter();
+
+        // A call InvokeMethod would refuse outright fails only its own item
+        let mut responses = Vec::with_capacity(requests.len());
+        for req in requests {
+            let response = self
+                .invoke(role.as_deref(), req)
+                .await
+                .unwrap_or_else(|status| {
+                    let error = format!("{:?}: {}", status.code(), status.message());
+                    failed(ErrorCode::Internal, error)
+                });
+            responses.push(response);
+        }
+        Ok(Response::new(InvokeBatchResponse { responses }))
+    }
+
+    type InvokeMethodStreamStream = ReceiverStream<Result<InvokeStreamChunk, Status>>;
+
+    async fn invoke_method_stream(
+        &self,
+        request: Request<InvokeMethodRequest>,
+    ) -> Result<Response<Self::InvokeMethodStreamStream>, Status> {
+        let role = self
+            .roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let req = request.into_inner();
+        let start = Instant::now();
+        // In flight until the last item is sent or the client goes away
+        let call = self.in_flight.enter();
+        let (sender, receiver) = mpsc::channel(streaming::BUFFER);
+
+        let func = self.streams.read().get(&req.method_name).cloned();
+        let is_stateful = self
+            .metadata
+            .read()
+            .get(&req.method_name)
+            .is_some_and(|meta| meta.is_stateful);
+        // A stateful stream holds its context's turn until its last item
+        let mut turn = None;
+        let items = match func {
+            Some(func) => match self
+                .prepare(role.as_deref(), &req)
+                .map_err(|status| *status)?
+            {
+                Ok((args, context)) => {
+                    if is_stateful {
+                        turn = Some(context.take_turn().await);
+                    }
+                    panic::catch_unwind(AssertUnwindSafe(|| func(&context, args))).unwrap_or_else(
+                        |payload| Err(format!("panicked: {}", panic_message(payload.as_ref()))),
+                    )
+                }
+                Err((_, error)) => Err(error),
+            },
+            None if self.methods.read().contains_key(&req.method_name) => Err(format!(
+                "{} doesn't stream its results; call it with InvokeMethod",
+                req.method_name
+            )),
+            None => Err(format!("Method not found: {}", req.method_name)),
+        };
+
+        match items {
+            Ok(items) => {
+                let method = req.method_name;
+                tokio::task::spawn_blocking(move || {
+                    let (_call, _turn) = (call, turn);
+                    streaming::drive(&method, items, &sender, start)
+                });
+            }
+            Err(error) => {
+                error!("Error streaming {}: {}", req.method_name, error);
+                // The channel is empty, so this can't fail for lack of room
+                let _ = sender.try_send(Ok(streaming::end(0, error, None)));
+            }
+        }
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn inspect_state(
+        &self,
+        request: Request<InspectStateRequest>,
+    ) -> Result<Response<InspectStateResponse>, Status> {
+        self.roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let req = request.into_inner();
+
+        match self.live_context(&req.context_id) {
+            Some(context) => {
+                let state = context.get_all_state();
+                let state_json = serde_json::to_string(&state).unwrap_or_else(|_| "{}".to_string());
+
+                Ok(Response::new(InspectStateResponse {
+                    success: true,
+                    state: state_json,
+                    error: String::new(),
+                }))
+            }
+            None => Ok(Response::new(InspectStateResponse {
+                success: false,
+                state: String::new(),
+                error: format!("Context not found: {}", req.context_id),
+            })),
+        }
+    }
+
+    async fn export_context(
+        &self,
+        request: Request<ExportContextRequest>,
+    ) -> Result<Response<ExportContextResponse>, Status> {
+        self.roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let req = request.into_inner();
+
+        match self.live_context(&req.context_id) {
+            Some(context) => {
+                let state = context.get_all_state();
+                let state_json = serde_json::to_string(&state).unwrap_or_else(|_| "{}".to_string());
+                debug!(
+                    "Exported context: {} ({} keys)",
+                    req.context_id,
+                    state.len()
+                );
+
+                Ok(Response::new(ExportContextResponse {
+                    success: true,
+                    context_id: req.context_id,
+                    state: state_json,
+                    error: String::new(),
+                }))
+            }
+            None => Ok(Response::new(ExportContextResponse {
+                success: false,
+                error: format!("Context not found: {}", req.context_id),
+                context_id: req.context_id,
+                state: String::new(),
+            })),
+        }
+    }
+
+    async fn import_context(
+        &self,
+        request: Request<ImportContextRequest>,
+    ) -> Result<Response<ImportContextResponse>, Status> {
+        self.roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let req = request.into_inner();
+
+        // Unlike CreateContext, state that isn't a JSON object is an error
+        // rather than an empty context
+        let state: Result<HashMap<String, JsonValue>, String> =
+            match json_depth::from_str(&req.state, self.max_json_depth) {
+                Ok(JsonValue::Object(state)) => Ok(state.into_iter().collect()),
+                Ok(other) => Err(format!(
+                    "state must be a JSON object, got {}",
+                    arguments::json_type(&other)
+                )),
+                Err(e @ DepthError::TooDeep { .. }) => {
+                    return Err(Status::invalid_argument(format!("state: {}", e)));
+                }
+                Err(DepthError::Invalid(e)) => Err(format!("Invalid state JSON: {}", e)),
+            };
+
+        match state {
+            Ok(state) => {
+                let keys = state.len();
+                let context_id = self.insert_context(state);
+                info!("Imported context: {} ({} keys)", context_id, keys);
+                Ok(Response::new(ImportContextResponse {
+                    success: true,
+                    context_id,
+                    error: String::new(),
+                    ttl_secs: self.context_ttl.map_or(0, |ttl| ttl.as_secs()),
+                }))
+            }
+            Err(error) => {
+                warn!("Rejected context import: {}", error);
+                Ok(Response::new(ImportContextResponse {
+                    success: false,
+                    context_id: String::new(),
+                    error,
+                    ttl_secs: 0,
+                }))
+            }
+        }
+    }
+
+    async fn destroy_context(
+        &self,
+        request: Request<DestroyContextRequest>,
+    ) -> Result<Response<DestroyContextResponse>, Status> {
+        self.roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let req = request.into_inner();
+
+        let removed = self.contexts.write().remove(&req.context_id).is_some();
+
+        if removed {
+            info!("Destroyed context: {}", req.context_id);
+            Ok(Response::new(DestroyContextResponse {
+                success: true,
+                error: String::new(),
+            }))
+        } else {
+            Ok(Response::new(DestroyContextResponse {
+                success: false,
+                error: format!("Context not found: {}", req.context_id),
+            }))
+        }
+    }
+
+    async fn reset_context(
+        &self,
+        request: Request<ResetContextRequest>,
+    ) -> Result<Response<ResetContextResponse>, Status> {
+        self.roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let req = request.into_inner();
+
+        match self.live_context(&req.context_id) {
+            Some(context) => {
+                // Not in the middle of a stateful call
+                let _turn = context.take_turn().await;
+                context.reset();
+                debug!("Reset context: {}", req.context_id);
+                Ok(Response::new(ResetContextResponse {
+                    success: true,
+                    error: String::new(),
+                }))
+            }
+            None => Ok(Response::new(ResetContextResponse {
+                success: false,
+                error: format!("Context not found: {}", req.context_id),
+            })),
+        }
+    }
+
+    async fn list_contexts(
+        &self,
+        request: Request<ListContextsRequest>,
+    ) -> Result<Response<ListContextsResponse>, Status> {
+        self.roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        // Listing doesn't count as a use, so it leaves the TTL alone
+        let mut contexts: Vec<ContextInfo> = self
+            .contexts
+            .read()
+            .values()
+            .filter(|context| !self.context_ttl.is_some_and(|ttl| context.expired(ttl)))
+            .map(ExecutionContext::info)
+            .collect();
+        contexts.sort_by_key(|info| info.created_at_ms);
+        Ok(Response::new(ListContextsResponse { contexts }))
+    }
+
+    async fn destroy_all_contexts(
+        &self,
+        request: Request<DestroyAllContextsRequest>,
+    ) -> Result<Response<DestroyAllContextsResponse>, Status> {
+        self.roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let destroyed = std::mem::take(&mut *self.contexts.write()).len();
+        info!("Destroyed all {} contexts", destroyed);
+        Ok(Response::new(DestroyAllContextsResponse {
+            destroyed: destroyed as u32,
+        }))
+    }
+
+    async fn list_methods(
+        &self,
+        request: Request<ListMethodsRequest>,
+    ) -> Result<Response<ListMethodsResponse>, Status> {
+        let role = self
+            .roles
+            .caller_role(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        let is_admin = role.as_deref() == Some(auth::ADMIN_ROLE);
+        let req = request.into_inner();
+        let metadata = self.metadata.read();
+
+        let methods: Vec<MethodInfo> = metadata
+            .iter()
+            .filter(|(name, _)| req.prefix.is_empty() || name.starts_with(&req.prefix))
+            .filter(|(_, meta)| is_admin || auth::is_allowed(&meta.allowed_roles, role.as_deref()))
+            .map(|(name, meta)| MethodInfo {
+                name: name.clone(),
+                description: meta.description.clone(),
+                is_stateful: meta.is_stateful,
+                parameter_types: meta.parameter_types.clone(),
+                return_type: meta.return_type.clone(),
+                is_streaming: meta.is_streaming,
+            })
+            .collect();
+
+        Ok(Response::new(ListMethodsResponse { methods }))
+    }
+
+    async fn get_server_info(
+        &self,
+        _request: Request<GetServerInfoRequest>,
+    ) -> Result<Response<GetServerInfoResponse>, Status> {
+        let rejected = self.rejected_invocations.load(Ordering::Relaxed);
+        Ok(Response::new(server_info(&self.registration, rejected)))
+    }
+}
+
+/// Payload of `GetServerInfo`, shared with the `__server_info` built-in
+fn server_info(
+    registration: &RegistrationTracker,
+    rejected_invocations: u64,
+) -> GetServerInfoResponse {
+    GetServerInfoResponse {
+        runtime: "rust".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: env!("GIT_HASH").to_string(),
+        registration_complete: registration.is_complete(),
+        registration_error: registration.error().unwrap_or_default(),
+        protocol_version: protocol::PROTOCOL_VERSION,
+        encodings: vec![Encoding::Json as i32, Encoding::Msgpack as i32],
+        rejected_invocations,
+    }
+}
+
+/// Refuse names in the reserved `__` namespace, which belongs to the
+/// built-in diagnostics
+pub(crate) fn check_name(name: &str) -> Result<(), String> {
+    if builtins::is_reserved(name) {
+        return Err(format!(
+            "names starting with '{}' are reserved",
+            builtins::RESERVED_PREFIX
+        ));
+    }
+    Ok(())
+}
+
+/// A synchronous function as a registered one. It's called when the future
+/// is first polled, so it's measured like the rest of the call.
+fn blocking<F>(func: F) -> RegisteredFunction
+where
+    F: Fn(&ExecutionContext, JsonValue) -> Result<JsonValue, String> + Send + Sync + 'static,
+{
+    let func = Arc::new(func);
+    Arc::new(
+        move |ctx: &ExecutionContext, args: JsonValue| -> FunctionFuture {
+            let (func, ctx) = (Arc::clone(&func), ctx.clone());
+            Box::pin(async move { func(&ctx, args) })
+        },
+    )
+}
+
+/// What a function panicked with, when it's a message
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(non-string payload)".to_string()
+    }
+}
+
+/// Await `future`, catching a panic in any of its polls
+async fn catch_unwind<T>(future: impl Future<Output = T>) -> std::thread::Result<T> {
+    let mut future = pin!(future);
+    poll_fn(
+        |cx| match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(value)) => Poll::Ready(Ok(value)),
+            Err(payload) => Poll::Ready(Err(payload)),
+        },
+    )
+    .await
+}
+
+#[derive(Parser)]
+#[command(name = "transpile-test-server")]
 #[command(about = "Rust gRPC server for transpilation testing")]
 struct Args {
     /// Server port
     #[arg(short, long, default_value = "50052")]
     port: u16,
 
+    /// JSON file mapping bearer tokens to roles
+    #[arg(long)]
+    roles_file: Option<PathBuf>,
+
+    /// Seconds to wait for registration sources before reporting them as stuck
+    #[arg(long, default_value = "30")]
+    registration_timeout: u64,
+
+    /// Deepest JSON nesting accepted in arguments and initial state
+    #[arg(long, default_value_t = json_depth::DEFAULT_MAX_DEPTH)]
+    max_json_depth: usize,
+
+    /// Seconds a context may go unused before it's removed (0: never)
+    #[arg(long, default_value = "0")]
+    context_ttl_secs: u64,
+
+    /// Directory of WebAssembly modules whose functions to register
+    #[arg(long)]
+    wasm_dir: Option<PathBuf>,
+
+    /// Milliseconds a call may run when it doesn't set a timeout (0: no limit)
+    #[arg(long, default_value = "0")]
+    default_timeout_ms: u64,
+
+    /// Seconds in-flight calls get to finish after SIGINT or SIGTERM
+    #[arg(long, default_value = "10")]
+    shutdown_grace_secs: u64,
+
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
@@ -339,24 +1328,730 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Initialize tracing
     let log_level = if args.verbose { "debug" } else { "info" };
-    tracing_subscriber::fmt()
-        .with_env_filter(log_level)
-        .init();
+    tracing_subscriber::fmt().with_env_filter(log_level).init();
 
     let addr = format!("0.0.0.0:{}", args.port).parse()?;
-    let server = TranspileTestServer::new();
+    let mut server = TranspileTestServer::new()
+        .with_max_json_depth(args.max_json_depth)
+        .with_context_ttl(args.context_ttl_secs)
+        .with_default_timeout(args.default_timeout_ms);
+    if let Some(path) = &args.roles_file {
+        let roles = Roles::load(path)?;
+        info!(
+            "Loaded {} auth tokens from {}",
+            roles.token_count(),
+            path.display()
+        );
+        server = server.with_roles(roles);
+    }
 
     // Register example functions
+    server
+        .registration()
+        .spawn_timeout(Duration::from_secs(args.registration_timeout));
+    let source = server.registration().begin("examples::simple_math");
     examples::register_simple_math(&server);
+    source.finish();
+    let source = server.registration().begin("examples::collections");
+    examples::register_collections(&server);
+    source.finish();
+    let source = server.registration().begin("examples::restricted");
+    examples::register_restricted(&server);
+    source.finish();
     info!("Registered example functions");
 
+    // Loaded after the examples so a module's functions replace theirs.
+    // Compiling modules can take a while, so it happens while the server
+    // answers, with registration_complete false until it's done.
+    let server = Arc::new(server);
+    if let Some(dir) = args.wasm_dir.clone() {
+        let source = server
+            .registration()
+            .begin(format!("wasm:{}", dir.display()));
+        let server = Arc::clone(&server);
+        tokio::task::spawn_blocking(move || match wasm::load_dir(&server, &dir) {
+            Ok(count) => {
+                info!("Registered {} functions from {}", count, dir.display());
+                source.finish();
+            }
+            Err(e) => source.fail(e),
+        });
+    }
+
+    server.spawn_context_sweeper();
+    let grace = Duration::from_secs(args.shutdown_grace_secs);
+    let shutdown = Shutdown::listen(grace, server.in_flight.clone())?;
+    let contexts = server.contexts.clone();
+
     info!("Rust gRPC server starting on {}", addr);
     println!("Rust gRPC server listening on port {}", args.port);
 
-    Server::builder()
-        .add_service(TranspileTestServiceServer::new(server))
-        .serve(addr)
-        .await?;
-
+    let service = TranspileTestServiceServer::from_arc(server)
+        .max_decoding_message_size(MAX_MESSAGE_BYTES)
+        .max_encoding_message_size(MAX_MESSAGE_BYTES);
+    let serve = Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(addr, shutdown.requested());
+    // Serving ends once every connection has finished its calls
+    let abandoned = tokio::select! {
+        result = serve => {
+            result?;
+            0
+        }
+        () = shutdown.grace_expired() => shutdown.in_flight(),
+    };
+    if abandoned > 0 {
+        warn!(
+            "Abandoning {} calls still running after {}s",
+            abandoned,
+            grace.as_secs()
+        );
+    }
+    shutdown.destroy_contexts(&contexts);
+    info!("Shutdown complete");
+    if abandoned > 0 {
+        // Returning would wait for the blocking threads of abandoned calls
+        std::process::exit(0);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server with the restricted examples and one unrestricted method
+    fn server() -> TranspileTestServer {
+        let tokens = [
+            ("admin-token", "admin"),
+            ("fs-token", "filesystem"),
+            ("ci-token", "ci"),
+        ];
+        let tokens = tokens
+            .iter()
+            .map(|&(token, role)| (token.to_string(), role.to_string()))
+            .collect();
+        let server = TranspileTestServer::new().with_roles(Roles::new(tokens));
+        examples::register_simple_math(&server);
+        examples::register_restricted(&server);
+        server
+    }
+
+    fn request<T>(message: T, token: Option<&str>) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(token) = token {
+            let header = format!("Bearer {}", token).parse().unwrap();
+            request.metadata_mut().insert("authorization", header);
+        }
+        request
+    }
+
+    fn read_manifest() -> InvokeMethodRequest {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        InvokeMethodRequest {
+            method_name: "read_text_file".to_string(),
+            arguments: serde_json::json!({ "path": path }).to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn unauthenticated<T>(result: Result<T, Status>) {
+        let code = result.err().map(|status| status.code());
+        assert_eq!(code, Some(tonic::Code::Unauthenticated));
+    }
+
+    async fn listed(server: &TranspileTestServer, token: Option<&str>) -> Vec<String> {
+        let listing = server
+            .list_methods(request(ListMethodsRequest::default(), token))
+            .await
+            .unwrap();
+        listing
+            .into_inner()
+            .methods
+            .into_iter()
+            .map(|m| m.name)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn allowed_roles_may_invoke() {
+        let server = server();
+        for token in ["fs-token", "admin-token"] {
+            let response = server
+                .invoke_method(request(read_manifest(), Some(token)))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(response.success, "{}", response.error);
+            assert!(response.result.contains("[package]"));
+        }
+    }
+
+    #[tokio::test]
+    async fn other_roles_and_anonymous_callers_are_denied() {
+        let server = server();
+        for token in [Some("ci-token"), None] {
+            let status = server
+                .invoke_method(request(read_manifest(), token))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        }
+        let info = server
+            .get_server_info(request(GetServerInfoRequest {}, None))
+            .await
+            .unwrap();
+        assert_eq!(info.into_inner().rejected_invocations, 2);
+
+        // Unrestricted methods stay open to everyone
+        let add = InvokeMethodRequest {
+            method_name: "add".to_string(),
+            arguments: r#"{"a": 1, "b": 2}"#.to_string(),
+            ..Default::default()
+        };
+        let response = server.invoke_method(request(add, None)).await.unwrap();
+        assert_eq!(response.into_inner().result, "3");
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_are_unauthenticated() {
+        let server = server();
+        let token = Some("forged");
+        unauthenticated(server.invoke_method(request(read_manifest(), token)).await);
+        unauthenticated(
+            server
+                .list_methods(request(Default::default(), token))
+                .await,
+        );
+        unauthenticated(
+            server
+                .create_context(request(Default::default(), token))
+                .await,
+        );
+        unauthenticated(
+            server
+                .destroy_context(request(Default::default(), token))
+                .await,
+        );
+        unauthenticated(
+            server
+                .reset_context(request(Default::default(), token))
+                .await,
+        );
+        unauthenticated(
+            server
+                .inspect_state(request(Default::default(), token))
+                .await,
+        );
+        unauthenticated(
+            server
+                .export_context(request(Default::default(), token))
+                .await,
+        );
+        unauthenticated(
+            server
+                .import_context(request(Default::default(), token))
+                .await,
+        );
+        assert!(server.contexts.read().is_empty());
+
+        // Without a token, the same calls go ahead anonymously
+        let created = server
+            .create_context(request(Default::default(), None))
+            .await;
+        assert!(created.unwrap().into_inner().success);
+    }
+
+    #[tokio::test]
+    async fn listing_hides_what_the_caller_cannot_invoke() {
+        let server = server();
+        let restricted = ["admin_reset_state", "read_text_file"];
+        let shown =
+            |methods: &[String]| restricted.map(|name| methods.iter().any(|method| method == name));
+
+        assert_eq!(shown(&listed(&server, None).await), [false, false]);
+        assert_eq!(
+            shown(&listed(&server, Some("ci-token")).await),
+            [false, false]
+        );
+        assert_eq!(
+            shown(&listed(&server, Some("fs-token")).await),
+            [false, true]
+        );
+        assert_eq!(
+            shown(&listed(&server, Some("admin-token")).await),
+            [true, true]
+        );
+        assert!(listed(&server, None)
+            .await
+            .iter()
+            .any(|method| method == "add"));
+    }
+
+    /// `calls` calls of `method` at once on one context starting from a
+    /// counter of 0, and the counter they leave behind
+    async fn count_concurrently(server: TranspileTestServer, method: &str, calls: usize) -> String {
+        let server = Arc::new(server);
+        let created = server
+            .create_context(request(
+                CreateContextRequest {
+                    initial_state: r#"{"counter": 0}"#.to_string(),
+                },
+                None,
+            ))
+            .await
+            .unwrap();
+        let context_id = created.into_inner().context_id;
+        let call = |method: &str| InvokeMethodRequest {
+            context_id: context_id.clone(),
+            method_name: method.to_string(),
+            arguments: "{}".to_string(),
+            ..Default::default()
+        };
+
+        let mut running = tokio::task::JoinSet::new();
+        for _ in 0..calls {
+            let (server, call) = (Arc::clone(&server), call(method));
+            running.spawn(async move { server.invoke_method(request(call, None)).await });
+        }
+        while let Some(response) = running.join_next().await {
+            let response = response.unwrap().unwrap().into_inner();
+            assert!(response.success, "{}", response.error);
+        }
+        let counter = server
+            .invoke_method(request(call("counter_get"), None))
+            .await;
+        counter.unwrap().into_inner().result
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_increments_on_one_context_take_turns() {
+        let server = TranspileTestServer::new();
+        examples::register_simple_math(&server);
+        assert_eq!(
+            count_concurrently(server, "counter_increment", 100).await,
+            "100"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn stateful_calls_keep_their_turn_across_awaits() {
+        let server = TranspileTestServer::new();
+        examples::register_simple_math(&server);
+        // Reads, then lets the other calls run before writing, so calls that
+        // didn't take turns would all read the same counter
+        server.register_async_function(
+            "slow_increment",
+            |ctx, _args| {
+                let ctx = ctx.clone();
+                async move {
+                    let current = ctx
+                        .get_state("counter")
+                        .and_then(|v| v.as_i64())
+                        .unwrap_or(0);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    ctx.set_state("counter".to_string(), serde_json::json!(current + 1));
+                    Ok(serde_json::json!(current + 1))
+                }
+            },
+            "Increment a counter, sleeping between the read and the write",
+            true,
+            vec![],
+            "int",
+        );
+        assert_eq!(
+            count_concurrently(server, "slow_increment", 100).await,
+            "100"
+        );
+    }
+
+    /// `busy_loop` for `ms` with a `timeout_ms`, and how long it took to answer
+    async fn busy_loop(
+        server: &TranspileTestServer,
+        ms: u64,
+        timeout_ms: u64,
+    ) -> (InvokeMethodResponse, Duration) {
+        let call = InvokeMethodRequest {
+            method_name: "busy_loop".to_string(),
+            arguments: serde_json::json!({ "ms": ms }).to_string(),
+            timeout_ms,
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let response = server.invoke_method(request(call, None)).await.unwrap();
+        (response.into_inner(), started.elapsed())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn calls_past_their_timeout_are_cut_off() {
+        let server = server();
+        let (response, took) = busy_loop(&server, 1_000, 100).await;
+        assert!(!response.success);
+        assert_eq!(response.error_code(), ErrorCode::Timeout);
+        assert_eq!(response.error, "TimeoutError: exceeded 100ms");
+        assert!(
+            took < Duration::from_millis(800),
+            "answered after {:?}",
+            took
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn the_default_timeout_applies_to_calls_without_one() {
+        let server = TranspileTestServer::new().with_default_timeout(100);
+        examples::register_simple_math(&server);
+        let (response, took) = busy_loop(&server, 1_000, 0).await;
+        assert_eq!(response.error_code(), ErrorCode::Timeout);
+        assert_eq!(response.error, "TimeoutError: exceeded 100ms");
+        assert!(
+            took < Duration::from_millis(800),
+            "answered after {:?}",
+            took
+        );
+
+        // A call's own timeout_ms takes precedence
+        let (response, _) = busy_loop(&server, 200, 5_000).await;
+        assert!(response.success, "{}", response.error);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn calls_within_their_timeout_succeed() {
+        let server = server();
+        let (response, _) = busy_loop(&server, 10, 5_000).await;
+        assert!(response.success, "{}", response.error);
+        assert_eq!(response.result, "10");
+        assert!(response.metadata.unwrap().execution_time_us >= 10_000);
+    }
+
+    /// `method` called with `arguments`, as (success, result, error)
+    async fn call(method: &str, arguments: &str) -> (bool, String, String) {
+        let server = TranspileTestServer::new();
+        examples::register_collections(&server);
+        let call = InvokeMethodRequest {
+            method_name: method.to_string(),
+            arguments: arguments.to_string(),
+            ..Default::default()
+        };
+        let response = server.invoke_method(request(call, None)).await.unwrap();
+        let response = response.into_inner();
+        (response.success, response.result, response.error)
+    }
+
+    #[tokio::test]
+    async fn named_parameters_take_positional_arguments() {
+        let same = (true, "false".to_string(), String::new());
+        assert_eq!(call("same_type", "[1, 1.0]").await, same);
+        assert_eq!(call("same_type", r#"{"a": 1, "b": 1.0}"#).await, same);
+        assert_eq!(call("type_name", "[[1, 2]]").await.1, r#""list""#);
+    }
+
+    #[tokio::test]
+    async fn a_single_value_is_the_only_argument() {
+        assert_eq!(call("type_name", "2.5").await.1, r#""float""#);
+        assert_eq!(call("type_name", r#""text""#).await.1, r#""str""#);
+        assert_eq!(call("type_name", "null").await.1, r#""NoneType""#);
+    }
+
+    #[tokio::test]
+    async fn positional_arity_is_checked() {
+        let (success, _, error) = call("same_type", "[1, 2, 3]").await;
+        assert!(!success);
+        assert_eq!(error, "ArgumentError: expected 2 arguments, got 3");
+        let (_, _, error) = call("same_type", "7").await;
+        assert_eq!(error, "ArgumentError: expected 2 arguments, got 1");
+        let (_, _, error) = call("type_name", "[]").await;
+        assert_eq!(error, "ArgumentError: expected 1 argument, got 0");
+    }
+
+    /// `memory_bytes` reported for summing the integers below `n`
+    #[cfg(feature = "memory-tracking")]
+    async fn sum_vec_memory(server: &TranspileTestServer, n: i64) -> i64 {
+        let call = InvokeMethodRequest {
+            method_name: "sum_vec".to_string(),
+            arguments: serde_json::json!({ "n": n }).to_string(),
+            ..Default::default()
+        };
+        let response = server.invoke_method(request(call, None)).await.unwrap();
+        let response = response.into_inner();
+        assert!(response.success, "{}", response.error);
+        response.metadata.unwrap().memory_bytes
+    }
+
+    #[cfg(feature = "memory-tracking")]
+    #[tokio::test]
+    async fn memory_is_the_peak_a_call_allocated() {
+        let server = server();
+        let mut smaller = 0;
+        for n in [1_000, 100_000] {
+            let peak = sum_vec_memory(&server, n).await;
+            assert!(peak >= n * 8, "{} bytes for {} integers", peak, n);
+            assert!(peak > smaller, "{} bytes for {} integers", peak, n);
+            assert_eq!(sum_vec_memory(&server, n).await, peak);
+            smaller = peak;
+        }
+    }
+
+    #[tokio::test]
+    async fn arguments_nested_too_deep_are_invalid_arguments() {
+        let server = TranspileTestServer::new().with_max_json_depth(4);
+        examples::register_simple_math(&server);
+        let deep = serde_json::json!({ "a": [[[[1]]]], "b": 1 });
+        let calls = [
+            InvokeMethodRequest {
+                method_name: "add".to_string(),
+                arguments: deep.to_string(),
+                ..Default::default()
+            },
+            InvokeMethodRequest {
+                method_name: "add".to_string(),
+                encoding: Encoding::Msgpack as i32,
+                payload: msgpack::to_vec(&deep),
+                ..Default::default()
+            },
+        ];
+        for call in calls.clone() {
+            let response = server.invoke_method(request(call, None)).await.unwrap();
+            let response = response.into_inner();
+            assert_eq!(response.error_code(), ErrorCode::InvalidArguments);
+            assert!(
+                response.error.contains("maximum depth of 4"),
+                "{}",
+                response.error
+            );
+        }
+        let batch = InvokeBatchRequest {
+            requests: calls.to_vec(),
+        };
+        let responses = server.invoke_batch(request(batch, None)).await.unwrap();
+        for response in responses.into_inner().responses {
+            assert_eq!(response.error_code(), ErrorCode::InvalidArguments);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_panic_fails_the_call_and_the_service_carries_on() {
+        let server = server();
+        let element_at = |index: usize| InvokeMethodRequest {
+            method_name: "element_at".to_string(),
+            arguments: serde_json::json!({ "items": [10, 20], "index": index }).to_string(),
+            ..Default::default()
+        };
+        let response = server
+            .invoke_method(request(element_at(5), None))
+            .await
+            .unwrap();
+        let response = response.into_inner();
+        assert!(!response.success);
+        assert_eq!(response.error_code(), ErrorCode::ExecutionError);
+        assert_eq!(
+            response.error,
+            "panicked: index out of bounds: the len is 2 but the index is 5"
+        );
+
+        let response = server
+            .invoke_method(request(element_at(1), None))
+            .await
+            .unwrap();
+        let response = response.into_inner();
+        assert!(response.success, "{}", response.error);
+        assert_eq!(response.result, "20");
+    }
+
+    /// Wait for every call to leave the in-flight count, as shutdown does
+    async fn drained(server: &TranspileTestServer) {
+        let give_up = Instant::now() + Duration::from_secs(5);
+        while server.in_flight.count() > 0 {
+            assert!(
+                Instant::now() < give_up,
+                "{} calls still in flight",
+                server.in_flight.count()
+            );
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn an_open_stream_is_in_flight_until_it_ends() {
+        let server = server();
+        let squares = || InvokeMethodRequest {
+            method_name: "range_squares".to_string(),
+            arguments: r#"{"n": 1000}"#.to_string(),
+            ..Default::default()
+        };
+
+        // Unread, the stream waits on a full buffer for as long as it's open
+        let stream = server
+            .invoke_method_stream(request(squares(), None))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.in_flight.count(), 1);
+        drop(stream);
+        drained(&server).await;
+
+        let stream = server
+            .invoke_method_stream(request(squares(), None))
+            .await
+            .unwrap();
+        let mut chunks = stream.into_inner().into_inner();
+        let mut last = None;
+        while let Some(chunk) = chunks.recv().await {
+            last = Some(chunk.unwrap());
+        }
+        let last = last.unwrap();
+        assert!(last.done && last.error.is_empty(), "{}", last.error);
+        assert_eq!(last.sequence, 1000);
+        drained(&server).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn a_batch_is_in_flight_until_its_last_item() {
+        let server = Arc::new(server());
+        let busy_loop = InvokeMethodRequest {
+            method_name: "busy_loop".to_string(),
+            arguments: r#"{"ms": 100}"#.to_string(),
+            ..Default::default()
+        };
+        let batch = InvokeBatchRequest {
+            requests: vec![busy_loop.clone(), busy_loop],
+        };
+        let running = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.invoke_batch(request(batch, None)).await }
+        });
+        // Between the items as well as during them
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(server.in_flight.count(), 1);
+        }
+        let responses = running.await.unwrap().unwrap().into_inner().responses;
+        assert!(responses.iter().all(|response| response.success));
+        assert_eq!(server.in_flight.count(), 0);
+    }
+
+    /// A server with `count_up`, a stateful stream that reads the counter
+    /// and sleeps before writing it back, and a context with a counter of 0
+    async fn counting_stream_server() -> (Arc<TranspileTestServer>, String) {
+        let server = TranspileTestServer::new();
+        examples::register_simple_math(&server);
+        server.register_streaming_function(
+            "count_up",
+            |ctx, args| {
+                let n = args["n"].as_u64().ok_or("Missing or invalid 'n'")?;
+                let ctx = ctx.clone();
+                let items = (0..n).map(move |_| {
+                    let current = ctx
+                        .get_state("counter")
+                        .and_then(|v| v.as_i64())
+                        .unwrap_or(0);
+                    std::thread::sleep(Duration::from_millis(1));
+                    ctx.set_state("counter".to_string(), serde_json::json!(current + 1));
+                    Ok(serde_json::json!(current + 1))
+                });
+                Ok(Box::new(items) as streaming::Items)
+            },
+            "Increment a counter n times, yielding each value",
+            true,
+            vec!["int".to_string()],
+            "int",
+        );
+        let created = server
+            .create_context(request(
+                CreateContextRequest {
+                    initial_state: r#"{"counter": 0}"#.to_string(),
+                },
+                None,
+            ))
+            .await
+            .unwrap();
+        (Arc::new(server), created.into_inner().context_id)
+    }
+
+    /// The items of `count_up` streamed on `context_id`, read as they come
+    fn count_up(
+        server: &Arc<TranspileTestServer>,
+        context_id: &str,
+        n: u64,
+    ) -> tokio::task::JoinHandle<Vec<i64>> {
+        let call = InvokeMethodRequest {
+            context_id: context_id.to_string(),
+            method_name: "count_up".to_string(),
+            arguments: serde_json::json!({ "n": n }).to_string(),
+            ..Default::default()
+        };
+        let server = Arc::clone(server);
+        tokio::spawn(async move {
+            let stream = server
+                .invoke_method_stream(request(call, None))
+                .await
+                .unwrap();
+            let mut chunks = stream.into_inner().into_inner();
+            let mut items = Vec::new();
+            while let Some(chunk) = chunks.recv().await {
+                let chunk = chunk.unwrap();
+                assert!(chunk.error.is_empty(), "{}", chunk.error);
+                if !chunk.done {
+                    items.push(chunk.item.parse().unwrap());
+                }
+            }
+            items
+        })
+    }
+
+    async fn counter(server: &TranspileTestServer, context_id: &str) -> String {
+        let call = InvokeMethodRequest {
+            context_id: context_id.to_string(),
+            method_name: "counter_get".to_string(),
+            arguments: "{}".to_string(),
+            ..Default::default()
+        };
+        let response = server.invoke_method(request(call, None)).await.unwrap();
+        response.into_inner().result
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn a_stateful_stream_and_invocations_take_turns() {
+        let (server, context_id) = counting_stream_server().await;
+        let streamed = count_up(&server, &context_id, 50);
+        let mut running = tokio::task::JoinSet::new();
+        for _ in 0..20 {
+            let server = Arc::clone(&server);
+            let call = InvokeMethodRequest {
+                context_id: context_id.clone(),
+                method_name: "counter_increment".to_string(),
+                arguments: "{}".to_string(),
+                ..Default::default()
+            };
+            running.spawn(async move { server.invoke_method(request(call, None)).await });
+        }
+        while let Some(response) = running.join_next().await {
+            let response = response.unwrap().unwrap().into_inner();
+            assert!(response.success, "{}", response.error);
+        }
+
+        // No increment landed between a stream item's read and its write
+        let items = streamed.await.unwrap();
+        assert_eq!(items.len(), 50);
+        assert!(
+            items.windows(2).all(|pair| pair[1] == pair[0] + 1),
+            "{:?}",
+            items
+        );
+        assert_eq!(counter(&server, &context_id).await, "70");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn a_reset_waits_for_a_stateful_stream() {
+        let (server, context_id) = counting_stream_server().await;
+        let streamed = count_up(&server, &context_id, 50);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let reset = ResetContextRequest {
+            context_id: context_id.clone(),
+        };
+        let response = server.reset_context(request(reset, None)).await.unwrap();
+        assert!(response.into_inner().success);
+
+        assert_eq!(streamed.await.unwrap(), (1..=50).collect::<Vec<i64>>());
+        assert_eq!(counter(&server, &context_id).await, "0");
+    }
+}
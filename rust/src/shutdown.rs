@@ -0,0 +1,146 @@
+/*!
+Graceful shutdown on SIGINT or SIGTERM (`--shutdown-grace-secs`).
+
+The first signal stops the server accepting RPCs, while calls already in
+flight run on for up to the grace period. Then the contexts left behind are
+logged and destroyed and the server exits 0. A second signal exits at once
+with status 1, for when waiting isn't worth it.
+*/
+
+use crate::ExecutionContext;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Count of calls currently running
+#[derive(Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// Count a call until the returned guard is dropped
+    pub fn enter(&self) -> CallGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        CallGuard(Arc::clone(&self.0))
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+pub struct CallGuard(Arc<AtomicUsize>);
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Signals received, and what to do about them
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+    grace: Duration,
+    in_flight: InFlight,
+}
+
+impl Shutdown {
+    /// Start listening for signals
+    pub fn listen(grace: Duration, in_flight: InFlight) -> std::io::Result<Self> {
+        let mut signals = Signals::new()?;
+        let (tx, requested) = watch::channel(false);
+        tokio::spawn(async move {
+            let name = signals.next().await;
+            info!("{} received; stopping (send another to exit now)", name);
+            let _ = tx.send(true);
+            let name = signals.next().await;
+            warn!("{} received while stopping; exiting without waiting", name);
+            std::process::exit(1);
+        });
+        Ok(Self {
+            requested,
+            grace,
+            in_flight,
+        })
+    }
+
+    /// Calls still running
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.count()
+    }
+
+    /// Resolves on the first signal, for `serve_with_shutdown`
+    pub async fn requested(&self) {
+        let mut requested = self.requested.clone();
+        // The sender lives as long as the process
+        let _ = requested.wait_for(|requested| *requested).await;
+        info!(
+            "Waiting up to {}s for {} in-flight calls",
+            self.grace.as_secs(),
+            self.in_flight.count()
+        );
+    }
+
+    /// Resolves the grace period after the first signal
+    pub async fn grace_expired(&self) {
+        let mut requested = self.requested.clone();
+        let _ = requested.wait_for(|requested| *requested).await;
+        tokio::time::sleep(self.grace).await;
+    }
+
+    /// Log and destroy every context still open
+    pub fn destroy_contexts(&self, contexts: &RwLock<HashMap<String, ExecutionContext>>) {
+        let contexts = std::mem::take(&mut *contexts.write());
+        for (id, context) in &contexts {
+            info!(
+                "Destroying context {} at shutdown (created {}s ago, {} state keys)",
+                id,
+                context.created_at.elapsed().as_secs(),
+                context.get_all_state().len()
+            );
+        }
+        info!("Destroyed {} contexts", contexts.len());
+    }
+}
+
+/// SIGINT and SIGTERM, or Ctrl-C where there are no Unix signals
+struct Signals {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    #[cfg(unix)]
+    fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> std::io::Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Wait for the next signal and name it
+    #[cfg(unix)]
+    async fn next(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.interrupt.recv() => "SIGINT",
+            _ = self.terminate.recv() => "SIGTERM",
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn next(&mut self) -> &'static str {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
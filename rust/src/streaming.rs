@@ -0,0 +1,91 @@
+/*!
+Streaming functions, for `InvokeMethodStream`.
+
+A function registered with `register_streaming_function` returns an
+iterator of items rather than one result, like a Python generator, so a
+large output never has to be held in full. The iterator is driven on a
+blocking thread and each item is sent as a chunk through a bounded channel
+into the response stream, so a slow client holds back the function instead
+of items piling up. An item that fails, or a panic, ends the stream with an
+error; a client that goes away ends it silently.
+
+The final chunk carries the number of items, any error and the execution
+metadata. Its `memory_bytes` is the most any one item took to produce.
+*/
+
+use crate::transpile_test::{ExecutionMetadata, InvokeStreamChunk};
+use crate::{memory, panic_message};
+use serde_json::Value as JsonValue;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tonic::Status;
+use tracing::{debug, error};
+
+/// Items a streaming function yields
+pub type Items = Box<dyn Iterator<Item = Result<JsonValue, String>> + Send>;
+
+/// Chunks waiting for the client before the function is held back
+pub const BUFFER: usize = 16;
+
+pub type Sender = mpsc::Sender<Result<InvokeStreamChunk, Status>>;
+
+/// Send every item of `items`, then the final chunk. Blocks, so it runs on
+/// a blocking thread.
+pub fn drive(method: &str, mut items: Items, sender: &Sender, start: Instant) {
+    let mut sequence = 0;
+    let mut memory_bytes = 0;
+    let error = loop {
+        let (next, bytes) =
+            memory::measure(|| panic::catch_unwind(AssertUnwindSafe(|| items.next())));
+        memory_bytes = memory_bytes.max(bytes);
+        let item = match next {
+            Ok(None) => break String::new(),
+            Ok(Some(Ok(item))) => item,
+            Ok(Some(Err(e))) => {
+                error!("Error streaming {} at item {}: {}", method, sequence, e);
+                break e;
+            }
+            Err(payload) => {
+                let message = panic_message(payload.as_ref());
+                error!("{} panicked at item {}: {}", method, sequence, message);
+                break format!("panicked: {}", message);
+            }
+        };
+        let chunk = InvokeStreamChunk {
+            sequence,
+            item: serde_json::to_string(&item).unwrap_or_else(|_| "null".to_string()),
+            done: false,
+            error: String::new(),
+            metadata: None,
+        };
+        if sender.blocking_send(Ok(chunk)).is_err() {
+            debug!("Client stopped reading {} after {} items", method, sequence);
+            return;
+        }
+        sequence += 1;
+    };
+
+    let execution_time_us = start.elapsed().as_micros() as i64;
+    debug!(
+        "Streamed {} items of {} in {}μs, peak {} bytes per item",
+        sequence, method, execution_time_us, memory_bytes
+    );
+    let metadata = ExecutionMetadata {
+        execution_time_us,
+        memory_bytes,
+        runtime: "rust".to_string(),
+    };
+    let _ = sender.blocking_send(Ok(end(sequence, error, Some(metadata))));
+}
+
+/// The final chunk of a stream of `count` items
+pub fn end(count: u64, error: String, metadata: Option<ExecutionMetadata>) -> InvokeStreamChunk {
+    InvokeStreamChunk {
+        sequence: count,
+        item: String::new(),
+        done: true,
+        error,
+        metadata,
+    }
+}
@@ -0,0 +1,371 @@
+/*!
+Functions loaded from WebAssembly modules with `--wasm-dir`.
+
+Every `.wasm` (or `.wat`) file in the directory is compiled at startup and
+each function it exports with the signature `(i32, i32) -> i64` is
+registered under its export name, replacing any method already registered
+under that name. A module must export:
+
+- `memory`, its linear memory
+- `alloc(len: i32) -> i32`, returning where the host may write `len` bytes
+
+A call writes the arguments as a JSON object into memory from `alloc` and
+passes the pointer and length. The function returns where its output is,
+packed as `(ptr << 32) | len`: a JSON object, either `{"ok": <result>}` or
+`{"error": "<message>"}`. Modules may not import anything.
+
+Each call gets a fresh instance, so guests can't leave state behind for
+the next call or corrupt each other's memory. A trap, such as an
+`unreachable` or an out-of-bounds access, fails the call with a
+`wasm trap:` error rather than taking down the server.
+
+Descriptions, parameters and return types come from a sidecar JSON file
+next to the module (`add.wasm` and `add.json`), or else from a custom
+section named `transpile_test` in a binary module, both in this form:
+
+```json
+{
+  "functions": {
+    "add": {
+      "description": "Add two numbers",
+      "parameters": [{"name": "a", "type": "int"}, {"name": "b", "type": "int"}],
+      "return_type": "int"
+    }
+  }
+}
+```
+
+Parameters listed there are checked before each call, like those of
+`declare_parameters`. Without the `wasm` feature, `--wasm-dir` fails.
+*/
+
+use crate::TranspileTestServer;
+use std::error::Error;
+use std::path::Path;
+
+#[cfg(feature = "wasm")]
+mod guest {
+    use crate::TranspileTestServer;
+    use serde::Deserialize;
+    use serde_json::Value as JsonValue;
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tracing::{info, warn};
+    use wasmtime::{
+        format_err, Engine, ExternType, InstancePre, Linker, Module, Store, Trap, ValType,
+    };
+
+    /// Name of the custom section metadata may be read from
+    const METADATA_SECTION: &str = "transpile_test";
+
+    #[derive(Default, Deserialize)]
+    struct Metadata {
+        #[serde(default)]
+        functions: HashMap<String, FunctionInfo>,
+    }
+
+    #[derive(Default, Deserialize)]
+    struct FunctionInfo {
+        #[serde(default)]
+        description: String,
+        #[serde(default)]
+        parameters: Option<Vec<ParameterInfo>>,
+        #[serde(default = "any_type")]
+        return_type: String,
+    }
+
+    #[derive(Deserialize)]
+    struct ParameterInfo {
+        name: String,
+        #[serde(rename = "type", default = "any_type")]
+        type_name: String,
+    }
+
+    fn any_type() -> String {
+        "any".to_string()
+    }
+
+    /// What a guest function's output says
+    #[derive(Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Output {
+        Ok(JsonValue),
+        Error(String),
+    }
+
+    /// A compiled module, ready to be instantiated for each call
+    struct Guest {
+        instance: InstancePre<()>,
+    }
+
+    impl Guest {
+        /// Call `export` with `args` in a fresh instance
+        fn call(&self, export: &str, args: &JsonValue) -> Result<JsonValue, String> {
+            let mut store = Store::new(self.instance.module().engine(), ());
+            let output = (|| -> wasmtime::Result<Vec<u8>> {
+                let instance = self.instance.instantiate(&mut store)?;
+                let memory = instance
+                    .get_memory(&mut store, "memory")
+                    .ok_or_else(|| format_err!("the module exports no `memory`"))?;
+                let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+                let func = instance.get_typed_func::<(i32, i32), i64>(&mut store, export)?;
+
+                let input = serde_json::to_vec(args)?;
+                let len = i32::try_from(input.len())?;
+                let ptr = alloc.call(&mut store, len)?;
+                memory.write(&mut store, ptr as u32 as usize, &input)?;
+                let packed = func.call(&mut store, (ptr, len))? as u64;
+
+                let mut output = vec![0; (packed & 0xffff_ffff) as usize];
+                memory.read(&store, (packed >> 32) as usize, &mut output)?;
+                Ok(output)
+            })()
+            // A trap already reads "wasm trap: ..."
+            .map_err(|e| match e.downcast_ref::<Trap>() {
+                Some(trap) => trap.to_string(),
+                None => format!("wasm call failed: {:#}", e),
+            })?;
+
+            match serde_json::from_slice(&output) {
+                Ok(Output::Ok(result)) => Ok(result),
+                Ok(Output::Error(error)) => Err(error),
+                Err(e) => Err(format!("Invalid output from the wasm module: {}", e)),
+            }
+        }
+    }
+
+    pub fn load_dir(server: &TranspileTestServer, dir: &Path) -> Result<usize, Box<dyn Error>> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read --wasm-dir {}: {}", dir.display(), e))?;
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "wasm" || extension == "wat")
+            })
+            .collect();
+        paths.sort();
+
+        let engine = Engine::default();
+        let mut registered = 0;
+        for path in paths {
+            // One broken module shouldn't keep the others from loading
+            match load_module(server, &engine, &path) {
+                Ok(count) => registered += count,
+                Err(e) => warn!("Skipping {}: {}", path.display(), e),
+            }
+        }
+        Ok(registered)
+    }
+
+    fn load_module(
+        server: &TranspileTestServer,
+        engine: &Engine,
+        path: &Path,
+    ) -> Result<usize, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        let module = Module::new(engine, &bytes).map_err(|e| format!("{:#}", e))?;
+        if let Some(import) = module.imports().next() {
+            return Err(format!(
+                "imports {}::{}; modules may not import anything",
+                import.module(),
+                import.name()
+            ));
+        }
+        let metadata = metadata(path, &bytes)?;
+        let instance = Linker::new(engine)
+            .instantiate_pre(&module)
+            .map_err(|e| format!("{:#}", e))?;
+        let guest = Arc::new(Guest { instance });
+
+        let mut registered = 0;
+        for export in module.exports() {
+            let ExternType::Func(ty) = export.ty() else {
+                continue;
+            };
+            let params: Vec<ValType> = ty.params().collect();
+            let results: Vec<ValType> = ty.results().collect();
+            let callable = matches!(params[..], [ValType::I32, ValType::I32])
+                && matches!(results[..], [ValType::I64]);
+            if !callable {
+                continue;
+            }
+
+            let name = export.name().to_string();
+            if let Err(e) = crate::check_name(&name) {
+                warn!("Skipping {} from {}: {}", name, path.display(), e);
+                continue;
+            }
+            let info = metadata.functions.get(&name);
+            let parameters = info.and_then(|info| info.parameters.as_deref());
+            let parameter_types = parameters
+                .map(|parameters| parameters.iter().map(|p| p.type_name.clone()).collect())
+                .unwrap_or_default();
+            if server.metadata.read().contains_key(&name) {
+                info!(
+                    "{} from {} replaces the registered one",
+                    name,
+                    path.display()
+                );
+            }
+
+            let guest = Arc::clone(&guest);
+            let export = name.clone();
+            server.register_function(
+                name.clone(),
+                move |_ctx, args| guest.call(&export, &args),
+                info.map(|info| info.description.clone())
+                    .unwrap_or_default(),
+                false,
+                parameter_types,
+                info.map_or_else(any_type, |info| info.return_type.clone()),
+            );
+            if let Some(parameters) = parameters {
+                let parameters: Vec<(&str, &str)> = parameters
+                    .iter()
+                    .map(|p| (p.name.as_str(), p.type_name.as_str()))
+                    .collect();
+                server.declare_parameters(&name, &parameters);
+            }
+            registered += 1;
+        }
+        info!("Loaded {} functions from {}", registered, path.display());
+        Ok(registered)
+    }
+
+    /// Metadata from the sidecar file, or else the custom section
+    fn metadata(path: &Path, bytes: &[u8]) -> Result<Metadata, String> {
+        let sidecar = path.with_extension("json");
+        let json = if sidecar.exists() {
+            std::fs::read(&sidecar)
+                .map_err(|e| format!("Failed to read {}: {}", sidecar.display(), e))?
+        } else {
+            match custom_section(bytes, METADATA_SECTION) {
+                Some(section) => section.to_vec(),
+                None => return Ok(Metadata::default()),
+            }
+        };
+        serde_json::from_slice(&json).map_err(|e| format!("Invalid function metadata: {}", e))
+    }
+
+    /// Contents of the custom section called `name` in a binary module
+    fn custom_section<'a>(bytes: &'a [u8], name: &str) -> Option<&'a [u8]> {
+        let mut rest = bytes.strip_prefix(b"\0asm")?.get(4..)?;
+        while let Some((&id, after_id)) = rest.split_first() {
+            let (size, after_size) = leb128(after_id)?;
+            let section = after_size.get(..size)?;
+            rest = &after_size[size..];
+            if id == 0 {
+                let (name_len, after_len) = leb128(section)?;
+                if after_len.get(..name_len)? == name.as_bytes() {
+                    return Some(&after_len[name_len..]);
+                }
+            }
+        }
+        None
+    }
+
+    /// An unsigned LEB128 number and what follows it
+    fn leb128(bytes: &[u8]) -> Option<(usize, &[u8])> {
+        let mut value = 0usize;
+        for (index, &byte) in bytes.iter().enumerate().take(5) {
+            value |= ((byte & 0x7f) as usize) << (7 * index);
+            if byte & 0x80 == 0 {
+                return Some((value, &bytes[index + 1..]));
+            }
+        }
+        None
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::transpile_test::transpile_test_service_server::TranspileTestService;
+        use crate::transpile_test::{ErrorCode, InvokeMethodRequest, InvokeMethodResponse};
+        use serde_json::json;
+        use tonic::Request;
+
+        /// A server with the modules of `examples/wasm`
+        fn server() -> TranspileTestServer {
+            let server = TranspileTestServer::new();
+            let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples/wasm");
+            assert_eq!(load_dir(&server, &dir).unwrap(), 3);
+            server
+        }
+
+        async fn call(
+            server: &TranspileTestServer,
+            method: &str,
+            args: JsonValue,
+        ) -> InvokeMethodResponse {
+            let call = InvokeMethodRequest {
+                method_name: method.to_string(),
+                arguments: args.to_string(),
+                ..Default::default()
+            };
+            server
+                .invoke_method(Request::new(call))
+                .await
+                .unwrap()
+                .into_inner()
+        }
+
+        #[tokio::test]
+        async fn echo_round_trips_its_arguments() {
+            let server = server();
+            let args =
+                json!({"text": "héllo \"wasm\"", "n": [1, 2.5, null], "nested": {"ok": true}});
+            let response = call(&server, "wasm_echo", args.clone()).await;
+            assert!(response.success, "{}", response.error);
+            assert_eq!(
+                serde_json::from_str::<JsonValue>(&response.result).unwrap(),
+                args
+            );
+        }
+
+        #[tokio::test]
+        async fn a_trap_fails_the_call_but_not_the_server() {
+            let server = server();
+            let response = call(&server, "wasm_trap", json!({})).await;
+            assert!(!response.success);
+            assert_eq!(response.error_code(), ErrorCode::ExecutionError);
+            assert!(
+                response.error.starts_with("wasm trap: "),
+                "{}",
+                response.error
+            );
+
+            let response = call(&server, "wasm_echo", json!({"after": "trap"})).await;
+            assert!(response.success, "{}", response.error);
+            assert_eq!(response.result, r#"{"after":"trap"}"#);
+        }
+
+        #[tokio::test]
+        async fn calls_start_from_a_fresh_instance() {
+            let server = server();
+            for _ in 0..3 {
+                let response = call(&server, "wasm_calls", json!({})).await;
+                assert!(response.success, "{}", response.error);
+                assert_eq!(response.result, "1");
+            }
+        }
+    }
+}
+
+/// Register the functions of every module in `dir`, returning how many
+#[cfg(feature = "wasm")]
+pub fn load_dir(server: &TranspileTestServer, dir: &Path) -> Result<usize, Box<dyn Error>> {
+    guest::load_dir(server, dir)
+}
+
+/// WebAssembly support isn't in this build
+#[cfg(not(feature = "wasm"))]
+pub fn load_dir(_server: &TranspileTestServer, dir: &Path) -> Result<usize, Box<dyn Error>> {
+    Err(format!(
+        "Cannot load {}: the server was built without the `wasm` feature",
+        dir.display()
+    )
+    .into())
+}
@@ -0,0 +1,155 @@
+/*!
+Stopping the server with signals, as `test-runner` does when it launches one.
+
+Each test starts the built `test-server` on a free port, starts a
+`delayed_add` call, signals the server mid-call and checks how the call and
+the process end.
+*/
+
+#![cfg(unix)]
+
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+
+mod proto {
+    tonic::include_proto!("transpile_test");
+}
+
+use proto::transpile_test_service_client::TranspileTestServiceClient;
+use proto::{Encoding, InvokeMethodRequest, InvokeMethodResponse};
+
+/// Grace period the server is started with
+const GRACE: Duration = Duration::from_secs(3);
+
+/// A running server, killed if a test fails before it exits
+struct Server {
+    child: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start() -> Self {
+        // Ask the OS for a port nothing is listening on
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_test-server"))
+            .args(["--port", &port.to_string()])
+            .args(["--shutdown-grace-secs", &GRACE.as_secs().to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Self { child, port }
+    }
+
+    async fn connect(&mut self) -> TranspileTestServiceClient<Channel> {
+        let address = format!("http://127.0.0.1:{}", self.port);
+        let started = Instant::now();
+        loop {
+            if let Ok(client) = TranspileTestServiceClient::connect(address.clone()).await {
+                return client;
+            }
+            assert!(
+                self.child.try_wait().unwrap().is_none(),
+                "the server exited"
+            );
+            assert!(
+                started.elapsed() < Duration::from_secs(30),
+                "the server never listened"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    fn signal(&self, signal: libc::c_int) {
+        // SAFETY: kill has no memory-safety preconditions
+        let sent = unsafe { libc::kill(self.child.id() as libc::pid_t, signal) };
+        assert_eq!(sent, 0, "{}", std::io::Error::last_os_error());
+    }
+
+    /// Exit status, or `None` if the server is still running after `timeout`
+    async fn wait_exit(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return Some(status);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        None
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Start `delayed_add(2, 3)` sleeping `delay_ms`, and give it time to reach
+/// the server
+async fn slow_call(
+    client: &TranspileTestServiceClient<Channel>,
+    delay_ms: u64,
+) -> tokio::task::JoinHandle<Result<InvokeMethodResponse, tonic::Status>> {
+    let mut client = client.clone();
+    let request = InvokeMethodRequest {
+        context_id: String::new(),
+        method_name: "delayed_add".to_string(),
+        arguments: format!(r#"{{"a": 2, "b": 3, "delay_ms": {}}}"#, delay_ms),
+        timeout_ms: 0,
+        encoding: Encoding::Json as i32,
+        payload: Vec::new(),
+    };
+    let call = tokio::spawn(async move { Ok(client.invoke_method(request).await?.into_inner()) });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    call
+}
+
+#[tokio::test]
+async fn sigterm_lets_calls_in_flight_finish() {
+    let mut server = Server::start();
+    let client = server.connect().await;
+    let call = slow_call(&client, 1000).await;
+    let signalled = Instant::now();
+    server.signal(libc::SIGTERM);
+
+    let response = call.await.unwrap().unwrap();
+    assert!(response.success, "{}", response.error);
+    assert_eq!(response.result, "5");
+
+    let status = server.wait_exit(GRACE).await;
+    assert_eq!(status.and_then(|s| s.code()), Some(0));
+    assert!(signalled.elapsed() < GRACE);
+}
+
+#[tokio::test]
+async fn calls_outlasting_the_grace_period_are_dropped() {
+    let mut server = Server::start();
+    let client = server.connect().await;
+    let call = slow_call(&client, 60_000).await;
+    server.signal(libc::SIGTERM);
+
+    let status = server.wait_exit(GRACE + Duration::from_secs(2)).await;
+    assert_eq!(status.and_then(|s| s.code()), Some(0));
+    assert!(call.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn a_second_signal_exits_at_once() {
+    let mut server = Server::start();
+    let client = server.connect().await;
+    let call = slow_call(&client, 60_000).await;
+    server.signal(libc::SIGTERM);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    server.signal(libc::SIGINT);
+
+    let status = server.wait_exit(GRACE / 2).await;
+    assert_eq!(status.and_then(|s| s.code()), Some(1));
+    assert!(call.await.unwrap().is_err());
+}
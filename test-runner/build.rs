@@ -1,8 +1,8 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
-        .build_server(false)
+        .build_server(true)
         .build_client(true)
-        .compile(&["../proto/transpile_test.proto"], &["../proto"])?;
+        .compile_protos(&["../proto/transpile_test.proto"], &["../proto"])?;
 
     Ok(())
 }
@@ -0,0 +1,123 @@
+/*!
+`--batch-size N`: run consecutive simple tests in one round trip per server.
+
+For thousands of tiny tests the cost of a gRPC call dwarfs the method
+itself. With `--batch-size`, runs of up to N consecutive tests that make one
+stateless call, with nothing else to do around it, are sent to each server
+as a single `InvokeBatch`, and each test is then judged exactly as if it
+had been called on its own. A test is left out of batches if it is
+stateful, has steps, checks state, has a `timeout`, `perf`, `warmup`,
+`resources`, `setup` or `teardown`, streams its results, or would be served
+from the cache.
+
+A server answering `Unimplemented` doesn't support batching; the runner says
+so once and calls every test on its own from then on. A batch that fails
+for any other reason is rerun one test at a time, so retries apply as
+usual. A batched test's wall time is its share of the batch's.
+*/
+
+use crate::{rpc::Execution, TestCase, TestResult, TestRunner};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Whether `test` can share a batch with others
+pub fn batchable(test: &TestCase, runner: &TestRunner) -> bool {
+    let warmup = runner.options.warmup.or(test.warmup).unwrap_or(0);
+    !test.method.is_empty()
+        && test.steps.is_empty()
+        && !test.stream
+        && !test.stateful
+        && test.expected_state.is_none()
+        && test.timeout().is_none()
+        && test.perf.is_none()
+        && warmup == 0
+        && test.resources().is_empty()
+        && test.setup.is_empty()
+        && test.teardown.is_empty()
+}
+
+/// Run `tests` as one batch on every server, or one by one once a server
+/// turns out not to support batching (recorded in `unsupported`)
+pub async fn run(
+    runner: &mut TestRunner,
+    tests: &[TestCase],
+    unsupported: &AtomicBool,
+) -> Vec<TestResult> {
+    if !unsupported.load(Ordering::Relaxed) {
+        if let Some(results) = try_batch(runner, tests, unsupported).await {
+            return results;
+        }
+    }
+    let mut results = Vec::with_capacity(tests.len());
+    for test in tests {
+        results.push(runner.run_with_retries(test).await);
+    }
+    results
+}
+
+/// Results of `tests` from one batch per server, or None if a batch failed
+async fn try_batch(
+    runner: &mut TestRunner,
+    tests: &[TestCase],
+    unsupported: &AtomicBool,
+) -> Option<Vec<TestResult>> {
+    info!("Running batch of {}: {} ...", tests.len(), tests[0].name);
+    let started = Instant::now();
+    let args_json: Vec<String> = tests
+        .iter()
+        .map(|test| {
+            runner
+                .usage
+                .serialize(|| crate::canonical::to_json_string(&test.arguments))
+        })
+        .collect();
+    let calls: Vec<(&str, &str)> = tests
+        .iter()
+        .zip(&args_json)
+        .map(|(test, args)| (test.method.as_str(), args.as_str()))
+        .collect();
+
+    let mut per_target = Vec::with_capacity(runner.clients.len());
+    for target in 0..runner.clients.len() {
+        let _timer = runner.timings.start(&runner.names[target]);
+        let name = runner.names[target].clone();
+        match runner.server(target).invoke_batch(&calls).await {
+            Ok(executions) => per_target.push(executions.into_iter()),
+            Err(status) if status.code() == tonic::Code::Unimplemented => {
+                if !unsupported.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "{} server doesn't support batches; calling tests one by one",
+                        name
+                    );
+                }
+                return None;
+            }
+            Err(status) => {
+                warn!(
+                    "Batch failed on {} ({}); rerunning its tests one by one",
+                    name, status
+                );
+                return None;
+            }
+        }
+    }
+
+    let share = started.elapsed().as_micros() as u64 / tests.len() as u64;
+    let results = tests
+        .iter()
+        .map(|test| {
+            let mut executions: Vec<Execution> = per_target
+                .iter_mut()
+                .map(|executions| executions.next().expect("one execution per test"))
+                .collect();
+            let outcome = runner.judge(test, &mut executions);
+            let times = executions.iter().map(|e| e.time_us).collect();
+            let mut result = runner.test_result(test, started, executions, times, outcome);
+            result.wall_time_us = share;
+            debug!("{}: batched", test.name);
+            result
+        })
+        .collect();
+    Some(results)
+}
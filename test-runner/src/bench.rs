@@ -0,0 +1,500 @@
+/*!
+`bench`: time the tests of a suite on every server over many iterations.
+
+```bash
+test-runner bench --suite test-defs/counter.yaml --iterations 500 --context-mode reuse
+```
+
+Every selected single-call test is invoked `--iterations` times on each
+server. The round trip of each iteration is summarized as median, p90, min
+and max, next to the median `execution_time_us` the server reported.
+Results aren't compared; run the suite for that.
+
+A stateful test needs a context, and `--context-mode` decides where it
+comes from, which changes what is being measured:
+- `fresh`: every iteration creates a context and destroys it again, and
+  both calls count toward its time
+- `reuse`: one context per server serves every iteration, put back to its
+  initial state with `ResetContext` between iterations, outside the timing
+- `pooled`: `--pool-size` contexts per server take the iterations in
+  turn, round-robin, and run them concurrently, so the times include
+  contention between contexts
+
+The mode heads the printed results and is recorded in `--output-json`.
+Tests without a context run the same in every mode.
+*/
+
+use crate::targets::Targets;
+use crate::transpile_test::{
+    CreateContextRequest, DestroyContextRequest, Encoding, InvokeMethodRequest, ResetContextRequest,
+};
+use crate::{canonical, perf, rpc, timing, transport, TestCase};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::*;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::warn;
+
+/// Where each iteration of a stateful test gets its context
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextMode {
+    /// A new context per iteration, created and destroyed within its timing
+    #[default]
+    Fresh,
+    /// One context per server, reset between iterations
+    Reuse,
+    /// --pool-size contexts per server, used round-robin and concurrently
+    Pooled,
+}
+
+impl ContextMode {
+    fn describe(self, pool_size: usize) -> String {
+        match self {
+            ContextMode::Fresh => "fresh (a context created and destroyed per iteration)".into(),
+            ContextMode::Reuse => "reuse (one context per server, reset between iterations)".into(),
+            ContextMode::Pooled => format!(
+                "pooled ({} contexts per server, used concurrently and reset between iterations)",
+                pool_size
+            ),
+        }
+    }
+}
+
+pub struct BenchOptions {
+    pub iterations: u32,
+    pub context_mode: ContextMode,
+    pub pool_size: usize,
+}
+
+/// Timings of every benched test
+#[derive(Serialize)]
+pub struct BenchReport {
+    pub context_mode: ContextMode,
+    /// Contexts per server in `pooled` mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool_size: Option<usize>,
+    pub iterations: u32,
+    pub tests: Vec<TestBench>,
+}
+
+#[derive(Serialize)]
+pub struct TestBench {
+    pub name: String,
+    pub method: String,
+    pub stateful: bool,
+    pub targets: Vec<TargetBench>,
+}
+
+/// One server's timings for a test, in microseconds
+#[derive(Default, Serialize)]
+pub struct TargetBench {
+    pub target: String,
+    /// Iterations that succeeded, which the times cover
+    pub succeeded: u32,
+    pub failed: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_error: Option<String>,
+    pub median_us: Option<i64>,
+    pub p90_us: Option<i64>,
+    pub min_us: Option<i64>,
+    pub max_us: Option<i64>,
+    /// Median of the `execution_time_us` the server reported
+    pub server_median_us: Option<i64>,
+}
+
+/// One iteration: its round trip and what the server said the call took
+type Sample = Result<(i64, Option<i64>), String>;
+
+/// Bench the single-call tests among `tests` on every server of `servers`
+pub async fn run(
+    servers: &Targets,
+    tests: &[TestCase],
+    options: &BenchOptions,
+) -> Result<BenchReport> {
+    let mut clients = Vec::new();
+    for target in servers.iter() {
+        let endpoint = transport::Target::new(&target.name, &target.config)?;
+        let client = endpoint
+            .connect()
+            .await
+            .with_context(|| format!("Cannot connect to {}", endpoint.url()))?;
+        clients.push((target.name.clone(), client));
+    }
+
+    let mut report = BenchReport {
+        context_mode: options.context_mode,
+        pool_size: (options.context_mode == ContextMode::Pooled).then_some(options.pool_size),
+        iterations: options.iterations,
+        tests: Vec::new(),
+    };
+    for test in tests {
+        if !test.steps.is_empty() || test.stream {
+            warn!("{}: only single-call tests are benched", test.name);
+            continue;
+        }
+        let mut bench = TestBench {
+            name: test.name.clone(),
+            method: test.method.clone(),
+            stateful: test.stateful,
+            targets: Vec::new(),
+        };
+        for (name, client) in &clients {
+            let samples = bench_target(client.clone(), test, options).await;
+            bench.targets.push(summarize(name, samples));
+        }
+        report.tests.push(bench);
+    }
+    Ok(report)
+}
+
+/// Every iteration of `test` on one server
+async fn bench_target(
+    mut client: rpc::Client,
+    test: &TestCase,
+    options: &BenchOptions,
+) -> Vec<Sample> {
+    let call = Call::new(test);
+    let iterations = options.iterations;
+    if !test.stateful {
+        let mut samples = Vec::new();
+        for _ in 0..iterations {
+            samples.push(call.invoke(&mut client, "").await);
+        }
+        return samples;
+    }
+
+    match options.context_mode {
+        ContextMode::Fresh => {
+            let mut samples = Vec::new();
+            for _ in 0..iterations {
+                let started = Instant::now();
+                let sample = match call.create(&mut client).await {
+                    Ok(context_id) => {
+                        let sample = call.invoke(&mut client, &context_id).await;
+                        destroy(&mut client, &context_id).await;
+                        sample.map(|(_, server_us)| (elapsed_us(started), server_us))
+                    }
+                    Err(e) => Err(e),
+                };
+                samples.push(sample);
+            }
+            samples
+        }
+        ContextMode::Reuse => contexts_in_turn(client, call, 1, iterations).await,
+        ContextMode::Pooled => {
+            let pool_size = options.pool_size.clamp(1, iterations.max(1) as usize);
+            contexts_in_turn(client, call, pool_size, iterations).await
+        }
+    }
+}
+
+/// Spread `iterations` round-robin over `pool_size` contexts running
+/// concurrently, each reset between its iterations
+async fn contexts_in_turn(
+    mut client: rpc::Client,
+    call: Call,
+    pool_size: usize,
+    iterations: u32,
+) -> Vec<Sample> {
+    let mut contexts = Vec::with_capacity(pool_size);
+    for _ in 0..pool_size {
+        match call.create(&mut client).await {
+            Ok(context_id) => contexts.push(context_id),
+            Err(e) => {
+                for context_id in &contexts {
+                    destroy(&mut client, context_id).await;
+                }
+                return vec![Err(e); iterations as usize];
+            }
+        }
+    }
+
+    let call = std::sync::Arc::new(call);
+    let mut slots = JoinSet::new();
+    for (slot, context_id) in contexts.iter().cloned().enumerate() {
+        let turns = (slot..iterations as usize).step_by(pool_size).count();
+        let (mut client, call) = (client.clone(), call.clone());
+        slots.spawn(async move {
+            let mut samples = Vec::with_capacity(turns);
+            for turn in 0..turns {
+                if turn > 0 {
+                    if let Err(e) = reset(&mut client, &context_id).await {
+                        // The context can't be trusted for the rest of its turns
+                        samples.extend(std::iter::repeat_n(Err(e), turns - turn));
+                        break;
+                    }
+                }
+                samples.push(call.invoke(&mut client, &context_id).await);
+            }
+            samples
+        });
+    }
+    let mut samples = Vec::with_capacity(iterations as usize);
+    while let Some(joined) = slots.join_next().await {
+        samples.extend(joined.unwrap_or_else(|e| vec![Err(format!("bench task failed: {}", e))]));
+    }
+    for context_id in &contexts {
+        destroy(&mut client, context_id).await;
+    }
+    samples
+}
+
+/// What every iteration of a test sends
+struct Call {
+    method: String,
+    arguments: String,
+    initial_state: String,
+    timeout_ms: u64,
+}
+
+impl Call {
+    fn new(test: &TestCase) -> Self {
+        Self {
+            method: test.method.clone(),
+            arguments: canonical::to_json_string(&test.arguments),
+            initial_state: test.initial_state.clone().unwrap_or_default(),
+            timeout_ms: test.timeout_ms.unwrap_or_default(),
+        }
+    }
+
+    async fn create(&self, client: &mut rpc::Client) -> Result<String, String> {
+        let request = CreateContextRequest {
+            initial_state: self.initial_state.clone(),
+        };
+        match client.create_context(request).await {
+            Ok(resp) if resp.get_ref().success => Ok(resp.into_inner().context_id),
+            Ok(resp) => Err(format!("CreateContext failed: {}", resp.into_inner().error)),
+            Err(status) => Err(format!("CreateContext failed: {}", status)),
+        }
+    }
+
+    async fn invoke(&self, client: &mut rpc::Client, context_id: &str) -> Sample {
+        let request = InvokeMethodRequest {
+            context_id: context_id.to_string(),
+            method_name: self.method.clone(),
+            arguments: self.arguments.clone(),
+            timeout_ms: self.timeout_ms,
+            encoding: Encoding::Json as i32,
+            payload: Vec::new(),
+        };
+        let started = Instant::now();
+        let resp = client
+            .invoke_method(request)
+            .await
+            .map_err(|status| status.to_string())?
+            .into_inner();
+        let round_trip = elapsed_us(started);
+        if !resp.success {
+            return Err(resp.error);
+        }
+        Ok((round_trip, resp.metadata.map(|m| m.execution_time_us)))
+    }
+}
+
+async fn reset(client: &mut rpc::Client, context_id: &str) -> Result<(), String> {
+    let request = ResetContextRequest {
+        context_id: context_id.to_string(),
+    };
+    match client.reset_context(request).await {
+        Ok(resp) if resp.get_ref().success => Ok(()),
+        Ok(resp) => Err(format!("ResetContext failed: {}", resp.into_inner().error)),
+        Err(status) => Err(format!("ResetContext failed: {}", status)),
+    }
+}
+
+async fn destroy(client: &mut rpc::Client, context_id: &str) {
+    let request = DestroyContextRequest {
+        context_id: context_id.to_string(),
+    };
+    if let Err(status) = client.destroy_context(request).await {
+        warn!("Failed to destroy context {}: {}", context_id, status);
+    }
+}
+
+fn elapsed_us(started: Instant) -> i64 {
+    started.elapsed().as_micros() as i64
+}
+
+fn summarize(target: &str, samples: Vec<Sample>) -> TargetBench {
+    let mut bench = TargetBench {
+        target: target.to_string(),
+        ..Default::default()
+    };
+    let mut round_trips = Vec::new();
+    let mut server_times = Vec::new();
+    for sample in samples {
+        match sample {
+            Ok((round_trip, server_us)) => {
+                round_trips.push(round_trip);
+                server_times.extend(server_us);
+            }
+            Err(error) => {
+                bench.failed += 1;
+                bench.first_error.get_or_insert(error);
+            }
+        }
+    }
+    bench.succeeded = round_trips.len() as u32;
+    bench.median_us = perf::median(&mut round_trips);
+    bench.p90_us = percentile(&round_trips, 90);
+    bench.min_us = round_trips.first().copied();
+    bench.max_us = round_trips.last().copied();
+    bench.server_median_us = perf::median(&mut server_times);
+    bench
+}
+
+/// The `p`th percentile of sorted `times`, by nearest rank
+fn percentile(times: &[i64], p: usize) -> Option<i64> {
+    let rank = (times.len() * p).div_ceil(100);
+    times.get(rank.max(1) - 1).copied()
+}
+
+fn format_us(us: Option<i64>) -> String {
+    us.map(|us| timing::format_duration(Duration::from_micros(us.max(0) as u64)))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn print(report: &BenchReport) {
+    println!(
+        "{}: {} iteration(s) per server, context mode {}",
+        "Bench".bright_blue().bold(),
+        report.iterations,
+        report
+            .context_mode
+            .describe(report.pool_size.unwrap_or_default())
+    );
+    for test in &report.tests {
+        let mode = if test.stateful { "" } else { " (no context)" };
+        println!("\n{}{}", test.name.bright_white().bold(), mode.dimmed());
+        let width = test
+            .targets
+            .iter()
+            .map(|t| t.target.len())
+            .max()
+            .unwrap_or(0);
+        for target in &test.targets {
+            print!(
+                "  {:<width$}  median {}  p90 {}  min {}  max {}  server {}",
+                target.target,
+                format_us(target.median_us).cyan(),
+                format_us(target.p90_us),
+                format_us(target.min_us),
+                format_us(target.max_us),
+                format_us(target.server_median_us),
+                width = width
+            );
+            match &target.first_error {
+                Some(error) => {
+                    println!("  {}", format!("{} failed: {}", target.failed, error).red())
+                }
+                None => println!(),
+            }
+        }
+    }
+}
+
+impl BenchReport {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let value = serde_json::to_value(self).context("Failed to serialize bench results")?;
+        std::fs::write(path, canonical::to_json_string_pretty(&value) + "\n")
+            .with_context(|| format!("Failed to write bench results {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{servers, Calls, Mock};
+
+    fn counter_test(stateful: bool) -> TestCase {
+        serde_yaml::from_str(&format!(
+            "{{name: count, method: counter_increment, stateful: {}}}",
+            stateful
+        ))
+        .unwrap()
+    }
+
+    async fn bench(mode: ContextMode, stateful: bool) -> (BenchReport, [Mock; 2]) {
+        let mocks = [Mock::default(), Mock::default()];
+        let servers = servers(&[&mocks[0], &mocks[1]]).await;
+        let options = BenchOptions {
+            iterations: 10,
+            context_mode: mode,
+            pool_size: 3,
+        };
+        let report = run(&servers, &[counter_test(stateful)], &options)
+            .await
+            .unwrap();
+        (report, mocks)
+    }
+
+    #[tokio::test]
+    async fn reuse_creates_one_context_per_server() {
+        let (report, mocks) = bench(ContextMode::Reuse, true).await;
+        for mock in &mocks {
+            assert_eq!(Calls::get(&mock.calls.create_context), 1);
+            assert_eq!(Calls::get(&mock.calls.reset_context), 9);
+            assert_eq!(Calls::get(&mock.calls.invoke_method), 10);
+            assert_eq!(Calls::get(&mock.calls.destroy_context), 1);
+        }
+        let targets = &report.tests[0].targets;
+        assert!(targets.iter().all(|t| t.succeeded == 10 && t.failed == 0));
+        assert_eq!(targets[0].server_median_us, Some(1));
+    }
+
+    #[tokio::test]
+    async fn fresh_creates_a_context_per_iteration() {
+        let (_, mocks) = bench(ContextMode::Fresh, true).await;
+        for mock in &mocks {
+            assert_eq!(Calls::get(&mock.calls.create_context), 10);
+            assert_eq!(Calls::get(&mock.calls.reset_context), 0);
+            assert_eq!(Calls::get(&mock.calls.destroy_context), 10);
+        }
+    }
+
+    #[tokio::test]
+    async fn pooled_spreads_iterations_over_the_pool() {
+        let (report, mocks) = bench(ContextMode::Pooled, true).await;
+        for mock in &mocks {
+            assert_eq!(Calls::get(&mock.calls.create_context), 3);
+            // Slots take 4, 3 and 3 iterations, resetting before all but the first
+            assert_eq!(Calls::get(&mock.calls.reset_context), 7);
+            assert_eq!(Calls::get(&mock.calls.invoke_method), 10);
+            assert_eq!(Calls::get(&mock.calls.destroy_context), 3);
+        }
+        assert_eq!(report.tests[0].targets[0].succeeded, 10);
+    }
+
+    #[tokio::test]
+    async fn stateless_tests_create_no_contexts() {
+        let (_, mocks) = bench(ContextMode::Reuse, false).await;
+        assert_eq!(Calls::get(&mocks[0].calls.create_context), 0);
+        assert_eq!(Calls::get(&mocks[0].calls.invoke_method), 10);
+    }
+
+    #[tokio::test]
+    async fn report_records_the_mode() {
+        let (report, _) = bench(ContextMode::Pooled, true).await;
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["context_mode"], "pooled");
+        assert_eq!(value["pool_size"], 3);
+
+        let (report, _) = bench(ContextMode::Reuse, true).await;
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["context_mode"], "reuse");
+        assert!(value.get("pool_size").is_none());
+    }
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let times: Vec<i64> = (1..=10).collect();
+        assert_eq!(percentile(&times, 90), Some(9));
+        assert_eq!(percentile(&times, 100), Some(10));
+        assert_eq!(percentile(&[5], 90), Some(5));
+        assert_eq!(percentile(&[], 90), None);
+    }
+}
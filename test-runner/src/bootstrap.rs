@@ -0,0 +1,413 @@
+/*!
+`bootstrap`: check that a fresh checkout can build, start and test a server.
+
+Builds the Rust server (or takes `--server-bin`), starts it on a free port,
+checks it was built from the same protocol revision as the runner, runs a
+small built-in suite against it, and stops it again with SIGTERM, checking
+a call in flight then still completes and the server exits cleanly within
+its grace period. Every check prints a ✓ or ✗ line, and a failed one says
+how to fix it. Checks depend on the ones before them, so the first failure
+ends the run.
+*/
+
+use crate::connections::{Connections, CONNECT_TIMEOUT};
+use crate::doctor::{fail, pass};
+use crate::launch::{self, ServerProcess};
+use crate::targets::{Target, Targets};
+use crate::transpile_test::{Encoding, GetServerInfoRequest, InvokeMethodRequest};
+use crate::{
+    rpc, run_tests, transport, wait_for_registration, RunnerOptions, TestCase, TestRunner,
+};
+use crate::{ServerConfig, Timings, Usage};
+use colored::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use transpile_common::json_depth::DEFAULT_MAX_DEPTH;
+use transpile_common::protocol::PROTOCOL_VERSION;
+
+/// Lines of server or build output shown with a failure
+const OUTPUT_TAIL: usize = 10;
+
+/// `--shutdown-grace-secs` of the started server
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Run against the started server: a reserved built-in plus two of the
+/// example math functions, so both the transport and registration are
+/// covered, and a stateful test whose `setup` prepares its context
+const SMOKE_TESTS: &str = r#"
+- name: echo_round_trip
+  method: __echo
+  arguments: {text: "héllo \"wörld\" 中", values: [1, 2.5, null, true]}
+  expected: {text: "héllo \"wörld\" 中", values: [1, 2.5, null, true]}
+- name: add
+  method: add
+  arguments: {a: 2, b: 40}
+  expected: 42
+- name: multiply
+  method: multiply
+  arguments: {a: -6, b: 7}
+  expected: -42
+- name: counter_after_setup
+  method: counter_get
+  stateful: true
+  initial_state: '{"counter": 0}'
+  setup:
+    - method: counter_increment
+    - method: counter_increment
+  expected: 2
+"#;
+
+/// Run every check, printing as it goes; true if all passed
+pub async fn run(server_bin: Option<PathBuf>) -> bool {
+    println!("{}", "Bootstrap".bright_blue().bold());
+
+    let binary = match server_bin {
+        Some(path) => given_binary(path),
+        None => build_server(),
+    };
+    let Some(binary) = binary else {
+        return finish(false);
+    };
+
+    let port = match launch::free_port() {
+        Ok(port) => port,
+        Err(e) => {
+            fail(&format!("cannot reserve a local port: {}", e));
+            hint("check that nothing is blocking loopback networking for this user");
+            return finish(false);
+        }
+    };
+
+    let Some(server) = start_server(&binary, port).await else {
+        return finish(false);
+    };
+    let (mut process, mut client) = server;
+
+    let healthy = check_protocol(&mut client).await
+        && check_registration(&mut client, &process).await
+        && run_smoke_suite(port).await;
+    #[cfg(unix)]
+    let healthy = healthy && check_shutdown(&mut process, &client).await;
+
+    drop(client);
+    let log = process.log_path().to_path_buf();
+    drop(process);
+    pass(&format!("stopped server on port {}", port));
+    if healthy {
+        let _ = std::fs::remove_file(&log);
+    } else {
+        println!("      server log kept at {}", log.display());
+    }
+    finish(healthy)
+}
+
+fn given_binary(path: PathBuf) -> Option<PathBuf> {
+    if path.is_file() {
+        pass(&format!("using server binary {}", path.display()));
+        Some(path)
+    } else {
+        fail(&format!("server binary {} does not exist", path.display()));
+        hint("build it with `cargo build` in rust/, or leave out --server-bin to let bootstrap build it");
+        None
+    }
+}
+
+/// Build the Rust server of this checkout and return its binary
+fn build_server() -> Option<PathBuf> {
+    let checkout = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap_or(Path::new("."));
+    let rust_dir = checkout.join("rust");
+    let manifest = rust_dir.join("Cargo.toml");
+    if !manifest.is_file() {
+        fail(&format!("no Rust server crate at {}", rust_dir.display()));
+        hint("run bootstrap from a full checkout, or pass --server-bin");
+        return None;
+    }
+
+    let output = Command::new("cargo")
+        .args(["build", "--bin", "test-server", "--manifest-path"])
+        .arg(&manifest)
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            fail(&format!("cannot run cargo: {}", e));
+            hint("install a Rust toolchain from https://rustup.rs and make sure cargo is on PATH");
+            return None;
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        fail("building the Rust server failed");
+        print_tail(&stderr);
+        if stderr.contains("protoc") {
+            hint("install protoc (`apt install protobuf-compiler` or `brew install protobuf`), or set PROTOC to its path");
+        } else {
+            hint(&format!(
+                "fix the errors above, then check `cargo build` succeeds in {}",
+                rust_dir.display()
+            ));
+        }
+        return None;
+    }
+
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| rust_dir.join("target"));
+    let binary = target_dir
+        .join("debug")
+        .join(format!("test-server{}", std::env::consts::EXE_SUFFIX));
+    if binary.is_file() {
+        pass(&format!("built Rust server {}", binary.display()));
+        Some(binary)
+    } else {
+        fail(&format!(
+            "build succeeded but {} is missing",
+            binary.display()
+        ));
+        hint("pass the server binary with --server-bin");
+        None
+    }
+}
+
+async fn start_server(binary: &Path, port: u16) -> Option<(ServerProcess, rpc::Client)> {
+    let log = std::env::temp_dir().join(format!("transpile-bootstrap-{}.log", std::process::id()));
+    let args = [
+        "--port".to_string(),
+        port.to_string(),
+        "--shutdown-grace-secs".to_string(),
+        SHUTDOWN_GRACE.as_secs().to_string(),
+    ];
+    let mut process = match ServerProcess::spawn(binary, &args, log) {
+        Ok(process) => process,
+        Err(e) => {
+            fail(&format!("cannot start {}: {}", binary.display(), e));
+            hint("check the binary is executable and built for this platform");
+            return None;
+        }
+    };
+
+    let target = transport::Target::new("Rust", &ServerConfig::local(port))
+        .expect("a local plaintext server needs no certificates");
+    match process.wait_ready(&target, launch::STARTUP_TIMEOUT).await {
+        Ok(client) => {
+            pass(&format!("server listening on port {}", port));
+            Some((process, client))
+        }
+        Err(e) => {
+            fail(&e);
+            let tail = process.log_tail(OUTPUT_TAIL);
+            print_tail(&tail);
+            if tail.contains("in use") {
+                hint("another process took the port; run bootstrap again");
+            } else {
+                hint(&format!(
+                    "see the full server log at {}",
+                    process.log_path().display()
+                ));
+            }
+            None
+        }
+    }
+}
+
+/// The server must be built from the proto this runner was built from
+async fn check_protocol(client: &mut rpc::Client) -> bool {
+    let info = match client.get_server_info(GetServerInfoRequest {}).await {
+        Ok(resp) => resp.into_inner(),
+        Err(e) => {
+            fail(&format!("GetServerInfo failed: {}", e));
+            hint("the server binary may predate GetServerInfo; rebuild it with `cargo build` in rust/");
+            return false;
+        }
+    };
+
+    let server = info.protocol_version;
+    if server == PROTOCOL_VERSION {
+        pass(&format!(
+            "protocol version {} matches ({} {}, {})",
+            server, info.runtime, info.version, info.git_hash
+        ));
+        return true;
+    }
+    fail(&format!(
+        "server speaks protocol version {}, runner speaks {}",
+        server, PROTOCOL_VERSION
+    ));
+    if server < PROTOCOL_VERSION {
+        hint("the server binary is older than proto/transpile_test.proto; rebuild it with `cargo build` in rust/");
+    } else {
+        hint("the runner is older than the server; rebuild it with `cargo build` in test-runner/");
+    }
+    false
+}
+
+async fn check_registration(client: &mut rpc::Client, process: &ServerProcess) -> bool {
+    match wait_for_registration(client, "Rust").await {
+        Ok(()) => {
+            pass("all registration sources finished");
+            true
+        }
+        Err(e) => {
+            fail(&format!("{:#}", e));
+            print_tail(&process.log_tail(OUTPUT_TAIL));
+            hint("a registration source is stuck or failed; see the server log above");
+            false
+        }
+    }
+}
+
+/// Run `SMOKE_TESTS` through the normal runner, with the started server as
+/// the only target so results are checked against `expected`
+async fn run_smoke_suite(port: u16) -> bool {
+    let tests: Vec<TestCase> =
+        serde_yaml::from_str(SMOKE_TESTS).expect("built-in smoke suite is valid YAML");
+    let servers = Targets::new(vec![Target {
+        name: "rust".to_string(),
+        config: ServerConfig::local(port),
+    }]);
+    let options = RunnerOptions {
+        show_types: false,
+        max_json_depth: DEFAULT_MAX_DEPTH,
+        retries: None,
+        warmup: None,
+        batch_size: 1,
+        skip_perf: false,
+        strict_xfail: false,
+        encoding: None,
+        setup: Vec::new(),
+        teardown: Vec::new(),
+    };
+    let runner = match TestRunner::new(
+        &servers,
+        &mut Connections::new(CONNECT_TIMEOUT),
+        Timings::new(),
+        Usage::new(servers.names()),
+        options,
+    )
+    .await
+    {
+        Ok(runner) => runner,
+        Err(e) => {
+            fail(&format!("smoke suite could not connect: {:#}", e));
+            hint("the server stopped accepting connections; see its log");
+            return false;
+        }
+    };
+
+    let results = run_tests(&runner, &tests, None, 1).await;
+    let mut passed = true;
+    for result in &results {
+        if result.passed {
+            pass(&format!("smoke test {}", result.name));
+        } else {
+            passed = false;
+            let reason = result.error_message.as_deref().unwrap_or("results differ");
+            fail(&format!("smoke test {}: {}", result.name, reason));
+        }
+    }
+    if !passed {
+        hint("the server answers but gets built-in cases wrong; check rust/src/examples.rs and rust/src/builtins.rs are unmodified");
+    }
+    passed
+}
+
+/// A JSON-encoded call of `method`
+fn json_call(context_id: &str, method: &str, arguments: &str) -> InvokeMethodRequest {
+    InvokeMethodRequest {
+        context_id: context_id.to_string(),
+        method_name: method.to_string(),
+        arguments: arguments.to_string(),
+        timeout_ms: 0,
+        encoding: Encoding::Json as i32,
+        payload: Vec::new(),
+    }
+}
+
+/// Send SIGTERM while a call is running: the call must still succeed and the
+/// server exit 0 within its grace period
+#[cfg(unix)]
+async fn check_shutdown(process: &mut ServerProcess, client: &rpc::Client) -> bool {
+    let mut caller = client.clone();
+    let request = json_call("", "delayed_add", r#"{"a": 2, "b": 3, "delay_ms": 500}"#);
+    let call = tokio::spawn(async move { caller.invoke_method(request).await });
+    // Let the call reach the server before the signal does
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    if let Err(e) = process.terminate() {
+        fail(&format!("cannot send SIGTERM to the server: {}", e));
+        return false;
+    }
+
+    let completed = match call.await.expect("the call task doesn't panic") {
+        Ok(resp) if resp.get_ref().success && resp.get_ref().result == "5" => {
+            pass("call in flight at SIGTERM completed");
+            true
+        }
+        Ok(resp) => {
+            let resp = resp.into_inner();
+            fail(&format!(
+                "call in flight at SIGTERM returned {:?} (error: {:?})",
+                resp.result, resp.error
+            ));
+            false
+        }
+        Err(status) => {
+            fail(&format!("call in flight at SIGTERM failed: {}", status));
+            false
+        }
+    };
+
+    let exited = match process
+        .wait_exit(SHUTDOWN_GRACE + Duration::from_secs(2))
+        .await
+    {
+        Some(status) if status.success() => {
+            pass("server exited cleanly after SIGTERM");
+            true
+        }
+        Some(status) => {
+            fail(&format!("server exited ({}) after SIGTERM", status));
+            false
+        }
+        None => {
+            fail(&format!(
+                "server still running {}s after SIGTERM",
+                SHUTDOWN_GRACE.as_secs() + 2
+            ));
+            false
+        }
+    };
+    if !(completed && exited) {
+        print_tail(&process.log_tail(OUTPUT_TAIL));
+        hint("the server doesn't drain calls on SIGTERM; check the shutdown handling in rust/src/shutdown.rs");
+    }
+    completed && exited
+}
+
+fn finish(healthy: bool) -> bool {
+    if healthy {
+        println!(
+            "\n{}",
+            "Bootstrap complete: ready to run suites"
+                .bright_green()
+                .bold()
+        );
+    } else {
+        println!("\n{}", "Bootstrap failed".bright_red().bold());
+    }
+    healthy
+}
+
+/// The fix for the failure just printed
+fn hint(message: &str) {
+    println!("      {} {}", "→".yellow(), message);
+}
+
+fn print_tail(output: &str) {
+    let lines: Vec<&str> = output.lines().collect();
+    for line in &lines[lines.len().saturating_sub(OUTPUT_TAIL)..] {
+        println!("      {}", line.dimmed());
+    }
+}
@@ -0,0 +1,232 @@
+/*!
+On-disk cache of test results.
+
+A result is stored under a key derived from the fully-resolved test
+definition, the suite's encoding and hooks, and the identity of every
+server it ran against, so rebuilding a server (new version or git hash),
+changing the methods it registers or their signatures, or editing a test
+or the suite settings it runs with invalidates exactly the affected
+entries. Entries are plain canonical JSON files named by key.
+
+Only passes are stored: a failure is always run again, since it is what
+someone is working on. An entry that can't be read is ignored with a
+warning, and `--cache-clear` empties the directory before the run.
+*/
+
+use crate::canonical;
+use crate::{TestCase, TestResult};
+use anyhow::{Context, Result};
+use serde_json::{json, Value as JsonValue};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+pub struct ResultCache {
+    dir: PathBuf,
+    server_identity: JsonValue,
+    /// Suite settings the results depend on; empty for most suites
+    suite: JsonValue,
+}
+
+impl ResultCache {
+    /// Open (creating if needed) a cache directory for the given servers
+    /// and suite settings
+    pub fn open(dir: PathBuf, server_identity: JsonValue, suite: JsonValue) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            server_identity,
+            suite,
+        })
+    }
+
+    /// Cache key for a test against the current servers
+    pub fn key(&self, test: &TestCase) -> String {
+        let mut material = json!({
+            "test": serde_json::to_value(test).unwrap_or(JsonValue::Null),
+            "servers": self.server_identity,
+        });
+        // Left out when empty so suites without settings keep their keys
+        if self.suite.as_object().is_some_and(|s| !s.is_empty()) {
+            material["suite"] = self.suite.clone();
+        }
+        fingerprint(&material)
+    }
+
+    /// The passing result stored under `key`, if any
+    pub fn load(&self, key: &str) -> Option<TestResult> {
+        let path = self.entry_path(key);
+        let content = std::fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<TestResult>(&content) {
+            // Written before failures stopped being stored
+            Ok(result) if !result.passed => None,
+            Ok(mut result) => {
+                debug!("Cache hit: {}", path.display());
+                result.cached = true;
+                Some(result)
+            }
+            Err(e) => {
+                warn!("Ignoring unreadable cache entry {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Store `result` under `key` if it passed
+    pub fn store(&self, key: &str, result: &TestResult) {
+        if !result.passed {
+            return;
+        }
+        let path = self.entry_path(key);
+        let content = match serde_json::to_value(result) {
+            Ok(value) => canonical::to_json_string(&value),
+            Err(e) => {
+                warn!("Failed to serialize result for {}: {}", result.name, e);
+                return;
+            }
+        };
+        if let Err(e) = std::fs::write(&path, content) {
+            warn!("Failed to write cache entry {}: {}", path.display(), e);
+        }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+/// Delete every entry in `dir`, returning how many there were
+pub fn clear(dir: &Path) -> Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read cache {}", dir.display())),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Failed to read cache {}", dir.display()))?
+            .path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to delete {}", path.display()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Hex FNV-1a hash of `value`'s canonical JSON
+pub fn fingerprint(value: &JsonValue) -> String {
+    format!(
+        "{:016x}",
+        fnv1a_64(canonical::to_json_string(value).as_bytes())
+    )
+}
+
+/// FNV-1a: stable across platforms and Rust versions, unlike `DefaultHasher`
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(version: &str) -> JsonValue {
+        json!({
+            "python": {"version": "1.0.0", "git_hash": "abc", "methods": "0011"},
+            "rust": {"version": version, "git_hash": "def", "methods": "2233"},
+        })
+    }
+
+    fn test_case(yaml: &str) -> TestCase {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn add(a: i64) -> TestCase {
+        test_case(&format!(
+            "{{name: add, method: add, arguments: {{a: {}, b: 2}}}}",
+            a
+        ))
+    }
+
+    fn passed(test: &TestCase) -> TestResult {
+        serde_json::from_value(json!({"name": test.name, "passed": true, "targets": []})).unwrap()
+    }
+
+    /// A cache in `dir` holding a pass of `add(1)`, keyed against version 1.0.0
+    fn filled(dir: &Path) -> ResultCache {
+        let cache = ResultCache::open(dir.into(), identity("1.0.0"), json!({})).unwrap();
+        cache.store(&cache.key(&add(1)), &passed(&add(1)));
+        cache
+    }
+
+    #[test]
+    fn unchanged_test_and_servers_hit() {
+        let dir = tempfile::tempdir().unwrap();
+        filled(dir.path());
+        let cache = ResultCache::open(dir.path().into(), identity("1.0.0"), json!({})).unwrap();
+        let result = cache.load(&cache.key(&add(1))).unwrap();
+        assert!(result.cached);
+    }
+
+    #[test]
+    fn changed_server_build_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        filled(dir.path());
+        let rebuilt = ResultCache::open(dir.path().into(), identity("1.0.1"), json!({})).unwrap();
+        assert!(rebuilt.load(&rebuilt.key(&add(1))).is_none());
+
+        let mut methods = identity("1.0.0");
+        methods["rust"]["methods"] = json!("4455");
+        let changed = ResultCache::open(dir.path().into(), methods, json!({})).unwrap();
+        assert!(changed.load(&changed.key(&add(1))).is_none());
+    }
+
+    #[test]
+    fn changed_arguments_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = filled(dir.path());
+        assert!(cache.load(&cache.key(&add(2))).is_none());
+    }
+
+    #[test]
+    fn changed_suite_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = filled(dir.path());
+        let edited = test_case("{name: add, method: add, arguments: {a: 1, b: 2}, expected: 3}");
+        assert!(cache.load(&cache.key(&edited)).is_none());
+
+        let hooks = json!({"setup": [{"method": "reset_cache", "arguments": {}}]});
+        let hooked = ResultCache::open(dir.path().into(), identity("1.0.0"), hooks).unwrap();
+        assert!(hooked.load(&hooked.key(&add(1))).is_none());
+        let encoded = json!({"encoding": "msgpack"});
+        let encoded = ResultCache::open(dir.path().into(), identity("1.0.0"), encoded).unwrap();
+        assert!(encoded.load(&encoded.key(&add(1))).is_none());
+    }
+
+    #[test]
+    fn failures_are_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResultCache::open(dir.path().into(), identity("1.0.0"), json!({})).unwrap();
+        let mut failed = passed(&add(1));
+        failed.passed = false;
+        cache.store(&cache.key(&add(1)), &failed);
+        assert!(cache.load(&cache.key(&add(1))).is_none());
+        assert_eq!(clear(dir.path()).unwrap(), 0);
+    }
+
+    #[test]
+    fn clear_removes_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = filled(dir.path());
+        assert_eq!(clear(dir.path()).unwrap(), 1);
+        assert!(cache.load(&cache.key(&add(1))).is_none());
+    }
+}
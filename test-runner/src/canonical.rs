@@ -0,0 +1,371 @@
+/*!
+Canonical JSON and YAML serialization.
+
+Everything the runner writes or hashes goes through this module so that the
+same value always produces byte-identical output:
+- Object keys are sorted, regardless of how the map was built
+- Floats use the shortest representation that round-trips
+- Arrays keep their order
+
+YAML is written in block style, two spaces per level, with empty
+collections and scalars inline; multi-line strings become literal blocks.
+*/
+
+use serde_json::Value as JsonValue;
+use std::fmt::Write;
+
+/// Render a value as compact canonical JSON
+pub fn to_json_string(value: &JsonValue) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+/// Render a value as canonical JSON indented two spaces per level
+pub fn to_json_string_pretty(value: &JsonValue) -> String {
+    let mut out = String::new();
+    write_pretty(&mut out, value, 0);
+    out
+}
+
+/// Render a value as a canonical block-style YAML document
+pub fn to_yaml_string(value: &JsonValue) -> String {
+    let mut out = String::new();
+    match inline_yaml(value, 0) {
+        Some(scalar) => out.push_str(&scalar),
+        None => write_block(&mut out, value, 0),
+    }
+    out.push('\n');
+    out
+}
+
+/// Render `value` as the value of a YAML key at column `indent`, for
+/// splicing after `key:` into a block-style document: a space and the value
+/// for scalars, else its block on the following lines
+pub fn to_yaml_value(value: &JsonValue, indent: usize) -> String {
+    match inline_yaml(value, indent) {
+        Some(scalar) => format!(" {}", scalar),
+        None => {
+            let mut out = String::new();
+            out.push('\n');
+            write_block(&mut out, value, indent + 2);
+            out
+        }
+    }
+}
+
+fn write_value(out: &mut String, value: &JsonValue) {
+    match value {
+        JsonValue::Null => out.push_str("null"),
+        JsonValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        JsonValue::Number(n) => write_number(out, n),
+        JsonValue::String(s) => write_string(out, s),
+        JsonValue::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, item);
+            }
+            out.push(']');
+        }
+        JsonValue::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, key);
+                out.push(':');
+                write_value(out, &map[key]);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_number(out: &mut String, n: &serde_json::Number) {
+    if let Some(i) = n.as_i64() {
+        let _ = write!(out, "{}", i);
+    } else if let Some(u) = n.as_u64() {
+        let _ = write!(out, "{}", u);
+    } else {
+        // serde_json formats floats with ryu, which yields the shortest
+        // string that parses back to the same f64
+        out.push_str(&n.to_string());
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    // serde_json's string escaping is already deterministic
+    out.push_str(&serde_json::to_string(s).unwrap_or_else(|_| "\"\"".to_string()));
+}
+
+fn write_pretty(out: &mut String, value: &JsonValue, indent: usize) {
+    match value {
+        JsonValue::Array(items) if !items.is_empty() => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(if i > 0 { ",\n" } else { "\n" });
+                push_indent(out, indent + 2);
+                write_pretty(out, item, indent + 2);
+            }
+            out.push('\n');
+            push_indent(out, indent);
+            out.push(']');
+        }
+        JsonValue::Object(map) if !map.is_empty() => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                out.push_str(if i > 0 { ",\n" } else { "\n" });
+                push_indent(out, indent + 2);
+                write_string(out, key);
+                out.push_str(": ");
+                write_pretty(out, &map[key], indent + 2);
+            }
+            out.push('\n');
+            push_indent(out, indent);
+            out.push('}');
+        }
+        _ => write_value(out, value),
+    }
+}
+
+/// Lines of a non-empty array or object at column `indent`, each ending in
+/// a newline except the last
+fn write_block(out: &mut String, value: &JsonValue, indent: usize) {
+    match value {
+        JsonValue::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                push_indent(out, indent);
+                out.push_str("- ");
+                match inline_yaml(item, indent) {
+                    Some(scalar) => out.push_str(&scalar),
+                    None => {
+                        // The item's first line follows the dash; the rest
+                        // line up with it
+                        let mut nested = String::new();
+                        write_block(&mut nested, item, indent + 2);
+                        out.push_str(&nested[indent + 2..]);
+                    }
+                }
+            }
+        }
+        JsonValue::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                push_indent(out, indent);
+                out.push_str(&yaml_key(key));
+                out.push(':');
+                out.push_str(&to_yaml_value(&map[key], indent));
+            }
+        }
+        scalar => out.push_str(&inline_yaml(scalar, indent).unwrap_or_default()),
+    }
+}
+
+/// A scalar or empty collection as written after `key: ` or `- ` at column
+/// `indent`; `None` for values that need a block
+fn inline_yaml(value: &JsonValue, indent: usize) -> Option<String> {
+    match value {
+        JsonValue::Array(items) if items.is_empty() => Some("[]".to_string()),
+        JsonValue::Object(map) if map.is_empty() => Some("{}".to_string()),
+        JsonValue::Array(_) | JsonValue::Object(_) => None,
+        JsonValue::String(s) => Some(yaml_string(s, indent)),
+        scalar => Some(to_json_string(scalar)),
+    }
+}
+
+/// A string as serde_yaml quotes it, which is plain, quoted or, when it
+/// spans lines, a literal block whose lines are indented past `indent`
+fn yaml_string(s: &str, indent: usize) -> String {
+    let Ok(rendered) = serde_yaml::to_string(s) else {
+        return to_json_string(&JsonValue::from(s));
+    };
+    let rendered = rendered.strip_suffix('\n').unwrap_or(&rendered);
+    let mut lines = rendered.split('\n');
+    let mut out = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        out.push('\n');
+        if !line.is_empty() {
+            push_indent(&mut out, indent);
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+/// Keys are single-line; one that isn't is written double-quoted
+fn yaml_key(key: &str) -> String {
+    let rendered = yaml_string(key, 0);
+    if rendered.contains('\n') {
+        to_json_string(&JsonValue::from(key))
+    } else {
+        rendered
+    }
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+    out.extend(std::iter::repeat_n(' ', indent));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{record, TestCase, TestResult};
+    use serde_json::json;
+
+    const SUITE: &str = "\
+name: Recorded
+servers:
+  python:
+    host: localhost
+    port: 50051
+tests:
+  # Comments and key order survive recording
+  - name: nested
+    method: make_record
+    arguments: {id: 7}
+  - description: Recorded over an older value
+    name: scalar
+    method: add
+    arguments:
+      a: 1
+      b: 2
+    expected:
+      - 0
+    tags: [smoke]
+";
+
+    fn tricky() -> JsonValue {
+        json!({
+            "zeta": [1.5, 0.1, 1e100, -0.0, {"b": "x\ny", "a": null}],
+            "alpha": {"yes": "yes", "number": "123", "empty": "", "colon": "a: b"},
+            "nested": [[1, [2]], [], {}],
+            "text": "  leading\nand trailing  \n",
+            "unicode": "naïve ✓",
+        })
+    }
+
+    fn result(name: &str, value: JsonValue) -> TestResult {
+        serde_json::from_value(json!({
+            "name": name,
+            "passed": true,
+            "targets": [{"target": "python", "result": value}],
+            "error_message": null,
+        }))
+        .unwrap()
+    }
+
+    fn tests_of(content: &str) -> Vec<TestCase> {
+        let doc: serde_yaml::Value = serde_yaml::from_str(content).unwrap();
+        serde_yaml::from_value(doc["tests"].clone()).unwrap()
+    }
+
+    #[test]
+    fn json_sorts_keys_at_every_level() {
+        let value = json!({"b": {"y": 1, "x": [true, null]}, "a": 0.5});
+        assert_eq!(
+            to_json_string(&value),
+            r#"{"a":0.5,"b":{"x":[true,null],"y":1}}"#
+        );
+        assert_eq!(
+            to_json_string_pretty(&value),
+            r#"{
+  "a": 0.5,
+  "b": {
+    "x": [
+      true,
+      null
+    ],
+    "y": 1
+  }
+}"#
+        );
+        assert_eq!(
+            to_json_string_pretty(&json!({"empty": [], "none": {}})),
+            "{\n  \"empty\": [],\n  \"none\": {}\n}"
+        );
+    }
+
+    #[test]
+    fn floats_use_the_shortest_round_trip() {
+        let value = json!([0.1, 0.30000000000000004, 1e100, 2.5e-8]);
+        let text = to_json_string(&value);
+        assert_eq!(text, "[0.1,0.30000000000000004,1e+100,2.5e-8]");
+        assert_eq!(serde_json::from_str::<JsonValue>(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn yaml_reads_back_as_the_same_value() {
+        let value = tricky();
+        let yaml = to_yaml_string(&value);
+        assert_eq!(serde_yaml::from_str::<JsonValue>(&yaml).unwrap(), value);
+        assert_eq!(to_yaml_string(&serde_yaml::from_str(&yaml).unwrap()), yaml);
+    }
+
+    #[test]
+    fn yaml_is_block_style_with_sorted_keys() {
+        let value =
+            json!({"tests": [{"name": "a", "arguments": {"b": 2, "a": [1, 2]}}], "name": "S"});
+        assert_eq!(
+            to_yaml_string(&value),
+            "\
+name: S
+tests:
+  - arguments:
+      a:
+        - 1
+        - 2
+      b: 2
+    name: a
+"
+        );
+        assert_eq!(to_yaml_string(&json!("123")), "'123'\n");
+        assert_eq!(to_yaml_value(&json!([]), 4), " []");
+    }
+
+    #[test]
+    fn recording_twice_is_byte_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("suite.yaml");
+        std::fs::write(&path, SUITE).unwrap();
+
+        let tests = tests_of(SUITE);
+        let results = [
+            result("nested", tricky()),
+            result("scalar", json!({"sum": 3})),
+        ];
+        let results: Vec<&TestResult> = results.iter().collect();
+
+        let first = record::record(&path, &tests, &results, true).unwrap();
+        assert_eq!(first.written, 2);
+        let recorded = std::fs::read_to_string(&path).unwrap();
+        assert!(recorded.contains("# Comments and key order survive recording"));
+        assert!(recorded.contains("    tags: [smoke]\n"));
+
+        let read_back = tests_of(&recorded);
+        assert_eq!(read_back[0].expected, Some(tricky()));
+        assert_eq!(read_back[1].expected, Some(json!({"sum": 3})));
+
+        // Recording the same results over the recorded file changes nothing
+        let tests = tests_of(&recorded);
+        record::record(&path, &tests, &results, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), recorded);
+    }
+}
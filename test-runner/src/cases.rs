@@ -0,0 +1,80 @@
+/*!
+`cases`: one test body run over a table of inputs.
+
+```yaml
+- name: is_prime
+  method: is_prime
+  cases:
+    - {arguments: {n: 7}, expected: true}
+    - {arguments: {n: 8}, expected: false}
+    - {name: large, arguments: {n: 7919}, expected: true}
+```
+
+Each case becomes a test of its own, named after the parent and the case's
+`name` or, without one, its arguments: `is_prime[n=7]`, `is_prime[n=8]`,
+`is_prime[large]`. A case replaces the parent's `arguments` and, if it has
+one, `expected`; everything else (method, tolerances, tags, ...) is the
+parent's. Cases are expanded as the suite is read, so `--filter`,
+`--shuffle` and reports all see the expanded names. Multi-step tests can't
+have cases yet.
+*/
+
+use crate::{canonical, TestCase};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Case {
+    /// Suffix of the test name; derived from `arguments` if unset
+    #[serde(default)]
+    pub name: Option<String>,
+    pub arguments: JsonValue,
+    #[serde(default)]
+    pub expected: Option<JsonValue>,
+}
+
+impl Case {
+    /// What goes between the brackets of the expanded test's name
+    fn label(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match &self.arguments {
+            JsonValue::Object(fields) => fields
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, canonical::to_json_string(value)))
+                .collect::<Vec<_>>()
+                .join(", "),
+            other => canonical::to_json_string(other),
+        }
+    }
+}
+
+/// `test` itself if it has no cases, otherwise one test per case
+pub fn expand(mut test: TestCase) -> Result<Vec<TestCase>> {
+    if test.cases.is_empty() {
+        return Ok(vec![test]);
+    }
+    if !test.steps.is_empty() {
+        anyhow::bail!(
+            "Test '{}' has both `cases` and `steps`; multi-step tests can't have cases yet",
+            test.name
+        );
+    }
+
+    let cases = std::mem::take(&mut test.cases);
+    Ok(cases
+        .into_iter()
+        .map(|case| {
+            let mut expanded = test.clone();
+            expanded.name = format!("{}[{}]", test.name, case.label());
+            expanded.arguments = case.arguments;
+            if case.expected.is_some() {
+                expanded.expected = case.expected;
+            }
+            expanded.from_cases = true;
+            expanded
+        })
+        .collect())
+}
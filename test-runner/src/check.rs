@@ -0,0 +1,497 @@
+/*!
+`--check`: lint suite files without running any tests.
+
+Normal loading ignores keys it doesn't know, so a typo like `argments:`
+silently drops the arguments. Checking reads each file strictly instead and
+reports every problem it finds, with the line it's on where that can be
+found:
+- keys that aren't suite, server, test, step or hook fields
+- anything the normal loader rejects
+- `stateful` tests with neither `initial_state` nor `steps`
+- `initial_state` that isn't valid JSON
+- `expected_state` that isn't an object, or on a test without a context
+- `snapshot_state` on a test without a context
+- `perf` with `steps`, no iterations, or a `faster_than` that isn't a server
+- `match: subset` without `expected`, where it has nothing to match
+- `expect_error` with an invalid `regex`, alongside `expected`, or on a
+  test with `steps`
+- methods, `setup` and `teardown` calls included, that aren't listed by
+  every server's `ListMethods`
+- positional `arguments` (a list, or a single value) of a different length
+  than the parameters a server lists for the method, unless the test
+  expects an error
+
+Servers are only asked for their method lists; no test method is invoked.
+*/
+
+use crate::connections::Connections;
+use crate::loader::load_suite;
+use crate::record::{find_test, key_start};
+use crate::rpc;
+use crate::{MatchMode, TestSuite};
+use colored::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+// Keep in step with the fields of `TestSuite`, `ServerConfig`, `TestCase`,
+// `TestStep` and `Hook`
+const SUITE_FIELDS: &[&str] = &[
+    "name",
+    "description",
+    "servers",
+    "reference",
+    "timeout_ms",
+    "encoding",
+    "include",
+    "variables",
+    "setup",
+    "teardown",
+    "tests",
+];
+const SERVER_FIELDS: &[&str] = &[
+    "host",
+    "port",
+    "command",
+    "args",
+    "startup_timeout_ms",
+    "tls",
+    "auth_token",
+];
+const TEST_FIELDS: &[&str] = &[
+    "name",
+    "description",
+    "method",
+    "arguments",
+    "cases",
+    "stateful",
+    "initial_state",
+    "initial_state_file",
+    "snapshot_state",
+    "expected",
+    "expected_state",
+    "expect_error",
+    "perf",
+    "warmup",
+    "stream",
+    "no_cache",
+    "type_sensitive",
+    "abs_tolerance",
+    "rel_tolerance",
+    "ignore_paths",
+    "match",
+    "compare_output",
+    "tags",
+    "steps",
+    "setup",
+    "teardown",
+    "timeout_ms",
+    "resources",
+    "serial_group",
+    "allow_failure",
+    "retries",
+    "retry_delay_ms",
+];
+const STEP_FIELDS: &[&str] = &["method", "arguments", "expected"];
+const CASE_FIELDS: &[&str] = &["name", "arguments", "expected"];
+const HOOK_FIELDS: &[&str] = &["method", "arguments"];
+
+/// Something wrong with a suite file, at a 1-based line if known
+pub struct Problem {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Every problem in the suite at `path`; empty if it's fine
+pub async fn check_file(
+    path: &Path,
+    max_json_depth: usize,
+    defines: &[(String, String)],
+    connections: &mut Connections,
+) -> Vec<Problem> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => return vec![problem(None, format!("cannot read file: {}", e))],
+    };
+    let doc: serde_yaml::Value = match serde_yaml::from_str(&content) {
+        Ok(doc) => doc,
+        Err(e) => {
+            let line = e.location().map(|l| l.line());
+            return vec![problem(line, format!("invalid YAML: {}", e))];
+        }
+    };
+    let lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+    let mut problems = unknown_fields(&doc, &lines);
+    let suite = match load_suite(path, max_json_depth, defines) {
+        Ok(suite) => suite,
+        Err(e) => {
+            let line = e
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<serde_yaml::Error>())
+                .and_then(|e| e.location())
+                .map(|l| l.line());
+            problems.push(problem(line, format!("{:#}", e)));
+            return problems;
+        }
+    };
+    problems.extend(test_problems(&suite, &lines));
+    problems.extend(method_problems(&suite, &lines, connections).await);
+    problems.sort_by_key(|p| p.line);
+    problems
+}
+
+/// Keys the normal loader would silently ignore
+fn unknown_fields(doc: &serde_yaml::Value, lines: &[String]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut check = |map: &serde_yaml::Value, known: &[&str], what: &str, from: usize| {
+        let Some(map) = map.as_mapping() else {
+            return;
+        };
+        for key in map.keys().filter_map(|k| k.as_str()) {
+            if known.contains(&key) {
+                continue;
+            }
+            let mut message = format!("unknown {} field `{}`", what, key);
+            if let Some(known) = closest(key, known) {
+                message.push_str(&format!(" (did you mean `{}`?)", known));
+            }
+            problems.push(problem(key_line(lines, key, from), message));
+        }
+    };
+
+    check(doc, SUITE_FIELDS, "suite", 0);
+    for list in ["setup", "teardown"] {
+        let hooks = doc.get(list).and_then(|h| h.as_sequence());
+        for hook in hooks.into_iter().flatten() {
+            check(hook, HOOK_FIELDS, "hook", 0);
+        }
+    }
+    if let Some(servers) = doc.get("servers").and_then(|s| s.as_mapping()) {
+        for server in servers.values() {
+            check(server, SERVER_FIELDS, "server", 0);
+        }
+    }
+    let tests = doc.get("tests").and_then(|t| t.as_sequence());
+    for test in tests.into_iter().flatten() {
+        let start = test
+            .get("name")
+            .and_then(|n| n.as_str())
+            .and_then(|name| find_test(lines, name))
+            .map_or(0, |(start, _)| start);
+        check(test, TEST_FIELDS, "test", start);
+        let steps = test.get("steps").and_then(|s| s.as_sequence());
+        for step in steps.into_iter().flatten() {
+            check(step, STEP_FIELDS, "step", start);
+        }
+        let cases = test.get("cases").and_then(|c| c.as_sequence());
+        for case in cases.into_iter().flatten() {
+            check(case, CASE_FIELDS, "case", start);
+        }
+        for list in ["setup", "teardown"] {
+            let hooks = test.get(list).and_then(|h| h.as_sequence());
+            for hook in hooks.into_iter().flatten() {
+                check(hook, HOOK_FIELDS, "hook", start);
+            }
+        }
+    }
+    problems
+}
+
+/// Tests that parse but can't run as written
+fn test_problems(suite: &TestSuite, lines: &[String]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for test in &suite.tests {
+        let line = test_line(lines, &test.name);
+        if test.method.is_empty() && test.steps.is_empty() {
+            problems.push(problem(
+                line,
+                format!("test '{}' defines neither a method nor steps", test.name),
+            ));
+        }
+        if test.stateful && test.initial_state.is_none() && test.steps.is_empty() {
+            problems.push(problem(
+                line,
+                format!(
+                    "test '{}' is stateful but has neither initial_state nor steps",
+                    test.name
+                ),
+            ));
+        }
+        if let Some(expected) = &test.expected_state {
+            let start = line.map_or(0, |l| l - 1);
+            let at = key_line(lines, "expected_state", start).or(line);
+            if !expected.is_object() {
+                problems.push(problem(
+                    at,
+                    format!("test '{}' expected_state must be an object", test.name),
+                ));
+            }
+            if !test.stateful && test.steps.is_empty() {
+                problems.push(problem(
+                    at,
+                    format!(
+                        "test '{}' has expected_state but no context; set `stateful: true`",
+                        test.name
+                    ),
+                ));
+            }
+        }
+        if test.snapshot_state.is_some() && !test.stateful && test.steps.is_empty() {
+            let start = line.map_or(0, |l| l - 1);
+            problems.push(problem(
+                key_line(lines, "snapshot_state", start).or(line),
+                format!(
+                    "test '{}' has snapshot_state but no context; set `stateful: true`",
+                    test.name
+                ),
+            ));
+        }
+        if let Some(perf) = &test.perf {
+            let start = line.map_or(0, |l| l - 1);
+            let at = key_line(lines, "perf", start).or(line);
+            let mut invalid = |why: String| {
+                problems.push(problem(at, format!("test '{}' perf: {}", test.name, why)))
+            };
+            if !test.steps.is_empty() {
+                invalid("only single-method tests can be timed".to_string());
+            }
+            if perf.min_iterations == 0 {
+                invalid("min_iterations must be at least 1".to_string());
+            }
+            if perf.max_ratio.is_nan() || perf.max_ratio <= 0.0 {
+                invalid("max_ratio must be positive".to_string());
+            }
+            if let Err(e) = perf.baseline(&suite.servers.names()) {
+                invalid(e);
+            }
+        }
+        if test.match_mode == MatchMode::Subset && test.expected.is_none() {
+            let start = line.map_or(0, |l| l - 1);
+            problems.push(problem(
+                key_line(lines, "match", start).or(line),
+                format!(
+                    "test '{}' has `match: subset` but no expected to match",
+                    test.name
+                ),
+            ));
+        }
+        if let Some(expect) = test.expect_error.as_ref().filter(|e| e.is_set()) {
+            let start = line.map_or(0, |l| l - 1);
+            let at = key_line(lines, "expect_error", start).or(line);
+            if let Err(e) = expect.regex() {
+                problems.push(problem(
+                    at,
+                    format!("test '{}' expect_error regex is invalid: {}", test.name, e),
+                ));
+            }
+            if let Err(e) = expect.code() {
+                problems.push(problem(
+                    at,
+                    format!("test '{}' expect_error code is invalid: {}", test.name, e),
+                ));
+            }
+            if test.expected.is_some() {
+                problems.push(problem(
+                    at,
+                    format!("test '{}' has both expected and expect_error", test.name),
+                ));
+            }
+            if !test.steps.is_empty() {
+                problems.push(problem(
+                    at,
+                    format!(
+                        "test '{}' has steps, which don't support expect_error",
+                        test.name
+                    ),
+                ));
+            }
+        }
+        if let Some(state) = &test.initial_state {
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(state) {
+                let start = line.map_or(0, |l| l - 1);
+                problems.push(problem(
+                    key_line(lines, "initial_state", start).or(line),
+                    format!(
+                        "test '{}' initial_state is not valid JSON: {}",
+                        test.name, e
+                    ),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Methods some test calls that a server doesn't list, and calls passing a
+/// listed method the wrong number of positional arguments
+async fn method_problems(
+    suite: &TestSuite,
+    lines: &[String],
+    connections: &mut Connections,
+) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    // Each server's methods with their number of parameters
+    let mut listed: HashMap<String, BTreeMap<String, usize>> = HashMap::new();
+    for target in suite.servers.iter() {
+        let methods = match connections.get(&target.name, &target.config).await {
+            Ok(mut client) => rpc::method_infos(&mut client).await,
+            Err(e) => Err(e),
+        };
+        match methods {
+            Ok(methods) => {
+                let methods = methods
+                    .into_iter()
+                    .map(|m| (m.name, m.parameter_types.len()))
+                    .collect();
+                listed.insert(target.name.clone(), methods);
+            }
+            Err(e) => problems.push(problem(
+                key_line(lines, &target.name, 0),
+                format!("cannot list methods of {} server: {:#}", target.name, e),
+            )),
+        }
+    }
+
+    for hook in suite.setup.iter().chain(&suite.teardown) {
+        for target in suite.servers.names() {
+            let missing = listed
+                .get(&target)
+                .is_some_and(|available| !available.contains_key(&hook.method));
+            if missing {
+                problems.push(problem(
+                    method_line(lines, &hook.method, 0),
+                    format!(
+                        "suite setup or teardown calls `{}`, which the {} server doesn't have",
+                        hook.method, target
+                    ),
+                ));
+            }
+        }
+    }
+
+    for test in &suite.tests {
+        let hooks = test.setup.iter().chain(&test.teardown);
+        let calls = std::iter::once((&test.method, &test.arguments))
+            .chain(
+                test.steps
+                    .iter()
+                    .map(|step| (&step.method, &step.arguments)),
+            )
+            .chain(hooks.map(|hook| (&hook.method, &hook.arguments)))
+            .filter(|(method, _)| !method.is_empty());
+        let line = test_line(lines, &test.name);
+        let start = line.map_or(0, |l| l - 1);
+        let expects_error = test.expect_error.as_ref().is_some_and(|e| e.is_set());
+        let mut seen = BTreeSet::new();
+        for (method, arguments) in calls {
+            for target in suite.servers.names() {
+                let Some(available) = listed.get(&target) else {
+                    continue;
+                };
+                match available.get(method) {
+                    None => {
+                        if seen.insert((method, target.clone())) {
+                            problems.push(problem(
+                                method_line(lines, method, start).or(line),
+                                format!(
+                                    "test '{}' calls `{}`, which the {} server doesn't have",
+                                    test.name, method, target
+                                ),
+                            ));
+                        }
+                    }
+                    // Servers list no types for methods they don't describe,
+                    // and a test may get the count wrong to see it fail
+                    Some(&declared) if declared > 0 && !expects_error => {
+                        let given = match arguments {
+                            serde_json::Value::Object(_) => continue,
+                            serde_json::Value::Array(values) => values.len(),
+                            _ => 1,
+                        };
+                        if given != declared {
+                            problems.push(problem(
+                                key_line(lines, "arguments", start).or(line),
+                                format!(
+                                    "test '{}' passes {} arguments to `{}`, which takes {} on \
+                                     the {} server",
+                                    test.name, given, method, declared, target
+                                ),
+                            ));
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Print the problems of one file; nothing if there are none
+pub fn print(path: &Path, problems: &[Problem]) {
+    for p in problems {
+        let location = match p.line {
+            Some(line) => format!("{}:{}", path.display(), line),
+            None => path.display().to_string(),
+        };
+        println!(
+            "{}: {} {}",
+            location.bright_white(),
+            "error:".bright_red().bold(),
+            p.message
+        );
+    }
+}
+
+fn problem(line: Option<usize>, message: String) -> Problem {
+    Problem { line, message }
+}
+
+fn test_line(lines: &[String], name: &str) -> Option<usize> {
+    find_test(lines, name).map(|(start, _)| start + 1)
+}
+
+/// 1-based line of the first `key:` at or after line index `from`
+fn key_line(lines: &[String], key: &str, from: usize) -> Option<usize> {
+    let prefix = format!("{}:", key);
+    (from..lines.len())
+        .find(|&i| key_start(&lines[i]).1.starts_with(&prefix))
+        .map(|i| i + 1)
+}
+
+/// 1-based line of the first `method: <method>` at or after line index `from`
+fn method_line(lines: &[String], method: &str, from: usize) -> Option<usize> {
+    (from..lines.len())
+        .find(|&i| {
+            key_start(&lines[i])
+                .1
+                .strip_prefix("method:")
+                .and_then(|raw| serde_yaml::from_str::<String>(raw.trim()).ok())
+                .is_some_and(|m| m == method)
+        })
+        .map(|i| i + 1)
+}
+
+/// The known field within two edits of `key`, if any
+fn closest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|field| (edit_distance(key, field), *field))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(x != *y);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
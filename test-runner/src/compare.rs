@@ -0,0 +1,308 @@
+/*!
+Value comparison semantics shared by every check the runner makes.
+
+Numbers compare exactly by default, as `serde_json` does: `5` and `5.0`
+differ, since one runtime serialized an integer and the other a float.
+`type_sensitive` goes further and keeps integers and floats apart even
+where a tolerance would otherwise let them match. Strings never compare
+equal to numbers.
+
+Tests can also set `abs_tolerance` and/or `rel_tolerance`, in which case
+every numeric leaf only has to be within tolerance of its counterpart.
+Without a tolerance numbers must match exactly.
+
+Fields that legitimately differ between runs and runtimes, such as
+timestamps or generated ids, can be left out of every comparison with
+`ignore_paths`. With `match: subset`, `expected` only needs to be contained
+in each result: objects may have keys it lacks, and those keys aren't
+compared between targets either. Arrays must still have the same length.
+
+JSON has no NaN or infinity. Rust's serializer writes them as `null` while
+Python's writes bare `NaN`/`Infinity` tokens, so `parse_result` maps those
+tokens to `null` and both runtimes' non-finite floats compare alike.
+*/
+
+use crate::json_path::JsonPath;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::borrow::Cow;
+use transpile_common::json_depth::{self, DepthError};
+
+/// How a test's `expected` is matched against results
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    Exact,
+    /// Results may have object keys `expected` doesn't
+    Subset,
+}
+
+/// Per-test comparison settings
+#[derive(Clone, Debug, Default)]
+pub struct CompareOptions {
+    /// Keep `5` and `5.0` apart even within a tolerance
+    pub type_sensitive: bool,
+    /// Largest accepted absolute difference between numbers
+    pub abs_tolerance: Option<f64>,
+    /// Largest accepted difference relative to the larger magnitude
+    pub rel_tolerance: Option<f64>,
+    /// Parts of results and `expected` left out of comparisons
+    pub ignore_paths: Vec<JsonPath>,
+    pub match_mode: MatchMode,
+    /// Also compare what each call printed to stdout
+    pub compare_output: bool,
+}
+
+impl CompareOptions {
+    fn has_tolerance(&self) -> bool {
+        self.abs_tolerance.is_some() || self.rel_tolerance.is_some()
+    }
+
+    fn within_tolerance(&self, x: f64, y: f64) -> bool {
+        if x == y {
+            return true;
+        }
+        let diff = (x - y).abs();
+        let abs_ok = self.abs_tolerance.is_some_and(|tol| diff <= tol);
+        let rel_ok = self
+            .rel_tolerance
+            .is_some_and(|tol| diff <= tol * x.abs().max(y.abs()));
+        abs_ok || rel_ok
+    }
+}
+
+/// `value` as it is compared under `options`: without its ignored paths
+/// and, in subset mode, with objects cut down to the keys `template` has.
+/// Borrowed when there's nothing to change.
+pub fn prepare<'a>(
+    value: &'a JsonValue,
+    template: Option<&JsonValue>,
+    options: &CompareOptions,
+) -> Cow<'a, JsonValue> {
+    let template = template.filter(|_| options.match_mode == MatchMode::Subset);
+    if options.ignore_paths.is_empty() && template.is_none() {
+        return Cow::Borrowed(value);
+    }
+    let mut value = value.clone();
+    for path in &options.ignore_paths {
+        path.remove(&mut value);
+    }
+    if let Some(template) = template {
+        project(&mut value, template);
+    }
+    Cow::Owned(value)
+}
+
+/// Drop the object keys of `value` that `template` doesn't have, at any
+/// depth. Keys `template` has but `value` lacks stay missing.
+fn project(value: &mut JsonValue, template: &JsonValue) {
+    match (value, template) {
+        (JsonValue::Object(fields), JsonValue::Object(wanted)) => {
+            fields.retain(|key, _| wanted.contains_key(key));
+            for (key, field) in fields.iter_mut() {
+                project(field, &wanted[key]);
+            }
+        }
+        (JsonValue::Array(items), JsonValue::Array(wanted)) => {
+            for (item, wanted) in items.iter_mut().zip(wanted) {
+                project(item, wanted);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether two optional results are equal under `options`
+pub fn results_equal(
+    a: Option<&JsonValue>,
+    b: Option<&JsonValue>,
+    options: &CompareOptions,
+) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => values_equal(a, b, options),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+pub fn values_equal(a: &JsonValue, b: &JsonValue, options: &CompareOptions) -> bool {
+    match (a, b) {
+        (JsonValue::Number(x), JsonValue::Number(y)) => {
+            if options.type_sensitive && x.is_f64() != y.is_f64() {
+                false
+            } else if options.has_tolerance() {
+                match (x.as_f64(), y.as_f64()) {
+                    (Some(x), Some(y)) => options.within_tolerance(x, y),
+                    _ => x == y,
+                }
+            } else {
+                x == y
+            }
+        }
+        (JsonValue::Array(xs), JsonValue::Array(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| values_equal(x, y, options))
+        }
+        (JsonValue::Object(xs), JsonValue::Object(ys)) => {
+            xs.len() == ys.len()
+                && xs
+                    .iter()
+                    .all(|(key, x)| ys.get(key).is_some_and(|y| values_equal(x, y, options)))
+        }
+        _ => a == b,
+    }
+}
+
+/// Parse a result as sent by a server, accepting the bare `NaN`,
+/// `Infinity` and `-Infinity` tokens Python emits and reading them as `null`.
+/// Results nested deeper than `max_depth` are rejected.
+pub fn parse_result(text: &str, max_depth: usize) -> Result<JsonValue, DepthError> {
+    match json_depth::from_str(text, max_depth) {
+        Err(DepthError::Invalid(_)) => json_depth::from_str(&replace_non_finite(text), max_depth),
+        parsed => parsed,
+    }
+}
+
+fn replace_non_finite(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut in_string = false;
+    while let Some(c) = rest.chars().next() {
+        if in_string {
+            if c == '\\' {
+                let escaped: String = rest.chars().take(2).collect();
+                out.push_str(&escaped);
+                rest = &rest[escaped.len()..];
+                continue;
+            }
+            in_string = c != '"';
+        } else if c == '"' {
+            in_string = true;
+        } else if let Some(token) = ["-Infinity", "Infinity", "NaN"]
+            .into_iter()
+            .find(|token| rest.starts_with(token))
+        {
+            out.push_str("null");
+            rest = &rest[token.len()..];
+            continue;
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn equal(a: JsonValue, b: JsonValue, options: &CompareOptions) -> bool {
+        values_equal(&a, &b, options)
+    }
+
+    #[test]
+    fn numbers_compare_exactly_by_default() {
+        let options = CompareOptions::default();
+        assert!(equal(json!(5), json!(5), &options));
+        assert!(equal(json!(0.5), json!(0.5), &options));
+        assert!(!equal(json!(5), json!(5.0), &options));
+        assert!(!equal(
+            json!([1, {"a": 2}]),
+            json!([1, {"a": 2.0}]),
+            &options
+        ));
+        assert!(!equal(json!(5), json!("5"), &options));
+    }
+
+    #[test]
+    fn type_sensitive_separates_ints_from_floats_within_tolerance() {
+        let tolerant = CompareOptions {
+            abs_tolerance: Some(0.1),
+            ..Default::default()
+        };
+        assert!(equal(json!(5), json!(5.05), &tolerant));
+        assert!(equal(json!(5), json!(5.0), &tolerant));
+        let strict = CompareOptions {
+            type_sensitive: true,
+            ..tolerant
+        };
+        assert!(!equal(json!(5), json!(5.0), &strict));
+        assert!(equal(json!(5.0), json!(5.05), &strict));
+    }
+
+    #[test]
+    fn subset_matches_objects_on_the_expected_keys() {
+        let subset = CompareOptions {
+            match_mode: MatchMode::Subset,
+            ..Default::default()
+        };
+        // expected, result, how they differ ("" when they match)
+        let cases = [
+            (json!({"a": 1}), json!({"a": 1, "b": 2}), ""),
+            (
+                json!({"a": {"b": 1}}),
+                json!({"a": {"b": 1, "c": 2}, "d": 3}),
+                "",
+            ),
+            (
+                json!([{"a": 1}, {"b": 2}]),
+                json!([{"a": 1, "x": 0}, {"b": 2, "y": 0}]),
+                "",
+            ),
+            (json!({}), json!({"a": 1}), ""),
+            // Arrays are matched element by element, and must be as long
+            (
+                json!([{"a": 1}]),
+                json!([{"a": 2, "x": 0}]),
+                "$[0].a: expected=1 result=2",
+            ),
+            (
+                json!([{"a": 1}]),
+                json!([{"a": 1}, {"a": 2}]),
+                "$[1]: missing in expected",
+            ),
+            (json!([1, 2]), json!([1]), "$[1]: missing in result"),
+            // A key the result lacks is missing, not null
+            (
+                json!({"a": 1, "b": 2}),
+                json!({"a": 1}),
+                "$.b: missing in result",
+            ),
+            (json!({"a": null}), json!({}), "$.a: missing in result"),
+            // A value of another type is compared whole, extra keys and all
+            (
+                json!({"a": {"b": 1}}),
+                json!({"a": 5}),
+                r#"$.a: expected={"b":1} result=5"#,
+            ),
+            (
+                json!({"a": 5}),
+                json!({"a": {"b": 1}}),
+                r#"$.a: expected=5 result={"b":1}"#,
+            ),
+            (
+                json!({"a": [1]}),
+                json!({"a": {"0": 1}}),
+                r#"$.a: expected=[1] result={"0":1}"#,
+            ),
+        ];
+        for (expected, result, differences) in cases {
+            let prepared = prepare(&result, Some(&expected), &subset);
+            assert_eq!(
+                equal(expected.clone(), prepared.clone().into_owned(), &subset),
+                differences.is_empty(),
+                "{} in {}",
+                expected,
+                result
+            );
+            let found = crate::diff::structural(
+                ("expected", Some(&expected)),
+                ("result", Some(&prepared)),
+                &subset,
+                false,
+            );
+            assert_eq!(found, differences, "{} in {}", expected, result);
+        }
+    }
+}
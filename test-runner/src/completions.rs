@@ -0,0 +1,342 @@
+/*!
+Shell completion scripts.
+
+The static part of each script is generated by `clap_complete` from the
+clap command definition, so new flags show up without touching this
+module. Flags whose values are test names, tags or groups complete
+dynamically: a hook appended to the generated script answers them by
+calling the hidden `__complete-tests` subcommand, which only parses the
+suite file and never contacts a server, and leaves every other word to
+the generated completer.
+*/
+
+use crate::suites;
+use clap::{Arg, Command, ValueEnum};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Name of the installed binary the scripts complete
+const BIN: &str = "test-runner";
+
+/// Flags completed with names read from the suite given by `--suite`
+const DYNAMIC_FLAGS: &[(&str, &str)] = &[("filter", "tests"), ("tag", "tags"), ("group", "groups")];
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    fn generator(self) -> clap_complete::Shell {
+        match self {
+            Shell::Bash => clap_complete::Shell::Bash,
+            Shell::Zsh => clap_complete::Shell::Zsh,
+            Shell::Fish => clap_complete::Shell::Fish,
+        }
+    }
+}
+
+/// What `__complete-tests` lists
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum NameKind {
+    #[default]
+    Tests,
+    Tags,
+    /// `serial_group` and `resources` names
+    Groups,
+}
+
+pub fn generate(shell: Shell, mut cmd: Command) -> String {
+    let mut script = Vec::new();
+    clap_complete::generate(shell.generator(), &mut cmd, BIN, &mut script);
+    let mut script = String::from_utf8(script).expect("completion scripts are UTF-8");
+    let flags = dynamic_flags(&cmd);
+    script.push_str(&match shell {
+        Shell::Bash => bash(&flags),
+        Shell::Zsh => zsh(&flags),
+        Shell::Fish => fish(&flags),
+    });
+    script
+}
+/// Names printed by `__complete-tests`: one per line, nothing if the suite
+/// can't be read or parsed. A directory or glob collects every suite in it.
+pub fn suite_names(suite: &Path, kind: NameKind) -> Vec<String> {
+    let files = suites::discover(&[suite.to_path_buf()]).unwrap_or_default();
+    let mut names: Vec<String> = files
+        .iter()
+        .flat_map(|file| file_names(file, kind))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn file_names(suite: &Path, kind: NameKind) -> Vec<String> {
+    let Ok(content) = std::fs::read_to_string(suite) else {
+        return Vec::new();
+    };
+    let Ok(doc) = serde_yaml::from_str::<serde_yaml::Value>(&content) else {
+        return Vec::new();
+    };
+    let Some(tests) = doc.get("tests").and_then(|t| t.as_sequence()) else {
+        return Vec::new();
+    };
+
+    match kind {
+        NameKind::Tags => tests
+            .iter()
+            .filter_map(|t| t.get("tags").and_then(|tags| tags.as_sequence()))
+            .flatten()
+            .filter_map(|tag| tag.as_str().map(str::to_string))
+            .collect(),
+        NameKind::Groups => tests
+            .iter()
+            .flat_map(|t| {
+                let group = t.get("serial_group").into_iter();
+                let resources = t.get("resources").and_then(|r| r.as_sequence());
+                group.chain(resources.into_iter().flatten())
+            })
+            .filter_map(|name| name.as_str().map(str::to_string))
+            .collect(),
+        NameKind::Tests => tests
+            .iter()
+            .filter_map(|t| t.get("name").and_then(|n| n.as_str()).map(str::to_string))
+            .collect(),
+    }
+}
+
+/// Each dynamically completed flag with the kind of names it takes
+fn dynamic_flags(cmd: &Command) -> Vec<(&Arg, &'static str)> {
+    DYNAMIC_FLAGS
+        .iter()
+        .filter_map(|(id, kind)| {
+            let arg = cmd.get_arguments().find(|arg| arg.get_id() == *id)?;
+            Some((arg, *kind))
+        })
+        .collect()
+}
+
+/// `--long` and `-s` spellings of `arg`
+fn spellings(arg: &Arg) -> Vec<String> {
+    let long = arg.get_long().map(|long| format!("--{}", long));
+    let short = arg.get_short().map(|short| format!("-{}", short));
+    long.into_iter().chain(short).collect()
+}
+
+/// Wraps `_test__runner`, the function `clap_complete` generates
+fn bash(flags: &[(&Arg, &str)]) -> String {
+    let func = format!("_{}", BIN.replace('-', "_"));
+    let mut cases = String::new();
+    for (arg, kind) in flags {
+        let _ = writeln!(
+            cases,
+            "        {})\n            COMPREPLY=($(compgen -W \"$({}_names {})\" -- \"$cur\"))\n            ;;",
+            spellings(arg).join("|"),
+            func,
+            kind
+        );
+    }
+
+    format!(
+        r#"
+{func}_names() {{
+    local i suite
+    for ((i = 1; i < ${{#COMP_WORDS[@]}}; i++)); do
+        case "${{COMP_WORDS[i]}}" in
+            --suite|-s) suite="${{COMP_WORDS[i+1]}}" ;;
+        esac
+    done
+    [[ -n "$suite" ]] && "${{COMP_WORDS[0]}}" __complete-tests --suite "$suite" --kind "$1" 2>/dev/null
+}}
+
+{func}_dynamic() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    case "${{COMP_WORDS[COMP_CWORD-1]}}" in
+{cases}        *)
+            _{generated} "$@"
+            ;;
+    esac
+}}
+
+complete -F {func}_dynamic -o bashdefault -o default {bin}
+"#,
+        bin = BIN,
+        func = func,
+        cases = cases,
+        generated = BIN.replace('-', "__"),
+    )
+}
+
+/// Wraps `_test-runner`, the function `clap_complete` generates
+fn zsh(flags: &[(&Arg, &str)]) -> String {
+    let func = format!("_{}", BIN.replace('-', "_"));
+    let mut cases = String::new();
+    for (arg, kind) in flags {
+        let _ = writeln!(
+            cases,
+            "        {}) {}_names {} ;;",
+            spellings(arg).join("|"),
+            func,
+            kind
+        );
+    }
+
+    format!(
+        r#"
+{func}_names() {{
+    local suite i
+    for ((i = 1; i < ${{#words}}; i++)); do
+        if [[ "${{words[i]}}" == "--suite" || "${{words[i]}}" == "-s" ]]; then
+            suite="${{words[i+1]}}"
+        fi
+    done
+    [[ -n "$suite" ]] || return
+    local -a names
+    names=(${{(f)"$(${{words[1]}} __complete-tests --suite "$suite" --kind "$1" 2>/dev/null)"}})
+    compadd -a names
+}}
+
+{func}_dynamic() {{
+    case "${{words[CURRENT-1]}}" in
+{cases}        *) _{bin} "$@" ;;
+    esac
+}}
+
+compdef {func}_dynamic {bin}
+"#,
+        bin = BIN,
+        func = func,
+        cases = cases,
+    )
+}
+
+/// Fish merges these candidates into the generated flags' own
+fn fish(flags: &[(&Arg, &str)]) -> String {
+    let func = format!("__{}", BIN.replace('-', "_"));
+    let mut lines = String::new();
+    for (arg, kind) in flags {
+        let mut line = format!("complete -c {}", BIN);
+        if let Some(long) = arg.get_long() {
+            let _ = write!(line, " -l {}", long);
+        }
+        if let Some(short) = arg.get_short() {
+            let _ = write!(line, " -s {}", short);
+        }
+        let _ = writeln!(lines, "{} -x -a '({}_names {})'", line, func, kind);
+    }
+
+    format!(
+        r#"
+function {func}_names
+    set -l tokens (commandline -opc)
+    set -l suite
+    for i in (seq (count $tokens))
+        if contains -- $tokens[$i] --suite -s
+            set suite $tokens[(math $i + 1)]
+        end
+    end
+    test -n "$suite"; and {bin} __complete-tests --suite $suite --kind $argv[1] 2>/dev/null
+end
+
+{lines}"#,
+        bin = BIN,
+        func = func,
+        lines = lines,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader;
+
+    const SUITE: &str = "\
+name: fixture
+servers:
+  python: {host: localhost, port: 50051}
+  rust: {host: localhost, port: 50052}
+tests:
+  - {name: test_add, method: add, tags: [math, fast]}
+  - {name: test_counter, method: counter_increment, serial_group: counters}
+  - {name: test_upload, method: upload, tags: [io], resources: [bucket, counters]}
+  - {name: test_add_large, method: add, tags: [math]}
+";
+
+    fn suite_file(dir: &tempfile::TempDir, name: &str, content: &str) -> std::path::PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_names_match_the_loaded_suite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = suite_file(&dir, "suite.yaml", SUITE);
+        let suite = loader::load_suite(&path, 128, &[]).unwrap();
+        let mut loaded: Vec<String> = suite.tests.into_iter().map(|t| t.name).collect();
+        loaded.sort();
+
+        assert_eq!(suite_names(&path, NameKind::Tests), loaded);
+        assert_eq!(
+            loaded,
+            ["test_add", "test_add_large", "test_counter", "test_upload"]
+        );
+    }
+
+    #[test]
+    fn tags_and_groups_are_listed_once_each() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = suite_file(&dir, "suite.yaml", SUITE);
+        assert_eq!(suite_names(&path, NameKind::Tags), ["fast", "io", "math"]);
+        assert_eq!(suite_names(&path, NameKind::Groups), ["bucket", "counters"]);
+    }
+
+    #[test]
+    fn a_directory_lists_the_names_of_every_suite() {
+        let dir = tempfile::tempdir().unwrap();
+        suite_file(&dir, "a.yaml", SUITE);
+        suite_file(
+            &dir,
+            "b.yaml",
+            "tests: [{name: test_other, method: other}]\n",
+        );
+        let names = suite_names(dir.path(), NameKind::Tests);
+        assert_eq!(names.len(), 5);
+        assert!(names.contains(&"test_other".to_string()));
+    }
+
+    #[test]
+    fn malformed_or_missing_suites_list_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let malformed = suite_file(&dir, "broken.yaml", "tests: [{name: test_add\n");
+        let no_tests = suite_file(&dir, "empty.yaml", "name: fixture\n");
+        for path in [malformed, no_tests, dir.path().join("missing.yaml")] {
+            for kind in [NameKind::Tests, NameKind::Tags, NameKind::Groups] {
+                assert!(suite_names(&path, kind).is_empty(), "{}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn scripts_hook_names_into_the_generated_completer() {
+        use clap::CommandFactory;
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = generate(shell, crate::Args::command());
+            assert!(script.contains("max-json-depth"));
+            assert!(script.contains("__complete-tests --suite"));
+        }
+        let bash = generate(Shell::Bash, crate::Args::command());
+        assert!(bash.contains("_test__runner() {"));
+        assert!(bash.contains("--filter|-f)\n"));
+        assert!(bash.contains("--group)\n"));
+        let zsh = generate(Shell::Zsh, crate::Args::command());
+        assert!(zsh.contains("_test-runner() {"));
+        assert!(zsh.contains("--tag|-t) _test_runner_names tags ;;"));
+        let fish = generate(Shell::Fish, crate::Args::command());
+        assert!(fish.contains(
+            "complete -c test-runner -l filter -s f -x -a '(__test_runner_names tests)'"
+        ));
+    }
+}
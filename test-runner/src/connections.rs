@@ -0,0 +1,228 @@
+/*!
+Server connections shared by every suite of a run.
+
+Suites usually point at the same servers, so clients are kept per host and
+port: the first suite naming a server connects and waits for its
+registration, later ones reuse the channel.
+
+A server that should already be running may still be booting, e.g. when a
+script starts the servers and the runner together. Connecting retries with
+exponential backoff until `--connect-timeout` runs out, and the servers of
+a suite are waited for concurrently, so the wait is that of the slowest
+rather than the sum. A host name that doesn't resolve fails at once: no
+amount of waiting fixes a typo.
+
+A server with a `command` is started by the runner on first use instead,
+with its output in a log file in the temp directory. It keeps running for
+the rest of the run and is killed when the `Connections` are dropped.
+*/
+
+use crate::launch::{self, ServerProcess};
+use crate::rpc::Client;
+use crate::targets::Targets;
+use crate::transport::Target;
+use crate::{wait_for_registration, ServerConfig};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tracing::{debug, info};
+
+/// Lines of a started server's log shown when it fails
+const LOG_TAIL: usize = 20;
+
+/// How long a server that isn't started by the runner gets to accept
+/// connections by default
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause after the first failed connection attempt, doubled after each
+/// further one up to `MAX_RETRY_DELAY`
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+pub struct Connections {
+    clients: HashMap<(String, u16), Client>,
+    /// Servers the runner started, by host and port
+    processes: HashMap<(String, u16), ServerProcess>,
+    /// Budget for connecting to each server the runner doesn't start
+    connect_timeout: Duration,
+}
+
+impl Connections {
+    pub fn new(connect_timeout: Duration) -> Self {
+        Self {
+            clients: HashMap::new(),
+            processes: HashMap::new(),
+            connect_timeout,
+        }
+    }
+
+    /// Forget every connection and stop the servers the runner started, so
+    /// the next suite connects, and starts them, afresh
+    pub fn reset(&mut self) {
+        self.clients.clear();
+        self.processes.clear();
+    }
+
+    /// Clients for every target of `servers`, in order. Servers that are
+    /// already running are connected to concurrently.
+    pub async fn get_all(&mut self, servers: &Targets) -> Result<Vec<Client>> {
+        let mut pending = Vec::new();
+        for target in servers.iter() {
+            let config = &target.config;
+            let key = (config.host.clone(), config.port);
+            if config.command.is_some()
+                || self.clients.contains_key(&key)
+                || pending.iter().any(|(k, _)| *k == key)
+            {
+                continue;
+            }
+            let remote = Target::new(&target.name, config)?;
+            let name = target.name.clone();
+            let budget = self.connect_timeout;
+            pending.push((key, tokio::spawn(connect(name, remote, budget))));
+        }
+        for (key, task) in pending {
+            let client = task.await.context("Connection task failed")??;
+            self.clients.insert(key, client);
+        }
+
+        let mut clients = Vec::new();
+        for target in servers.iter() {
+            clients.push(self.get(&target.name, &target.config).await?);
+        }
+        Ok(clients)
+    }
+
+    /// Client for the server of target `name`, connecting on first use
+    pub async fn get(&mut self, name: &str, config: &ServerConfig) -> Result<Client> {
+        let key = (config.host.clone(), config.port);
+        if let Some(client) = self.clients.get(&key) {
+            debug!("Reusing connection to {}:{}", config.host, config.port);
+            return Ok(client.clone());
+        }
+
+        let target = Target::new(name, config)?;
+        let client = match &config.command {
+            Some(command) => {
+                let mut client = self.start(name, command, config, &target).await?;
+                if let Err(e) = wait_for_registration(&mut client, name).await {
+                    return Err(match self.processes.get(&key) {
+                        Some(process) => with_log(e, process),
+                        None => e,
+                    });
+                }
+                client
+            }
+            None => connect(name.to_string(), target, self.connect_timeout).await?,
+        };
+        self.clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// Start the server of target `name` and connect once it listens
+    async fn start(
+        &mut self,
+        name: &str,
+        command: &str,
+        config: &ServerConfig,
+        target: &Target,
+    ) -> Result<Client> {
+        let address = format!("{}:{}", config.host, config.port);
+        if TcpStream::connect(&address).await.is_ok() {
+            anyhow::bail!(
+                "Cannot start {} server: something is already listening on {}; \
+                 stop it or remove `command` to use it",
+                name,
+                address
+            );
+        }
+
+        let log = std::env::temp_dir().join(format!(
+            "transpile-{}-{}-{}.log",
+            name,
+            config.port,
+            std::process::id()
+        ));
+        let mut process = ServerProcess::spawn(Path::new(command), &config.args, log)
+            .with_context(|| format!("Failed to start {} server `{}`", name, command))?;
+        info!(
+            "Started {} server `{} {}`, logging to {}",
+            name,
+            command,
+            config.args.join(" "),
+            process.log_path().display()
+        );
+
+        let timeout = config
+            .startup_timeout_ms
+            .map_or(launch::STARTUP_TIMEOUT, Duration::from_millis);
+        let ready = process.wait_ready(target, timeout).await;
+        let result = ready.map_err(|e| {
+            with_log(
+                anyhow::anyhow!("{} server did not start: {}", name, e),
+                &process,
+            )
+        });
+        self.processes
+            .insert((config.host.clone(), config.port), process);
+        result
+    }
+}
+
+/// Connect to a server the runner doesn't start and wait for its
+/// registration, retrying for up to `budget` while the server boots
+async fn connect(name: String, target: Target, budget: Duration) -> Result<Client> {
+    info!("Connecting to {} server at {}", name, target.url());
+    target
+        .resolve()
+        .await
+        .with_context(|| format!("Failed to connect to {} server", name))?;
+
+    let started = Instant::now();
+    let mut delay = FIRST_RETRY_DELAY;
+    let mut attempt = 1;
+    let mut client = loop {
+        // Even a budget that has run out allows one attempt
+        let limit = budget
+            .saturating_sub(started.elapsed())
+            .max(MAX_RETRY_DELAY);
+        let error = match tokio::time::timeout(limit, target.connect()).await {
+            Ok(Ok(client)) => break client,
+            Ok(Err(e)) => anyhow::Error::new(e),
+            Err(_) => anyhow::anyhow!("no response within {}ms", limit.as_millis()),
+        };
+        let remaining = budget.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(error.context(format!(
+                "Failed to connect to {} server after {} attempt(s) in {:.1}s",
+                name,
+                attempt,
+                started.elapsed().as_secs_f64()
+            )));
+        }
+        let pause = delay.min(remaining);
+        debug!(
+            "Connection attempt {} to {} server failed: {:#}; retrying in {}ms",
+            attempt,
+            name,
+            error,
+            pause.as_millis()
+        );
+        tokio::time::sleep(pause).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+        attempt += 1;
+    };
+    wait_for_registration(&mut client, &name).await?;
+    Ok(client)
+}
+
+/// `error` followed by the end of the server's log
+fn with_log(error: anyhow::Error, process: &ServerProcess) -> anyhow::Error {
+    error.context(format!(
+        "last lines of {}:\n{}",
+        process.log_path().display(),
+        process.log_tail(LOG_TAIL)
+    ))
+}
@@ -0,0 +1,153 @@
+/*!
+Server-side context lifetimes.
+
+A `ContextGuard` is handed out for every context the runner creates. The
+normal path destroys it with `cleanup().await`; if the guard is dropped
+instead — an early return, a panicking test task, a cancelled future — the
+context is pushed onto the run's `CleanupQueue`, which is drained once all
+tests have finished. Either way every created context gets a
+`DestroyContext` attempt.
+*/
+
+use crate::rpc::Client;
+use crate::transpile_test::DestroyContextRequest;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+
+/// Contexts whose guards were dropped without being cleaned up
+#[derive(Clone, Default)]
+pub struct CleanupQueue {
+    pending: Arc<Mutex<Vec<(Client, String)>>>,
+}
+
+impl CleanupQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take ownership of a freshly created context
+    pub fn guard(&self, client: &Client, context_id: String) -> ContextGuard {
+        ContextGuard {
+            client: client.clone(),
+            context_id: Some(context_id),
+            queue: self.clone(),
+        }
+    }
+
+    /// Destroy every queued context, returning how many there were
+    pub async fn drain(&self) -> usize {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let count = pending.len();
+        for (mut client, context_id) in pending {
+            debug!("Destroying leaked context {}", context_id);
+            if let Err(e) = destroy(&mut client, &context_id).await {
+                warn!("Failed to destroy context {}: {}", context_id, e);
+            }
+        }
+        count
+    }
+}
+
+/// A created context that still has to be destroyed
+#[must_use = "call `cleanup().await` so the context is destroyed promptly"]
+pub struct ContextGuard {
+    client: Client,
+    context_id: Option<String>,
+    queue: CleanupQueue,
+}
+
+impl ContextGuard {
+    pub fn id(&self) -> &str {
+        self.context_id.as_deref().unwrap_or_default()
+    }
+
+    /// Destroy the context now. Failures are logged, not returned: a test's
+    /// outcome doesn't depend on whether its context could be torn down.
+    pub async fn cleanup(mut self) {
+        if let Some(context_id) = self.context_id.take() {
+            if let Err(e) = destroy(&mut self.client, &context_id).await {
+                warn!("Failed to destroy context {}: {}", context_id, e);
+            }
+        }
+    }
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        // Drop can't await, so leave the DestroyContext call to the queue
+        if let Some(context_id) = self.context_id.take() {
+            self.queue
+                .pending
+                .lock()
+                .unwrap()
+                .push((self.client.clone(), context_id));
+        }
+    }
+}
+
+async fn destroy(client: &mut Client, context_id: &str) -> Result<(), tonic::Status> {
+    client
+        .destroy_context(DestroyContextRequest {
+            context_id: context_id.to_string(),
+        })
+        .await
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{self, Fault, Mock};
+    use crate::TestCase;
+    use std::time::Duration;
+
+    fn steps_test() -> TestCase {
+        serde_yaml::from_str("{name: steps, steps: [{method: add}, {method: add}]}").unwrap()
+    }
+
+    fn stateful_test() -> TestCase {
+        serde_yaml::from_str("{name: stateful, method: add, stateful: true}").unwrap()
+    }
+
+    #[tokio::test]
+    async fn contexts_are_destroyed_when_a_peer_fails_to_create_one() {
+        let (python, rust) = (Mock::default(), Mock::failing(Fault::CreateContext));
+        let mut runner = mock::runner(&[&python, &rust]).await;
+        for test in [steps_test(), stateful_test()] {
+            let result = runner.run_test(&test).await.unwrap();
+            assert!(!result.passed, "{}", test.name);
+        }
+        assert_eq!(runner.cleanup.drain().await, 0);
+        assert_eq!(python.open_contexts(), 0);
+    }
+
+    #[tokio::test]
+    async fn contexts_are_destroyed_when_an_invocation_fails() {
+        let (python, rust) = (Mock::default(), Mock::failing(Fault::Invoke));
+        let mut runner = mock::runner(&[&python, &rust]).await;
+        for test in [steps_test(), stateful_test()] {
+            let result = runner.run_test(&test).await.unwrap();
+            assert!(!result.passed, "{}", test.name);
+        }
+        assert_eq!(runner.cleanup.drain().await, 0);
+        for mock in [&python, &rust] {
+            assert_eq!(mock.open_contexts(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelled_tests_leave_their_contexts_to_the_queue() {
+        let (python, rust) = (Mock::slow(Duration::from_secs(60)), Mock::default());
+        let mut runner = mock::runner(&[&python, &rust]).await;
+        let test = steps_test();
+        let run = runner.run_test(&test);
+        assert!(tokio::time::timeout(Duration::from_millis(200), run)
+            .await
+            .is_err());
+
+        assert_eq!((python.open_contexts(), rust.open_contexts()), (1, 1));
+        assert_eq!(runner.cleanup.drain().await, 2);
+        for mock in [&python, &rust] {
+            assert_eq!(mock.open_contexts(), 0);
+        }
+    }
+}
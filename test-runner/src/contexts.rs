@@ -0,0 +1,104 @@
+/*!
+`contexts`: list the contexts that exist on the servers of a suite.
+
+A runner that crashes mid-run leaves its contexts behind, and so does a
+client that never destroys them. This prints, for each server, every live
+context with how long ago it was created and last used and how many keys
+its state holds, oldest first. `--destroy-all` then destroys them all with
+`DestroyAllContexts`.
+*/
+
+use crate::targets::Targets;
+use crate::transpile_test::{ContextInfo, DestroyAllContextsRequest, ListContextsRequest};
+use crate::{rpc, transport, ServerConfig};
+use colored::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// List the contexts of every server, destroying them after with
+/// `destroy_all`; true if every server answered
+pub async fn run(servers: &Targets, destroy_all: bool) -> bool {
+    let mut answered = true;
+    for target in servers.iter() {
+        println!("{} server", target.name.bright_white().bold());
+        if let Err(e) = list(&target.name, &target.config, destroy_all).await {
+            println!("  {}", format!("{:#}", e).red());
+            answered = false;
+        }
+        println!();
+    }
+    answered
+}
+
+async fn list(name: &str, config: &ServerConfig, destroy_all: bool) -> anyhow::Result<()> {
+    let target = transport::Target::new(name, config)?;
+    let mut client: rpc::Client = target
+        .connect()
+        .await
+        .map_err(|e| anyhow::anyhow!("cannot connect to {}: {}", target.url(), e))?;
+
+    let contexts = client
+        .list_contexts(ListContextsRequest {})
+        .await
+        .map_err(|status| anyhow::anyhow!("ListContexts failed: {}", status))?
+        .into_inner()
+        .contexts;
+    print_table(&contexts);
+
+    if destroy_all {
+        let destroyed = client
+            .destroy_all_contexts(DestroyAllContextsRequest {})
+            .await
+            .map_err(|status| anyhow::anyhow!("DestroyAllContexts failed: {}", status))?
+            .into_inner()
+            .destroyed;
+        println!(
+            "  {}",
+            format!("Destroyed {} context(s)", destroyed).yellow()
+        );
+    }
+    Ok(())
+}
+
+fn print_table(contexts: &[ContextInfo]) {
+    if contexts.is_empty() {
+        println!("  {}", "No contexts".dimmed());
+        return;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64);
+    let id_width = contexts
+        .iter()
+        .map(|c| c.context_id.len())
+        .max()
+        .unwrap_or(0)
+        .max("CONTEXT".len());
+    println!(
+        "  {:<id_width$}  {:>10}  {:>10}  {:>5}",
+        "CONTEXT".bold(),
+        "CREATED".bold(),
+        "LAST USED".bold(),
+        "KEYS".bold(),
+    );
+    for context in contexts {
+        println!(
+            "  {:<id_width$}  {:>10}  {:>10}  {:>5}",
+            context.context_id,
+            ago(now - context.created_at_ms),
+            ago(now - context.last_used_at_ms),
+            context.state_keys,
+        );
+    }
+    println!("  {} context(s)", contexts.len());
+}
+
+/// `ms` milliseconds in the past, to the second: "42s ago", "3m 05s ago"
+fn ago(ms: i64) -> String {
+    let secs = Duration::from_millis(ms.max(0) as u64).as_secs();
+    let age = match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    };
+    format!("{} ago", age)
+}
@@ -0,0 +1,142 @@
+/*!
+`--coverage`: which registered methods the run's tests exercise.
+
+After a suite runs, every server's `ListMethods` is compared with the
+methods its tests call (single-call tests and every step). Three lists come
+out of that: methods tested, methods a server registers but no test calls,
+and methods tests call that a server doesn't have. The last are a
+transpilation gap and fail the run like a failed test. Built-in `__`
+methods are never reported as untested.
+*/
+
+use crate::rpc::{self, Client};
+use crate::TestCase;
+use anyhow::{Context, Result};
+use colored::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Prefix of the methods every server provides itself
+const BUILTIN_PREFIX: &str = "__";
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct Coverage {
+    /// Methods some test calls that every server has
+    pub tested: Vec<String>,
+    /// Methods each server registers that no test calls
+    pub untested: Vec<TargetMethods>,
+    /// Methods some test calls that a server doesn't have
+    pub missing: Vec<MissingMethod>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct TargetMethods {
+    pub target: String,
+    pub methods: Vec<String>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct MissingMethod {
+    pub method: String,
+    /// Targets that don't have it
+    pub missing_from: Vec<String>,
+}
+
+/// Coverage of the servers behind `clients` (named by the parallel `names`)
+/// by `tests`
+pub async fn collect(
+    clients: &mut [Client],
+    names: &[String],
+    tests: &[TestCase],
+) -> Result<Coverage> {
+    let called: BTreeSet<&str> = tests
+        .iter()
+        .flat_map(|test| {
+            std::iter::once(&test.method).chain(test.steps.iter().map(|step| &step.method))
+        })
+        .filter(|method| !method.is_empty())
+        .map(String::as_str)
+        .collect();
+
+    let mut registered = Vec::with_capacity(clients.len());
+    for (name, client) in names.iter().zip(clients) {
+        let methods = rpc::list_methods(client)
+            .await
+            .with_context(|| format!("Failed to list methods of {} server", name))?;
+        registered.push(methods);
+    }
+
+    let mut coverage = Coverage {
+        tested: Vec::new(),
+        untested: Vec::new(),
+        missing: Vec::new(),
+    };
+    for method in &called {
+        let missing_from: Vec<String> = names
+            .iter()
+            .zip(&registered)
+            .filter(|(_, methods)| !methods.contains(*method))
+            .map(|(name, _)| name.clone())
+            .collect();
+        if missing_from.is_empty() {
+            coverage.tested.push(method.to_string());
+        } else {
+            coverage.missing.push(MissingMethod {
+                method: method.to_string(),
+                missing_from,
+            });
+        }
+    }
+    for (name, methods) in names.iter().zip(&registered) {
+        let untested: Vec<String> = methods
+            .iter()
+            .filter(|m| !m.starts_with(BUILTIN_PREFIX) && !called.contains(m.as_str()))
+            .cloned()
+            .collect();
+        coverage.untested.push(TargetMethods {
+            target: name.clone(),
+            methods: untested,
+        });
+    }
+    Ok(coverage)
+}
+
+pub fn print(coverage: &Coverage) {
+    println!("{}", "Coverage".bright_blue().bold());
+    println!(
+        "  {} tested: {}",
+        coverage.tested.len().to_string().bright_green(),
+        list(&coverage.tested)
+    );
+    for target in &coverage.untested {
+        println!(
+            "  {} untested on {}: {}",
+            target.methods.len().to_string().yellow(),
+            target.target,
+            list(&target.methods)
+        );
+    }
+    if !coverage.missing.is_empty() {
+        println!(
+            "  {} missing from a server:",
+            coverage.missing.len().to_string().bright_red()
+        );
+        for missing in &coverage.missing {
+            println!(
+                "    {} (not on {})",
+                missing.method.red(),
+                missing.missing_from.join(", ")
+            );
+        }
+    }
+    println!();
+}
+
+fn list(methods: &[String]) -> String {
+    if methods.is_empty() {
+        "-".to_string()
+    } else {
+        methods.join(", ")
+    }
+}
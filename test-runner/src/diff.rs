@@ -0,0 +1,333 @@
+/*!
+Rendering of values in failure output.
+
+With `--show-types` every leaf is annotated with its JSON type, which makes
+"same text, different type" divergences (`"5"` vs `5`, `5` vs `5.0`) visible
+in messages that would otherwise show two identical-looking values.
+
+Mismatched results are reported as a structural diff listing only the paths
+that differ, e.g. `$.items[37].price: python=19.99 rust=19.98999`, so a single
+wrong element of a large array doesn't drown in two full dumps. The raw
+values stay in the `--verbose` log and the JSON report. Captured output is
+diffed the same way, line by line.
+*/
+
+use crate::canonical;
+use crate::compare::{self, CompareOptions};
+use serde_json::Value as JsonValue;
+
+/// Differing paths listed before the rest are summarized
+const MAX_DIFFERENCES: usize = 20;
+
+/// Render an optional result for a failure message
+pub fn render(value: Option<&JsonValue>, show_types: bool) -> String {
+    match value {
+        Some(value) if show_types => render_typed(value),
+        Some(value) => canonical::to_json_string(value),
+        None => "(no result)".to_string(),
+    }
+}
+
+/// JSON type name of a value; numbers distinguish `int` from `float`
+pub fn type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "bool",
+        JsonValue::Number(n) if n.is_f64() => "float",
+        JsonValue::Number(_) => "int",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+/// Canonical rendering with every leaf followed by its type, e.g.
+/// `{"a":5 <int>,"b":["5" <string>,null <null>]}`
+pub fn render_typed(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Array(items) => {
+            let items: Vec<String> = items.iter().map(render_typed).collect();
+            format!("[{}]", items.join(","))
+        }
+        JsonValue::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let entries: Vec<String> = keys
+                .into_iter()
+                .map(|key| {
+                    format!(
+                        "{}:{}",
+                        canonical::to_json_string(&JsonValue::String(key.clone())),
+                        render_typed(&map[key])
+                    )
+                })
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        }
+        leaf => format!("{} <{}>", canonical::to_json_string(leaf), type_name(leaf)),
+    }
+}
+
+/// One side of a structural diff: a label such as a target name, and its result
+pub type Side<'a> = (&'a str, Option<&'a JsonValue>);
+
+/// The paths at which `left` and `right` differ under `options`, one per
+/// line, capped at `MAX_DIFFERENCES` with an "…and N more" line
+pub fn structural(
+    left: Side<'_>,
+    right: Side<'_>,
+    options: &CompareOptions,
+    show_types: bool,
+) -> String {
+    let mut walk = Walk {
+        names: (left.0, right.0),
+        options,
+        show_types,
+        lines: Vec::new(),
+        total: 0,
+    };
+    match (left.1, right.1) {
+        (Some(a), Some(b)) => walk.value("$".to_string(), a, b),
+        (a, b) => walk.leaf("$".to_string(), a, b),
+    }
+
+    let mut lines = walk.lines;
+    if walk.total > lines.len() {
+        lines.push(format!("…and {} more", walk.total - lines.len()));
+    }
+    lines.join("\n")
+}
+
+/// The lines at which two captured outputs differ, as `line 3:
+/// python="a" rust="b"`, capped like `structural`. Lines are quoted so
+/// trailing whitespace and `\r` show; a missing final newline shows as a
+/// missing empty last line.
+pub fn lines(left: (&str, &str), right: (&str, &str)) -> String {
+    let xs: Vec<&str> = left.1.split('\n').collect();
+    let ys: Vec<&str> = right.1.split('\n').collect();
+    let mut differences = Vec::new();
+    for i in 0..xs.len().max(ys.len()) {
+        let line = match (xs.get(i), ys.get(i)) {
+            (Some(x), Some(y)) if x == y => continue,
+            (Some(x), Some(y)) => format!("line {}: {}={:?} {}={:?}", i + 1, left.0, x, right.0, y),
+            (Some(_), None) => format!("line {}: missing in {}", i + 1, right.0),
+            (None, _) => format!("line {}: missing in {}", i + 1, left.0),
+        };
+        differences.push(line);
+    }
+
+    let total = differences.len();
+    differences.truncate(MAX_DIFFERENCES);
+    if total > differences.len() {
+        differences.push(format!("…and {} more", total - MAX_DIFFERENCES));
+    }
+    differences.join("\n")
+}
+
+struct Walk<'a> {
+    names: (&'a str, &'a str),
+    options: &'a CompareOptions,
+    show_types: bool,
+    lines: Vec<String>,
+    /// Differences found, including those past the cap
+    total: usize,
+}
+
+impl Walk<'_> {
+    fn value(&mut self, path: String, a: &JsonValue, b: &JsonValue) {
+        match (a, b) {
+            (JsonValue::Array(xs), JsonValue::Array(ys)) => {
+                for (i, (x, y)) in xs.iter().zip(ys).enumerate() {
+                    self.value(format!("{}[{}]", path, i), x, y);
+                }
+                for i in ys.len()..xs.len() {
+                    self.missing(format!("{}[{}]", path, i), self.names.1);
+                }
+                for i in xs.len()..ys.len() {
+                    self.missing(format!("{}[{}]", path, i), self.names.0);
+                }
+            }
+            (JsonValue::Object(xs), JsonValue::Object(ys)) => {
+                let mut keys: Vec<&String> = xs.keys().chain(ys.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                for key in keys {
+                    let path = format!("{}{}", path, key_segment(key));
+                    match (xs.get(key), ys.get(key)) {
+                        (Some(x), Some(y)) => self.value(path, x, y),
+                        (Some(_), None) => self.missing(path, self.names.1),
+                        (None, _) => self.missing(path, self.names.0),
+                    }
+                }
+            }
+            _ => self.leaf(path, Some(a), Some(b)),
+        }
+    }
+
+    fn leaf(&mut self, path: String, a: Option<&JsonValue>, b: Option<&JsonValue>) {
+        if compare::results_equal(a, b, self.options) {
+            return;
+        }
+        let line = format!(
+            "{}: {}={} {}={}",
+            path,
+            self.names.0,
+            render(a, self.show_types),
+            self.names.1,
+            render(b, self.show_types)
+        );
+        self.push(line);
+    }
+
+    fn missing(&mut self, path: String, side: &str) {
+        self.push(format!("{}: missing in {}", path, side));
+    }
+
+    fn push(&mut self, line: String) {
+        self.total += 1;
+        if self.lines.len() < MAX_DIFFERENCES {
+            self.lines.push(line);
+        }
+    }
+}
+
+/// `.key` for identifier-like keys, `["key"]` for anything else
+pub fn key_segment(key: &str) -> String {
+    let plain = key
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if plain {
+        format!(".{}", key)
+    } else {
+        format!(
+            "[{}]",
+            canonical::to_json_string(&JsonValue::String(key.to_string()))
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn diff(a: JsonValue, b: JsonValue) -> String {
+        structural(
+            ("python", Some(&a)),
+            ("rust", Some(&b)),
+            &CompareOptions::default(),
+            false,
+        )
+    }
+
+    #[test]
+    fn type_names() {
+        let values = [
+            (json!(null), "null"),
+            (json!(true), "bool"),
+            (json!(5), "int"),
+            (json!(-5), "int"),
+            (json!(5.0), "float"),
+            (json!("5"), "string"),
+            (json!([5]), "array"),
+            (json!({"a": 5}), "object"),
+        ];
+        for (value, name) in values {
+            assert_eq!(type_name(&value), name, "{}", value);
+        }
+    }
+
+    #[test]
+    fn typed_rendering_annotates_every_leaf() {
+        let value = json!({"b": ["5", null, 5.0], "a": 5, "c": {"d": false}});
+        assert_eq!(
+            render_typed(&value),
+            r#"{"a":5 <int>,"b":["5" <string>,null <null>,5.0 <float>],"c":{"d":false <bool>}}"#
+        );
+        assert_eq!(render(Some(&json!("5")), false), r#""5""#);
+        assert_eq!(render(None, true), "(no result)");
+    }
+
+    #[test]
+    fn leaves_of_each_type() {
+        assert_eq!(diff(json!(null), json!(0)), "$: python=null rust=0");
+        assert_eq!(diff(json!(true), json!(false)), "$: python=true rust=false");
+        assert_eq!(diff(json!(1), json!(2)), "$: python=1 rust=2");
+        assert_eq!(
+            diff(json!(19.99), json!(19.98999)),
+            "$: python=19.99 rust=19.98999"
+        );
+        assert_eq!(diff(json!("a"), json!("b")), r#"$: python="a" rust="b""#);
+        assert_eq!(
+            diff(json!([1]), json!({"a": 1})),
+            r#"$: python=[1] rust={"a":1}"#
+        );
+        assert_eq!(diff(json!("same"), json!("same")), "");
+    }
+
+    #[test]
+    fn numbers_show_their_type_when_asked() {
+        assert_eq!(diff(json!(5), json!(5.0)), "$: python=5 rust=5.0");
+        let options = CompareOptions {
+            type_sensitive: true,
+            ..Default::default()
+        };
+        let (a, b) = (json!(5), json!(5.0));
+        let found = structural(("python", Some(&a)), ("rust", Some(&b)), &options, true);
+        assert_eq!(found, "$: python=5 <int> rust=5.0 <float>");
+    }
+
+    #[test]
+    fn arrays_list_differing_and_missing_elements() {
+        let found = diff(json!([1, 2, 3, 4]), json!([1, 20, 3]));
+        assert_eq!(found, "$[1]: python=2 rust=20\n$[3]: missing in rust");
+        assert_eq!(diff(json!([]), json!([null])), "$[0]: missing in python");
+    }
+
+    #[test]
+    fn objects_list_differing_and_missing_keys() {
+        let a = json!({"items": [{"price": 19.99}], "total": 1, "odd key": 1});
+        let b = json!({"items": [{"price": 19.98}], "extra": true, "odd key": 2});
+        assert_eq!(
+            diff(a, b),
+            "$.extra: missing in python\n\
+             $.items[0].price: python=19.99 rust=19.98\n\
+             $[\"odd key\"]: python=1 rust=2\n\
+             $.total: missing in rust"
+        );
+    }
+
+    #[test]
+    fn differences_are_capped() {
+        let a = JsonValue::Array((0..25).map(|i| json!(i)).collect());
+        let b = JsonValue::Array((0..25).map(|i| json!(-i - 1)).collect());
+        let found = diff(a, b);
+        let lines: Vec<&str> = found.lines().collect();
+        assert_eq!(lines.len(), MAX_DIFFERENCES + 1);
+        assert_eq!(lines[MAX_DIFFERENCES], "…and 5 more");
+    }
+
+    #[test]
+    fn missing_result() {
+        let value = json!(1);
+        let found = structural(
+            ("python", Some(&value)),
+            ("rust", None),
+            &Default::default(),
+            false,
+        );
+        assert_eq!(found, "$: python=1 rust=(no result)");
+    }
+
+    #[test]
+    fn output_lines() {
+        let found = lines(("python", "a\nb\nc\n"), ("rust", "a\nB\nc"));
+        assert_eq!(
+            found,
+            "line 2: python=\"b\" rust=\"B\"\nline 4: missing in rust"
+        );
+        assert_eq!(lines(("python", "same"), ("rust", "same")), "");
+    }
+}
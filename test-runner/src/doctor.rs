@@ -0,0 +1,162 @@
+/*!
+`doctor`: check that the servers of a suite are reachable and healthy.
+
+For each server it connects, prints `GetServerInfo`, then round-trips
+random payloads of increasing size through the reserved `__echo` method and
+checks they come back intact. Payloads mix ASCII, characters that need JSON
+escaping and multi-byte UTF-8, so encoding problems show up as mismatches
+rather than as mysterious test failures later.
+*/
+
+use crate::random::{self, XorShift};
+use crate::targets::Targets;
+use crate::transpile_test::{Encoding, GetServerInfoRequest, InvokeMethodRequest};
+use crate::{canonical, compare, rpc, timing, transport, ServerConfig};
+use colored::*;
+use serde_json::{json, Value as JsonValue};
+use std::time::Instant;
+use transpile_common::json_depth;
+
+/// Payload sizes (characters of random text) for the echo checks
+const ECHO_SIZES: &[usize] = &[0, 64, 1 << 10, 16 << 10, 256 << 10, 1 << 20];
+
+/// Characters payloads are drawn from
+const ALPHABET: &[char] = &[
+    'a', 'b', 'z', 'A', 'Z', '0', '9', ' ', '"', '\\', '/', '\n', '\t', '\u{1}', 'é', 'ß', '中',
+    '😀',
+];
+
+/// Run every check, printing as it goes; true if all passed.
+/// Payloads are derived from `seed`, or from the clock if none is given.
+pub async fn run(servers: &Targets, seed: Option<u64>) -> bool {
+    let seed = seed.unwrap_or_else(random::clock_seed);
+    println!("{}: payload seed {}", "Doctor".bright_blue().bold(), seed);
+
+    let mut healthy = true;
+    for target in servers.iter() {
+        println!("\n{} server", target.name.bright_white().bold());
+        healthy &= check_server(&target.name, &target.config, seed).await;
+    }
+    healthy
+}
+
+async fn check_server(name: &str, config: &ServerConfig, seed: u64) -> bool {
+    let target = match transport::Target::new(name, config) {
+        Ok(target) => target,
+        Err(e) => {
+            fail(&format!("{:#}", e));
+            return false;
+        }
+    };
+    let url = target.url();
+    let mut client = match target.connect().await {
+        Ok(client) => {
+            pass(&format!("connected to {}", url));
+            client
+        }
+        Err(e) => {
+            fail(&format!("cannot connect to {}: {}", url, e));
+            return false;
+        }
+    };
+
+    let mut healthy = true;
+    match client.get_server_info(GetServerInfoRequest {}).await {
+        Ok(resp) => {
+            let info = resp.into_inner();
+            pass(&format!(
+                "{} {} ({})",
+                info.runtime, info.version, info.git_hash
+            ));
+            if !info.registration_error.is_empty() {
+                healthy = false;
+                fail(&info.registration_error);
+            } else if !info.registration_complete {
+                healthy = false;
+                fail("registration still in progress");
+            }
+        }
+        Err(e) => {
+            healthy = false;
+            fail(&format!("GetServerInfo failed: {}", e));
+        }
+    }
+
+    let mut rng = XorShift::new(seed);
+    for &size in ECHO_SIZES {
+        healthy &= check_echo(&mut client, size, &mut rng).await;
+    }
+    healthy
+}
+
+async fn check_echo(client: &mut rpc::Client, size: usize, rng: &mut XorShift) -> bool {
+    let payload = json!({
+        "text": random_text(rng, size),
+        "numbers": (0..8).map(|_| rng.next() as i64 >> 12).collect::<Vec<_>>(),
+        "nested": {"flag": rng.next() & 1 == 0, "nothing": null},
+    });
+    let label = format!("__echo {} chars", size);
+
+    let started = Instant::now();
+    let response = client
+        .invoke_method(InvokeMethodRequest {
+            context_id: String::new(),
+            method_name: "__echo".to_string(),
+            arguments: canonical::to_json_string(&payload),
+            timeout_ms: 0,
+            encoding: Encoding::Json as i32,
+            payload: Vec::new(),
+        })
+        .await;
+    let elapsed = timing::format_duration(started.elapsed());
+
+    let resp = match response {
+        Ok(resp) => resp.into_inner(),
+        Err(e) => {
+            fail(&format!("{}: {}", label, e));
+            return false;
+        }
+    };
+    if !resp.success {
+        fail(&format!("{}: {}", label, resp.error));
+        return false;
+    }
+    match compare::parse_result(&resp.result, json_depth::DEFAULT_MAX_DEPTH) {
+        Ok(echoed) if echoed == payload => {
+            pass(&format!("{} ({})", label, elapsed));
+            true
+        }
+        Ok(echoed) => {
+            fail(&format!("{}: payload changed in transit", label));
+            if let Some(field) = first_difference(&payload, &echoed) {
+                println!("      first difference at {}", field);
+            }
+            false
+        }
+        Err(e) => {
+            fail(&format!("{}: {}", label, e));
+            false
+        }
+    }
+}
+
+fn first_difference(sent: &JsonValue, echoed: &JsonValue) -> Option<String> {
+    let (sent, echoed) = (sent.as_object()?, echoed.as_object()?);
+    sent.iter()
+        .find(|(key, value)| echoed.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+}
+
+pub fn pass(message: &str) {
+    println!("  {} {}", "✓".bright_green().bold(), message);
+}
+
+pub fn fail(message: &str) {
+    println!("  {} {}", "✗".bright_red().bold(), message.red());
+}
+
+fn random_text(rng: &mut XorShift, len: usize) -> String {
+    (0..len)
+        .map(|_| ALPHABET[rng.below(ALPHABET.len())])
+        .collect()
+}
@@ -0,0 +1,298 @@
+/*!
+`expect_error`: tests that every implementation rejects the same input.
+
+```yaml
+- name: factorial_negative
+  method: factorial
+  arguments: {n: -1}
+  expect_error: true
+```
+
+passes only if every server returns an error for the call. The errors can
+also be required to say something in particular, with `contains` (a
+substring) and `regex`, or to be of a particular kind, with `code` (the
+`error_code` servers report, e.g. `INVALID_ARGUMENTS`); whatever is given
+must all match:
+
+```yaml
+  expect_error: {contains: "negative", regex: "n must be (>= 0|non-negative)"}
+  expect_error: {code: INVALID_ARGUMENTS}
+```
+
+A result from any server fails the test, and so does an error that doesn't
+match, naming the server whose message didn't. Transport errors, timeouts
+the runner enforces, and a method or context the server doesn't have never
+count as the expected error: they say nothing about how the implementation
+handles the input. A server's own `TimeoutError` is an ordinary error.
+*/
+
+use crate::display_result;
+use crate::rpc::Execution;
+use crate::transpile_test::ErrorCode;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum ExpectError {
+    /// `true` for any error; `false` is the same as leaving it out
+    Any(bool),
+    Matching {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        contains: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        regex: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<String>,
+    },
+}
+
+impl ExpectError {
+    /// Whether the test expects errors at all
+    pub fn is_set(&self) -> bool {
+        !matches!(self, ExpectError::Any(false))
+    }
+
+    /// The compiled `regex`, if there is one
+    pub fn regex(&self) -> Result<Option<Regex>, regex::Error> {
+        match self {
+            ExpectError::Matching {
+                regex: Some(pattern),
+                ..
+            } => Regex::new(pattern).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// The `code`, if there is one
+    pub fn code(&self) -> Result<Option<ErrorCode>, String> {
+        match self {
+            ExpectError::Matching {
+                code: Some(name), ..
+            } => ErrorCode::from_str_name(name)
+                .map(Some)
+                .ok_or_else(|| format!("unknown error code {:?}", name)),
+            _ => Ok(None),
+        }
+    }
+
+    /// What the errors must match, for messages
+    fn describe(&self) -> String {
+        match self {
+            ExpectError::Matching {
+                contains,
+                regex,
+                code,
+            } => {
+                let mut parts = Vec::new();
+                if let Some(text) = contains {
+                    parts.push(format!("contain {:?}", text));
+                }
+                if let Some(pattern) = regex {
+                    parts.push(format!("match /{}/", pattern));
+                }
+                if let Some(code) = code {
+                    parts.push(format!("have code {}", code));
+                }
+                parts.join(" and ")
+            }
+            ExpectError::Any(_) => String::new(),
+        }
+    }
+}
+
+/// Why `executions` don't all fail as `expect` requires, naming the first
+/// target that differs from the reference where one does
+pub fn check(
+    expect: &ExpectError,
+    names: &[String],
+    executions: &[Execution],
+) -> Result<(), (String, Option<usize>)> {
+    let regex = expect
+        .regex()
+        .map_err(|e| (format!("Invalid `expect_error` regex: {}", e), None))?;
+    let code = expect
+        .code()
+        .map_err(|e| (format!("Invalid `expect_error` code: {}", e), None))?;
+    let contains = match expect {
+        ExpectError::Matching { contains, .. } => contains.as_deref(),
+        ExpectError::Any(_) => None,
+    };
+
+    // Per target: whether it failed the way the test expects
+    let mut problems = Vec::new();
+    let mut as_expected = Vec::with_capacity(executions.len());
+    for (name, execution) in names.iter().zip(executions) {
+        let problem = match &execution.error {
+            None => Some(format!(
+                "{} succeeded with {} but an error was expected",
+                name,
+                display_result(&execution.result)
+            )),
+            // A server's own timeout is an ordinary error
+            Some(error)
+                if execution.transport_error
+                    || (execution.timed_out && execution.code != ErrorCode::Timeout) =>
+            {
+                Some(format!(
+                    "{} failed to run the method rather than rejecting the input: {}",
+                    name, error
+                ))
+            }
+            Some(error) if execution.misconfigured() => Some(format!(
+                "{} can't run the test as written (a suite error, not the expected one): {}",
+                name, error
+            )),
+            Some(error) => {
+                let matches = contains.is_none_or(|text| error.contains(text))
+                    && regex.as_ref().is_none_or(|re| re.is_match(error))
+                    && code.is_none_or(|code| execution.code == code);
+                (!matches).then(|| {
+                    let reported = match code {
+                        Some(_) => format!(" (code {})", execution.code.as_str_name()),
+                        None => String::new(),
+                    };
+                    format!(
+                        "{}'s error doesn't {}: {}{}",
+                        name,
+                        expect.describe(),
+                        error,
+                        reported
+                    )
+                })
+            }
+        };
+        as_expected.push(problem.is_none());
+        problems.extend(problem);
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let other = (1..executions.len()).find(|&i| as_expected[i] != as_expected[0]);
+    Err((problems.join("\n"), other))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn expect(yaml: &str) -> ExpectError {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn error(message: &str, code: ErrorCode) -> Execution {
+        Execution {
+            code,
+            ..Execution::failed(message.to_string())
+        }
+    }
+
+    fn success() -> Execution {
+        Execution {
+            result: Some(json!(1)),
+            error: None,
+            ..Execution::failed(String::new())
+        }
+    }
+
+    fn names() -> Vec<String> {
+        vec!["python".to_string(), "rust".to_string()]
+    }
+
+    #[test]
+    fn contains_requires_every_message_to_contain_the_text() {
+        let expect = expect("{contains: negative}");
+        let negative = || error("ValueError: n is negative", ErrorCode::ExecutionError);
+        assert_eq!(check(&expect, &names(), &[negative(), negative()]), Ok(()));
+
+        let other = error("ValueError: n is too big", ErrorCode::ExecutionError);
+        let (message, pair) = check(&expect, &names(), &[negative(), other]).unwrap_err();
+        assert_eq!(
+            message,
+            "rust's error doesn't contain \"negative\": ValueError: n is too big"
+        );
+        assert_eq!(pair, Some(1));
+    }
+
+    #[test]
+    fn code_requires_every_server_to_report_it() {
+        let expect = expect("{code: INVALID_ARGUMENTS}");
+        let invalid = || error("missing argument 'n'", ErrorCode::InvalidArguments);
+        assert_eq!(check(&expect, &names(), &[invalid(), invalid()]), Ok(()));
+
+        let raised = error("KeyError: 'n'", ErrorCode::ExecutionError);
+        let (message, pair) = check(&expect, &names(), &[raised, invalid()]).unwrap_err();
+        assert_eq!(
+            message,
+            "python's error doesn't have code INVALID_ARGUMENTS: KeyError: 'n' \
+             (code EXECUTION_ERROR)"
+        );
+        assert_eq!(pair, Some(1));
+    }
+
+    #[test]
+    fn every_matcher_given_must_match() {
+        let expect = expect("{contains: negative, code: INVALID_ARGUMENTS}");
+        let raised = error("n is negative", ErrorCode::ExecutionError);
+        let (message, _) = check(&expect, &names(), &[raised]).unwrap_err();
+        assert_eq!(
+            message,
+            "python's error doesn't contain \"negative\" and have code INVALID_ARGUMENTS: \
+             n is negative (code EXECUTION_ERROR)"
+        );
+    }
+
+    #[test]
+    fn an_unknown_code_is_reported() {
+        let expect = expect("{code: NOT_A_CODE}");
+        assert!(expect.code().is_err());
+        let (message, pair) = check(&expect, &names(), &[success()]).unwrap_err();
+        assert_eq!(
+            message,
+            "Invalid `expect_error` code: unknown error code \"NOT_A_CODE\""
+        );
+        assert_eq!(pair, None);
+    }
+
+    #[test]
+    fn a_success_fails_the_test() {
+        let expect = expect("true");
+        let rejected = error("n is negative", ErrorCode::ExecutionError);
+        let (message, pair) = check(&expect, &names(), &[rejected, success()]).unwrap_err();
+        assert_eq!(message, "rust succeeded with 1 but an error was expected");
+        assert_eq!(pair, Some(1));
+    }
+
+    #[test]
+    fn a_missing_method_is_never_the_expected_error() {
+        let expect = expect("true");
+        let missing = || error("Method not found: fact", ErrorCode::MethodNotFound);
+        let (message, pair) = check(&expect, &names(), &[missing(), missing()]).unwrap_err();
+        assert!(
+            message.starts_with("python can't run the test as written"),
+            "{}",
+            message
+        );
+        assert_eq!(pair, None);
+    }
+
+    #[test]
+    fn only_a_servers_own_timeout_can_be_expected() {
+        let expect = expect("{code: TIMEOUT}");
+        let server = || Execution {
+            timed_out: true,
+            ..error("TimeoutError: exceeded 100ms", ErrorCode::Timeout)
+        };
+        assert_eq!(check(&expect, &names(), &[server(), server()]), Ok(()));
+
+        let runner = Execution::timed_out(std::time::Duration::from_millis(600));
+        let (message, pair) = check(&expect, &names(), &[server(), runner]).unwrap_err();
+        assert_eq!(
+            message,
+            "rust failed to run the method rather than rejecting the input: timed out after 600ms"
+        );
+        assert_eq!(pair, Some(1));
+    }
+}
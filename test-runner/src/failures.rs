@@ -0,0 +1,237 @@
+/*!
+`--save-failures FILE`: the failing tests of a run, as a suite of their own.
+
+```bash
+test-runner --suite test-defs/ --save-failures failures.yaml
+test-runner --suite failures.yaml
+```
+
+The file is an ordinary suite holding each failing test as it ran: with
+`${VAR}` placeholders substituted, `cases` expanded and `initial_state_file`
+read into `initial_state`, so it needs nothing besides the servers. Where
+the reference target returned a result, that result becomes the test's
+`expected`, and rerunning the file checks the other targets against it.
+Multi-step tests keep their full step list, each step with its own
+`expected`; streamed and `expect_error` tests keep what they had. Each
+test's description names the suite, file and test it came from and why it
+failed, and `snapshot_state` is dropped so a rerun doesn't overwrite the
+original's snapshot.
+
+The saved suite has the servers, encoding and hooks of the first suite with
+a failure; failures from suites with others are saved anyway, with a
+warning. A test sharing its name with one saved earlier gets its suite's
+name appended. Nothing is written when no test failed.
+*/
+
+use crate::hooks::Hook;
+use crate::rpc::PayloadEncoding;
+use crate::targets::Targets;
+use crate::{canonical, TestCase, TestResult, TestSuite};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value as JsonValue};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Test fields kept even when they hold a default-looking value, because
+/// `false`, `0` or `[]` there is what the test checks or sends
+const KEEP_FIELDS: &[&str] = &["arguments", "expected", "expected_state"];
+
+/// Lines of a failure message kept in a saved test's description
+const REASON_LINES: usize = 4;
+
+/// Failing tests collected over a run
+#[derive(Default)]
+pub struct Failures {
+    /// Servers, encoding and hooks of the first suite with a failure
+    header: Option<Map<String, JsonValue>>,
+    /// Names of the suites failures came from, in run order
+    suites: Vec<String>,
+    tests: Vec<TestCase>,
+}
+
+/// The suite-wide settings the saved tests run with
+#[derive(Serialize)]
+struct Header<'a> {
+    servers: &'a Targets,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<PayloadEncoding>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    setup: &'a [Hook],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    teardown: &'a [Hook],
+}
+
+impl Failures {
+    /// Keep the failing tests among `tests` of `suite`, in the same order as
+    /// their `results`
+    pub fn add(&mut self, suite: &TestSuite, tests: &[TestCase], results: &[TestResult]) {
+        let failed: Vec<(&TestCase, &TestResult)> = tests
+            .iter()
+            .zip(results)
+            .filter(|(_, result)| result.failed())
+            .collect();
+        if failed.is_empty() {
+            return;
+        }
+
+        let header = Header {
+            servers: &suite.servers,
+            encoding: suite.encoding,
+            setup: &suite.setup,
+            teardown: &suite.teardown,
+        };
+        let header = match serde_json::to_value(&header).expect("suite settings serialize") {
+            JsonValue::Object(header) => header,
+            _ => unreachable!("a struct serializes to a mapping"),
+        };
+        match &self.header {
+            None => self.header = Some(header),
+            Some(saved) if *saved != header => warn!(
+                "{}: saving {} failure(s) to run against the servers and hooks of '{}'",
+                suite.name,
+                failed.len(),
+                self.suites[0]
+            ),
+            Some(_) => {}
+        }
+        self.suites.push(suite.name.clone());
+
+        let reference = suite.servers.names().into_iter().next();
+        for (test, result) in failed {
+            let mut saved = test.clone();
+            if self.tests.iter().any(|t| t.name == saved.name) {
+                saved.name = format!("{} ({})", test.name, suite.name);
+            }
+            saved.snapshot_state = None;
+
+            let message = result.error_message.as_deref().unwrap_or("Test failed");
+            let mut reason: Vec<&str> = message.lines().take(REASON_LINES + 1).collect();
+            if reason.len() > REASON_LINES {
+                reason[REASON_LINES] = "...";
+            }
+            let mut description = format!(
+                "Failed in '{}' ({}) as '{}': {}",
+                suite.name,
+                test.source.display(),
+                test.name,
+                reason.join("\n")
+            );
+            if let Some(value) = reference_result(test, result, reference.as_deref()) {
+                if let Some(old) = test.expected.as_ref().filter(|old| *old != value) {
+                    let old = canonical::to_json_string(old);
+                    description.push_str(&format!("\nExpected {} before", old));
+                }
+                saved.expected = Some(value.clone());
+            }
+            if let Some(original) = &test.description {
+                description.push('\n');
+                description.push_str(original);
+            }
+            saved.description = Some(description);
+            self.tests.push(saved);
+        }
+    }
+
+    /// Write the saved tests to `path` as a suite, if there are any
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let Some(header) = &self.header else {
+            info!("No tests failed; {} not written", path.display());
+            return Ok(());
+        };
+
+        let mut suite = Map::new();
+        suite.insert("name".into(), "Saved failures".into());
+        let description = format!(
+            "{} failing test(s) from {}, saved with --save-failures",
+            self.tests.len(),
+            self.suites.join(", ")
+        );
+        suite.insert("description".into(), description.into());
+        suite.extend(header.clone());
+        let tests = self
+            .tests
+            .iter()
+            .map(|test| serde_json::to_value(test).map(prune))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to serialize the failing tests")?;
+        suite.insert("tests".into(), tests.into());
+
+        let content = canonical::to_yaml_string(&JsonValue::Object(suite));
+        self.check_round_trip(&content)?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write failing tests to {}", path.display()))?;
+        println!(
+            "Saved {} failing test(s) to {}; rerun them with --suite {}\n",
+            self.tests.len(),
+            path.display(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// The written suite must read back as the tests that were saved
+    fn check_round_trip(&self, content: &str) -> Result<()> {
+        let suite: TestSuite =
+            serde_yaml::from_str(content).context("The saved failures don't parse as a suite")?;
+        if suite.tests.len() != self.tests.len() {
+            anyhow::bail!("The saved failures don't read back: test count differs");
+        }
+        for (read, saved) in suite.tests.iter().zip(&self.tests) {
+            let intact = read.name == saved.name
+                && read.arguments == saved.arguments
+                && read.expected == saved.expected
+                && read.steps.len() == saved.steps.len();
+            if !intact {
+                anyhow::bail!(
+                    "The saved failures don't read back: '{}' changed",
+                    saved.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the reference returned for a single-call test, to expect on rerun
+fn reference_result<'r>(
+    test: &TestCase,
+    result: &'r TestResult,
+    reference: Option<&str>,
+) -> Option<&'r JsonValue> {
+    let expects_error = test.expect_error.as_ref().is_some_and(|e| e.is_set());
+    if !test.steps.is_empty() || test.stream || expects_error {
+        return None;
+    }
+    let outcome = result.targets.first()?;
+    if Some(outcome.target.as_str()) != reference || outcome.error.is_some() {
+        return None;
+    }
+    outcome.result.as_ref()
+}
+
+/// A serialized test without the fields left at their defaults, which make
+/// up most of it
+fn prune(test: JsonValue) -> JsonValue {
+    let JsonValue::Object(mut fields) = test else {
+        return test;
+    };
+    if let Some(JsonValue::Array(steps)) = fields.get_mut("steps") {
+        for step in steps.iter_mut().filter_map(JsonValue::as_object_mut) {
+            step.retain(|_, value| !value.is_null());
+        }
+    }
+    fields.retain(|key, value| {
+        let keep = KEEP_FIELDS.contains(&key.as_str());
+        match value {
+            JsonValue::Null => false,
+            _ if keep => true,
+            JsonValue::Bool(flag) => *flag,
+            JsonValue::Number(number) => number.as_u64() != Some(0),
+            JsonValue::String(text) => !text.is_empty(),
+            JsonValue::Array(items) => !items.is_empty(),
+            JsonValue::Object(map) => !map.is_empty(),
+        }
+    });
+    JsonValue::Object(fields)
+}
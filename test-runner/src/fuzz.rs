@@ -0,0 +1,251 @@
+/*!
+`--fuzz METHOD`: look for divergence on generated inputs.
+
+Rather than hand-written cases, the runner builds random arguments from the
+parameter types the servers declare in `ListMethods` and invokes the method
+on every server of the suite, `--fuzz-iterations` times:
+
+```bash
+test-runner --suite test-defs/simple_math.yaml --fuzz add --fuzz-iterations 500
+```
+
+Integers are drawn from `-N..=N` (`--fuzz-max-int`, 1000 by default),
+floats from the same range, and both favour edge values such as zero,
+`±1` and the range ends. Strings are short and mix ASCII with multi-byte
+characters. An input diverges when the results differ or when some servers
+fail and others don't; inputs every server rejects agree. The first
+divergence of each method is printed as a test to paste into the suite.
+`--seed` replays the same inputs.
+
+Declarations only give types, so parameter names come from `name: type`
+declarations, or else from an existing test of the method in the suite.
+Methods whose parameters can't be named or typed, stateful methods and
+streaming methods are skipped with a warning.
+*/
+
+use crate::compare::CompareOptions;
+use crate::random::XorShift;
+use crate::record::flow;
+use crate::rpc::{self, Execution};
+use crate::{canonical, TestCase, TestRunner};
+use anyhow::{Context, Result};
+use colored::*;
+use serde_json::{json, Value as JsonValue};
+use tracing::warn;
+
+/// Inputs generated per method when `--fuzz-iterations` isn't given
+pub const DEFAULT_ITERATIONS: u32 = 100;
+/// Largest integer magnitude when `--fuzz-max-int` isn't given
+pub const DEFAULT_MAX_INT: i64 = 1000;
+
+/// Characters generated strings are made of
+const STRING_CHARS: &[char] = &[
+    'a', 'b', 'z', 'A', 'Z', '0', '9', ' ', '_', '-', 'é', 'ß', '中', '🙂',
+];
+const MAX_STRING_LEN: usize = 8;
+
+pub struct FuzzOptions {
+    pub iterations: u32,
+    pub max_int: i64,
+    pub seed: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParamType {
+    Int,
+    Float,
+    Str,
+    Bool,
+}
+
+impl ParamType {
+    fn parse(declared: &str) -> Option<Self> {
+        match declared.trim().to_ascii_lowercase().as_str() {
+            "int" | "integer" | "i32" | "i64" | "u32" | "u64" | "long" => Some(Self::Int),
+            "float" | "double" | "f32" | "f64" | "number" => Some(Self::Float),
+            "str" | "string" => Some(Self::Str),
+            "bool" | "boolean" => Some(Self::Bool),
+            _ => None,
+        }
+    }
+}
+
+/// Parameter names and types of a method, in declaration order
+type Signature = Vec<(String, ParamType)>;
+
+/// Fuzz every method in `methods` on the servers behind `runner`, naming
+/// parameters from `tests` where needed; the number of diverging inputs
+pub async fn run(
+    runner: &mut TestRunner,
+    tests: &[TestCase],
+    methods: &[String],
+    options: &FuzzOptions,
+) -> Result<usize> {
+    let reference = runner.names[0].clone();
+    let infos = rpc::method_infos(&mut runner.clients[0])
+        .await
+        .with_context(|| format!("Failed to list methods of {} server", reference))?;
+    let mut rng = XorShift::new(options.seed);
+    let mut divergences = 0;
+
+    for method in methods {
+        let Some(info) = infos.iter().find(|info| &info.name == method) else {
+            warn!(
+                "Skipping {}: not registered on the {} server",
+                method, reference
+            );
+            continue;
+        };
+        if info.is_stateful {
+            warn!("Skipping {}: stateful methods aren't fuzzed", method);
+            continue;
+        }
+        if info.is_streaming {
+            warn!("Skipping {}: streaming methods aren't fuzzed", method);
+            continue;
+        }
+        let signature = match signature(method, &info.parameter_types, tests) {
+            Ok(signature) => signature,
+            Err(why) => {
+                warn!("Skipping {}: {}", method, why);
+                continue;
+            }
+        };
+
+        let mut diverged = 0;
+        for iteration in 1..=options.iterations {
+            let arguments = generate(&signature, &mut rng, options.max_int);
+            let args_json = canonical::to_json_string(&arguments);
+            let mut executions = Vec::with_capacity(runner.clients.len());
+            for target in 0..runner.clients.len() {
+                executions.push(runner.server(target).invoke("", method, &args_json).await);
+            }
+            let Some(message) = divergence(runner, &executions) else {
+                continue;
+            };
+
+            println!(
+                "{} {} {}: {}",
+                "✗".bright_red().bold(),
+                method,
+                flow(&arguments),
+                message.lines().next().unwrap_or_default()
+            );
+            if diverged == 0 {
+                print_regression(method, &arguments, &message, options.seed, iteration);
+            }
+            diverged += 1;
+        }
+
+        let summary = format!(
+            "{}: {} input(s), {} diverged (seed {})",
+            method, options.iterations, diverged, options.seed
+        );
+        if diverged == 0 {
+            println!("{} {}", "✓".bright_green().bold(), summary);
+        } else {
+            println!("{} {}\n", "✗".bright_red().bold(), summary.bright_red());
+        }
+        divergences += diverged;
+    }
+    Ok(divergences)
+}
+
+/// Names and types of `method`'s parameters, or why they can't be known
+fn signature(method: &str, declared: &[String], tests: &[TestCase]) -> Result<Signature, String> {
+    let mut names = Vec::with_capacity(declared.len());
+    let mut types = Vec::with_capacity(declared.len());
+    for declaration in declared {
+        let (name, type_name) = match declaration.split_once(':') {
+            Some((name, type_name)) => (Some(name.trim().to_string()), type_name),
+            None => (None, declaration.as_str()),
+        };
+        let param_type = ParamType::parse(type_name)
+            .ok_or_else(|| format!("unsupported parameter type `{}`", type_name.trim()))?;
+        names.push(name);
+        types.push(param_type);
+    }
+    if names.iter().all(Option::is_some) {
+        return Ok(names.into_iter().flatten().zip(types).collect());
+    }
+
+    // Argument keys come back sorted, so they can only be paired with
+    // types in declaration order when every type is the same
+    let example = tests
+        .iter()
+        .filter(|test| test.method == method && test.steps.is_empty())
+        .filter_map(|test| test.arguments.as_object())
+        .find(|arguments| arguments.len() == declared.len())
+        .ok_or("parameter types are declared without names, and no test shows the names")?;
+    if types.windows(2).any(|pair| pair[0] != pair[1]) {
+        return Err(
+            "parameters of different types are declared without names (use `name: type`)".into(),
+        );
+    }
+    Ok(example.keys().cloned().zip(types).collect())
+}
+
+/// Random arguments for `signature`
+fn generate(signature: &Signature, rng: &mut XorShift, max_int: i64) -> JsonValue {
+    let mut arguments = serde_json::Map::new();
+    for (name, param_type) in signature {
+        let value = match param_type {
+            ParamType::Int => json!(int(rng, max_int)),
+            ParamType::Float => json!(float(rng, max_int)),
+            ParamType::Str => {
+                let len = rng.below(MAX_STRING_LEN + 1);
+                let text: String = (0..len)
+                    .map(|_| STRING_CHARS[rng.below(STRING_CHARS.len())])
+                    .collect();
+                json!(text)
+            }
+            ParamType::Bool => json!(rng.below(2) == 1),
+        };
+        arguments.insert(name.clone(), value);
+    }
+    arguments.into()
+}
+
+/// An edge value a quarter of the time, otherwise uniform in `-max..=max`
+fn int(rng: &mut XorShift, max: i64) -> i64 {
+    let max = max.max(1);
+    if rng.below(4) == 0 {
+        let edges = [0, 1, -1, max, -max];
+        return edges[rng.below(edges.len())];
+    }
+    let span = (max as u64) * 2 + 1;
+    (rng.next() % span) as i64 - max
+}
+
+fn float(rng: &mut XorShift, max: i64) -> f64 {
+    let max = max.max(1) as f64;
+    if rng.below(4) == 0 {
+        let edges = [0.0, -0.0, 0.5, -0.5, 1e-9, -1e-9, max, -max];
+        return edges[rng.below(edges.len())];
+    }
+    let unit = (rng.next() >> 11) as f64 / (1u64 << 53) as f64;
+    (unit * 2.0 - 1.0) * max
+}
+
+/// How `executions` disagree, if they do; inputs every target rejects are
+/// agreement
+fn divergence(runner: &TestRunner, executions: &[Execution]) -> Option<String> {
+    if executions.iter().all(|e| e.error.is_some()) {
+        return None;
+    }
+    runner
+        .compare_results(executions, &None, &CompareOptions::default())
+        .err()
+        .map(|mismatch| mismatch.message)
+}
+
+/// The diverging input as a test ready to paste into the suite
+fn print_regression(method: &str, arguments: &JsonValue, message: &str, seed: u64, n: u32) {
+    println!("\n  Regression test (seed {}, input {}):\n", seed, n);
+    for line in message.lines() {
+        println!("  # {}", line);
+    }
+    println!("  - name: {}_fuzz_{}_{}", method, seed, n);
+    println!("    method: {}", method);
+    println!("    arguments: {}\n", flow(arguments));
+}
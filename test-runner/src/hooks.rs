@@ -0,0 +1,115 @@
+/*!
+`setup` and `teardown`: calls made around tests rather than judged as part
+of them.
+
+```yaml
+setup:
+  - method: reset_cache
+tests:
+  - name: pop_after_pushes
+    method: stack_pop
+    stateful: true
+    setup:
+      - method: stack_push
+        arguments: {value: 1}
+    expected: 1
+```
+
+A suite's `setup` runs once on every server before any of its tests, and its
+`teardown` once after all of them, outside any context. A test's own
+`setup` and `teardown` run around it on every target, a stateful test's in
+its context, so setup can build up the state the test starts from. Results
+of hooks are never compared.
+
+A setup call that fails aborts what it guards: the whole suite, or the one
+test, is reported skipped with the failure rather than failed, since the
+test itself never ran. Teardown runs regardless, after failed setups and
+failed tests too, and its failures are only logged, like context cleanup's.
+*/
+
+use crate::rpc::Server;
+use crate::{canonical, TestRunner};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// One call of a `setup` or `teardown` list
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Hook {
+    pub method: String,
+    #[serde(default = "crate::empty_arguments")]
+    pub arguments: serde_json::Value,
+}
+
+/// Make the calls of `hooks` in order, in `context_id` (none if empty),
+/// stopping at the first that fails
+pub async fn setup(
+    server: &mut Server<'_>,
+    context_id: &str,
+    hooks: &[Hook],
+) -> Result<(), String> {
+    for (index, hook) in hooks.iter().enumerate() {
+        if let Some(error) = call(server, context_id, hook).await {
+            return Err(format!(
+                "{} ({}/{}): {}",
+                hook.method,
+                index + 1,
+                hooks.len(),
+                error
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Make every call of `hooks`, whatever fails; failures are logged against
+/// `scope`, the test or suite torn down
+pub async fn teardown(server: &mut Server<'_>, context_id: &str, hooks: &[Hook], scope: &str) {
+    for (index, hook) in hooks.iter().enumerate() {
+        if let Some(error) = call(server, context_id, hook).await {
+            warn!(
+                "{}: teardown {} ({}/{}) failed on {}: {}",
+                scope,
+                hook.method,
+                index + 1,
+                hooks.len(),
+                server.name,
+                error
+            );
+        }
+    }
+}
+
+/// Run the suite's `setup` on every server, failing with the message its
+/// skipped tests report
+pub async fn suite_setup(runner: &mut TestRunner) -> Result<(), String> {
+    let hooks = runner.options.setup.clone();
+    for target in 0..runner.clients.len() {
+        let mut server = runner.server(target);
+        if let Err(failure) = setup(&mut server, "", &hooks).await {
+            return Err(format!(
+                "Suite setup failed on {}: {}",
+                server.name, failure
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Run the suite's `teardown` on every server
+pub async fn suite_teardown(runner: &mut TestRunner) {
+    let hooks = runner.options.teardown.clone();
+    for target in 0..runner.clients.len() {
+        teardown(&mut runner.server(target), "", &hooks, "suite").await;
+    }
+}
+
+/// The error of calling `hook`, if it failed
+async fn call(server: &mut Server<'_>, context_id: &str, hook: &Hook) -> Option<String> {
+    let args_json = server
+        .usage
+        .serialize(|| canonical::to_json_string(&hook.arguments));
+    server
+        .invoke(context_id, &hook.method, &args_json)
+        .await
+        .error
+}
@@ -0,0 +1,454 @@
+/*!
+Self-contained HTML report for sharing a run with people who don't run it.
+
+One file with the CSS and the little JavaScript it needs embedded, so it
+can be attached to a PR or an issue as is. Each suite gets a table of its
+tests that sorts by any column when its header is clicked: status, every
+target's time and, for targets after the reference, the time relative to
+the reference with a bar (green when faster, red when slower). Failed tests
+expand to the failure message, which holds the structural diff, and every
+target's raw result.
+*/
+
+use crate::canonical;
+use crate::suites::{LoadError, SuiteRun};
+use crate::{Status, TestResult};
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Ratio at which a time bar fills its cell
+const MAX_BAR_RATIO: f64 = 4.0;
+
+const STYLE: &str = r#"
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+h1 { margin-bottom: 0.2em; }
+.summary span { margin-right: 1.5em; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { cursor: pointer; background: #f4f4f4; user-select: none; }
+th:hover { background: #e8e8e8; }
+.pass { color: #1a7f37; }
+.fail { color: #cf222e; }
+.skip { color: #9a6700; }
+.num { text-align: right; font-variant-numeric: tabular-nums; }
+.bar { display: inline-block; height: 0.7em; margin-right: 0.4em; }
+.faster { background: #4ac26b; }
+.slower { background: #ff8182; }
+pre { background: #f6f8fa; padding: 0.5em; overflow-x: auto; white-space: pre-wrap; }
+"#;
+
+/// Sorts a table by the clicked column, using `data-sort` where a cell has one
+const SCRIPT: &str = r#"
+document.querySelectorAll("th").forEach(function (th) {
+  th.addEventListener("click", function () {
+    var table = th.closest("table");
+    var body = table.tBodies[0];
+    var column = th.cellIndex;
+    var ascending = th.dataset.order !== "asc";
+    table.querySelectorAll("th").forEach(function (other) { delete other.dataset.order; });
+    th.dataset.order = ascending ? "asc" : "desc";
+    var key = function (row) {
+      var cell = row.cells[column];
+      var value = cell.dataset.sort !== undefined ? cell.dataset.sort : cell.textContent;
+      var number = parseFloat(value);
+      return isNaN(number) ? value.toLowerCase() : number;
+    };
+    Array.from(body.rows)
+      .sort(function (a, b) {
+        var x = key(a), y = key(b);
+        var order = x < y ? -1 : x > y ? 1 : 0;
+        return ascending ? order : -order;
+      })
+      .forEach(function (row) { body.appendChild(row); });
+  });
+});
+"#;
+
+/// Write the report for a finished run to `path`
+pub fn write(path: &Path, runs: &[SuiteRun], errors: &[LoadError], total: Duration) -> Result<()> {
+    std::fs::write(path, render(runs, errors, total))
+        .with_context(|| format!("Failed to write HTML report {}", path.display()))
+}
+
+fn render(runs: &[SuiteRun], errors: &[LoadError], total: Duration) -> String {
+    let results = runs.iter().flat_map(|run| &run.results);
+    let count = |status| results.clone().filter(|r| r.status == status).count();
+    let (passed, failed) = (count(Status::Passed), count(Status::Failed));
+    let (xfailed, xpassed) = (count(Status::XFailed), count(Status::XPassed));
+    let deselected: usize = runs.iter().map(|run| run.skipped.len()).sum();
+    let skipped = count(Status::Skipped) + deselected;
+
+    let mut html = String::from("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n");
+    html.push_str("<meta charset=\"utf-8\">\n<title>Test run report</title>\n");
+    let _ = writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE);
+    html.push_str("<h1>Test run report</h1>\n<p class=\"summary\">");
+    let _ = write!(
+        html,
+        "<span class=\"pass\">{} passed</span><span class=\"fail\">{} failed</span>",
+        passed, failed
+    );
+    if xfailed > 0 {
+        let _ = write!(html, "<span class=\"skip\">{} xfailed</span>", xfailed);
+    }
+    if xpassed > 0 {
+        let _ = write!(html, "<span class=\"skip\">{} xpassed</span>", xpassed);
+    }
+    if skipped > 0 {
+        let _ = write!(html, "<span class=\"skip\">{} skipped</span>", skipped);
+    }
+    let _ = writeln!(
+        html,
+        "<span>{} suites</span><span>{:.2}s</span></p>",
+        runs.len() + errors.len(),
+        total.as_secs_f64()
+    );
+
+    for error in errors {
+        let _ = writeln!(
+            html,
+            "<h2 class=\"fail\">{}</h2>\n<pre>Failed to load suite: {}</pre>",
+            escape(&error.path.display().to_string()),
+            escape(&error.message)
+        );
+    }
+    for run in runs {
+        render_suite(&mut html, run);
+    }
+
+    let _ = writeln!(html, "<script>{}</script>\n</body>\n</html>", SCRIPT);
+    html
+}
+
+fn render_suite(html: &mut String, run: &SuiteRun) {
+    let passed = run
+        .results
+        .iter()
+        .filter(|r| r.status == Status::Passed)
+        .count();
+    let ran = run
+        .results
+        .iter()
+        .filter(|r| r.status != Status::Skipped)
+        .count();
+    let _ = writeln!(
+        html,
+        "<h2>{}</h2>\n<p>{} &middot; {}/{} passed &middot; {:.2}s</p>",
+        escape(&run.name),
+        escape(&run.path.display().to_string()),
+        passed,
+        ran,
+        run.elapsed.as_secs_f64()
+    );
+
+    let targets = target_names(&run.results);
+    html.push_str("<table>\n<thead><tr><th>Test</th><th>Status</th>");
+    for (index, target) in targets.iter().enumerate() {
+        let _ = write!(html, "<th>{} (&micro;s)</th>", escape(target));
+        if index > 0 {
+            let _ = write!(
+                html,
+                "<th>{} / {}</th>",
+                escape(target),
+                escape(&targets[0])
+            );
+        }
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+
+    for result in &run.results {
+        render_result(html, result, &targets);
+    }
+    for test in &run.skipped {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td class=\"skip\">skipped</td>",
+            escape(&test.name)
+        );
+        // Skipped tests have no timings; empty cells keep the columns aligned
+        for index in 0..targets.len() {
+            html.push_str("<td></td>");
+            if index > 0 {
+                html.push_str("<td></td>");
+            }
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+fn render_result(html: &mut String, result: &TestResult, targets: &[String]) {
+    html.push_str("<tr><td>");
+    if result.error_message.is_none() {
+        html.push_str(&escape(&result.name));
+    } else {
+        let _ = write!(
+            html,
+            "<details><summary>{}</summary>{}</details>",
+            escape(&result.name),
+            failure_details(result)
+        );
+    }
+    html.push_str("</td>");
+
+    let (class, status) = match result.status {
+        Status::Passed => ("pass", "passed"),
+        Status::XFailed => ("skip", "xfailed"),
+        Status::XPassed => ("skip", "xpassed"),
+        Status::Skipped => ("skip", "skipped"),
+        Status::Failed if result.transport_error => ("fail", "error"),
+        Status::Failed => ("fail", "failed"),
+    };
+    let cached = if result.cached { " (cached pass)" } else { "" };
+    let _ = write!(html, "<td class=\"{}\">{}{}</td>", class, status, cached);
+
+    let time_of = |name: &str| {
+        result
+            .targets
+            .iter()
+            .find(|t| t.target == name)
+            .and_then(|t| t.time_us)
+    };
+    let reference = targets.first().and_then(|name| time_of(name));
+    for (index, target) in targets.iter().enumerate() {
+        let time = time_of(target);
+        match time {
+            Some(us) => {
+                let _ = write!(html, "<td class=\"num\" data-sort=\"{}\">{}</td>", us, us);
+            }
+            None => html.push_str("<td class=\"num\" data-sort=\"-1\">-</td>"),
+        }
+        if index > 0 {
+            html.push_str(&ratio_cell(reference, time));
+        }
+    }
+    html.push_str("</tr>\n");
+}
+
+/// Time relative to the reference, with a bar whose length grows with the
+/// ratio (or its inverse when faster) up to `MAX_BAR_RATIO`
+fn ratio_cell(reference: Option<i64>, time: Option<i64>) -> String {
+    let (reference, time) = match (reference, time) {
+        (Some(reference), Some(time)) if reference > 0 && time > 0 => (reference, time),
+        _ => return "<td class=\"num\" data-sort=\"-1\">-</td>".to_string(),
+    };
+    let ratio = time as f64 / reference as f64;
+    let (class, magnitude) = if ratio <= 1.0 {
+        ("faster", 1.0 / ratio)
+    } else {
+        ("slower", ratio)
+    };
+    let width = (magnitude.min(MAX_BAR_RATIO) / MAX_BAR_RATIO * 100.0).round();
+    format!(
+        "<td class=\"num\" data-sort=\"{:.4}\"><span class=\"bar {}\" style=\"width: {}px\"></span>{:.2}&times;</td>",
+        ratio, class, width, ratio
+    )
+}
+
+/// The failure message and every target's raw result or error
+fn failure_details(result: &TestResult) -> String {
+    let mut details = String::new();
+    if let Some(message) = &result.error_message {
+        let _ = write!(details, "<pre>{}</pre>", escape(message));
+    }
+    for target in &result.targets {
+        let text = match (&target.result, &target.error) {
+            (Some(value), _) => canonical::to_json_string(value),
+            (None, Some(error)) => format!("error: {}", error),
+            (None, None) => "(no result)".to_string(),
+        };
+        let _ = write!(
+            details,
+            "<p>{}</p><pre>{}</pre>",
+            escape(&target.target),
+            escape(&text)
+        );
+    }
+    details
+}
+
+/// Every target that reported a result, in the order of the first test
+/// naming it; the reference comes first
+fn target_names(results: &[TestResult]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for target in results.iter().flat_map(|r| &r.targets) {
+        if !names.contains(&target.target) {
+            names.push(target.target.clone());
+        }
+    }
+    names
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Python is the reference: one pass where Rust is faster, one failure
+    /// with a diff, one transport error and one deselected test
+    fn runs() -> Vec<SuiteRun> {
+        let results = [
+            json!({
+                "name": "add",
+                "passed": true,
+                "status": "passed",
+                "targets": [
+                    {"target": "python", "result": 3, "error": null, "time_us": 200},
+                    {"target": "rust", "result": 3, "error": null, "time_us": 50},
+                ],
+            }),
+            json!({
+                "name": "split <words>",
+                "passed": false,
+                "status": "failed",
+                "targets": [
+                    {"target": "python", "result": ["a", "b"], "error": null, "time_us": 100},
+                    {"target": "rust", "result": ["a & b"], "error": null, "time_us": 300},
+                ],
+                "error_message": "$[0]: \"a\" != \"a & b\"",
+            }),
+            json!({
+                "name": "divide",
+                "passed": false,
+                "status": "failed",
+                "targets": [
+                    {"target": "python", "result": 2, "error": null, "time_us": 80},
+                    {"target": "rust", "result": null, "error": "connection refused",
+                     "time_us": null},
+                ],
+                "error_message": "rust: connection refused",
+                "transport_error": true,
+            }),
+        ];
+        vec![SuiteRun {
+            name: "Simple Math".to_string(),
+            path: "test-defs/simple_math.yaml".into(),
+            results: results
+                .into_iter()
+                .map(|r| serde_json::from_value(r).unwrap())
+                .collect(),
+            skipped: vec![serde_yaml::from_str("{name: slow_sum, method: add}").unwrap()],
+            elapsed: Duration::from_millis(1500),
+            coverage: None,
+        }]
+    }
+
+    fn report() -> String {
+        let errors = [LoadError {
+            path: "test-defs/broken.yaml".into(),
+            message: "expected a mapping".to_string(),
+        }];
+        render(&runs(), &errors, Duration::from_millis(2000))
+    }
+
+    /// The `<tr>` of the test named `name`
+    fn row<'a>(html: &'a str, name: &str) -> &'a str {
+        let start = html.find(&format!("<tr><td>{}", name)).unwrap_or_else(|| {
+            let details = format!("<tr><td><details><summary>{}", name);
+            html.find(&details).expect(name)
+        });
+        let end = start + html[start..].find("</tr>").unwrap();
+        &html[start..end]
+    }
+
+    #[test]
+    fn summary_counts_every_status() {
+        let html = report();
+        assert!(html.contains("<span class=\"pass\">1 passed</span>"));
+        assert!(html.contains("<span class=\"fail\">2 failed</span>"));
+        assert!(html.contains("<span class=\"skip\">1 skipped</span>"));
+        assert!(html.contains("<span>2 suites</span><span>2.00s</span>"));
+        assert!(
+            html.contains("<p>test-defs/simple_math.yaml &middot; 1/3 passed &middot; 1.50s</p>")
+        );
+        assert!(html.contains("<pre>Failed to load suite: expected a mapping</pre>"));
+    }
+
+    #[test]
+    fn columns_follow_the_reference_target() {
+        let html = report();
+        assert!(html.contains(
+            "<tr><th>Test</th><th>Status</th><th>python (&micro;s)</th>\
+             <th>rust (&micro;s)</th><th>rust / python</th></tr>"
+        ));
+        // The deselected test keeps the columns aligned
+        assert_eq!(
+            row(&html, "slow_sum"),
+            "<tr><td>slow_sum</td><td class=\"skip\">skipped</td><td></td><td></td><td></td>"
+        );
+    }
+
+    #[test]
+    fn ratio_bars_show_faster_and_slower() {
+        let html = report();
+        let add = row(&html, "add");
+        assert!(add.contains("<td class=\"pass\">passed</td>"));
+        assert!(
+            add.contains("data-sort=\"0.2500\"><span class=\"bar faster\" style=\"width: 100px\">")
+        );
+        assert!(add.contains("0.25&times;"));
+        let split = row(&html, "split &lt;words&gt;");
+        assert!(split.contains("<span class=\"bar slower\" style=\"width: 75px\">"));
+        assert!(split.contains("3.00&times;"));
+        // No time to compare against
+        let divide = row(&html, "divide");
+        assert!(divide.contains("<td class=\"fail\">error</td>"));
+        assert!(divide.ends_with(
+            "<td class=\"num\" data-sort=\"-1\">-</td><td class=\"num\" data-sort=\"-1\">-</td>"
+        ));
+    }
+
+    #[test]
+    fn only_failures_expand_to_their_results() {
+        let html = report();
+        assert!(!row(&html, "add").contains("<details>"));
+        let split = row(&html, "split &lt;words&gt;");
+        assert!(split.contains("<pre>$[0]: &quot;a&quot; != &quot;a &amp; b&quot;</pre>"));
+        assert!(split.contains("<p>python</p><pre>[&quot;a&quot;,&quot;b&quot;]</pre>"));
+        assert!(split.contains("<p>rust</p><pre>[&quot;a &amp; b&quot;]</pre>"));
+        let divide = row(&html, "divide");
+        assert!(divide.contains("<p>rust</p><pre>error: connection refused</pre>"));
+    }
+
+    #[test]
+    fn report_is_self_contained() {
+        let html = report();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.ends_with("</html>\n"));
+        assert!(html.contains("<style>") && html.contains("<script>"));
+        for external in ["src=", "href=", "<link", "@import", "url("] {
+            assert!(
+                !html.contains(external),
+                "references an external asset: {}",
+                external
+            );
+        }
+    }
+
+    #[test]
+    fn ratio_needs_both_times() {
+        let empty = "<td class=\"num\" data-sort=\"-1\">-</td>";
+        assert_eq!(ratio_cell(None, Some(10)), empty);
+        assert_eq!(ratio_cell(Some(10), None), empty);
+        assert_eq!(ratio_cell(Some(0), Some(10)), empty);
+        // Bars stop growing at MAX_BAR_RATIO
+        assert!(ratio_cell(Some(10), Some(100)).contains("width: 100px"));
+        assert!(ratio_cell(Some(10), Some(10)).contains("bar faster\" style=\"width: 25px"));
+    }
+}
@@ -0,0 +1,265 @@
+/*!
+JSON paths naming parts of a result, as used by `ignore_paths`.
+
+The syntax is the one failure messages print: `$` for the whole value,
+`.key` or `["key"]` for an object member, `[3]` for an array element, and
+`[*]` or `.*` for every element or member:
+
+```yaml
+ignore_paths: ["$.created_at", "$.items[*].uuid"]
+```
+
+Paths are parsed when the suite is loaded, so a malformed one fails the
+load with its line rather than the run.
+*/
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    /// Every element of an array or member of an object
+    Any,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct JsonPath {
+    text: String,
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = |why: &str| format!("invalid JSON path `{}`: {}", text, why);
+        let mut rest = text
+            .strip_prefix('$')
+            .ok_or_else(|| invalid("must start with `$`"))?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                let key = &after[..end];
+                segments.push(match key {
+                    "" => return Err(invalid("empty key after `.`")),
+                    "*" => Segment::Any,
+                    key => Segment::Key(key.to_string()),
+                });
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let (segment, after) = bracket(after).map_err(|why| invalid(&why))?;
+                segments.push(segment);
+                rest = after;
+            } else {
+                return Err(invalid("expected `.` or `[`"));
+            }
+        }
+        Ok(Self {
+            text: text.to_string(),
+            segments,
+        })
+    }
+
+    /// Remove whatever the path names from `value`; paths that don't exist
+    /// in it are fine
+    pub fn remove(&self, value: &mut JsonValue) {
+        remove(&self.segments, value);
+    }
+}
+
+/// The segment of a `[...]` whose `[` has been consumed, and what follows it
+fn bracket(text: &str) -> Result<(Segment, &str), String> {
+    if let Some(after) = text.strip_prefix("*]") {
+        return Ok((Segment::Any, after));
+    }
+    if let Some(quote) = text.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let body = &text[1..];
+        // Skip escaped quotes in double-quoted keys
+        let mut escaped = false;
+        let end = body
+            .char_indices()
+            .find(|&(_, c)| {
+                let close = c == quote && !escaped;
+                escaped = quote == '"' && c == '\\' && !escaped;
+                close
+            })
+            .map(|(end, _)| end)
+            .ok_or_else(|| "unterminated quoted key".to_string())?;
+        let key = &body[..end];
+        let after = body[end + 1..]
+            .strip_prefix(']')
+            .ok_or_else(|| "expected `]` after quoted key".to_string())?;
+        // Double-quoted keys are JSON strings, as failure messages print them
+        let key = if quote == '"' {
+            serde_json::from_str(&format!("\"{}\"", key))
+                .map_err(|e| format!("invalid quoted key: {}", e))?
+        } else {
+            key.to_string()
+        };
+        return Ok((Segment::Key(key), after));
+    }
+    let end = text
+        .find(']')
+        .ok_or_else(|| "unterminated `[`".to_string())?;
+    let index = text[..end]
+        .parse()
+        .map_err(|_| format!("`[{}]` is not an index, `*` or quoted key", &text[..end]))?;
+    Ok((Segment::Index(index), &text[end + 1..]))
+}
+
+fn remove(segments: &[Segment], value: &mut JsonValue) {
+    let Some((segment, rest)) = segments.split_first() else {
+        return;
+    };
+    match (segment, value) {
+        (Segment::Key(key), JsonValue::Object(fields)) => {
+            if rest.is_empty() {
+                fields.remove(key);
+            } else if let Some(field) = fields.get_mut(key) {
+                remove(rest, field);
+            }
+        }
+        (Segment::Index(index), JsonValue::Array(items)) => {
+            if rest.is_empty() {
+                if *index < items.len() {
+                    items.remove(*index);
+                }
+            } else if let Some(item) = items.get_mut(*index) {
+                remove(rest, item);
+            }
+        }
+        (Segment::Any, JsonValue::Array(items)) => {
+            if rest.is_empty() {
+                items.clear();
+            } else {
+                items.iter_mut().for_each(|item| remove(rest, item));
+            }
+        }
+        (Segment::Any, JsonValue::Object(fields)) => {
+            if rest.is_empty() {
+                fields.clear();
+            } else {
+                fields.values_mut().for_each(|field| remove(rest, field));
+            }
+        }
+        _ => {}
+    }
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Self::parse(&text)
+    }
+}
+
+impl From<JsonPath> for String {
+    fn from(path: JsonPath) -> Self {
+        path.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use Segment::{Any, Index};
+
+    fn key(key: &str) -> Segment {
+        Segment::Key(key.to_string())
+    }
+
+    #[test]
+    fn paths_parse_into_segments() {
+        let cases = [
+            ("$", vec![]),
+            ("$.a", vec![key("a")]),
+            ("$.a.b", vec![key("a"), key("b")]),
+            ("$[3]", vec![Index(3)]),
+            ("$.items[0].uuid", vec![key("items"), Index(0), key("uuid")]),
+            ("$.items[*].uuid", vec![key("items"), Any, key("uuid")]),
+            ("$.*.id", vec![Any, key("id")]),
+            (r#"$["odd key"]"#, vec![key("odd key")]),
+            (r#"$["a.b[0]"]"#, vec![key("a.b[0]")]),
+            (r#"$["quote \" and \u00e9"]"#, vec![key("quote \" and é")]),
+            ("$['single']", vec![key("single")]),
+            (r#"$['no \u00e9 escapes']"#, vec![key(r"no \u00e9 escapes")]),
+        ];
+        for (text, segments) in cases {
+            let path = JsonPath::parse(text).unwrap_or_else(|e| panic!("{}", e));
+            assert_eq!(path.segments, segments, "{}", text);
+        }
+    }
+
+    #[test]
+    fn paths_in_failure_messages_parse_back() {
+        for name in [
+            "plain",
+            "odd key",
+            "a.b",
+            "quote \" here",
+            "back\\slash",
+            "é",
+        ] {
+            let text = format!("${}", crate::diff::key_segment(name));
+            let path = JsonPath::parse(&text).unwrap_or_else(|e| panic!("{}", e));
+            assert_eq!(path.segments, vec![key(name)], "{}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_paths_say_why() {
+        let cases = [
+            ("a", "must start with `$`"),
+            ("$.", "empty key after `.`"),
+            ("$..a", "empty key after `.`"),
+            ("$a", "expected `.` or `[`"),
+            ("$[", "unterminated `[`"),
+            ("$[1", "unterminated `[`"),
+            ("$[-1]", "`[-1]` is not an index, `*` or quoted key"),
+            ("$[x]", "`[x]` is not an index, `*` or quoted key"),
+            (r#"$["a]"#, "unterminated quoted key"),
+            (r#"$["a\"]"#, "unterminated quoted key"),
+            (r#"$["a"x]"#, "expected `]` after quoted key"),
+        ];
+        for (text, why) in cases {
+            let error = JsonPath::parse(text).unwrap_err();
+            assert_eq!(error, format!("invalid JSON path `{}`: {}", text, why));
+        }
+    }
+
+    #[test]
+    fn remove_drops_what_the_path_names() {
+        let value = json!({"id": 1, "items": [{"uuid": "a", "n": 1}, {"uuid": "b", "n": 2}]});
+        let cases = [
+            (
+                "$.id",
+                json!({"items": [{"uuid": "a", "n": 1}, {"uuid": "b", "n": 2}]}),
+            ),
+            (
+                "$.items[0]",
+                json!({"id": 1, "items": [{"uuid": "b", "n": 2}]}),
+            ),
+            (
+                "$.items[*].uuid",
+                json!({"id": 1, "items": [{"n": 1}, {"n": 2}]}),
+            ),
+            ("$.items[*]", json!({"id": 1, "items": []})),
+            ("$.*", json!({})),
+            // Paths the value doesn't have leave it as it is
+            ("$.missing.deeper", value.clone()),
+            ("$.items[5]", value.clone()),
+            ("$.id.deeper", value.clone()),
+            ("$.items.uuid", value.clone()),
+            ("$[0]", value.clone()),
+        ];
+        for (text, expected) in cases {
+            let mut removed = value.clone();
+            JsonPath::parse(text).unwrap().remove(&mut removed);
+            assert_eq!(removed, expected, "{}", text);
+        }
+    }
+}
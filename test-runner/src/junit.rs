@@ -0,0 +1,210 @@
+/*!
+JUnit XML report for CI dashboards.
+
+`<testsuites>` holds a `<testsuite>` per suite file, each with a
+`<testcase>` per result. Mismatched or failing results become `<failure>`;
+tests that never got a proper answer from a server (transport errors,
+runner errors) become `<error>`, which most CI tools show separately from
+assertion failures. Tests left out by
+`--filter`/`--tag` are listed as `<skipped/>`. Failures of tests marked
+`allow_failure` are `<skipped type="xfail">` with the failure as message,
+as pytest reports them, and those that pass anyway say XPASS in their
+`<system-out>`. A suite file that failed to load becomes a suite with a
+single errored `load` test.
+*/
+
+use crate::canonical;
+use crate::suites::{LoadError, SuiteRun};
+use crate::{Status, TestResult};
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Write the report for a finished run to `path`
+pub fn write(path: &Path, runs: &[SuiteRun], errors: &[LoadError], total: Duration) -> Result<()> {
+    std::fs::write(path, render(runs, errors, total))
+        .with_context(|| format!("Failed to write JUnit report {}", path.display()))
+}
+
+fn render(runs: &[SuiteRun], errors: &[LoadError], total: Duration) -> String {
+    let results = runs.iter().flat_map(|run| &run.results);
+    let tests = results.clone().count() + runs.iter().map(|r| r.skipped.len()).sum::<usize>();
+    let failures = results
+        .clone()
+        .filter(|r| r.failed() && !r.transport_error)
+        .count();
+    let test_errors = results.filter(|r| r.failed() && r.transport_error).count();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.6}\">",
+        tests + errors.len(),
+        failures,
+        test_errors + errors.len(),
+        total.as_secs_f64()
+    );
+    for run in runs {
+        render_suite(&mut xml, run);
+    }
+    for error in errors {
+        render_load_error(&mut xml, error);
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn render_suite(xml: &mut String, run: &SuiteRun) {
+    let (suite_name, results, skipped) = (&run.name, &run.results, &run.skipped);
+    let errors = results
+        .iter()
+        .filter(|r| r.failed() && r.transport_error)
+        .count();
+    let failures = results
+        .iter()
+        .filter(|r| r.failed() && !r.transport_error)
+        .count();
+    let not_run = results
+        .iter()
+        .filter(|r| matches!(r.status, Status::XFailed | Status::Skipped))
+        .count();
+
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" file=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.6}\">",
+        escape(suite_name),
+        escape(&run.path.display().to_string()),
+        results.len() + skipped.len(),
+        failures,
+        errors,
+        skipped.len() + not_run,
+        run.elapsed.as_secs_f64()
+    );
+
+    for result in results {
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+            escape(&result.name),
+            escape(suite_name),
+            seconds(result)
+        );
+        let message = result.error_message.as_deref().unwrap_or("Test failed");
+        match result.status {
+            Status::Passed => {
+                xml.push_str("/>\n");
+                continue;
+            }
+            Status::XPassed => {
+                xml.push_str(
+                    ">\n      <system-out>XPASS: passed despite allow_failure</system-out>",
+                );
+                xml.push_str("\n    </testcase>\n");
+                continue;
+            }
+            Status::XFailed => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <skipped type=\"xfail\" message=\"{}\">{}</skipped>\n    </testcase>",
+                    escape(message),
+                    escape(&details(result, message))
+                );
+                continue;
+            }
+            Status::Skipped => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <skipped message=\"{}\"/>\n    </testcase>",
+                    escape(message)
+                );
+                continue;
+            }
+            Status::Failed => {}
+        }
+
+        let element = if result.transport_error {
+            "error"
+        } else {
+            "failure"
+        };
+        let _ = writeln!(
+            xml,
+            ">\n      <{} message=\"{}\">{}</{}>\n    </testcase>",
+            element,
+            escape(message),
+            escape(&details(result, message)),
+            element
+        );
+    }
+
+    for test in skipped {
+        let _ = writeln!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"0\">\n      <skipped/>\n    </testcase>",
+            escape(&test.name),
+            escape(suite_name)
+        );
+    }
+
+    xml.push_str("  </testsuite>\n");
+}
+
+/// A suite file that didn't load, as a suite holding one errored test
+fn render_load_error(xml: &mut String, error: &LoadError) {
+    let path = escape(&error.path.display().to_string());
+    let message = escape(&error.message);
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{path}\" file=\"{path}\" tests=\"1\" failures=\"0\" errors=\"1\" skipped=\"0\" time=\"0\">\n    <testcase name=\"load\" classname=\"{path}\" time=\"0\">\n      <error message=\"Failed to load suite\">{message}</error>\n    </testcase>\n  </testsuite>"
+    );
+}
+
+/// Execution time reported by the first target after the reference (the
+/// implementation under test), wall-clock otherwise
+fn seconds(result: &TestResult) -> f64 {
+    let under_test = result.targets.get(1).or(result.targets.first());
+    match under_test.and_then(|t| t.time_us) {
+        Some(us) => us as f64 / 1_000_000.0,
+        None => result.wall_time_us as f64 / 1_000_000.0,
+    }
+}
+
+fn details(result: &TestResult, message: &str) -> String {
+    let mut text = format!("{}\n", message);
+    for target in &result.targets {
+        let label = &target.target;
+        match (&target.result, &target.error) {
+            (Some(value), _) => {
+                let _ = writeln!(
+                    text,
+                    "{} result: {}",
+                    label,
+                    canonical::to_json_string(value)
+                );
+            }
+            (None, Some(error)) => {
+                let _ = writeln!(text, "{} error: {}", label, error);
+            }
+            (None, None) => {}
+        }
+    }
+    text
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than tab/newline are invalid in XML 1.0
+            c if c.is_control() && c != '\n' && c != '\t' && c != '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
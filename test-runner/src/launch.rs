@@ -0,0 +1,267 @@
+/*!
+Server processes started by the runner.
+
+A `ServerProcess` owns a spawned server and kills it when dropped, so a
+failed check or an early return never leaves a server holding a port. The
+server's output goes to a log file whose tail can be shown when it doesn't
+come up.
+
+Ctrl-C or SIGTERM would end the runner without running destructors, so
+every running server is also listed in `RUNNING`, and
+`stop_servers_on_interrupt` kills them before the runner exits on either.
+*/
+
+use crate::rpc::Client;
+use crate::transport::Target;
+use std::fs::File;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tracing::warn;
+
+/// Pause between connection attempts while a server starts
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a server gets to start accepting connections by default
+pub const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Process ids of the servers started and not yet seen to exit
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+pub struct ServerProcess {
+    child: Child,
+    pid: u32,
+    log: PathBuf,
+}
+
+impl ServerProcess {
+    /// Start `program`, sending its stdout and stderr to `log`
+    pub fn spawn(program: &Path, args: &[String], log: PathBuf) -> std::io::Result<Self> {
+        let output = File::create(&log)?;
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(output.try_clone()?)
+            .stderr(output)
+            .kill_on_drop(true);
+        let child = command.spawn()?;
+        let pid = child.id().expect("a child just spawned has an id");
+        RUNNING.lock().unwrap().push(pid);
+        Ok(Self { child, pid, log })
+    }
+
+    /// Connect to `target` once the server accepts connections, failing
+    /// early if the process exits
+    pub async fn wait_ready(
+        &mut self,
+        target: &Target,
+        timeout: Duration,
+    ) -> Result<Client, String> {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.exited() {
+                return Err(format!(
+                    "server exited ({}) before accepting connections",
+                    status
+                ));
+            }
+            match target.connect().await {
+                Ok(client) => return Ok(client),
+                Err(e) if started.elapsed() >= timeout => {
+                    return Err(format!(
+                        "no connection to {} after {}ms: {}",
+                        target.url(),
+                        timeout.as_millis(),
+                        e
+                    ))
+                }
+                Err(_) => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+
+    /// Ask the server to shut down gracefully, with SIGTERM
+    #[cfg(unix)]
+    pub fn terminate(&self) -> std::io::Result<()> {
+        let Some(pid) = self.child.id() else {
+            // Already reaped, so there's nothing left to stop
+            return Ok(());
+        };
+        send_signal(pid, libc::SIGTERM)
+    }
+
+    /// How the server exited, if it does within `timeout`
+    pub async fn wait_exit(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        let status = tokio::time::timeout(timeout, self.child.wait())
+            .await
+            .ok()?
+            .ok()?;
+        forget(self.pid);
+        Some(status)
+    }
+
+    /// How the server exited, if it has
+    fn exited(&mut self) -> Option<ExitStatus> {
+        let status = self.child.try_wait().ok()??;
+        forget(self.pid);
+        Some(status)
+    }
+
+    pub fn log_path(&self) -> &Path {
+        &self.log
+    }
+
+    /// The last `lines` lines the server wrote
+    pub fn log_tail(&self, lines: usize) -> String {
+        let log = std::fs::read_to_string(&self.log).unwrap_or_default();
+        let all: Vec<&str> = log.lines().collect();
+        all[all.len().saturating_sub(lines)..].join("\n")
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        // Already exited if this fails; tokio reaps it either way
+        let _ = self.child.start_kill();
+        forget(self.pid);
+    }
+}
+
+fn forget(pid: u32) {
+    RUNNING.lock().unwrap().retain(|&running| running != pid);
+}
+
+/// Kill every server still running, for when the runner is about to exit
+/// without dropping them
+fn kill_running() {
+    for pid in std::mem::take(&mut *RUNNING.lock().unwrap()) {
+        #[cfg(unix)]
+        if let Err(e) = send_signal(pid, libc::SIGKILL) {
+            warn!("Failed to stop server process {}: {}", pid, e);
+        }
+        // Elsewhere Ctrl-C reaches the servers in the runner's console too
+        #[cfg(not(unix))]
+        let _ = pid;
+    }
+}
+
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    // SAFETY: kill only sends a signal, to a child not yet seen to exit
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// On Ctrl-C, or SIGTERM on Unix, kill the servers the runner started and
+/// exit with the status the signal would have. For runs that don't handle
+/// either themselves.
+pub fn stop_servers_on_interrupt() {
+    tokio::spawn(async {
+        let status = interrupted().await;
+        kill_running();
+        std::process::exit(status);
+    });
+}
+
+/// Wait for Ctrl-C, or SIGTERM on Unix, returning the exit status a shell
+/// reports for it
+async fn interrupted() -> i32 {
+    const SIGINT_STATUS: i32 = 128 + 2;
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                return tokio::select! {
+                    _ = tokio::signal::ctrl_c() => SIGINT_STATUS,
+                    _ = terminate.recv() => 128 + libc::SIGTERM,
+                };
+            }
+            Err(e) => warn!("Cannot handle SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Cannot handle Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+    SIGINT_STATUS
+}
+
+/// A local port nothing is listening on right now
+pub fn free_port() -> std::io::Result<u16> {
+    TcpListener::bind(("127.0.0.1", 0))?
+        .local_addr()
+        .map(|addr| addr.port())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    /// `kill_running` stops every registered server, so tests that spawn
+    /// take turns
+    static SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    fn sleeper() -> ServerProcess {
+        let log = std::env::temp_dir().join(format!("launch-test-{}.log", std::process::id()));
+        ServerProcess::spawn(Path::new("sleep"), &["30".to_string()], log).unwrap()
+    }
+
+    fn registered(pid: u32) -> bool {
+        RUNNING.lock().unwrap().contains(&pid)
+    }
+
+    /// Whether `pid` is gone, once it has had `timeout` to go
+    async fn gone(pid: u32, timeout: Duration) -> bool {
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            // SAFETY: signal 0 only checks the process exists
+            if unsafe { libc::kill(pid as libc::pid_t, 0) } != 0 {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn dropping_kills_the_server() {
+        let _turn = SERIAL.lock().await;
+        let process = sleeper();
+        let pid = process.pid;
+        assert!(registered(pid));
+        drop(process);
+        assert!(!registered(pid));
+        assert!(gone(pid, Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test]
+    async fn interrupts_kill_the_running_servers() {
+        let _turn = SERIAL.lock().await;
+        let mut process = sleeper();
+        kill_running();
+        assert!(!registered(process.pid));
+        let status = process.wait_exit(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+    }
+
+    #[tokio::test]
+    async fn terminate_sends_sigterm() {
+        let _turn = SERIAL.lock().await;
+        let mut process = sleeper();
+        assert!(process.wait_exit(Duration::from_millis(50)).await.is_none());
+        process.terminate().unwrap();
+        let status = process.wait_exit(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert!(!registered(process.pid));
+        // Nothing is left to signal once the server has been reaped
+        process.terminate().unwrap();
+    }
+}
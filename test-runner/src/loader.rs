@@ -0,0 +1,411 @@
+/*!
+Reading a suite file and the files it includes.
+
+A suite can pull in tests shared with other suites:
+
+```yaml
+include: [common/smoke.yaml, common/strings.yaml]
+```
+
+Paths are relative to the including file. Included files may include
+others in turn; their tests come first, in `include` order, followed by the
+suite's own. An included file may omit `servers` (and `name`): only the
+top-level suite's servers, `reference`, `encoding`, `variables`, `setup`
+and `teardown` are used. Its `timeout_ms` still applies to its own tests. A file that ends up
+including itself is an error naming the chain of includes, and tests
+sharing a name are reported with the file each one came from.
+
+Tests with `cases` are expanded into one test per case as they are read,
+and `initial_state_file` is read into `initial_state`.
+*/
+
+use crate::cases;
+use crate::hooks::Hook;
+use crate::rpc::PayloadEncoding;
+use crate::snapshot;
+use crate::targets::Targets;
+use crate::variables::{self, Variables};
+use crate::{TestCase, TestSuite};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use transpile_common::json_depth;
+
+/// A suite file as written; only the top-level file needs `servers`
+#[derive(Deserialize)]
+struct SuiteFile {
+    name: Option<String>,
+    description: Option<String>,
+    servers: Option<Targets>,
+    #[serde(default)]
+    reference: Option<String>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    encoding: Option<PayloadEncoding>,
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    variables: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    setup: Vec<Hook>,
+    #[serde(default)]
+    teardown: Vec<Hook>,
+    #[serde(default)]
+    tests: Vec<TestCase>,
+}
+
+/// Load the suite at `path` with everything it includes, substituting
+/// `defines` and the suite's `variables` into its tests
+pub fn load_suite(
+    path: &Path,
+    max_json_depth: usize,
+    defines: &[(String, String)],
+) -> Result<TestSuite> {
+    info!("Loading test suite from: {}", path.display());
+    let mut file = parse(path)?;
+    let name = file.name.take().context("Test suite has no `name`")?;
+    let mut servers = file.servers.take().context("Test suite has no `servers`")?;
+    if servers.iter().count() < 2 {
+        anyhow::bail!("Test suite needs at least two servers to compare");
+    }
+    let reference = file.reference.take();
+    if let Some(reference) = &reference {
+        servers
+            .set_reference(reference)
+            .map_err(anyhow::Error::msg)?;
+    }
+
+    let description = file.description.take();
+    let timeout_ms = file.timeout_ms;
+    let encoding = file.encoding;
+    let include = file.include.clone();
+    let suite_variables = std::mem::take(&mut file.variables);
+    let setup = std::mem::take(&mut file.setup);
+    let teardown = std::mem::take(&mut file.teardown);
+    let mut included = Vec::new();
+    let mut chain = vec![canonical(path)?];
+    let mut tests = collect_tests(path, file, &mut chain, &mut included)?;
+    warn_duplicates(&tests);
+    let variables = Variables {
+        defines,
+        suite: &suite_variables,
+    };
+    variables::substitute(&mut tests, &variables)?;
+
+    let suite = TestSuite {
+        name,
+        description,
+        servers,
+        reference,
+        timeout_ms,
+        encoding,
+        include,
+        variables: suite_variables,
+        setup,
+        teardown,
+        tests,
+        included,
+    };
+    check_suite_depth(&suite, max_json_depth)?;
+
+    info!("Loaded test suite: {}", suite.name);
+    if let Some(ref desc) = suite.description {
+        info!("Description: {}", desc);
+    }
+    Ok(suite)
+}
+
+fn parse(path: &Path) -> Result<SuiteFile> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read test suite file {}", path.display()))?;
+    serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse test suite YAML {}", path.display()))
+}
+
+/// Tests of `file` (read from `path`) after those of everything it
+/// includes. `chain` holds the canonical paths of the files currently
+/// being included, outermost first; `included` collects every file pulled
+/// in.
+fn collect_tests(
+    path: &Path,
+    file: SuiteFile,
+    chain: &mut Vec<PathBuf>,
+    included: &mut Vec<PathBuf>,
+) -> Result<Vec<TestCase>> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut tests = Vec::new();
+    for include in &file.include {
+        let include_path = base.join(include);
+        let canonical_path = canonical(&include_path)
+            .with_context(|| format!("Included from {}", path.display()))?;
+        if chain.contains(&canonical_path) {
+            let cycle: Vec<String> = chain
+                .iter()
+                .skip_while(|p| **p != canonical_path)
+                .chain(std::iter::once(&canonical_path))
+                .map(|p| p.display().to_string())
+                .collect();
+            anyhow::bail!("Recursive include: {}", cycle.join(" -> "));
+        }
+
+        let included_file = parse(&include_path)?;
+        if included_file.servers.is_some() {
+            info!(
+                "{}: ignoring `servers`; the including suite's are used",
+                include_path.display()
+            );
+        }
+        if !included.contains(&canonical_path) {
+            included.push(canonical_path.clone());
+        }
+        chain.push(canonical_path);
+        tests.extend(collect_tests(
+            &include_path,
+            included_file,
+            chain,
+            included,
+        )?);
+        chain.pop();
+    }
+
+    for mut test in file.tests {
+        test.timeout_ms = test.timeout_ms.or(file.timeout_ms);
+        test.source = path.to_path_buf();
+        snapshot::resolve(&mut test, base).with_context(|| format!("In {}", path.display()))?;
+        tests.extend(cases::expand(test).with_context(|| format!("In {}", path.display()))?);
+    }
+    Ok(tests)
+}
+
+fn canonical(path: &Path) -> Result<PathBuf> {
+    path.canonicalize()
+        .with_context(|| format!("Failed to read test suite file {}", path.display()))
+}
+
+/// Warn about tests that share a name, naming the file of each
+fn warn_duplicates(tests: &[TestCase]) {
+    for (name, sources) in duplicates(tests) {
+        warn!(
+            "Test '{}' is defined {} times: {}",
+            name,
+            sources.len(),
+            sources.join(", ")
+        );
+    }
+}
+
+/// Names used by more than one test, with the file of each such test
+fn duplicates(tests: &[TestCase]) -> Vec<(&str, Vec<String>)> {
+    let mut duplicates = Vec::new();
+    for (index, test) in tests.iter().enumerate() {
+        let earlier = tests[..index].iter().any(|t| t.name == test.name);
+        if earlier {
+            continue;
+        }
+        let sources: Vec<String> = tests
+            .iter()
+            .filter(|t| t.name == test.name)
+            .map(|t| t.source.display().to_string())
+            .collect();
+        if sources.len() > 1 {
+            duplicates.push((test.name.as_str(), sources));
+        }
+    }
+    duplicates
+}
+
+/// Reject tests whose values are nested deeper than the servers will accept
+fn check_suite_depth(suite: &TestSuite, max_depth: usize) -> Result<()> {
+    for test in &suite.tests {
+        let values = std::iter::once((&test.arguments, &test.expected)).chain(
+            test.steps
+                .iter()
+                .map(|step| (&step.arguments, &step.expected)),
+        );
+        for (arguments, expected) in values {
+            if value_depth(arguments) > max_depth
+                || expected
+                    .as_ref()
+                    .is_some_and(|e| value_depth(e) > max_depth)
+            {
+                anyhow::bail!(
+                    "Test '{}' nests values deeper than --max-json-depth {}",
+                    test.name,
+                    max_depth
+                );
+            }
+        }
+        if let Some(state) = &test.initial_state {
+            if let Err(e @ json_depth::DepthError::TooDeep { .. }) =
+                json_depth::from_str(state, max_depth)
+            {
+                anyhow::bail!("Test '{}' initial_state: {}", test.name, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Array/object nesting depth of a value, computed without recursion
+fn value_depth(value: &serde_json::Value) -> usize {
+    let mut max = 0;
+    let mut pending = vec![(value, 0)];
+    while let Some((value, depth)) = pending.pop() {
+        let children: Box<dyn Iterator<Item = &serde_json::Value>> = match value {
+            serde_json::Value::Array(items) => Box::new(items.iter()),
+            serde_json::Value::Object(fields) => Box::new(fields.values()),
+            _ => continue,
+        };
+        max = max.max(depth + 1);
+        pending.extend(children.map(|child| (child, depth + 1)));
+    }
+    max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SERVERS: &str = "servers:\n  python: {host: localhost, port: 50051}\n  \
+                           rust: {host: localhost, port: 50052}\n";
+
+    /// Write `files` (name, YAML) into a fresh directory
+    fn suite_dir(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    fn load(dir: &tempfile::TempDir, defines: &[(&str, &str)]) -> Result<TestSuite> {
+        let defines: Vec<(String, String)> = defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        load_suite(&dir.path().join("suite.yaml"), 128, &defines)
+    }
+
+    fn error(result: Result<TestSuite>) -> String {
+        match result {
+            Ok(_) => panic!("the suite should not load"),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    #[test]
+    fn include_cycles_name_the_chain() {
+        let dir = suite_dir(&[
+            (
+                "suite.yaml",
+                &format!("name: s\n{}include: [a.yaml]\n", SERVERS),
+            ),
+            ("a.yaml", "include: [b.yaml]\n"),
+            ("b.yaml", "include: [a.yaml]\n"),
+        ]);
+        let message = error(load(&dir, &[]));
+        let path = |name: &str| dir.path().canonicalize().unwrap().join(name);
+        assert_eq!(
+            message,
+            format!(
+                "Recursive include: {} -> {} -> {}",
+                path("a.yaml").display(),
+                path("b.yaml").display(),
+                path("a.yaml").display()
+            )
+        );
+    }
+
+    #[test]
+    fn including_itself_is_a_cycle() {
+        let suite = format!("name: s\n{}include: [suite.yaml]\n", SERVERS);
+        let dir = suite_dir(&[("suite.yaml", &suite)]);
+        assert!(error(load(&dir, &[])).starts_with("Recursive include: "));
+    }
+
+    #[test]
+    fn a_file_may_be_included_twice_without_a_cycle() {
+        let dir = suite_dir(&[
+            (
+                "suite.yaml",
+                &format!("name: s\n{}include: [a.yaml, b.yaml]\n", SERVERS),
+            ),
+            ("a.yaml", "include: [common.yaml]\n"),
+            ("b.yaml", "include: [common.yaml]\n"),
+            ("common.yaml", "tests: [{name: add, method: add}]\n"),
+        ]);
+        let suite = load(&dir, &[]).unwrap();
+        assert_eq!(suite.tests.len(), 2);
+        assert_eq!(suite.included.len(), 3);
+    }
+
+    #[test]
+    fn duplicate_names_report_each_source() {
+        let dir = suite_dir(&[
+            (
+                "suite.yaml",
+                &format!(
+                    "name: s\n{}include: [shared.yaml]\ntests:\n  \
+                     - {{name: add, method: add}}\n  - {{name: sub, method: sub}}\n",
+                    SERVERS
+                ),
+            ),
+            ("shared.yaml", "tests: [{name: add, method: add}]\n"),
+        ]);
+        // Duplicates are warned about, not rejected
+        let suite = load(&dir, &[]).unwrap();
+        let names: Vec<&str> = suite.tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["add", "add", "sub"]);
+        let sources = |name: &str| dir.path().join(name).display().to_string();
+        assert_eq!(
+            duplicates(&suite.tests),
+            [("add", vec![sources("shared.yaml"), sources("suite.yaml")])]
+        );
+    }
+
+    #[test]
+    fn fewer_than_two_servers_are_rejected() {
+        let one = "name: s\nservers:\n  python: {host: localhost, port: 50051}\ntests: []\n";
+        let dir = suite_dir(&[("suite.yaml", one)]);
+        assert_eq!(
+            error(load(&dir, &[])),
+            "Test suite needs at least two servers to compare"
+        );
+
+        let dir = suite_dir(&[("suite.yaml", "name: s\ntests: []\n")]);
+        assert_eq!(error(load(&dir, &[])), "Test suite has no `servers`");
+    }
+
+    #[test]
+    fn variables_are_substituted_into_expanded_cases() {
+        let suite = format!(
+            "name: s\n{}variables: {{N: 10, STEP: 2}}\ntests:\n  \
+             - name: sum\n    method: sum_range\n    cases:\n      \
+             - {{arguments: {{n: \"${{N}}\", step: \"${{STEP}}\"}}}}\n      \
+             - {{name: fixed, arguments: {{n: 3, step: \"${{STEP}}\"}}}}\n",
+            SERVERS
+        );
+        let dir = suite_dir(&[("suite.yaml", &suite)]);
+        let suite = load(&dir, &[("N", "1000")]).unwrap();
+        // Names come from the arguments as written, before substitution
+        let names: Vec<&str> = suite.tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["sum[n=\"${N}\", step=\"${STEP}\"]", "sum[fixed]"]);
+        assert_eq!(suite.tests[0].arguments, json!({"n": 1000, "step": 2}));
+        assert_eq!(suite.tests[1].arguments, json!({"n": 3, "step": 2}));
+    }
+
+    #[test]
+    fn undefined_variables_in_cases_fail_the_load() {
+        let suite = format!(
+            "name: s\n{}tests:\n  - name: sum\n    method: sum_range\n    \
+             cases: [{{arguments: {{n: \"${{MISSING}}\"}}}}]\n",
+            SERVERS
+        );
+        let dir = suite_dir(&[("suite.yaml", &suite)]);
+        assert!(error(load(&dir, &[])).contains("MISSING"));
+    }
+}
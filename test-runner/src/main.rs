@@ -6,437 +6,2289 @@ to ensure correctness of transpiled code.
 */
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueHint};
 use colored::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::io::Write;
 use std::path::PathBuf;
-use tonic::transport::Channel;
-use tracing::{debug, info, warn};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
 
 // Generated proto code
 pub mod transpile_test {
     tonic::include_proto!("transpile_test");
 }
 
-use transpile_test::transpile_test_service_client::TranspileTestServiceClient;
 use transpile_test::*;
 
+mod batch;
+mod bench;
+mod bootstrap;
+mod cache;
+mod canonical;
+mod cases;
+mod check;
+mod compare;
+mod completions;
+mod connections;
+mod context;
+mod contexts;
+mod coverage;
+mod diff;
+mod doctor;
+mod expect_error;
+mod failures;
+mod fuzz;
+mod hooks;
+mod html;
+mod json_path;
+mod junit;
+mod launch;
+mod loader;
+#[cfg(test)]
+mod mock;
+mod output;
+mod perf;
+mod random;
+mod record;
+mod repl;
+mod report;
+mod resources;
+mod rpc;
+mod schema;
+mod selection;
+mod snapshot;
+mod stream;
+mod suites;
+mod targets;
+mod timing;
+mod transport;
+mod usage;
+mod variables;
+mod watch;
+
+use cache::ResultCache;
+use compare::{CompareOptions, MatchMode};
+use connections::Connections;
+use context::CleanupQueue;
+use expect_error::ExpectError;
+use perf::PerfCheck;
+use rpc::{Execution, Server, StateError};
+use targets::Targets;
+
+use timing::Timings;
+use usage::Usage;
+
 #[derive(Debug, Deserialize, Serialize)]
 struct TestSuite {
     name: String,
     description: Option<String>,
-    servers: TestServers,
+    servers: Targets,
+    /// Target every other target is compared with; the first if unset
+    #[serde(default)]
+    reference: Option<String>,
+    /// Default `timeout_ms` for tests that don't set their own
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// How calls send arguments and get results, unless `--encoding` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<rpc::PayloadEncoding>,
+    /// Suite files whose tests run before this suite's own
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    /// Values for `${NAME}` placeholders, overridden by `-D`
+    #[serde(default)]
+    variables: std::collections::BTreeMap<String, serde_json::Value>,
+    /// Calls made on every server before any test runs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    setup: Vec<hooks::Hook>,
+    /// Calls made on every server once every test has run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    teardown: Vec<hooks::Hook>,
     tests: Vec<TestCase>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct TestServers {
-    python: ServerConfig,
-    rust: ServerConfig,
+    /// Canonical paths of every file pulled in through `include`
+    #[serde(skip)]
+    included: Vec<PathBuf>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 struct ServerConfig {
     host: String,
     port: u16,
+    /// Program that starts the server; without one the server must already run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    args: Vec<String>,
+    /// How long a started server gets to accept connections
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    startup_timeout_ms: Option<u64>,
+    /// Connect over TLS instead of plaintext
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls: Option<TlsConfig>,
+    /// Sent as `authorization: Bearer <token>` with every request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth_token: Option<String>,
+}
+
+impl ServerConfig {
+    /// A plaintext server on this machine that the runner doesn't start
+    fn local(port: u16) -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port,
+            command: None,
+            args: Vec::new(),
+            startup_timeout_ms: None,
+            tls: None,
+            auth_token: None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+struct TlsConfig {
+    /// CA certificate (PEM) to verify the server with; the system's trusted
+    /// roots if unset
+    #[serde(default)]
+    ca_cert: Option<PathBuf>,
+    /// Name to verify the server's certificate against; `host` if unset
+    #[serde(default)]
+    domain: Option<String>,
+    /// Client certificate (PEM) for mutual TLS, with `client_key`
+    #[serde(default)]
+    client_cert: Option<PathBuf>,
+    #[serde(default)]
+    client_key: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct TestCase {
     name: String,
     description: Option<String>,
+    /// Method of a single-call test; empty when the test has `steps`
+    #[serde(default)]
     method: String,
+    #[serde(default = "empty_arguments")]
     arguments: serde_json::Value,
+    /// Inputs to run this test over, each expanded into a test of its own
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    cases: Vec<cases::Case>,
     #[serde(default)]
     stateful: bool,
     #[serde(default)]
     initial_state: Option<String>,
+    /// JSON file read into `initial_state` when the suite is loaded. Left
+    /// out of the serialized test, whose `initial_state` has the contents.
+    #[serde(default, skip_serializing)]
+    initial_state_file: Option<PathBuf>,
+    /// File the reference server's context state is written to after the
+    /// test; stateful tests only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    snapshot_state: Option<PathBuf>,
     expected: Option<serde_json::Value>,
+    /// What the context must hold once the test has run; stateful tests only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expected_state: Option<serde_json::Value>,
+    /// Every target must reject the call, optionally with a matching error.
+    /// Left out of the serialized test so existing cache keys still match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expect_error: Option<ExpectError>,
+    /// Time the method on every server once the results agree
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    perf: Option<PerfCheck>,
+    /// Throwaway calls on each server before the measured one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    warmup: Option<u32>,
+    /// The method yields items, which are streamed and compared one by one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    /// Always execute, even when a result cache is configured
+    #[serde(default)]
+    no_cache: bool,
+    /// Keep `5` and `5.0` distinct even within a tolerance
+    #[serde(default)]
+    type_sensitive: bool,
+    /// Accept numbers within this absolute difference
+    #[serde(default)]
+    abs_tolerance: Option<f64>,
+    /// Accept numbers within this fraction of the larger magnitude
+    #[serde(default)]
+    rel_tolerance: Option<f64>,
+    /// Parts of the result never compared, e.g. `$.items[*].uuid`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ignore_paths: Vec<json_path::JsonPath>,
+    /// `subset` to let results have object keys `expected` doesn't
+    #[serde(default, rename = "match", skip_serializing_if = "is_exact")]
+    match_mode: MatchMode,
+    /// Also compare what each call printed to stdout
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    compare_output: bool,
+    /// Labels for selecting tests with --tag
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Calls made in order against one shared context per server
+    #[serde(default)]
+    steps: Vec<TestStep>,
+    /// Calls made on every target before the test, in its context if it
+    /// has one; a failure skips the test
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    setup: Vec<hooks::Hook>,
+    /// Calls made on every target after the test, however it went
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    teardown: Vec<hooks::Hook>,
+    /// Give up on any single server call after this long; unbounded if unset
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// Named resources this test must not share with concurrent tests
+    #[serde(default)]
+    resources: Vec<String>,
+    /// Shorthand for a single entry in `resources`
+    #[serde(default)]
+    serial_group: Option<String>,
+    /// Known to fail: a failure doesn't fail the run (xfail)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    allow_failure: bool,
+    /// Extra attempts after a transport error (never after a mismatch)
+    #[serde(default)]
+    retries: u32,
+    /// Pause between attempts
+    #[serde(default)]
+    retry_delay_ms: u64,
+    /// File the test was read from, which differs from the suite's own for
+    /// included tests
+    #[serde(skip)]
+    source: PathBuf,
+    /// Expanded from another test's `cases`, so it has no block of its own
+    /// in the file
+    #[serde(skip)]
+    from_cases: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TestStep {
+    method: String,
+    #[serde(default = "empty_arguments")]
+    arguments: serde_json::Value,
+    expected: Option<serde_json::Value>,
+}
+
+fn empty_arguments() -> serde_json::Value {
+    serde_json::json!({})
 }
 
-#[derive(Debug)]
+fn is_exact(mode: &MatchMode) -> bool {
+    *mode == MatchMode::Exact
+}
+
+impl TestCase {
+    /// Everything the scheduler must hold exclusively while this test runs
+    fn resources(&self) -> Vec<String> {
+        self.resources
+            .iter()
+            .chain(&self.serial_group)
+            .cloned()
+            .collect()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Whether anything besides agreement between targets is checked, which
+    /// is all a single-target run can check
+    fn has_expectation(&self) -> bool {
+        self.expected.is_some()
+            || self.expected_state.is_some()
+            || self.expect_error.as_ref().is_some_and(|e| e.is_set())
+            || self.steps.iter().any(|step| step.expected.is_some())
+    }
+
+    fn compare_options(&self) -> CompareOptions {
+        CompareOptions {
+            type_sensitive: self.type_sensitive,
+            abs_tolerance: self.abs_tolerance,
+            rel_tolerance: self.rel_tolerance,
+            ignore_paths: self.ignore_paths.clone(),
+            match_mode: self.match_mode,
+            compare_output: self.compare_output,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
 struct TestResult {
     name: String,
+    /// The targets agreed with each other and with `expected`
     passed: bool,
-    python_result: Option<serde_json::Value>,
-    rust_result: Option<serde_json::Value>,
-    python_error: Option<String>,
-    rust_error: Option<String>,
-    python_time_us: Option<i64>,
-    rust_time_us: Option<i64>,
+    /// What `passed` means for the run, given the test's `allow_failure`
+    #[serde(default)]
+    status: Status,
+    /// What each target returned, reference first
+    targets: Vec<TargetOutcome>,
     error_message: Option<String>,
+    /// The reference and the first target that disagreed with it
+    #[serde(default)]
+    diverged: Option<(String, String)>,
+    /// Reused from the result cache instead of executed
+    #[serde(default)]
+    cached: bool,
+    /// Wall-clock time for the whole test, both servers included
+    #[serde(default)]
+    wall_time_us: u64,
+    /// The test couldn't be carried out, e.g. a server was unreachable,
+    /// as opposed to producing mismatched results
+    #[serde(default)]
+    transport_error: bool,
+    /// Times the test was executed; more than one means it was retried
+    #[serde(default = "one_attempt")]
+    attempts: u32,
+    /// Items every stream yielded alike before the test ended; stream
+    /// tests only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    verified_items: Option<u64>,
 }
 
-struct TestRunner {
-    python_client: TranspileTestServiceClient<Channel>,
-    rust_client: TranspileTestServiceClient<Channel>,
+fn one_attempt() -> u32 {
+    1
 }
 
-impl TestRunner {
-    async fn new(servers: &TestServers) -> Result<Self> {
-        let python_url = format!("http://{}:{}", servers.python.host, servers.python.port);
-        let rust_url = format!("http://{}:{}", servers.rust.host, servers.rust.port);
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    #[default]
+    Passed,
+    Failed,
+    /// Failed, as its `allow_failure` said it would
+    XFailed,
+    /// Passed despite `allow_failure`
+    XPassed,
+    /// Never ran because its setup failed
+    Skipped,
+}
 
-        info!("Connecting to Python server at {}", python_url);
-        let python_client = TranspileTestServiceClient::connect(python_url)
-            .await
-            .context("Failed to connect to Python server")?;
+/// One target's side of a test
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+struct TargetOutcome {
+    target: String,
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+    time_us: Option<i64>,
+    /// What the target printed, if anything
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    stdout: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    stderr: String,
+}
 
-        info!("Connecting to Rust server at {}", rust_url);
-        let rust_client = TranspileTestServiceClient::connect(rust_url)
-            .await
-            .context("Failed to connect to Rust server")?;
+/// What `TestRunner::compare_values` compares, for its messages
+#[derive(Clone, Copy)]
+enum Compared {
+    Results,
+    States,
+}
+
+/// Why a test's executions don't agree
+struct Mismatch {
+    message: String,
+    /// The reference and the first target that disagreed with it; `None`
+    /// when they agree with each other but not with `expected`, or all failed
+    pair: Option<(String, String)>,
+}
+
+impl TestResult {
+    /// A test that couldn't be carried out at all
+    fn execution_failed(name: &str, message: String) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: false,
+            status: Status::Failed,
+            targets: Vec::new(),
+            error_message: Some(message),
+            diverged: None,
+            cached: false,
+            wall_time_us: 0,
+            transport_error: true,
+            attempts: 1,
+            verified_items: None,
+        }
+    }
+
+    /// A test that didn't run because its or its suite's setup failed
+    fn skipped(name: &str, message: String) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: false,
+            status: Status::Skipped,
+            targets: Vec::new(),
+            error_message: Some(message),
+            diverged: None,
+            cached: false,
+            wall_time_us: 0,
+            transport_error: false,
+            attempts: 1,
+            verified_items: None,
+        }
+    }
+
+    /// Settle `status` from `passed`. With `strict_xfail` a test that
+    /// passes despite `allow_failure` fails, so stale annotations get
+    /// removed. Skipped tests stay skipped.
+    fn classify(&mut self, allow_failure: bool, strict_xfail: bool) {
+        if self.status == Status::Skipped {
+            return;
+        }
+        self.status = match (self.passed, allow_failure) {
+            (true, false) => Status::Passed,
+            (false, false) => Status::Failed,
+            (false, true) => Status::XFailed,
+            (true, true) if strict_xfail => {
+                self.error_message = Some(
+                    "Passed, but is marked allow_failure; remove it (--strict-xfail)".to_string(),
+                );
+                Status::Failed
+            }
+            (true, true) => Status::XPassed,
+        };
+    }
+
+    /// Whether the test fails the run
+    fn failed(&self) -> bool {
+        self.status == Status::Failed
+    }
+}
+
+/// The outcome to report for a side whose context couldn't be created; a side
+/// that did get one destroys it again and reports nothing
+async fn context_failure(created: Result<context::ContextGuard, Execution>) -> Execution {
+    match created {
+        Ok(context) => {
+            context.cleanup().await;
+            Execution::default()
+        }
+        Err(execution) => execution,
+    }
+}
+
+/// Timeouts are reported on their own ("rust timed out after 5000ms")
+/// rather than as a generic failure of that target
+fn timeout_message(names: &[String], executions: &[Execution]) -> Option<String> {
+    let timed_out: Vec<String> = names
+        .iter()
+        .zip(executions)
+        .filter(|(_, execution)| execution.timed_out)
+        .map(|(name, execution)| {
+            let error = execution.error.as_deref().unwrap_or("timed out");
+            format!("{} {}", name, error)
+        })
+        .collect();
+    (!timed_out.is_empty()).then(|| timed_out.join("\n"))
+}
+
+/// A method or context a server doesn't know is reported as a mistake in
+/// the suite ("Suite error: ...") rather than compared like other failures
+fn misconfiguration_message(names: &[String], executions: &[Execution]) -> Option<String> {
+    let misconfigured: Vec<String> = names
+        .iter()
+        .zip(executions)
+        .filter(|(_, execution)| execution.misconfigured())
+        .map(|(name, execution)| {
+            format!(
+                "{}: {}",
+                name,
+                execution.error.as_deref().unwrap_or_default()
+            )
+        })
+        .collect();
+    (!misconfigured.is_empty()).then(|| {
+        format!(
+            "Suite error: the test names a method or context the server doesn't have\n{}",
+            misconfigured.join("\n")
+        )
+    })
+}
+
+fn add_times(total: Option<i64>, time: Option<i64>) -> Option<i64> {
+    match (total, time) {
+        (Some(total), Some(time)) => Some(total + time),
+        (total, time) => total.or(time),
+    }
+}
+
+/// Run-wide settings that affect how tests are executed and reported
+#[derive(Clone)]
+struct RunnerOptions {
+    /// Annotate values in failure messages with their JSON types
+    show_types: bool,
+    /// Deepest nesting accepted in server responses
+    max_json_depth: usize,
+    /// Overrides every test's `retries`
+    retries: Option<u32>,
+    /// Overrides every test's `warmup`
+    warmup: Option<u32>,
+    /// Most consecutive simple tests sent in one `InvokeBatch`; 1 sends
+    /// every call on its own
+    batch_size: usize,
+    /// Leave out every test's `perf` assertions
+    skip_perf: bool,
+    /// Fail tests that pass despite `allow_failure`
+    strict_xfail: bool,
+    /// How to send arguments and get results, where servers allow; JSON if
+    /// neither the flag nor the suite says
+    encoding: Option<rpc::PayloadEncoding>,
+    /// The suite's `setup` and `teardown`, made around all its tests
+    setup: Vec<hooks::Hook>,
+    teardown: Vec<hooks::Hook>,
+}
+
+impl RunnerOptions {
+    /// These options with `suite`'s own settings applied
+    fn for_suite(&self, suite: &TestSuite) -> Self {
+        Self {
+            encoding: self.encoding.or(suite.encoding),
+            setup: suite.setup.clone(),
+            teardown: suite.teardown.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Cheap to clone: clients share their underlying channels
+#[derive(Clone)]
+struct TestRunner {
+    /// One client per target, reference first
+    clients: Vec<rpc::Client>,
+    /// Encoding each target is called with, parallel to `clients`
+    encodings: Vec<Encoding>,
+    /// Target names, parallel to `clients`
+    names: Arc<[String]>,
+    timings: Timings,
+    options: RunnerOptions,
+    /// Contexts left behind by tests that didn't finish normally
+    cleanup: CleanupQueue,
+    usage: Usage,
+}
+
+impl TestRunner {
+    async fn new(
+        servers: &Targets,
+        connections: &mut Connections,
+        timings: Timings,
+        usage: Usage,
+        options: RunnerOptions,
+    ) -> Result<Self> {
+        let mut clients = connections.get_all(servers).await?;
+        let wanted = options.encoding.unwrap_or_default();
+        let mut encodings = Vec::with_capacity(clients.len());
+        for (target, client) in servers.iter().zip(&mut clients) {
+            encodings.push(rpc::negotiate(client, &target.name, wanted).await);
+        }
 
         Ok(Self {
-            python_client,
-            rust_client,
+            clients,
+            encodings,
+            names: servers.names().into(),
+            timings,
+            options,
+            cleanup: CleanupQueue::new(),
+            usage,
         })
     }
 
     async fn run_test(&mut self, test: &TestCase) -> Result<TestResult> {
         info!("Running test: {}", test.name);
 
-        let args_json = serde_json::to_string(&test.arguments)?;
+        if !test.steps.is_empty() {
+            return Ok(self.run_steps(test).await);
+        }
+        if test.method.is_empty() {
+            anyhow::bail!("Test defines neither a method nor steps");
+        }
+        if test.stream {
+            return Ok(stream::run(self, test).await);
+        }
 
-        // Run test on Python
-        let (python_result, python_error, python_time) =
-            self.execute_on_python(test, &args_json).await;
+        let started = Instant::now();
+        let args_json = self
+            .usage
+            .serialize(|| canonical::to_json_string(&test.arguments));
 
-        // Run test on Rust
-        let (rust_result, rust_error, rust_time) = self.execute_on_rust(test, &args_json).await;
+        let warmup = self.options.warmup.or(test.warmup).unwrap_or(0);
+        let expects_error = test.expect_error.as_ref().is_some_and(|e| e.is_set());
+        let mut executions = Vec::with_capacity(self.clients.len());
+        for target in 0..self.clients.len() {
+            if warmup > 0 && !expects_error {
+                let _timer = self.timings.start("warmup");
+                self.warm_up(target, test, &args_json, warmup).await;
+            }
+            let _timer = self.timings.start(&self.names[target]);
+            let server = self.server(target).with_timeout(test.timeout());
+            let inspect = test.expected_state.is_some();
+            let snapshot = target == 0;
+            match Self::execute_on(server, test, &args_json, inspect, snapshot).await {
+                Ok(execution) => executions.push(execution),
+                Err(failure) => {
+                    let message = format!("Setup failed on {}: {}", self.names[target], failure);
+                    return Ok(TestResult::skipped(&test.name, message));
+                }
+            }
+        }
 
-        // Compare results
-        let (passed, error_message) = self.compare_results(
-            &python_result,
-            &rust_result,
-            &python_error,
-            &rust_error,
-            &test.expected,
-        );
+        let outcome = self.judge(test, &mut executions);
+        let outcome = match (&test.perf, outcome) {
+            (Some(perf), Ok(())) if !self.options.skip_perf => {
+                self.check_perf(test, perf, &args_json).await
+            }
+            (_, outcome) => outcome,
+        };
+        let times = executions.iter().map(|e| e.time_us).collect();
+        Ok(self.test_result(test, started, executions, times, outcome))
+    }
 
-        Ok(TestResult {
-            name: test.name.clone(),
-            passed,
-            python_result,
-            rust_result,
-            python_error,
-            rust_error,
-            python_time_us: python_time,
-            rust_time_us: rust_time,
-            error_message,
-        })
+    /// Whether a single-method test's `executions`, one per target, pass
+    fn judge(&self, test: &TestCase, executions: &mut [Execution]) -> Result<(), Mismatch> {
+        let outcome = {
+            let _timer = self.timings.start("compare");
+            let options = test.compare_options();
+            match test.expect_error.as_ref().filter(|e| e.is_set()) {
+                Some(expect) => self.check_errors(expect, executions),
+                None => self
+                    .compare_results(executions, &test.expected, &options)
+                    .and_then(|()| match &test.expected_state {
+                        Some(expected) => {
+                            let states = executions.iter_mut().map(|e| e.state.take()).collect();
+                            self.compare_states(states, expected, &options)
+                        }
+                        None => Ok(()),
+                    }),
+            }
+        };
+        self.with_timeouts(executions, outcome)
     }
 
-    async fn execute_on_python(
-        &mut self,
-        test: &TestCase,
-        args_json: &str,
-    ) -> (Option<serde_json::Value>, Option<String>, Option<i64>) {
-        let context_id = if test.stateful {
-            match self
-                .python_client
-                .create_context(CreateContextRequest {
-                    initial_state: test.initial_state.clone().unwrap_or_default(),
-                })
-                .await
-            {
-                Ok(resp) => {
-                    let resp = resp.into_inner();
-                    if resp.success {
-                        Some(resp.context_id)
-                    } else {
-                        return (None, Some(resp.error), None);
-                    }
+    /// Make `count` throwaway calls of a single-method test on `target` so
+    /// the measured call doesn't pay for cold caches. Stateful tests warm up
+    /// in a scratch context of their own, set up like the test's. Failures
+    /// are only logged.
+    async fn warm_up(&mut self, target: usize, test: &TestCase, args_json: &str, count: u32) {
+        let names = Arc::clone(&self.names);
+        let mut server = self.server(target).with_timeout(test.timeout());
+        let scratch = if test.stateful {
+            let initial_state = test.initial_state.clone().unwrap_or_default();
+            match server.create_context(initial_state).await {
+                Ok(context) => Some(context),
+                Err(execution) => {
+                    let error = execution.error.unwrap_or_default();
+                    warn!(
+                        "{}: no warmup on {}, scratch context failed: {}",
+                        test.name, names[target], error
+                    );
+                    return;
                 }
-                Err(e) => return (None, Some(e.to_string()), None),
             }
         } else {
             None
         };
 
-        let request = InvokeMethodRequest {
-            context_id: context_id.clone().unwrap_or_default(),
-            method_name: test.method.clone(),
-            arguments: args_json.to_string(),
+        let context_id = scratch.as_ref().map(|c| c.id()).unwrap_or_default();
+        match hooks::setup(&mut server, context_id, &test.setup).await {
+            Ok(()) => {
+                for call in 1..=count {
+                    let execution = server.invoke(context_id, &test.method, args_json).await;
+                    if let Some(error) = execution.error {
+                        warn!(
+                            "{}: warmup call {}/{} on {} failed: {}",
+                            test.name, call, count, names[target], error
+                        );
+                        break;
+                    }
+                }
+            }
+            Err(failure) => warn!(
+                "{}: no warmup on {}, setup failed: {}",
+                test.name, names[target], failure
+            ),
+        }
+        hooks::teardown(&mut server, context_id, &test.teardown, &test.name).await;
+        if let Some(context) = scratch {
+            context.cleanup().await;
+        }
+    }
+
+    /// Time `min_iterations` more calls of a single-method test on every
+    /// target, alternating between them so drift affects all alike
+    async fn check_perf(
+        &mut self,
+        test: &TestCase,
+        perf: &PerfCheck,
+        args_json: &str,
+    ) -> Result<(), Mismatch> {
+        let failure = |message| Mismatch {
+            message,
+            pair: None,
         };
+        let targets = self.clients.len();
+        let mut times: Vec<Vec<i64>> = vec![Vec::new(); targets];
+        for iteration in 1..=perf.min_iterations.max(1) {
+            for (target, times) in times.iter_mut().enumerate() {
+                let _timer = self.timings.start(&self.names[target]);
+                let server = self.server(target).with_timeout(test.timeout());
+                let execution = Self::execute_on(server, test, args_json, false, false)
+                    .await
+                    .unwrap_or_else(|failure| Execution::failed(format!("setup {}", failure)));
+                if let Some(error) = execution.error {
+                    return Err(failure(format!(
+                        "perf: {} failed on iteration {}: {}",
+                        self.names[target], iteration, error
+                    )));
+                }
+                times.extend(execution.time_us);
+            }
+        }
 
-        let result = match self.python_client.invoke_method(request).await {
-            Ok(resp) => {
-                let resp = resp.into_inner();
-                if resp.success {
-                    let result: Option<serde_json::Value> =
-                        serde_json::from_str(&resp.result).ok();
-                    let time = resp.metadata.as_ref().map(|m| m.execution_time_us);
-                    (result, None, time)
-                } else {
-                    (None, Some(resp.error), None)
+        let mut medians = Vec::with_capacity(targets);
+        for (target, times) in times.iter_mut().enumerate() {
+            match perf::median(times) {
+                Some(median) => medians.push(median),
+                None => {
+                    return Err(failure(format!(
+                        "perf: {} server reported no execution times",
+                        self.names[target]
+                    )))
                 }
             }
-            Err(e) => (None, Some(e.to_string()), None),
-        };
+        }
+        debug!("{}: perf medians {:?}μs", test.name, medians);
+        perf.evaluate(&self.names, &medians).map_err(failure)
+    }
 
-        // Cleanup context if needed
-        if let Some(ctx_id) = context_id {
-            let _ = self
-                .python_client
-                .destroy_context(DestroyContextRequest { context_id: ctx_id })
+    /// Run a test, re-running it after transport errors up to its retry
+    /// budget. Mismatched results are never retried: that would hide real
+    /// divergence between the implementations.
+    async fn run_with_retries(&mut self, test: &TestCase) -> TestResult {
+        let retries = self.options.retries.unwrap_or(test.retries);
+        let mut attempt = 1;
+        loop {
+            let mut result = match self.run_test(test).await {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!("Failed to run test {}: {}", test.name, e);
+                    TestResult::execution_failed(
+                        &test.name,
+                        format!("Test execution failed: {}", e),
+                    )
+                }
+            };
+            let retryable = !result.passed && result.transport_error && !result.cached;
+            if !retryable || attempt > retries {
+                result.attempts = attempt;
+                return result;
+            }
+
+            warn!(
+                "{}: transport error on attempt {}/{}, retrying",
+                test.name,
+                attempt,
+                retries + 1
+            );
+            tokio::time::sleep(Duration::from_millis(test.retry_delay_ms)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Run a multi-step test: one context per target, every step invoked
+    /// against it in order, stopping at the first step that diverges
+    async fn run_steps(&mut self, test: &TestCase) -> TestResult {
+        let started = Instant::now();
+        let initial_state = test.initial_state.clone().unwrap_or_default();
+        let timeout = test.timeout();
+        let options = test.compare_options();
+        let targets = self.clients.len();
+
+        let mut created = Vec::with_capacity(targets);
+        for target in 0..targets {
+            let context = self
+                .server(target)
+                .with_timeout(timeout)
+                .create_context(initial_state.clone())
                 .await;
+            created.push(context);
         }
+        if created.iter().any(Result::is_err) {
+            let mut executions = Vec::with_capacity(targets);
+            for context in created {
+                executions.push(context_failure(context).await);
+            }
+            let outcome = self.compare_results(&executions, &None, &options);
+            let outcome = self.with_timeouts(&executions, outcome);
+            return self.test_result(test, started, executions, vec![None; targets], outcome);
+        }
+        let contexts: Vec<context::ContextGuard> = created.into_iter().flatten().collect();
+
+        for (target, context) in contexts.iter().enumerate() {
+            let mut server = self.server(target).with_timeout(timeout);
+            if let Err(failure) = hooks::setup(&mut server, context.id(), &test.setup).await {
+                let message = format!("Setup failed on {}: {}", server.name, failure);
+                self.tear_down(test, &contexts).await;
+                for context in contexts {
+                    context.cleanup().await;
+                }
+                return TestResult::skipped(&test.name, message);
+            }
+        }
+
+        let mut executions: Vec<Execution> = (0..targets).map(|_| Execution::default()).collect();
+        let mut times = vec![None; targets];
+        let mut outcome = Ok(());
 
-        result
+        for (index, step) in test.steps.iter().enumerate() {
+            let args_json = self
+                .usage
+                .serialize(|| canonical::to_json_string(&step.arguments));
+            for target in 0..targets {
+                let _timer = self.timings.start(&self.names[target]);
+                let execution = self
+                    .server(target)
+                    .with_timeout(timeout)
+                    .invoke(contexts[target].id(), &step.method, &args_json)
+                    .await;
+                times[target] = add_times(times[target], execution.time_us);
+                executions[target] = execution;
+            }
+
+            let compared = {
+                let _timer = self.timings.start("compare");
+                self.compare_results(&executions, &step.expected, &options)
+            };
+            if let Err(mismatch) = self.with_timeouts(&executions, compared) {
+                let mut states = String::new();
+                for (target, context) in contexts.iter().enumerate() {
+                    let state = self
+                        .server(target)
+                        .inspect_state(context.id())
+                        .await
+                        .unwrap_or_else(|e| format!("<unavailable: {}>", e));
+                    states.push_str(&format!("\n{} state: {}", self.names[target], state));
+                }
+                outcome = Err(Mismatch {
+                    message: format!(
+                        "Step {}/{} ({}) diverged:\n{}{}",
+                        index + 1,
+                        test.steps.len(),
+                        step.method,
+                        mismatch.message,
+                        states
+                    ),
+                    pair: mismatch.pair,
+                });
+                break;
+            }
+        }
+
+        if let (Some(expected), Ok(())) = (&test.expected_state, &outcome) {
+            let mut states = Vec::with_capacity(targets);
+            for (target, context) in contexts.iter().enumerate() {
+                let state = self
+                    .server(target)
+                    .with_timeout(timeout)
+                    .read_state(context.id())
+                    .await;
+                states.push(Some(state));
+            }
+            let _timer = self.timings.start("compare");
+            outcome = self.compare_states(states, expected, &options);
+        }
+        let mut reference = self.server(0).with_timeout(timeout);
+        snapshot::save(&mut reference, test, contexts[0].id()).await;
+        self.tear_down(test, &contexts).await;
+
+        for context in contexts {
+            context.cleanup().await;
+        }
+
+        self.test_result(test, started, executions, times, outcome)
     }
 
-    async fn execute_on_rust(
-        &mut self,
+    /// Run a multi-step test's `teardown` on every target, in its context
+    async fn tear_down(&mut self, test: &TestCase, contexts: &[context::ContextGuard]) {
+        for (target, context) in contexts.iter().enumerate() {
+            let mut server = self.server(target).with_timeout(test.timeout());
+            hooks::teardown(&mut server, context.id(), &test.teardown, &test.name).await;
+        }
+    }
+
+    /// Result of a test from each target's last execution; `times` are
+    /// reported separately because a multi-step test sums its steps
+    fn test_result(
+        &self,
         test: &TestCase,
-        args_json: &str,
-    ) -> (Option<serde_json::Value>, Option<String>, Option<i64>) {
-        let context_id = if test.stateful {
-            match self
-                .rust_client
-                .create_context(CreateContextRequest {
-                    initial_state: test.initial_state.clone().unwrap_or_default(),
-                })
+        started: Instant,
+        executions: Vec<Execution>,
+        times: Vec<Option<i64>>,
+        outcome: Result<(), Mismatch>,
+    ) -> TestResult {
+        let transport_error = executions.iter().any(|e| e.transport_error);
+        let targets = self
+            .names
+            .iter()
+            .zip(executions)
+            .zip(times)
+            .map(|((name, execution), time_us)| TargetOutcome {
+                target: name.clone(),
+                result: execution.result,
+                error: execution.error,
+                time_us,
+                stdout: execution.stdout,
+                stderr: execution.stderr,
+            })
+            .collect();
+        let (error_message, diverged) = match outcome {
+            Ok(()) => (None, None),
+            Err(mismatch) => (Some(mismatch.message), mismatch.pair),
+        };
+
+        TestResult {
+            name: test.name.clone(),
+            passed: error_message.is_none(),
+            status: Status::default(),
+            targets,
+            error_message,
+            diverged,
+            cached: false,
+            wall_time_us: started.elapsed().as_micros() as u64,
+            transport_error,
+            attempts: 1,
+            verified_items: None,
+        }
+    }
+
+    /// Identity of every server, used to key cached results: its version
+    /// and a fingerprint of the methods it registers
+    async fn server_identity(&mut self) -> Result<serde_json::Value> {
+        let mut identity = serde_json::Map::new();
+        for (name, client) in self.names.iter().zip(&mut self.clients) {
+            let info = client
+                .get_server_info(GetServerInfoRequest {})
                 .await
-            {
-                Ok(resp) => {
-                    let resp = resp.into_inner();
-                    if resp.success {
-                        Some(resp.context_id)
-                    } else {
-                        return (None, Some(resp.error), None);
-                    }
-                }
-                Err(e) => return (None, Some(e.to_string()), None),
+                .with_context(|| format!("Failed to get {} server info", name))?
+                .into_inner();
+            let mut methods: Vec<serde_json::Value> = rpc::method_infos(client)
+                .await
+                .with_context(|| format!("Failed to list methods of {} server", name))?
+                .into_iter()
+                .map(|m| {
+                    serde_json::json!([m.name, m.parameter_types, m.return_type, m.is_stateful])
+                })
+                .collect();
+            methods.sort_by_key(canonical::to_json_string);
+            identity.insert(
+                name.clone(),
+                serde_json::json!({
+                    "version": info.version,
+                    "git_hash": info.git_hash,
+                    "methods": cache::fingerprint(&methods.into()),
+                }),
+            );
+        }
+        Ok(identity.into())
+    }
+
+    /// The suite-wide settings a cached result depends on besides the test:
+    /// its encoding and hooks, where it has any
+    fn suite_identity(&self) -> serde_json::Value {
+        let mut identity = serde_json::Map::new();
+        if let Some(encoding) = self.options.encoding {
+            identity.insert("encoding".into(), serde_json::json!(encoding));
+        }
+        let hooks = [
+            ("setup", &self.options.setup),
+            ("teardown", &self.options.teardown),
+        ];
+        for (name, hooks) in hooks {
+            if !hooks.is_empty() {
+                identity.insert(name.into(), serde_json::json!(hooks));
+            }
+        }
+        identity.into()
+    }
+
+    fn server(&mut self, target: usize) -> Server<'_> {
+        Server {
+            name: &self.names[target],
+            client: &mut self.clients[target],
+            usage: &self.usage,
+            traffic: self.usage.traffic(&self.names[target]),
+            cleanup: &self.cleanup,
+            max_json_depth: self.options.max_json_depth,
+            timeout: None,
+            encoding: self.encodings[target],
+        }
+    }
+
+    /// Run a single-method test on one server between its `setup` and
+    /// `teardown`, reading the context's state afterwards if `inspect` is
+    /// set, and saving it to the test's `snapshot_state` if `snapshot` is.
+    /// Fails only if setup does.
+    async fn execute_on(
+        mut server: Server<'_>,
+        test: &TestCase,
+        args_json: &str,
+        inspect: bool,
+        snapshot: bool,
+    ) -> Result<Execution, String> {
+        let context = if test.stateful {
+            let initial_state = test.initial_state.clone().unwrap_or_default();
+            match server.create_context(initial_state).await {
+                Ok(context) => Some(context),
+                Err(execution) => return Ok(execution),
             }
         } else {
             None
         };
 
-        let request = InvokeMethodRequest {
-            context_id: context_id.clone().unwrap_or_default(),
-            method_name: test.method.clone(),
-            arguments: args_json.to_string(),
-        };
-
-        let result = match self.rust_client.invoke_method(request).await {
-            Ok(resp) => {
-                let resp = resp.into_inner();
-                if resp.success {
-                    let result: Option<serde_json::Value> =
-                        serde_json::from_str(&resp.result).ok();
-                    let time = resp.metadata.as_ref().map(|m| m.execution_time_us);
-                    (result, None, time)
-                } else {
-                    (None, Some(resp.error), None)
-                }
+        let context_id = context.as_ref().map(|c| c.id()).unwrap_or_default();
+        if let Err(failure) = hooks::setup(&mut server, context_id, &test.setup).await {
+            hooks::teardown(&mut server, context_id, &test.teardown, &test.name).await;
+            if let Some(context) = context {
+                context.cleanup().await;
             }
-            Err(e) => (None, Some(e.to_string()), None),
-        };
+            return Err(failure);
+        }
+        let mut result = server.invoke(context_id, &test.method, args_json).await;
+        if inspect && result.error.is_none() {
+            result.state = Some(match &context {
+                Some(context) => server.read_state(context.id()).await,
+                None => Err(StateError::NoContext),
+            });
+        }
+        if let Some(context) = context.as_ref().filter(|_| snapshot) {
+            snapshot::save(&mut server, test, context.id()).await;
+        }
+        hooks::teardown(&mut server, context_id, &test.teardown, &test.name).await;
 
         // Cleanup context if needed
-        if let Some(ctx_id) = context_id {
-            let _ = self
-                .rust_client
-                .destroy_context(DestroyContextRequest { context_id: ctx_id })
-                .await;
+        if let Some(context) = context {
+            context.cleanup().await;
         }
 
-        result
+        Ok(result)
     }
 
+    /// Report timeouts in place of the generic failure they caused
+    fn with_timeouts(
+        &self,
+        executions: &[Execution],
+        outcome: Result<(), Mismatch>,
+    ) -> Result<(), Mismatch> {
+        outcome.map_err(|mismatch| Mismatch {
+            message: timeout_message(&self.names, executions).unwrap_or(mismatch.message),
+            pair: mismatch.pair,
+        })
+    }
+
+    /// Check that every target rejected the call the way `expect` requires
+    fn check_errors(&self, expect: &ExpectError, executions: &[Execution]) -> Result<(), Mismatch> {
+        expect_error::check(expect, &self.names, executions).map_err(|(message, other)| Mismatch {
+            message,
+            pair: other.map(|i| (self.names[0].clone(), self.names[i].clone())),
+        })
+    }
+
+    /// Compare every target's execution with the reference's (the first),
+    /// then the reference's result with `expected`
     fn compare_results(
         &self,
-        python_result: &Option<serde_json::Value>,
-        rust_result: &Option<serde_json::Value>,
-        python_error: &Option<String>,
-        rust_error: &Option<String>,
+        executions: &[Execution],
         expected: &Option<serde_json::Value>,
-    ) -> (bool, Option<String>) {
-        // Both errored
-        if python_error.is_some() && rust_error.is_some() {
-            return (
-                false,
-                Some(format!(
-                    "Both implementations failed:\nPython: {}\nRust: {}",
-                    python_error.as_ref().unwrap(),
-                    rust_error.as_ref().unwrap()
-                )),
-            );
+        options: &CompareOptions,
+    ) -> Result<(), Mismatch> {
+        let names = &self.names;
+        if let Some(message) = misconfiguration_message(names, executions) {
+            return Err(Mismatch {
+                message,
+                pair: None,
+            });
         }
+        let failed: Vec<usize> = (0..executions.len())
+            .filter(|&i| executions[i].error.is_some())
+            .collect();
+        let error = |i: usize| executions[i].error.as_deref().unwrap_or_default();
 
-        // Only one errored
-        if python_error.is_some() {
-            return (
-                false,
-                Some(format!(
-                    "Python failed: {}",
-                    python_error.as_ref().unwrap()
-                )),
-            );
+        if let [only] = failed.as_slice() {
+            if executions.len() == 1 {
+                return Err(Mismatch {
+                    message: format!("{} failed: {}", names[*only], error(*only)),
+                    pair: None,
+                });
+            }
+        }
+        if failed.len() == executions.len() {
+            let mut message = if executions.len() == 2 {
+                "Both implementations failed:".to_string()
+            } else {
+                "All implementations failed:".to_string()
+            };
+            for &i in &failed {
+                message.push_str(&format!("\n{}: {}", names[i], error(i)));
+            }
+            return Err(Mismatch {
+                message,
+                pair: None,
+            });
         }
 
-        if rust_error.is_some() {
-            return (
-                false,
-                Some(format!("Rust failed: {}", rust_error.as_ref().unwrap())),
-            );
+        if !failed.is_empty() {
+            let message = failed
+                .iter()
+                .map(|&i| format!("{} failed: {}", names[i], error(i)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            // Some target succeeded, so one differs from the reference
+            let reference_failed = failed[0] == 0;
+            let other = (1..executions.len())
+                .find(|&i| executions[i].error.is_some() != reference_failed)
+                .unwrap_or(1);
+            return Err(Mismatch {
+                message,
+                pair: Some((names[0].clone(), names[other].clone())),
+            });
         }
 
-        // Compare results
-        if python_result != rust_result {
-            return (
-                false,
-                Some(format!(
-                    "Results differ:\nPython: {:?}\nRust: {:?}",
-                    python_result, rust_result
-                )),
-            );
+        let results: Vec<Option<&serde_json::Value>> =
+            executions.iter().map(|e| e.result.as_ref()).collect();
+        let compared = self.compare_values(Compared::Results, &results, expected.as_ref(), options);
+        if !options.compare_output {
+            return compared;
+        }
+        // Differing output is reported along with differing results
+        match (compared, self.compare_output(executions)) {
+            (Err(mut mismatch), Err(output)) => {
+                mismatch.message = format!("{}\n{}", mismatch.message, output.message);
+                Err(mismatch)
+            }
+            (Ok(()), output) => output,
+            (results, Ok(())) => results,
+        }
+    }
+
+    /// Compare what every target printed to stdout with the reference
+    fn compare_output(&self, executions: &[Execution]) -> Result<(), Mismatch> {
+        let names = &self.names;
+        let reference = &executions[0].stdout;
+        let diverging: Vec<usize> = (1..executions.len())
+            .filter(|&i| executions[i].stdout != *reference)
+            .collect();
+        let Some(&first) = diverging.first() else {
+            return Ok(());
+        };
+        let mut message = "Output differs:".to_string();
+        for &i in &diverging {
+            if diverging.len() > 1 {
+                message.push_str(&format!("\n{} vs {}:", names[0], names[i]));
+            }
+            let differences =
+                diff::lines((&names[0], reference), (&names[i], &executions[i].stdout));
+            message.push_str(&format!("\n{}", differences));
+        }
+        Err(Mismatch {
+            message,
+            pair: Some((names[0].clone(), names[first].clone())),
+        })
+    }
+
+    /// Compare every target's context state with the reference's, then with
+    /// `expected`; states that couldn't be read fail first
+    fn compare_states(
+        &self,
+        states: Vec<Option<Result<serde_json::Value, StateError>>>,
+        expected: &serde_json::Value,
+        options: &CompareOptions,
+    ) -> Result<(), Mismatch> {
+        let mut values = Vec::with_capacity(states.len());
+        let mut unreadable = Vec::new();
+        for (name, state) in self.names.iter().zip(states) {
+            match state.unwrap_or(Err(StateError::NoContext)) {
+                Ok(state) => values.push(state),
+                Err(e) => unreadable.push(format!("Cannot check {} state: {}", name, e)),
+            }
+        }
+        if !unreadable.is_empty() {
+            return Err(Mismatch {
+                message: unreadable.join("\n"),
+                pair: None,
+            });
+        }
+        let values: Vec<Option<&serde_json::Value>> = values.iter().map(Some).collect();
+        self.compare_values(Compared::States, &values, Some(expected), options)
+    }
+
+    /// Compare every target's value with the reference's (the first), then
+    /// the reference's with `expected`
+    fn compare_values(
+        &self,
+        compared: Compared,
+        values: &[Option<&serde_json::Value>],
+        expected: Option<&serde_json::Value>,
+        options: &CompareOptions,
+    ) -> Result<(), Mismatch> {
+        let names = &self.names;
+        let show_types = self.options.show_types;
+        let expected = expected.map(|exp| compare::prepare(exp, None, options));
+        let results: Vec<Option<Cow<serde_json::Value>>> = values
+            .iter()
+            .map(|value| Some(compare::prepare((*value)?, expected.as_deref(), options)))
+            .collect();
+        let reference = results[0].as_deref();
+        let (differ, doesnt_match) = match compared {
+            Compared::Results => ("Results differ:", "Result doesn't match expected:"),
+            Compared::States => ("States differ:", "State doesn't match expected_state:"),
+        };
+
+        let diverging: Vec<usize> = (1..values.len())
+            .filter(|&i| !compare::results_equal(reference, results[i].as_deref(), options))
+            .collect();
+        if let Some(&first) = diverging.first() {
+            let mut message = differ.to_string();
+            for &i in &diverging {
+                if diverging.len() > 1 {
+                    message.push_str(&format!("\n{} vs {}:", names[0], names[i]));
+                }
+                let differences = diff::structural(
+                    (&names[0], reference),
+                    (&names[i], results[i].as_deref()),
+                    options,
+                    show_types,
+                );
+                message.push_str(&format!("\n{}", differences));
+            }
+            return Err(Mismatch {
+                message,
+                pair: Some((names[0].clone(), names[first].clone())),
+            });
         }
 
         // Check against expected if provided
-        if let Some(exp) = expected {
-            if Some(exp) != python_result.as_ref() {
-                return (
-                    false,
-                    Some(format!(
-                        "Result doesn't match expected:\nExpected: {:?}\nGot: {:?}",
-                        exp, python_result
-                    )),
+        if let Some(exp) = expected.as_deref() {
+            if !compare::results_equal(Some(exp), reference, options) {
+                let differences = diff::structural(
+                    ("expected", Some(exp)),
+                    (&names[0], reference),
+                    options,
+                    show_types,
                 );
+                return Err(Mismatch {
+                    message: format!("{}\n{}", doesnt_match, differences),
+                    pair: None,
+                });
             }
         }
 
-        (true, None)
+        Ok(())
     }
 }
 
-fn print_results(suite_name: &str, results: &[TestResult]) {
-    println!("\n{}", "=".repeat(80).bright_blue());
-    println!("{}: {}", "Test Suite".bright_blue().bold(), suite_name);
-    println!("{}", "=".repeat(80).bright_blue());
+/// Run `tests` with up to `jobs` in flight, returning results in suite order.
+///
+/// Every stateful test creates and destroys its own contexts, so tests never
+/// share server-side state and can be interleaved freely. With a batch size
+/// above 1, runs of simple tests take one task and one call per server.
+async fn run_tests(
+    runner: &TestRunner,
+    tests: &[TestCase],
+    cache: Option<&ResultCache>,
+    jobs: usize,
+) -> Vec<TestResult> {
+    // The suite's setup gates every test; its teardown runs after them all
+    let mut hooks_runner = runner.clone();
+    if let Err(failure) = hooks::suite_setup(&mut hooks_runner).await {
+        error!("{}", failure);
+        hooks::suite_teardown(&mut hooks_runner).await;
+        return tests
+            .iter()
+            .map(|test| TestResult::skipped(&test.name, failure.clone()))
+            .collect();
+    }
 
-    let mut passed = 0;
-    let mut failed = 0;
+    let mut results: Vec<Option<TestResult>> = tests.iter().map(|_| None).collect();
+    let mut cache_keys: Vec<Option<String>> = tests.iter().map(|_| None).collect();
 
-    for result in results {
-        if result.passed {
-            passed += 1;
-            println!(
-                "\n  {} {}",
-                "✓".bright_green().bold(),
-                result.name.bright_white()
-            );
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let resources = Arc::new(resources::ResourceLocks::for_tests(tests));
+    let unsupported = Arc::new(AtomicBool::new(false));
+    let mut tasks = JoinSet::new();
+    let mut task_indices = std::collections::HashMap::new();
 
-            if let (Some(py_time), Some(rs_time)) = (result.python_time_us, result.rust_time_us) {
-                println!(
-                    "    ⏱  Python: {}μs | Rust: {}μs",
-                    py_time.to_string().cyan(),
-                    rs_time.to_string().cyan()
-                );
+    // Each unit runs as one task: a single test, or consecutive tests that
+    // can share a batch
+    let batch_size = runner.options.batch_size;
+    let mut units: Vec<Vec<usize>> = Vec::new();
+    let mut open_batch: Option<usize> = None;
+    for (index, test) in tests.iter().enumerate() {
+        // A snapshot is only written when the test actually runs
+        let cacheable = !test.no_cache && test.snapshot_state.is_none();
+        if let Some(cache) = cache.filter(|_| cacheable) {
+            let key = cache.key(test);
+            if let Some(result) = cache.load(&key) {
+                results[index] = Some(result);
+                continue;
             }
+            cache_keys[index] = Some(key);
+        }
+        let batchable = batch_size > 1 && batch::batchable(test, runner);
+        match open_batch {
+            Some(unit) if batchable && units[unit].len() < batch_size => {
+                units[unit].push(index);
+                continue;
+            }
+            _ => {}
+        }
+        open_batch = batchable.then_some(units.len());
+        units.push(vec![index]);
+    }
 
-            if let Some(ref res) = result.python_result {
-                println!("    Result: {}", serde_json::to_string(res).unwrap().dimmed());
+    for unit in units {
+        // Waiting for a permit here keeps jobs=1 strictly sequential
+        let mut permit = Arc::clone(&semaphore)
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let mut runner = runner.clone();
+        let unit_tests: Vec<TestCase> = unit.iter().map(|&i| tests[i].clone()).collect();
+        let semaphore = Arc::clone(&semaphore);
+        let resources = Arc::clone(&resources);
+        let unsupported = Arc::clone(&unsupported);
+        let handle = tasks.spawn(async move {
+            if unit_tests.len() > 1 {
+                let results = batch::run(&mut runner, &unit_tests, &unsupported).await;
+                drop(permit);
+                return results;
             }
-        } else {
-            failed += 1;
-            println!(
-                "\n  {} {}",
-                "✗".bright_red().bold(),
-                result.name.bright_white()
-            );
+            let test = &unit_tests[0];
+            let needed = test.resources();
+            // Give the job slot back while waiting on a busy resource so
+            // unrelated tests keep running
+            let _held = match resources.try_acquire(&needed) {
+                Some(held) => held,
+                None => {
+                    drop(permit);
+                    debug!("{} waiting for resources {:?}", test.name, needed);
+                    let held = resources.acquire(&needed).await;
+                    permit = semaphore
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore is never closed");
+                    held
+                }
+            };
+            let result = runner.run_with_retries(test).await;
+            drop(permit);
+            vec![result]
+        });
+        task_indices.insert(handle.id(), unit);
+    }
 
-            if let Some(ref err) = result.error_message {
-                for line in err.lines() {
-                    println!("    {}", line.red());
+    while let Some(joined) = tasks.join_next_with_id().await {
+        match joined {
+            Ok((id, unit_results)) => {
+                for (&index, result) in task_indices[&id].iter().zip(unit_results) {
+                    let outcomes: Vec<String> = result
+                        .targets
+                        .iter()
+                        .map(|t| {
+                            format!("{}={} ({:?})", t.target, display_result(&t.result), t.error)
+                        })
+                        .collect();
+                    debug!("{}: {}", result.name, outcomes.join(", "));
+                    if let (Some(cache), Some(key)) = (cache, &cache_keys[index]) {
+                        cache.store(key, &result);
+                    }
+                    results[index] = Some(result);
+                }
+            }
+            Err(e) => {
+                for &index in &task_indices[&e.id()] {
+                    results[index] = Some(TestResult::execution_failed(
+                        &tests[index].name,
+                        format!("Test task panicked: {}", e),
+                    ));
                 }
             }
         }
     }
 
-    println!("\n{}", "=".repeat(80).bright_blue());
-    println!(
-        "{}: {}/{} passed",
-        "Summary".bright_blue().bold(),
-        passed.to_string().bright_green(),
-        (passed + failed).to_string().bright_white()
-    );
+    hooks::suite_teardown(&mut hooks_runner).await;
+
+    // Every task has finished, so any context still queued was leaked by a
+    // test that panicked or was cancelled
+    let leaked = runner.cleanup.drain().await;
+    if leaked > 0 {
+        warn!(
+            "Destroyed {} context(s) left behind by unfinished tests",
+            leaked
+        );
+    }
+
+    let strict_xfail = runner.options.strict_xfail;
+    results
+        .into_iter()
+        .flatten()
+        .zip(tests)
+        .map(|(mut result, test)| {
+            result.classify(test.allow_failure, strict_xfail);
+            result
+        })
+        .collect()
+}
+
+/// How long to wait for a server to finish registering its methods
+const REGISTRATION_WAIT: Duration = Duration::from_secs(60);
+const REGISTRATION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Block until the server reports that every registration source has finished,
+/// so tests never run against a partially populated method table
+async fn wait_for_registration(client: &mut rpc::Client, label: &str) -> Result<()> {
+    let deadline = Instant::now() + REGISTRATION_WAIT;
+    loop {
+        let info = match client.get_server_info(GetServerInfoRequest {}).await {
+            Ok(resp) => resp.into_inner(),
+            Err(status) if status.code() == tonic::Code::Unimplemented => {
+                debug!("{} server predates GetServerInfo; not waiting", label);
+                return Ok(());
+            }
+            Err(status) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to get {} server info: {}",
+                    label,
+                    status
+                ))
+            }
+        };
+
+        if info.registration_complete {
+            if !info.registration_error.is_empty() {
+                warn!("{} server: {}", label, info.registration_error);
+            }
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!(
+                "{} server did not finish registering methods within {}s",
+                label,
+                REGISTRATION_WAIT.as_secs()
+            );
+        }
 
-    if failed > 0 {
-        println!("  {} tests failed", failed.to_string().bright_red());
+        debug!("Waiting for {} server to finish registration", label);
+        tokio::time::sleep(REGISTRATION_POLL_INTERVAL).await;
     }
-    println!("{}\n", "=".repeat(80).bright_blue());
+}
+
+fn display_result(result: &Option<serde_json::Value>) -> String {
+    result
+        .as_ref()
+        .map(canonical::to_json_string)
+        .unwrap_or_else(|| "-".to_string())
 }
 
 #[derive(Parser)]
 #[command(name = "transpile-test-runner")]
 #[command(about = "Run cross-language transpilation tests")]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to test suite YAML file
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Test suite YAML file, directory of suites or quoted glob (repeatable)
+    #[arg(short, long, required = true, value_hint = ValueHint::AnyPath)]
+    suite: Vec<PathBuf>,
+
+    /// Only run tests whose name contains this, or matches it as a glob
     #[arg(short, long)]
-    suite: PathBuf,
+    filter: Option<String>,
+
+    /// Only run tests with this tag (repeatable; any tag matches)
+    #[arg(short, long)]
+    tag: Vec<String>,
+
+    /// Only run tests in this serial_group or holding this resource
+    /// (repeatable; any group matches)
+    #[arg(long)]
+    group: Vec<String>,
+
+    /// Skip tests that passed before with the same definition and servers,
+    /// keeping their results in this directory
+    #[arg(long, visible_alias = "cache", value_hint = ValueHint::DirPath)]
+    cache_dir: Option<PathBuf>,
+
+    /// Delete every cached result before running
+    #[arg(long, requires = "cache_dir")]
+    cache_clear: bool,
+
+    /// Set a `${NAME}` placeholder, overriding the suite's `variables`
+    /// (repeatable)
+    #[arg(short = 'D', value_name = "NAME=VALUE", value_parser = variables::parse_define)]
+    define: Vec<(String, String)>,
+
+    /// Run each suite's tests in random order, to expose tests that depend
+    /// on what ran before them
+    #[arg(long)]
+    shuffle: bool,
+
+    /// Seed for --shuffle or --fuzz, to repeat the order or inputs of an
+    /// earlier run; implies --shuffle unless fuzzing
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Instead of running the tests, invoke this method with generated
+    /// arguments and report inputs the servers disagree on (repeatable)
+    #[arg(long, value_name = "METHOD", conflicts_with = "target")]
+    fuzz: Vec<String>,
+
+    /// Inputs to generate per --fuzz method
+    #[arg(long, value_name = "N", default_value_t = fuzz::DEFAULT_ITERATIONS)]
+    fuzz_iterations: u32,
+
+    /// Largest magnitude of generated numbers; zero and negatives are
+    /// always included
+    #[arg(long, value_name = "N", default_value_t = fuzz::DEFAULT_MAX_INT)]
+    fuzz_max_int: i64,
+
+    /// Seconds to keep retrying connections to servers that aren't up yet
+    #[arg(long, value_name = "SECS", default_value_t = connections::CONNECT_TIMEOUT.as_secs())]
+    connect_timeout: u64,
+
+    /// Number of tests to run concurrently
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// Fail tests marked `allow_failure` that pass (XPASS)
+    #[arg(long)]
+    strict_xfail: bool,
+
+    /// Don't check tests' `perf` timings, e.g. on slow or noisy machines
+    #[arg(long)]
+    skip_perf: bool,
+
+    /// How to print results: every test in full, or one character per test
+    #[arg(long, value_enum, default_value_t)]
+    format: output::Format,
+
+    /// Print only failing tests, one line each, and the final summary
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Don't style output; also set by a non-empty NO_COLOR variable
+    #[arg(long)]
+    no_color: bool,
+
+    /// Annotate every value in failure output with its JSON type
+    #[arg(long)]
+    show_types: bool,
+
+    /// Retry every test this many times after transport errors, overriding
+    /// the suite
+    #[arg(long)]
+    retries: Option<u32>,
+
+    /// Make this many throwaway calls on each server before the measured
+    /// one, overriding the suite
+    #[arg(long, value_name = "N")]
+    warmup: Option<u32>,
+
+    /// Send up to N consecutive stateless tests to each server in one
+    /// round trip, on servers that support it
+    #[arg(long, value_name = "N")]
+    batch_size: Option<usize>,
+
+    /// How to send arguments and get results; servers without MessagePack
+    /// get JSON. Overrides a suite's `encoding` [default: json]
+    #[arg(long, value_enum)]
+    encoding: Option<rpc::PayloadEncoding>,
+
+    /// Deepest JSON nesting accepted in suite values and server responses
+    #[arg(long, default_value_t = transpile_common::json_depth::DEFAULT_MAX_DEPTH)]
+    max_json_depth: usize,
+
+    /// Run only this server, checking results against `expected` instead of
+    /// other implementations; tests without expectations are skipped
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["record", "record_overwrite"])]
+    target: Option<String>,
+
+    /// Keep running, rerunning suites when they or --watch-path files change
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "check", "fuzz", "record", "record_overwrite", "junit", "report_html", "output_json",
+            "save_failures"
+        ]
+    )]
+    watch: bool,
+
+    /// Directory whose changes rerun every suite with --watch, e.g. an
+    /// implementation's sources (repeatable)
+    #[arg(long, value_name = "DIR", requires = "watch", value_hint = ValueHint::DirPath)]
+    watch_path: Vec<PathBuf>,
+
+    /// Check the suites for mistakes without running any tests
+    #[arg(long)]
+    check: bool,
+
+    /// Write agreed results into the suite as `expected` where it has none
+    #[arg(long)]
+    record: bool,
+
+    /// Like --record, but also replace existing `expected` values
+    #[arg(long)]
+    record_overwrite: bool,
+
+    /// Also write results as JUnit XML to this file
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    junit: Option<PathBuf>,
+
+    /// After each suite, list which servers' methods its tests call and fail
+    /// if a called method is missing from a server
+    #[arg(long)]
+    coverage: bool,
+
+    /// Also write a self-contained HTML report to this file
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    report_html: Option<PathBuf>,
+
+    /// Also write the full run as JSON to this file
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    output_json: Option<PathBuf>,
+
+    /// Write the failing tests to this file as a suite of their own, with
+    /// the reference's results as `expected`, to rerun with --suite
+    #[arg(long, value_name = "FILE", conflicts_with = "target", value_hint = ValueHint::FilePath)]
+    save_failures: Option<PathBuf>,
 
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: completions::Shell,
+    },
+
+    /// Check the servers of a suite are reachable and round-trip payloads intact
+    Doctor {
+        /// Suite whose servers to check
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        suite: PathBuf,
+
+        /// Reproduce the payloads of an earlier run
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+
+    /// List the contexts left on the servers of a suite
+    Contexts {
+        /// Suite whose servers to inspect
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        suite: PathBuf,
+
+        /// Only this server
+        #[arg(long)]
+        server: Option<String>,
+
+        /// Destroy every context once they're listed
+        #[arg(long)]
+        destroy_all: bool,
+    },
+
+    /// Call methods on every server of a suite interactively, diffing the
+    /// results
+    Repl {
+        /// Suite whose servers to call
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        suite: PathBuf,
+    },
+
+    /// Time the single-call tests of a suite over many iterations on every
+    /// server
+    Bench {
+        /// Suite whose tests to time
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        suite: PathBuf,
+
+        /// Only tests whose name matches, as with --filter on a run
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// Invocations per test on each server
+        #[arg(long, default_value_t = 100)]
+        iterations: u32,
+
+        /// Where each iteration of a stateful test gets its context
+        #[arg(long, value_enum, default_value_t)]
+        context_mode: bench::ContextMode,
+
+        /// Contexts per server with --context-mode pooled
+        #[arg(long, default_value_t = 4)]
+        pool_size: usize,
+
+        /// Also write the timings, with the context mode, as JSON
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        output_json: Option<PathBuf>,
+    },
+
+    /// Build and start a Rust server, then run a smoke suite against it
+    Bootstrap {
+        /// Use this server binary instead of building one
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        server_bin: Option<PathBuf>,
+    },
+
+    /// Print the JSON Schema of the --output-json report
+    ReportSchema,
+
+    /// Check a --output-json report against the current schema
+    ValidateReport {
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+    },
+
+    /// Print test names, tags or groups from a suite, for completion scripts
+    #[command(name = "__complete-tests", hide = true)]
+    CompleteTests {
+        #[arg(long)]
+        suite: PathBuf,
+
+        #[arg(long, value_enum, default_value_t)]
+        kind: completions::NameKind,
+    },
+}
+
+/// Print how `file` compares to the current report schema; false if invalid
+fn validate_report(file: &std::path::Path) -> Result<bool> {
+    let content = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read report {}", file.display()))?;
+    let report: serde_json::Value =
+        serde_json::from_str(&content).context("Report is not valid JSON")?;
+
+    let current = report::REPORT_VERSION;
+    match report.get("version").and_then(|v| v.as_u64()) {
+        Some(version) if version == current => {
+            println!("Report version {} (current)", version)
+        }
+        Some(version) if version < current => println!(
+            "Report version {} is {} behind the current version {}",
+            version,
+            current - version,
+            current
+        ),
+        Some(version) => println!(
+            "Report version {} is {} ahead of the current version {}",
+            version,
+            version - current,
+            current
+        ),
+        None => println!("Report has no version; current version is {}", current),
+    }
+
+    let errors = schema::validate(&report, &schema::report_schema());
+    if errors.is_empty() {
+        println!("{}", "Report is valid".green());
+        return Ok(true);
+    }
+    println!("{}", format!("{} schema violation(s):", errors.len()).red());
+    for error in errors {
+        println!("  {}", error);
+    }
+    Ok(false)
+}
+
+/// Split a suite's tests into those to run and those skipped by the
+/// selection or, with `--target`, for having nothing to check against;
+/// `--target` also drops the suite's other servers
+fn plan(
+    suite: &mut TestSuite,
+    selection: &selection::Selection,
+    target: Option<&str>,
+) -> Result<(Vec<TestCase>, Vec<TestCase>)> {
+    let (mut selected, mut skipped): (Vec<TestCase>, Vec<TestCase>) = suite
+        .tests
+        .iter()
+        .cloned()
+        .partition(|t| selection.matches(t));
+    if !skipped.is_empty() {
+        info!(
+            "{}: skipping {} test(s) not matching {}",
+            suite.name,
+            skipped.len(),
+            selection.describe()
+        );
+    }
+    if let Some(target) = target {
+        suite
+            .servers
+            .only(target)
+            .map_err(|e| anyhow::anyhow!("{}: {}", suite.name, e))?;
+        let (checkable, unchecked): (Vec<TestCase>, Vec<TestCase>) =
+            selected.into_iter().partition(TestCase::has_expectation);
+        if !unchecked.is_empty() {
+            info!(
+                "{}: skipping {} test(s) without `expected`, which need another \
+                 target to compare with",
+                suite.name,
+                unchecked.len()
+            );
+        }
+        selected = checkable;
+        skipped.extend(unchecked);
+    }
+    Ok((selected, skipped))
+}
+
+fn runner_options(args: &Args) -> RunnerOptions {
+    RunnerOptions {
+        show_types: args.show_types,
+        max_json_depth: args.max_json_depth,
+        retries: args.retries,
+        warmup: args.warmup,
+        batch_size: args.batch_size.unwrap_or(1).max(1),
+        // Speed is relative to other targets, which aren't running
+        skip_perf: args.skip_perf || args.target.is_some(),
+        strict_xfail: args.strict_xfail,
+        encoding: args.encoding,
+        setup: Vec::new(),
+        teardown: Vec::new(),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    // Watch mode exits on Ctrl-C itself, dropping the servers
+    if !args.watch {
+        launch::stop_servers_on_interrupt();
+    }
+
+    // Completion helpers print to stdout, so they run before logging is set up
+    match args.command {
+        Some(Command::Completions { shell }) => {
+            let script = completions::generate(shell, Args::command());
+            // Ignore write errors such as a closed pipe from `| head`
+            let _ = std::io::stdout().write_all(script.as_bytes());
+            return Ok(());
+        }
+        Some(Command::CompleteTests { suite, kind }) => {
+            let names = completions::suite_names(&suite, kind);
+            let mut stdout = std::io::stdout().lock();
+            for name in names {
+                let _ = writeln!(stdout, "{}", name);
+            }
+            return Ok(());
+        }
+        Some(Command::Doctor { suite, seed }) => {
+            let suite = loader::load_suite(&suite, args.max_json_depth, &args.define)?;
+            if !doctor::run(&suite.servers, seed).await {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Contexts {
+            suite,
+            server,
+            destroy_all,
+        }) => {
+            let mut suite = loader::load_suite(&suite, args.max_json_depth, &args.define)?;
+            if let Some(server) = server {
+                suite
+                    .servers
+                    .only(&server)
+                    .map_err(|e| anyhow::anyhow!(e))?;
+            }
+            if !contexts::run(&suite.servers, destroy_all).await {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Repl { ref suite }) => {
+            tracing_subscriber::fmt().with_env_filter("warn").init();
+            let suite = loader::load_suite(suite, args.max_json_depth, &args.define)?;
+            let mut connections = Connections::new(Duration::from_secs(args.connect_timeout));
+            let mut runner = TestRunner::new(
+                &suite.servers,
+                &mut connections,
+                Timings::new(),
+                Usage::new(suite.servers.names()),
+                runner_options(&args).for_suite(&suite),
+            )
+            .await?;
+            return repl::run(&mut runner).await;
+        }
+        Some(Command::Bench {
+            suite,
+            filter,
+            iterations,
+            context_mode,
+            pool_size,
+            output_json,
+        }) => {
+            let suite = loader::load_suite(&suite, args.max_json_depth, &args.define)?;
+            let selection = selection::Selection {
+                filter,
+                ..Default::default()
+            };
+            let tests: Vec<TestCase> = suite
+                .tests
+                .into_iter()
+                .filter(|t| selection.matches(t))
+                .collect();
+            if tests.is_empty() {
+                anyhow::bail!("No tests to bench");
+            }
+            let options = bench::BenchOptions {
+                iterations,
+                context_mode,
+                pool_size,
+            };
+            let report = bench::run(&suite.servers, &tests, &options).await?;
+            bench::print(&report);
+            if let Some(path) = output_json {
+                report.write(&path)?;
+            }
+            return Ok(());
+        }
+        Some(Command::Bootstrap { server_bin }) => {
+            if !bootstrap::run(server_bin).await {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::ReportSchema) => {
+            let schema = serde_json::to_string_pretty(&schema::report_schema())?;
+            let _ = writeln!(std::io::stdout(), "{}", schema);
+            return Ok(());
+        }
+        Some(Command::ValidateReport { file }) => {
+            if !validate_report(&file)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    let no_color = output::no_color(args.no_color);
+    if no_color {
+        colored::control::set_override(false);
+    }
 
     // Initialize tracing
-    let log_level = if args.verbose { "debug" } else { "info" };
+    let log_level = match (args.verbose, args.quiet) {
+        (true, _) => "debug",
+        (false, true) => "warn",
+        (false, false) => "info",
+    };
     tracing_subscriber::fmt()
         .with_env_filter(log_level)
+        .with_ansi(!no_color)
         .init();
 
-    // Load test suite
-    info!("Loading test suite from: {}", args.suite.display());
-    let suite_content = tokio::fs::read_to_string(&args.suite)
-        .await
-        .context("Failed to read test suite file")?;
-
-    let suite: TestSuite =
-        serde_yaml::from_str(&suite_content).context("Failed to parse test suite YAML")?;
+    // Load test suites; with several, one that doesn't load doesn't stop the rest
+    let suite_paths = suites::discover(&args.suite)?;
+    let attempts: Vec<(PathBuf, Result<TestSuite>)> = suite_paths
+        .into_iter()
+        .map(|path| {
+            let suite = loader::load_suite(&path, args.max_json_depth, &args.define);
+            (path, suite)
+        })
+        .collect();
+    // A file another suite includes runs as part of that suite, not on its own
+    let included: Vec<PathBuf> = attempts
+        .iter()
+        .filter_map(|(_, suite)| suite.as_ref().ok())
+        .flat_map(|suite| suite.included.iter().cloned())
+        .collect();
+    let attempts: Vec<(PathBuf, Result<TestSuite>)> = attempts
+        .into_iter()
+        .filter(|(path, _)| !path.canonicalize().is_ok_and(|p| included.contains(&p)))
+        .collect();
+    let suite_paths: Vec<PathBuf> = attempts.iter().map(|(path, _)| path.clone()).collect();
 
-    info!("Loaded test suite: {}", suite.name);
-    if let Some(ref desc) = suite.description {
-        info!("Description: {}", desc);
+    if args.check {
+        let mut connections = Connections::new(Duration::from_secs(args.connect_timeout));
+        let mut problems = 0;
+        for path in &suite_paths {
+            let found =
+                check::check_file(path, args.max_json_depth, &args.define, &mut connections).await;
+            check::print(path, &found);
+            problems += found.len();
+        }
+        drop(connections);
+        if problems > 0 {
+            println!(
+                "\n{} problem(s) in {} suite file(s)",
+                problems.to_string().bright_red(),
+                suite_paths.len()
+            );
+            std::process::exit(1);
+        }
+        println!(
+            "{} suite file(s) OK",
+            suite_paths.len().to_string().bright_green()
+        );
+        return Ok(());
+    }
+    if args.watch {
+        return watch::run(&args, suite_paths).await;
+    }
+    if !args.fuzz.is_empty() {
+        let [(_, suite)] = <[_; 1]>::try_from(attempts)
+            .map_err(|_| anyhow::anyhow!("--fuzz takes a single suite, whose servers it calls"))?;
+        let suite = suite?;
+        let (seed, announcement) = random::announce(args.seed, "Fuzzing");
+        println!("{}\n", announcement);
+        let options = fuzz::FuzzOptions {
+            iterations: args.fuzz_iterations,
+            max_int: args.fuzz_max_int,
+            seed,
+        };
+        let mut connections = Connections::new(Duration::from_secs(args.connect_timeout));
+        let mut runner = TestRunner::new(
+            &suite.servers,
+            &mut connections,
+            Timings::new(),
+            Usage::new(suite.servers.names()),
+            runner_options(&args).for_suite(&suite),
+        )
+        .await?;
+        let divergences = fuzz::run(&mut runner, &suite.tests, &args.fuzz, &options).await?;
+        drop(connections);
+        if divergences > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
     }
 
-    // Create test runner
-    let mut runner = TestRunner::new(&suite.servers).await?;
-
-    // Run all tests
-    let mut results = Vec::new();
-    for test in &suite.tests {
-        match runner.run_test(test).await {
-            Ok(result) => results.push(result),
+    let mut loaded = Vec::new();
+    let mut load_errors = Vec::new();
+    for (path, suite) in attempts {
+        match suite {
+            Ok(suite) => loaded.push((path, suite)),
+            Err(e) if args.suite.len() == 1 && !args.suite[0].is_dir() => return Err(e),
             Err(e) => {
-                warn!("Failed to run test {}: {}", test.name, e);
-                results.push(TestResult {
-                    name: test.name.clone(),
-                    passed: false,
-                    python_result: None,
-                    rust_result: None,
-                    python_error: None,
-                    rust_error: None,
-                    python_time_us: None,
-                    rust_time_us: None,
-                    error_message: Some(format!("Test execution failed: {}", e)),
+                error!("Failed to load {}: {:#}", path.display(), e);
+                load_errors.push(suites::LoadError {
+                    path,
+                    message: format!("{:#}", e),
                 });
             }
         }
     }
 
-    // Print results
-    print_results(&suite.name, &results);
+    let selection = selection::Selection {
+        filter: args.filter.clone(),
+        tags: args.tag.clone(),
+        groups: args.group.clone(),
+    };
+    let mut planned = Vec::new();
+    for (path, mut suite) in loaded {
+        let (selected, skipped) = plan(&mut suite, &selection, args.target.as_deref())?;
+        planned.push((path, suite, selected, skipped));
+    }
+    if selection.is_active()
+        && planned
+            .iter()
+            .all(|(_, _, selected, _)| selected.is_empty())
+    {
+        match planned.as_slice() {
+            [(_, suite, _, _)] => anyhow::bail!(
+                "No tests in '{}' match {}",
+                suite.name,
+                selection.describe()
+            ),
+            _ => anyhow::bail!("No tests in any suite match {}", selection.describe()),
+        }
+    }
+
+    let run_started = Instant::now();
+    let timings = Timings::new();
+    let mut target_names: Vec<String> = Vec::new();
+    for name in planned
+        .iter()
+        .flat_map(|(_, suite, _, _)| suite.servers.names())
+    {
+        if !target_names.contains(&name) {
+            target_names.push(name);
+        }
+    }
+    let usage = Usage::new(target_names);
+    let options = runner_options(&args);
+    let mut connections = Connections::new(Duration::from_secs(args.connect_timeout));
+    let mut shuffle = (args.shuffle || args.seed.is_some()).then(|| {
+        let (seed, announcement) = random::announce(args.seed, "Shuffling tests");
+        println!("{}\n", announcement);
+        random::XorShift::new(seed)
+    });
+
+    if let (Some(dir), true) = (&args.cache_dir, args.cache_clear) {
+        let removed = cache::clear(dir)?;
+        info!(
+            "Cleared {} cached result(s) from {}",
+            removed,
+            dir.display()
+        );
+    }
+    let mut reporter = output::reporter(args.format, args.quiet, args.target.clone());
+    let mut runs = Vec::new();
+    let mut recorded = 0;
+    let mut record_failed = false;
+    let mut missing_methods = 0;
+    let mut coverage_failed = false;
+    let mut failures = failures::Failures::default();
+    for (path, suite, mut selected, skipped) in planned {
+        // Steps keep their order; only whole tests move
+        if let Some(rng) = &mut shuffle {
+            rng.shuffle(&mut selected);
+        }
+        let suite_started = Instant::now();
+        let mut runner = {
+            let _timer = timings.start("connect");
+            TestRunner::new(
+                &suite.servers,
+                &mut connections,
+                timings.clone(),
+                usage.clone(),
+                options.for_suite(&suite),
+            )
+            .await?
+        };
+
+        let cache = match &args.cache_dir {
+            Some(dir) => {
+                let identity = runner.server_identity().await?;
+                let suite = runner.suite_identity();
+                Some(ResultCache::open(dir.clone(), identity, suite)?)
+            }
+            None => None,
+        };
+
+        let results = run_tests(&runner, &selected, cache.as_ref(), args.jobs).await;
+        {
+            let _timer = timings.start("report");
+            reporter.suite(&suite.name, &results, skipped.len());
+        }
+        let coverage = if args.coverage {
+            let found = coverage::collect(&mut runner.clients, &runner.names, &selected).await;
+            match found {
+                Ok(coverage) => {
+                    coverage::print(&coverage);
+                    missing_methods += coverage.missing.len();
+                    Some(coverage)
+                }
+                Err(e) => {
+                    error!("{}: {:#}", suite.name, e);
+                    coverage_failed = true;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if args.record || args.record_overwrite {
+            let _timer = timings.start("record");
+            // Included tests are recorded into the file that defines them
+            let mut sources: Vec<&PathBuf> = Vec::new();
+            for test in &selected {
+                if !sources.contains(&&test.source) {
+                    sources.push(&test.source);
+                }
+            }
+            for source in sources {
+                let (tests, source_results): (Vec<TestCase>, Vec<&TestResult>) = selected
+                    .iter()
+                    .zip(&results)
+                    .filter(|(test, _)| &test.source == source)
+                    .map(|(test, result)| (test.clone(), result))
+                    .unzip();
+                match record::record(source, &tests, &source_results, args.record_overwrite) {
+                    Ok(written) => {
+                        record::print(source, &written);
+                        recorded += written.written;
+                    }
+                    Err(e) => {
+                        error!("{:#}", e);
+                        record_failed = true;
+                    }
+                }
+            }
+        }
+        if args.save_failures.is_some() {
+            failures.add(&suite, &selected, &results);
+        }
+        runs.push(suites::SuiteRun {
+            name: suite.name,
+            path,
+            results,
+            skipped,
+            elapsed: suite_started.elapsed(),
+            coverage,
+        });
+    }
+
+    {
+        let _timer = timings.start("report");
+        reporter.total(&runs, &load_errors);
+        if recorded > 0 {
+            println!(
+                "Recorded {} expectations; review them with `git diff` before committing\n",
+                recorded
+            );
+        }
+        // Written before the failure exit so CI still gets the report
+        if let Some(path) = &args.junit {
+            junit::write(path, &runs, &load_errors, run_started.elapsed())?;
+        }
+        if let Some(path) = &args.report_html {
+            html::write(path, &runs, &load_errors, run_started.elapsed())?;
+        }
+        if let Some(path) = &args.save_failures {
+            failures.write(path)?;
+        }
+    }
+    if let Some(path) = &args.output_json {
+        report::Report::new(
+            &runs,
+            &load_errors,
+            run_started.elapsed(),
+            &timings,
+            usage.report(),
+        )
+        .write(path)?;
+    }
+    if !args.quiet {
+        timing::print_breakdown(run_started.elapsed(), &timings, args.jobs > 1);
+        usage::print_usage(&usage.report());
+    }
+    // Stops servers the runner started; `exit` below skips destructors
+    drop(connections);
+
+    if missing_methods > 0 {
+        println!(
+            "{} method(s) called by tests are missing from a server\n",
+            missing_methods.to_string().bright_red()
+        );
+    }
 
-    // Exit with error code if any tests failed
-    if results.iter().any(|r| !r.passed) {
+    // Exit with error code if any tests failed, any suite didn't load or a
+    // server lacks a tested method
+    let failed = runs.iter().flat_map(|run| &run.results).any(|r| r.failed());
+    let incomplete = missing_methods > 0 || coverage_failed;
+    if failed || !load_errors.is_empty() || record_failed || incomplete {
         std::process::exit(1);
     }
 
@@ -0,0 +1,362 @@
+/*!
+An in-process server for the runner's tests.
+
+`Mock::start` serves the protocol on a free local port, and `runner`
+connects a `TestRunner` to several mocks as its targets. Every context call
+and invocation is counted in `calls`, so tests can check what the runner
+asked for, not just what it concluded. Methods echo their arguments and
+report `execution_time_us` of 1; contexts hold no state. Streamed calls
+yield the mock's `items`, and count how many the client took before it
+went away. A mock can also be made to fail one kind of call, or to take
+its time over every invocation; when each invocation ran is recorded. Like
+a real server, it fails an invocation that would run past the request's
+`timeout_ms` with `TIMEOUT` once that much time has passed.
+*/
+
+use crate::connections::Connections;
+use crate::targets::Targets;
+use crate::timing::Timings;
+use crate::transpile_test::transpile_test_service_server::{
+    TranspileTestService, TranspileTestServiceServer,
+};
+use crate::transpile_test::*;
+use crate::usage::Usage;
+use crate::{RunnerOptions, ServerConfig, TestRunner};
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+/// How many times each RPC was called
+#[derive(Default)]
+pub struct Calls {
+    pub create_context: AtomicUsize,
+    pub reset_context: AtomicUsize,
+    pub destroy_context: AtomicUsize,
+    pub invoke_method: AtomicUsize,
+    /// Items delivered by `InvokeMethodStream`, over every call
+    pub streamed_items: AtomicUsize,
+    /// Streams the client stopped reading before their end
+    pub streams_cancelled: AtomicUsize,
+}
+
+impl Calls {
+    pub fn get(counter: &AtomicUsize) -> usize {
+        counter.load(Ordering::SeqCst)
+    }
+}
+
+/// A call a mock answers with a failure
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    CreateContext,
+    Invoke,
+}
+
+/// An invocation's method and when it ran
+pub type Invocation = (String, Range<Instant>);
+
+#[derive(Clone, Default)]
+pub struct Mock {
+    pub calls: Arc<Calls>,
+    next_context: Arc<AtomicUsize>,
+    /// Contexts created and not destroyed yet
+    contexts: Arc<Mutex<HashSet<String>>>,
+    /// What every `InvokeMethodStream` call yields
+    items: Arc<Vec<JsonValue>>,
+    fault: Option<Fault>,
+    /// How long every invocation takes
+    delay: Duration,
+    invocations: Arc<Mutex<Vec<Invocation>>>,
+}
+
+impl Mock {
+    /// A mock whose streams yield `items`
+    pub fn streaming(items: Vec<JsonValue>) -> Self {
+        Self {
+            items: Arc::new(items),
+            ..Self::default()
+        }
+    }
+
+    /// A mock that fails every `fault` call
+    pub fn failing(fault: Fault) -> Self {
+        Self {
+            fault: Some(fault),
+            ..Self::default()
+        }
+    }
+
+    /// A mock whose invocations each take `delay`
+    pub fn slow(delay: Duration) -> Self {
+        Self {
+            delay,
+            ..Self::default()
+        }
+    }
+
+    /// Every invocation's method and when it ran
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().unwrap().clone()
+    }
+
+    /// How many contexts the runner created and hasn't destroyed
+    pub fn open_contexts(&self) -> usize {
+        self.contexts.lock().unwrap().len()
+    }
+
+    /// Serve on a free port until the test's runtime shuts down, returning
+    /// the config to reach it with
+    pub async fn start(&self) -> ServerConfig {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let service = TranspileTestServiceServer::new(self.clone());
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(incoming),
+        );
+        ServerConfig::local(port)
+    }
+}
+
+/// The mocks as targets python, rust, typescript..., in that order
+pub async fn servers(mocks: &[&Mock]) -> Targets {
+    let names = ["python", "rust", "typescript", "go"];
+    let mut yaml = String::new();
+    for (mock, name) in mocks.iter().zip(names) {
+        let config = mock.start().await;
+        yaml.push_str(&format!(
+            "{}: {{host: {}, port: {}}}\n",
+            name, config.host, config.port
+        ));
+    }
+    serde_yaml::from_str(&yaml).unwrap()
+}
+
+/// A runner with default options against the mocks, as `servers` names them
+pub async fn runner(mocks: &[&Mock]) -> TestRunner {
+    let servers = servers(mocks).await;
+    let options = RunnerOptions {
+        show_types: false,
+        max_json_depth: transpile_common::json_depth::DEFAULT_MAX_DEPTH,
+        retries: None,
+        warmup: None,
+        batch_size: 1,
+        skip_perf: false,
+        strict_xfail: false,
+        encoding: None,
+        setup: Vec::new(),
+        teardown: Vec::new(),
+    };
+    TestRunner::new(
+        &servers,
+        &mut Connections::new(Duration::from_secs(5)),
+        Timings::new(),
+        Usage::new(servers.names()),
+        options,
+    )
+    .await
+    .unwrap()
+}
+
+fn count(counter: &AtomicUsize) {
+    counter.fetch_add(1, Ordering::SeqCst);
+}
+
+fn metadata() -> Option<ExecutionMetadata> {
+    Some(ExecutionMetadata {
+        execution_time_us: 1,
+        memory_bytes: 0,
+        runtime: "mock".to_string(),
+    })
+}
+
+#[tonic::async_trait]
+impl TranspileTestService for Mock {
+    async fn create_context(
+        &self,
+        _request: Request<CreateContextRequest>,
+    ) -> Result<Response<CreateContextResponse>, Status> {
+        count(&self.calls.create_context);
+        if self.fault == Some(Fault::CreateContext) {
+            return Ok(Response::new(CreateContextResponse {
+                error: "mock: no context".to_string(),
+                ..Default::default()
+            }));
+        }
+        let id = self.next_context.fetch_add(1, Ordering::SeqCst);
+        let context_id = format!("ctx-{}", id);
+        self.contexts.lock().unwrap().insert(context_id.clone());
+        Ok(Response::new(CreateContextResponse {
+            context_id,
+            success: true,
+            ..Default::default()
+        }))
+    }
+
+    async fn invoke_method(
+        &self,
+        request: Request<InvokeMethodRequest>,
+    ) -> Result<Response<InvokeMethodResponse>, Status> {
+        count(&self.calls.invoke_method);
+        let started = Instant::now();
+        let request = request.into_inner();
+        let deadline = Duration::from_millis(request.timeout_ms);
+        let timed_out = request.timeout_ms > 0 && self.delay > deadline;
+        tokio::time::sleep(if timed_out { deadline } else { self.delay }).await;
+        self.invocations
+            .lock()
+            .unwrap()
+            .push((request.method_name.clone(), started..Instant::now()));
+        if timed_out {
+            return Ok(Response::new(InvokeMethodResponse {
+                error: format!("TimeoutError: exceeded {}ms", request.timeout_ms),
+                error_code: ErrorCode::Timeout.into(),
+                ..Default::default()
+            }));
+        }
+        if self.fault == Some(Fault::Invoke) {
+            return Ok(Response::new(InvokeMethodResponse {
+                error: "mock: invocation failed".to_string(),
+                error_code: ErrorCode::ExecutionError.into(),
+                metadata: metadata(),
+                ..Default::default()
+            }));
+        }
+        Ok(Response::new(InvokeMethodResponse {
+            success: true,
+            result: request.arguments,
+            metadata: metadata(),
+            ..Default::default()
+        }))
+    }
+
+    async fn invoke_batch(
+        &self,
+        _request: Request<InvokeBatchRequest>,
+    ) -> Result<Response<InvokeBatchResponse>, Status> {
+        Err(Status::unimplemented("InvokeBatch"))
+    }
+
+    type InvokeMethodStreamStream = ReceiverStream<Result<InvokeStreamChunk, Status>>;
+
+    async fn invoke_method_stream(
+        &self,
+        _request: Request<InvokeMethodRequest>,
+    ) -> Result<Response<Self::InvokeMethodStreamStream>, Status> {
+        let (sender, receiver) = mpsc::channel(1);
+        let (items, calls) = (self.items.clone(), self.calls.clone());
+        tokio::spawn(async move {
+            for (sequence, item) in items.iter().enumerate() {
+                let chunk = InvokeStreamChunk {
+                    sequence: sequence as u64,
+                    item: item.to_string(),
+                    ..Default::default()
+                };
+                if sender.send(Ok(chunk)).await.is_err() {
+                    count(&calls.streams_cancelled);
+                    return;
+                }
+                count(&calls.streamed_items);
+            }
+            let end = InvokeStreamChunk {
+                sequence: items.len() as u64,
+                done: true,
+                metadata: metadata(),
+                ..Default::default()
+            };
+            let _ = sender.send(Ok(end)).await;
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn inspect_state(
+        &self,
+        _request: Request<InspectStateRequest>,
+    ) -> Result<Response<InspectStateResponse>, Status> {
+        Ok(Response::new(InspectStateResponse {
+            success: true,
+            state: "{}".to_string(),
+            ..Default::default()
+        }))
+    }
+
+    async fn export_context(
+        &self,
+        _request: Request<ExportContextRequest>,
+    ) -> Result<Response<ExportContextResponse>, Status> {
+        Err(Status::unimplemented("ExportContext"))
+    }
+
+    async fn import_context(
+        &self,
+        _request: Request<ImportContextRequest>,
+    ) -> Result<Response<ImportContextResponse>, Status> {
+        Err(Status::unimplemented("ImportContext"))
+    }
+
+    async fn destroy_context(
+        &self,
+        request: Request<DestroyContextRequest>,
+    ) -> Result<Response<DestroyContextResponse>, Status> {
+        count(&self.calls.destroy_context);
+        let context_id = request.into_inner().context_id;
+        self.contexts.lock().unwrap().remove(&context_id);
+        Ok(Response::new(DestroyContextResponse {
+            success: true,
+            error: String::new(),
+        }))
+    }
+
+    async fn reset_context(
+        &self,
+        _request: Request<ResetContextRequest>,
+    ) -> Result<Response<ResetContextResponse>, Status> {
+        count(&self.calls.reset_context);
+        Ok(Response::new(ResetContextResponse {
+            success: true,
+            error: String::new(),
+        }))
+    }
+
+    async fn list_contexts(
+        &self,
+        _request: Request<ListContextsRequest>,
+    ) -> Result<Response<ListContextsResponse>, Status> {
+        Ok(Response::new(ListContextsResponse::default()))
+    }
+
+    async fn destroy_all_contexts(
+        &self,
+        _request: Request<DestroyAllContextsRequest>,
+    ) -> Result<Response<DestroyAllContextsResponse>, Status> {
+        Ok(Response::new(DestroyAllContextsResponse::default()))
+    }
+
+    async fn list_methods(
+        &self,
+        _request: Request<ListMethodsRequest>,
+    ) -> Result<Response<ListMethodsResponse>, Status> {
+        Ok(Response::new(ListMethodsResponse::default()))
+    }
+
+    async fn get_server_info(
+        &self,
+        _request: Request<GetServerInfoRequest>,
+    ) -> Result<Response<GetServerInfoResponse>, Status> {
+        Ok(Response::new(GetServerInfoResponse {
+            runtime: "mock".to_string(),
+            registration_complete: true,
+            ..Default::default()
+        }))
+    }
+}
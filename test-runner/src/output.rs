@@ -0,0 +1,433 @@
+/*!
+How results are printed as suites finish.
+
+The default `Terminal` output shows every test with its timings and result.
+For CI logs there are two terser reporters: `--quiet` prints one line per
+failing test and a final summary, and `--format compact` prints a line of
+`.` (passed) and `F` (failed) per suite, pytest style, then the failures in
+full. Every reporter ends with the same counts; exit codes don't depend on
+the reporter. Tests marked `allow_failure` that fail are counted as
+xfailed rather than failed, and those that pass anyway as xpassed. Tests
+whose setup failed are counted as skipped, along with those left out by
+`--filter` and `--tag`.
+
+With `--target`, every summary says which target ran, so a green
+single-target run isn't mistaken for cross-validation.
+
+`--no-color`, or a non-empty `NO_COLOR` environment variable, turns off
+styling everywhere, logs included.
+*/
+
+use crate::canonical;
+use crate::suites::{LoadError, SuiteRun};
+use crate::{Status, TestResult};
+use clap::ValueEnum;
+use colored::*;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Format {
+    /// Every test with its timings and result
+    #[default]
+    Full,
+    /// One character per test
+    Compact,
+}
+
+/// Prints the results of a run
+pub trait Reporter {
+    /// Results of one suite, once all its tests ran
+    fn suite(&mut self, name: &str, results: &[TestResult], skipped: usize);
+    /// Totals once every suite ran
+    fn total(&mut self, runs: &[SuiteRun], errors: &[LoadError]);
+}
+
+/// The reporter for the command-line options; `only` is the `--target`
+pub fn reporter(format: Format, quiet: bool, only: Option<String>) -> Box<dyn Reporter> {
+    match (format, quiet) {
+        (_, true) => Box::new(Quiet { only }),
+        (Format::Full, false) => Box::new(Terminal { only }),
+        (Format::Compact, false) => Box::new(Compact {
+            failures: Vec::new(),
+            only,
+        }),
+    }
+}
+
+/// Whether styling is off, by `--no-color` or the `NO_COLOR` convention
+pub fn no_color(flag: bool) -> bool {
+    flag || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+pub struct Terminal {
+    only: Option<String>,
+}
+
+impl Reporter for Terminal {
+    fn suite(&mut self, name: &str, results: &[TestResult], skipped: usize) {
+        println!("\n{}", "=".repeat(80).bright_blue());
+        println!("{}: {}", "Test Suite".bright_blue().bold(), name);
+        println!("{}", "=".repeat(80).bright_blue());
+
+        for result in results {
+            if matches!(result.status, Status::XFailed | Status::Skipped) {
+                continue;
+            }
+            if !result.failed() {
+                let xpass = if result.status == Status::XPassed {
+                    " (XPASS: marked allow_failure)".yellow()
+                } else {
+                    "".normal()
+                };
+                println!(
+                    "\n  {} {}{}{}{}",
+                    "✓".bright_green().bold(),
+                    result.name.bright_white(),
+                    cached_marker(result),
+                    retry_marker(result),
+                    xpass
+                );
+
+                let times: Vec<String> = result
+                    .targets
+                    .iter()
+                    .filter_map(|t| {
+                        t.time_us
+                            .map(|us| format!("{}: {}μs", t.target, us.to_string().cyan()))
+                    })
+                    .collect();
+                if !times.is_empty() && times.len() == result.targets.len() {
+                    println!("    ⏱  {}", times.join(" | "));
+                }
+
+                if let Some(res) = result.targets.first().and_then(|t| t.result.as_ref()) {
+                    println!("    Result: {}", canonical::to_json_string(res).dimmed());
+                }
+            } else {
+                println!(
+                    "\n  {} {}{}{}",
+                    "✗".bright_red().bold(),
+                    result.name.bright_white(),
+                    cached_marker(result),
+                    retry_marker(result)
+                );
+
+                if let Some(ref err) = result.error_message {
+                    for line in err.lines() {
+                        println!("    {}", line.red());
+                    }
+                }
+            }
+        }
+
+        let xfailed: Vec<&TestResult> = results
+            .iter()
+            .filter(|r| r.status == Status::XFailed)
+            .collect();
+        if !xfailed.is_empty() {
+            println!("\n  {}", "Expected failures (allow_failure):".yellow());
+            for result in xfailed {
+                let message = result.error_message.as_deref().unwrap_or_default();
+                println!(
+                    "    {} {}: {}",
+                    "~".yellow(),
+                    result.name,
+                    message.lines().next().unwrap_or_default().dimmed()
+                );
+            }
+        }
+
+        let setup_failed: Vec<&TestResult> = results
+            .iter()
+            .filter(|r| r.status == Status::Skipped)
+            .collect();
+        if !setup_failed.is_empty() {
+            println!("\n  {}", "Skipped (setup failed):".yellow());
+            for result in setup_failed {
+                let message = result.error_message.as_deref().unwrap_or_default();
+                println!(
+                    "    {} {}: {}",
+                    "-".yellow(),
+                    result.name,
+                    message.lines().next().unwrap_or_default().dimmed()
+                );
+            }
+        }
+
+        let counts = Counts::of(results);
+        let skipped = skipped + counts.skipped;
+        println!("\n{}", "=".repeat(80).bright_blue());
+        println!(
+            "{}: {}/{} passed{}",
+            "Summary".bright_blue().bold(),
+            counts.passed.to_string().bright_green(),
+            counts.total().to_string().bright_white(),
+            counts.xfail_suffix()
+        );
+        if let Some(note) = only_note(&self.only) {
+            println!("  {}", note);
+        }
+
+        if counts.failed > 0 {
+            println!("  {} tests failed", counts.failed.to_string().bright_red());
+            for result in results.iter().filter(|r| r.failed()) {
+                if let Some((reference, other)) = &result.diverged {
+                    println!("    {}: {} diverged from {}", result.name, other, reference);
+                }
+            }
+        }
+        if skipped > 0 {
+            println!("  {} tests skipped", skipped.to_string().yellow());
+        }
+        println!("{}\n", "=".repeat(80).bright_blue());
+    }
+
+    /// Totals over every suite; a single suite's summary already says it all
+    fn total(&mut self, runs: &[SuiteRun], errors: &[LoadError]) {
+        if runs.len() + errors.len() <= 1 {
+            return;
+        }
+        println!("{}", "=".repeat(80).bright_blue());
+        println!(
+            "{}: {} suites",
+            "Total".bright_blue().bold(),
+            runs.len() + errors.len()
+        );
+        for run in runs {
+            let counts = Counts::of(&run.results);
+            let text = format!("{}/{} passed", counts.passed, counts.total());
+            let counts = if counts.failed == 0 {
+                text.bright_green()
+            } else {
+                text.bright_red()
+            };
+            println!("  {}  {} ({})", counts, run.name, run.path.display());
+        }
+        for error in errors {
+            println!(
+                "  {}  {}: {}",
+                "error".bright_red().bold(),
+                error.path.display(),
+                error.message
+            );
+        }
+
+        let counts = Counts::of(runs.iter().flat_map(|run| &run.results));
+        let skipped = counts.skipped + runs.iter().map(|run| run.skipped.len()).sum::<usize>();
+        println!(
+            "\n  {}/{} tests passed{}",
+            counts.passed.to_string().bright_green(),
+            counts.total().to_string().bright_white(),
+            counts.xfail_suffix()
+        );
+        if let Some(note) = only_note(&self.only) {
+            println!("  {}", note);
+        }
+        if skipped > 0 {
+            println!("  {} tests skipped", skipped.to_string().yellow());
+        }
+        if !errors.is_empty() {
+            println!(
+                "  {} suites failed to load",
+                errors.len().to_string().bright_red()
+            );
+        }
+        println!("{}\n", "=".repeat(80).bright_blue());
+    }
+}
+
+/// Failing tests only, one line each
+pub struct Quiet {
+    only: Option<String>,
+}
+
+impl Reporter for Quiet {
+    fn suite(&mut self, name: &str, results: &[TestResult], _skipped: usize) {
+        for result in results.iter().filter(|r| r.failed()) {
+            let message = result.error_message.as_deref().unwrap_or_default();
+            println!(
+                "{} {} :: {}: {}",
+                "✗".bright_red().bold(),
+                name,
+                result.name,
+                message.lines().next().unwrap_or_default()
+            );
+        }
+    }
+
+    fn total(&mut self, runs: &[SuiteRun], errors: &[LoadError]) {
+        print_errors(errors);
+        println!("{}", summary(runs, errors, &self.only));
+    }
+}
+
+/// `.` or `F` per test (`x` and `X` for xfailed and xpassed, `s` for
+/// skipped), failures in full at the end
+pub struct Compact {
+    /// Suite name, test name and message of every failure so far
+    failures: Vec<(String, String, String)>,
+    only: Option<String>,
+}
+
+impl Reporter for Compact {
+    fn suite(&mut self, name: &str, results: &[TestResult], _skipped: usize) {
+        let marks: String = results
+            .iter()
+            .map(|r| {
+                match r.status {
+                    Status::Passed => ".".green(),
+                    Status::Failed => "F".bright_red().bold(),
+                    Status::XFailed => "x".yellow(),
+                    Status::XPassed => "X".yellow(),
+                    Status::Skipped => "s".yellow(),
+                }
+                .to_string()
+            })
+            .collect();
+        println!("{} {}", name, marks);
+        for result in results.iter().filter(|r| r.failed()) {
+            self.failures.push((
+                name.to_string(),
+                result.name.clone(),
+                result.error_message.clone().unwrap_or_default(),
+            ));
+        }
+    }
+
+    fn total(&mut self, runs: &[SuiteRun], errors: &[LoadError]) {
+        if !self.failures.is_empty() {
+            println!("\n{}", "Failures".bright_red().bold());
+        }
+        for (suite, test, message) in &self.failures {
+            println!("\n{} :: {}", suite, test.bright_white());
+            for line in message.lines() {
+                println!("    {}", line);
+            }
+        }
+        print_errors(errors);
+        println!("\n{}\n", summary(runs, errors, &self.only));
+    }
+}
+
+fn print_errors(errors: &[LoadError]) {
+    for error in errors {
+        println!(
+            "{} {}: {}",
+            "error".bright_red().bold(),
+            error.path.display(),
+            error.message
+        );
+    }
+}
+
+/// One line of counts over the whole run
+pub fn summary(runs: &[SuiteRun], errors: &[LoadError], only: &Option<String>) -> String {
+    let counts = Counts::of(runs.iter().flat_map(|run| &run.results));
+    let skipped = counts.skipped + runs.iter().map(|run| run.skipped.len()).sum::<usize>();
+    let failed_count = format!("{} failed", counts.failed);
+    let failed_count = if counts.failed > 0 {
+        failed_count.bright_red()
+    } else {
+        failed_count.normal()
+    };
+    let mut parts = vec![
+        format!("{} passed", counts.passed)
+            .bright_green()
+            .to_string(),
+        failed_count.to_string(),
+    ];
+    if counts.xfailed > 0 {
+        parts.push(format!("{} xfailed", counts.xfailed).yellow().to_string());
+    }
+    if counts.xpassed > 0 {
+        parts.push(format!("{} xpassed", counts.xpassed).yellow().to_string());
+    }
+    if skipped > 0 {
+        parts.push(format!("{} skipped", skipped).yellow().to_string());
+    }
+    if !errors.is_empty() {
+        parts.push(
+            format!("{} suites failed to load", errors.len())
+                .bright_red()
+                .to_string(),
+        );
+    }
+    let mut line = parts.join(", ");
+    if let Some(note) = only_note(only) {
+        line.push_str(&format!(" ({})", note));
+    }
+    line
+}
+
+/// Says a `--target` run checked one implementation, not cross-validated
+fn only_note(only: &Option<String>) -> Option<ColoredString> {
+    only.as_ref().map(|target| {
+        format!(
+            "{} only: checked against expected values, not other targets",
+            target
+        )
+        .yellow()
+    })
+}
+
+/// Results by status
+#[derive(Default)]
+struct Counts {
+    passed: usize,
+    failed: usize,
+    xfailed: usize,
+    xpassed: usize,
+    /// Setup failed, so they never ran
+    skipped: usize,
+}
+
+impl Counts {
+    fn of<'a>(results: impl IntoIterator<Item = &'a TestResult>) -> Self {
+        let mut counts = Self::default();
+        for result in results {
+            match result.status {
+                Status::Passed => counts.passed += 1,
+                Status::Failed => counts.failed += 1,
+                Status::XFailed => counts.xfailed += 1,
+                Status::XPassed => counts.xpassed += 1,
+                Status::Skipped => counts.skipped += 1,
+            }
+        }
+        counts
+    }
+
+    /// Tests that ran
+    fn total(&self) -> usize {
+        self.passed + self.failed + self.xfailed + self.xpassed
+    }
+
+    /// `, 1 xfailed, 2 xpassed` when there are any
+    fn xfail_suffix(&self) -> ColoredString {
+        let mut suffix = String::new();
+        if self.xfailed > 0 {
+            suffix.push_str(&format!(", {} xfailed", self.xfailed));
+        }
+        if self.xpassed > 0 {
+            suffix.push_str(&format!(", {} xpassed", self.xpassed));
+        }
+        suffix.yellow()
+    }
+}
+
+fn cached_marker(result: &TestResult) -> ColoredString {
+    if result.cached {
+        " (cached pass)".dimmed()
+    } else {
+        "".normal()
+    }
+}
+
+/// Keeps flakiness visible: retried tests say so even when they passed
+fn retry_marker(result: &TestResult) -> ColoredString {
+    let retries = result.attempts.saturating_sub(1);
+    match (retries, result.passed) {
+        (0, _) => "".normal(),
+        (1, true) => " (passed after 1 retry)".yellow(),
+        (n, true) => format!(" (passed after {} retries)", n).yellow(),
+        (_, false) => format!(" (failed {} attempts)", result.attempts).red(),
+    }
+}
@@ -0,0 +1,102 @@
+/*!
+`perf`: assertions on how fast each implementation runs a test.
+
+```yaml
+- name: fibonacci_30
+  method: fibonacci
+  arguments: {n: 30}
+  perf: {max_ratio: 1.5, min_iterations: 20}
+```
+
+Once the results agree, the method is invoked `min_iterations` more times
+on every server and the median of the `execution_time_us` each server
+reports is taken. Every target's median divided by the baseline's must not
+exceed `max_ratio`. The baseline is the reference unless `faster_than`
+names another target, so with `python` as the reference,
+`faster_than: rust` checks python/rust instead of rust/python. Medians
+rather than means keep a single garbage-collection pause from failing the
+test.
+
+`--skip-perf` turns the assertions off for machines too slow or noisy to
+time on.
+*/
+
+use serde::{Deserialize, Serialize};
+
+/// Timed invocations per target when a test doesn't say
+pub const DEFAULT_ITERATIONS: u32 = 10;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PerfCheck {
+    /// Largest accepted target median / baseline median
+    pub max_ratio: f64,
+    #[serde(default = "default_iterations")]
+    pub min_iterations: u32,
+    /// Target the others are measured against; the reference if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub faster_than: Option<String>,
+}
+
+fn default_iterations() -> u32 {
+    DEFAULT_ITERATIONS
+}
+
+impl PerfCheck {
+    /// Index in `names` of the baseline target
+    pub fn baseline(&self, names: &[String]) -> Result<usize, String> {
+        match &self.faster_than {
+            Some(name) => names.iter().position(|n| n == name).ok_or_else(|| {
+                format!(
+                    "perf: faster_than names `{}`, which isn't one of the servers ({})",
+                    name,
+                    names.join(", ")
+                )
+            }),
+            None => Ok(0),
+        }
+    }
+
+    /// Check the per-target `medians` (µs, parallel to `names`), describing
+    /// every target over budget
+    pub fn evaluate(&self, names: &[String], medians: &[i64]) -> Result<(), String> {
+        let baseline = self.baseline(names)?;
+        let base = medians[baseline];
+        let mut over = Vec::new();
+        for (i, &median) in medians.iter().enumerate() {
+            if i == baseline {
+                continue;
+            }
+            let ratio = median as f64 / base.max(1) as f64;
+            if ratio > self.max_ratio {
+                over.push(format!(
+                    "{} median {}μs / {} median {}μs = {:.2}, above max_ratio {}",
+                    names[i], median, names[baseline], base, ratio, self.max_ratio
+                ));
+            }
+        }
+        if over.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Too slow over {} iterations:\n{}",
+                self.min_iterations,
+                over.join("\n")
+            ))
+        }
+    }
+}
+
+/// Median of `times`, which are sorted in the process; `None` if empty
+pub fn median(times: &mut [i64]) -> Option<i64> {
+    if times.is_empty() {
+        return None;
+    }
+    times.sort_unstable();
+    let mid = times.len() / 2;
+    Some(if times.len().is_multiple_of(2) {
+        (times[mid - 1] + times[mid]) / 2
+    } else {
+        times[mid]
+    })
+}
@@ -0,0 +1,99 @@
+/*!
+Seeded randomness for anything a run should be able to reproduce.
+
+The runner prints the seed it used; passing it back in replays the same
+sequence, so an ordering or payload that exposed a bug can be re-run.
+*/
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A seed from the clock, for runs that weren't given one
+pub fn clock_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(1)
+}
+
+/// `seed`, or one from the clock, and the line reporting it so the run
+/// can be repeated with `--seed`
+pub fn announce(seed: Option<u64>, doing: &str) -> (u64, String) {
+    let seed = seed.unwrap_or_else(clock_seed);
+    (
+        seed,
+        format!("{} with seed {} (repeat with --seed {})", doing, seed, seed),
+    )
+}
+
+/// Small deterministic PRNG (xorshift64)
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero, so keep the state odd
+        Self(seed | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A value in `0..bound`
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    /// Fisher-Yates shuffle of `items`
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(seed: u64) -> Vec<u64> {
+        let mut rng = XorShift::new(seed);
+        (0..16).map(|_| rng.next()).collect()
+    }
+
+    fn shuffled(seed: u64) -> Vec<usize> {
+        let mut items: Vec<usize> = (0..32).collect();
+        XorShift::new(seed).shuffle(&mut items);
+        items
+    }
+
+    #[test]
+    fn a_seed_reproduces_its_values() {
+        assert_eq!(values(42), values(42));
+        assert_eq!(shuffled(42), shuffled(42));
+        assert_ne!(values(42), values(7));
+        assert_ne!(shuffled(42), shuffled(7));
+    }
+
+    #[test]
+    fn the_seed_is_reported() {
+        let (seed, line) = announce(Some(42), "Shuffling tests");
+        assert_eq!(seed, 42);
+        assert_eq!(line, "Shuffling tests with seed 42 (repeat with --seed 42)");
+    }
+
+    #[test]
+    fn a_clock_seed_is_reported_as_used() {
+        let (seed, line) = announce(None, "Fuzzing");
+        let reported: u64 = line
+            .strip_suffix(')')
+            .and_then(|line| line.rsplit(' ').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(reported, seed);
+        assert_eq!(values(reported), values(seed));
+    }
+}
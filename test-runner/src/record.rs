@@ -0,0 +1,277 @@
+/*!
+`--record`: write observed results back into suite files as `expected`.
+
+A test is recorded when every target succeeded and agreed with the
+reference; its reference result becomes the test's `expected`. Tests that
+already have an `expected` are left alone unless `--record-overwrite` is
+given. Diverging tests are never recorded and still fail the run, and
+multi-step tests are skipped since each step has its own `expected`, as are
+tests with `expect_error`, tests expanded from `cases` and tests whose setup
+failed.
+
+The file is edited as text rather than re-serialized, so comments, key
+order and formatting survive: the test's existing `expected:` value is
+replaced, or a new one is added at the end of the test's block. Values are
+written as canonical block YAML, so recording the same results again leaves
+the file byte-identical. The edited file is parsed again before it is
+written, and left untouched if any recorded value doesn't read back.
+*/
+
+use crate::{canonical, compare, Status, TestCase, TestResult};
+use anyhow::{Context, Result};
+use colored::*;
+use serde_json::Value as JsonValue;
+use std::path::Path;
+
+/// What recording did to one suite file
+#[derive(Default)]
+pub struct Recorded {
+    /// Expectations written
+    pub written: usize,
+    /// Tests whose targets disagreed or failed
+    pub disagreed: usize,
+    /// Tests whose block couldn't be found in the file, e.g. flow-style YAML
+    pub not_found: Vec<String>,
+}
+
+/// Record the results of `tests` (in the same order as `results`) into the
+/// suite at `path`
+pub fn record(
+    path: &Path,
+    tests: &[TestCase],
+    results: &[&TestResult],
+    overwrite: bool,
+) -> Result<Recorded> {
+    let mut recorded = Recorded::default();
+    let mut values = Vec::new();
+    for (test, result) in tests.iter().zip(results) {
+        let expects_error = test.expect_error.as_ref().is_some_and(|e| e.is_set());
+        let unrecordable =
+            !test.steps.is_empty() || expects_error || test.from_cases || test.stream;
+        let ran = result.status != Status::Skipped;
+        if unrecordable || !ran || (test.expected.is_some() && !overwrite) {
+            continue;
+        }
+        match agreed_value(result) {
+            Some(value) => values.push((test.name.as_str(), value)),
+            None => recorded.disagreed += 1,
+        }
+    }
+    if values.is_empty() {
+        return Ok(recorded);
+    }
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut written = Vec::new();
+    for (name, value) in values {
+        if set_expected(&mut lines, name, value) {
+            written.push((name, value));
+        } else {
+            recorded.not_found.push(name.to_string());
+        }
+    }
+    if written.is_empty() {
+        return Ok(recorded);
+    }
+
+    let mut edited = lines.join("\n");
+    if content.ends_with('\n') {
+        edited.push('\n');
+    }
+    check_round_trip(&content, &edited, &written)
+        .with_context(|| format!("Not recording into {}", path.display()))?;
+    std::fs::write(path, edited).with_context(|| format!("Failed to write {}", path.display()))?;
+    recorded.written = written.len();
+    Ok(recorded)
+}
+
+/// The reference result, if every target returned one and they all agreed
+fn agreed_value(result: &TestResult) -> Option<&JsonValue> {
+    let agreed = !result.targets.is_empty()
+        && result.diverged.is_none()
+        && result.targets.iter().all(|t| t.error.is_none());
+    if agreed {
+        result.targets[0].result.as_ref()
+    } else {
+        None
+    }
+}
+
+/// Set `expected` in the block of test `name`; false if there's no such block
+fn set_expected(lines: &mut Vec<String>, name: &str, value: &JsonValue) -> bool {
+    let Some((start, key_indent)) = find_test(lines, name) else {
+        return false;
+    };
+
+    // The block runs until the first content line indented less than its keys
+    let mut end = start + 1;
+    let mut last_content = start;
+    while end < lines.len() {
+        let line = &lines[end];
+        let trimmed = line.trim_start();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            if indent(line) < key_indent {
+                break;
+            }
+            last_content = end;
+        }
+        end += 1;
+    }
+
+    let existing =
+        (start..=last_content).find(|&i| key_at(&lines[i], key_indent) == Some("expected"));
+    match existing {
+        Some(line) => {
+            // A block value continues on lines indented past the key, or
+            // level with it for a sequence's `- ` items
+            let mut value_end = line + 1;
+            while value_end <= last_content && {
+                let next = &lines[value_end];
+                next.trim().is_empty()
+                    || indent(next) > key_indent
+                    || (indent(next) == key_indent && next.trim_start().starts_with("- "))
+            } {
+                value_end += 1;
+            }
+            let prefix = &lines[line][..lines[line].find("expected:").unwrap_or(0)];
+            let replaced = format!(
+                "{}expected:{}",
+                prefix,
+                canonical::to_yaml_value(value, key_indent)
+            );
+            lines.splice(line..value_end, replaced.split('\n').map(str::to_string));
+        }
+        None => {
+            let added = format!(
+                "{}expected:{}",
+                " ".repeat(key_indent),
+                canonical::to_yaml_value(value, key_indent)
+            );
+            let at = last_content + 1;
+            lines.splice(at..at, added.split('\n').map(str::to_string));
+        }
+    }
+    true
+}
+
+/// Line of the test's `- ` item and the indentation of its keys
+pub fn find_test(lines: &[String], name: &str) -> Option<(usize, usize)> {
+    for (index, line) in lines.iter().enumerate() {
+        let (key_indent, rest) = key_start(line);
+        let Some(raw) = rest.strip_prefix("name:") else {
+            continue;
+        };
+        if serde_yaml::from_str::<String>(raw.trim()).ok().as_deref() != Some(name) {
+            continue;
+        }
+        if line.trim_start().starts_with('-') {
+            return Some((index, key_indent));
+        }
+        // `name` isn't the first key; find the `- ` that opens the item
+        let opener = lines[..index].iter().rposition(|l| {
+            let trimmed = l.trim_start();
+            trimmed.starts_with("- ") && key_start(l).0 == key_indent
+        })?;
+        return Some((opener, key_indent));
+    }
+    None
+}
+
+/// Column where the line's key starts (after any `- `) and the text from there
+pub fn key_start(line: &str) -> (usize, &str) {
+    let rest = line.trim_start();
+    match rest.strip_prefix('-') {
+        Some(item) if item.starts_with(' ') => {
+            let key = item.trim_start();
+            (line.len() - key.len(), key)
+        }
+        _ => (indent(line), rest),
+    }
+}
+
+/// The key on `line` if it sits at `key_indent`
+fn key_at(line: &str, key_indent: usize) -> Option<&str> {
+    let (column, rest) = key_start(line);
+    if column != key_indent {
+        return None;
+    }
+    rest.split_once(':').map(|(key, _)| key.trim())
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// Single-line YAML for `value`, for printing: JSON with a space after every
+/// separator, which YAML reads back as the same value
+pub fn flow(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Array(items) => {
+            let items: Vec<String> = items.iter().map(flow).collect();
+            format!("[{}]", items.join(", "))
+        }
+        JsonValue::Object(fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(key, value)| format!("{}: {}", JsonValue::from(key.as_str()), flow(value)))
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        scalar => scalar.to_string(),
+    }
+}
+
+/// Parse the edited suite and check each recorded test reads back its value,
+/// and its arguments, in whichever shape, as they were
+fn check_round_trip(original: &str, edited: &str, values: &[(&str, &JsonValue)]) -> Result<()> {
+    let before = read_tests(original)?;
+    let after = read_tests(edited).context("the edited suite doesn't parse")?;
+    let exact = compare::CompareOptions {
+        type_sensitive: true,
+        abs_tolerance: None,
+        rel_tolerance: None,
+        ..Default::default()
+    };
+    for (name, value) in values {
+        let read_back = after.iter().find(|t| t.name == *name);
+        let expected = read_back.and_then(|t| t.expected.as_ref());
+        if !compare::results_equal(expected, Some(value), &exact) {
+            anyhow::bail!("the expected value of '{}' doesn't read back intact", name);
+        }
+        let original = before.iter().find(|t| t.name == *name);
+        if original.map(|t| &t.arguments) != read_back.map(|t| &t.arguments) {
+            anyhow::bail!("the arguments of '{}' don't read back intact", name);
+        }
+    }
+    Ok(())
+}
+
+/// The tests of a suite file's text
+fn read_tests(content: &str) -> Result<Vec<TestCase>> {
+    let doc: serde_yaml::Value = serde_yaml::from_str(content).context("not valid YAML")?;
+    serde_yaml::from_value(doc["tests"].clone()).context("the tests don't parse")
+}
+
+/// One line per suite, plus warnings for anything that wasn't recorded
+pub fn print(path: &Path, recorded: &Recorded) {
+    println!(
+        "{}: {} expectations written to {}",
+        "Record".bright_blue().bold(),
+        recorded.written.to_string().bright_green(),
+        path.display()
+    );
+    if recorded.disagreed > 0 {
+        println!(
+            "  {} tests not recorded: targets failed or disagreed",
+            recorded.disagreed.to_string().yellow()
+        );
+    }
+    for name in &recorded.not_found {
+        println!(
+            "  {} not recorded: no block-style entry for it in the file",
+            name.yellow()
+        );
+    }
+}
@@ -0,0 +1,260 @@
+/*!
+`repl`: call methods on every server of a suite by hand.
+
+Each line is a method and its arguments as JSON, sent to every server, with
+each server's result and time printed and any difference diffed:
+
+```text
+> add {"a": 2, "b": 3}
+  python  5  (41μs)
+  rust    5  (12μs)
+  ✓ agree
+```
+
+The arguments default to `{}`, and JSON that doesn't parse is reported
+without calling anything. Lines starting with `:` are commands:
+
+- `:methods` lists every server's methods
+- `:ctx new {"counter": 5}` creates a context on every server, which later
+  calls share until `:ctx drop`
+- `:state` shows each server's state of that context
+- `:quit` (or end of input) leaves, destroying the context
+*/
+
+use crate::compare::CompareOptions;
+use crate::context::ContextGuard;
+use crate::{canonical, display_result, rpc, Compared, TestRunner};
+use anyhow::Result;
+use colored::*;
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+  METHOD [ARGS]        call METHOD on every server; ARGS is JSON, {} if left out
+  :methods             list every server's methods
+  :ctx new [STATE]     create a context on every server from STATE (JSON, {} if left out)
+  :ctx drop            destroy the context; calls run without one again
+  :state               show every server's state of the context
+  :help                show this
+  :quit                leave";
+
+/// Read commands from stdin until `:quit` or end of input
+pub async fn run(runner: &mut TestRunner) -> Result<()> {
+    println!(
+        "Connected to {}. Type :help for commands.",
+        runner.names.join(", ")
+    );
+    let mut contexts: Vec<ContextGuard> = Vec::new();
+    let stdin = std::io::stdin();
+    loop {
+        print!("{} ", ">".bright_blue().bold());
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+        let line = line.trim();
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (line, ""),
+        };
+        match command {
+            "" => {}
+            ":quit" | ":q" | ":exit" => break,
+            ":help" | ":h" => println!("{}", HELP),
+            ":methods" => methods(runner).await,
+            ":ctx" => match rest.split_once(char::is_whitespace).unwrap_or((rest, "")) {
+                ("new", state) => new_context(runner, &mut contexts, state.trim()).await,
+                ("drop", _) if contexts.is_empty() => println!("  no context to drop"),
+                ("drop", _) => {
+                    drop_contexts(&mut contexts).await;
+                    println!("  context dropped");
+                }
+                _ => error("usage: :ctx new [STATE] | :ctx drop"),
+            },
+            ":state" => state(runner, &contexts).await,
+            command if command.starts_with(':') => {
+                error(&format!("unknown command `{}`; try :help", command))
+            }
+            method => invoke(runner, &contexts, method, rest).await,
+        }
+    }
+    drop_contexts(&mut contexts).await;
+    Ok(())
+}
+
+/// `text` as JSON, `{}` if empty, re-serialized canonically
+fn parse_json(text: &str) -> Result<String, String> {
+    if text.is_empty() {
+        return Ok("{}".to_string());
+    }
+    serde_json::from_str::<serde_json::Value>(text)
+        .map(|value| canonical::to_json_string(&value))
+        .map_err(|e| format!("invalid JSON: {}", e))
+}
+
+async fn invoke(runner: &mut TestRunner, contexts: &[ContextGuard], method: &str, args: &str) {
+    let args_json = match parse_json(args) {
+        Ok(json) => json,
+        Err(e) => return error(&e),
+    };
+    let mut executions = Vec::with_capacity(runner.clients.len());
+    for target in 0..runner.clients.len() {
+        let context_id = contexts
+            .get(target)
+            .map(ContextGuard::id)
+            .unwrap_or_default();
+        executions.push(
+            runner
+                .server(target)
+                .invoke(context_id, method, &args_json)
+                .await,
+        );
+    }
+
+    let width = name_width(runner);
+    for (name, execution) in runner.names.iter().zip(&executions) {
+        let time = execution
+            .time_us
+            .map(|us| format!("  ({}μs)", us).dimmed().to_string())
+            .unwrap_or_default();
+        match (&execution.result, &execution.error) {
+            (_, Some(e)) => println!("  {:width$}  {}{}", name, e.red(), time),
+            (result, None) => println!("  {:width$}  {}{}", name, display_result(result), time),
+        }
+    }
+    let compared = runner.compare_results(&executions, &None, &CompareOptions::default());
+    report(compared.map_err(|mismatch| mismatch.message));
+}
+
+async fn methods(runner: &mut TestRunner) {
+    let mut registered = Vec::with_capacity(runner.clients.len());
+    for (name, client) in runner.names.iter().zip(&mut runner.clients) {
+        match rpc::method_infos(client).await {
+            Ok(infos) => registered.push(infos),
+            Err(e) => return error(&format!("{}: {:#}", name, e)),
+        }
+    }
+    let all: BTreeSet<&str> = registered
+        .iter()
+        .flatten()
+        .map(|info| info.name.as_str())
+        .collect();
+    for method in all {
+        let mut missing_from = Vec::new();
+        let mut signature = None;
+        for (name, infos) in runner.names.iter().zip(&registered) {
+            match infos.iter().find(|info| info.name == method) {
+                Some(info) => {
+                    signature.get_or_insert_with(|| {
+                        let stateful = if info.is_stateful { " [stateful]" } else { "" };
+                        format!(
+                            "({}) -> {}{}",
+                            info.parameter_types.join(", "),
+                            info.return_type,
+                            stateful
+                        )
+                    });
+                }
+                None => missing_from.push(name.as_str()),
+            }
+        }
+        let missing = if missing_from.is_empty() {
+            String::new()
+        } else {
+            format!("  missing from {}", missing_from.join(", "))
+                .red()
+                .to_string()
+        };
+        println!(
+            "  {}{}{}",
+            method.bright_white(),
+            signature.unwrap_or_default().dimmed(),
+            missing
+        );
+    }
+}
+
+async fn new_context(runner: &mut TestRunner, contexts: &mut Vec<ContextGuard>, state: &str) {
+    let initial_state = match parse_json(state) {
+        Ok(json) => json,
+        Err(e) => return error(&e),
+    };
+    drop_contexts(contexts).await;
+    for target in 0..runner.clients.len() {
+        let created = runner
+            .server(target)
+            .create_context(initial_state.clone())
+            .await;
+        match created {
+            Ok(context) => contexts.push(context),
+            Err(execution) => {
+                let why = execution.error.unwrap_or_default();
+                error(&format!(
+                    "{} couldn't create a context: {}",
+                    runner.names[target], why
+                ));
+                drop_contexts(contexts).await;
+                return;
+            }
+        }
+    }
+    println!("  context created; calls now share it until :ctx drop");
+}
+
+async fn state(runner: &mut TestRunner, contexts: &[ContextGuard]) {
+    if contexts.is_empty() {
+        return error("no context; create one with :ctx new");
+    }
+    let width = name_width(runner);
+    let mut states = Vec::with_capacity(contexts.len());
+    for (target, context) in contexts.iter().enumerate() {
+        let state = runner.server(target).read_state(context.id()).await;
+        match &state {
+            Ok(value) => println!(
+                "  {:width$}  {}",
+                runner.names[target],
+                canonical::to_json_string(value)
+            ),
+            Err(e) => println!("  {:width$}  {}", runner.names[target], e.to_string().red()),
+        }
+        states.push(state);
+    }
+    if let Ok(states) = states.into_iter().collect::<Result<Vec<_>, _>>() {
+        let values: Vec<Option<&serde_json::Value>> = states.iter().map(Some).collect();
+        let options = CompareOptions::default();
+        let compared = runner.compare_values(Compared::States, &values, None, &options);
+        report(compared.map_err(|mismatch| mismatch.message));
+    }
+}
+
+async fn drop_contexts(contexts: &mut Vec<ContextGuard>) {
+    for context in contexts.drain(..) {
+        context.cleanup().await;
+    }
+}
+
+fn name_width(runner: &TestRunner) -> usize {
+    runner
+        .names
+        .iter()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+}
+
+fn report(compared: Result<(), String>) {
+    match compared {
+        Ok(()) => println!("  {} agree", "✓".bright_green().bold()),
+        Err(message) => {
+            for line in message.lines() {
+                println!("  {}", line.red());
+            }
+        }
+    }
+}
+
+fn error(message: &str) {
+    println!("  {} {}", "error:".bright_red().bold(), message);
+}
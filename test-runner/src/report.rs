@@ -0,0 +1,156 @@
+/*!
+Machine-readable report of a run.
+
+The report carries every `TestResult` as-is, grouped by suite, plus
+per-suite and overall summaries and the phase timing breakdown, so scripts
+can post-process runs with `jq` or compare them over time without scraping
+terminal output. Its shape is described by `schema::report_schema`.
+*/
+
+use crate::coverage::Coverage;
+use crate::suites::{LoadError, SuiteRun};
+use crate::timing::Timings;
+use crate::usage::UsageReport;
+use crate::{Status, TestResult};
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// Bumped whenever the report changes in a way consumers could notice
+pub const REPORT_VERSION: u64 = 9;
+
+#[derive(JsonSchema, Serialize)]
+pub struct Report<'a> {
+    pub version: u64,
+    /// Totals over every suite
+    pub summary: Summary,
+    pub timings: Vec<PhaseReport>,
+    /// The runner's own resource usage
+    pub usage: UsageReport,
+    pub suites: Vec<SuiteReport<'a>>,
+    /// Suite files that couldn't be loaded
+    pub errors: Vec<ErrorReport<'a>>,
+}
+
+#[derive(JsonSchema, Serialize)]
+pub struct SuiteReport<'a> {
+    pub suite: &'a str,
+    pub path: String,
+    pub summary: Summary,
+    pub tests: &'a [TestResult],
+    /// Present when the run used --coverage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<&'a Coverage>,
+}
+
+#[derive(JsonSchema, Serialize)]
+pub struct ErrorReport<'a> {
+    pub path: String,
+    pub message: &'a str,
+}
+
+#[derive(JsonSchema, Serialize)]
+pub struct Summary {
+    pub total: usize,
+    pub passed: usize,
+    pub failed: usize,
+    /// Failed, but marked `allow_failure`
+    pub xfailed: usize,
+    /// Passed despite `allow_failure`
+    pub xpassed: usize,
+    /// Tests left out by --filter/--tag, or whose setup failed
+    pub skipped: usize,
+    pub wall_time_us: u64,
+}
+
+#[derive(JsonSchema, Serialize)]
+pub struct PhaseReport {
+    pub phase: String,
+    pub wall_us: u64,
+    pub summed_us: u64,
+}
+
+impl Summary {
+    fn new<'r>(
+        results: impl Iterator<Item = &'r TestResult>,
+        skipped: usize,
+        wall_time: Duration,
+    ) -> Self {
+        let (mut passed, mut failed, mut xfailed, mut xpassed) = (0, 0, 0, 0);
+        let mut skipped = skipped;
+        for result in results {
+            match result.status {
+                Status::Passed => passed += 1,
+                Status::Failed => failed += 1,
+                Status::XFailed => xfailed += 1,
+                Status::XPassed => xpassed += 1,
+                Status::Skipped => skipped += 1,
+            }
+        }
+        let total = passed + failed + xfailed + xpassed;
+        Self {
+            total,
+            passed,
+            failed,
+            xfailed,
+            xpassed,
+            skipped,
+            wall_time_us: wall_time.as_micros() as u64,
+        }
+    }
+}
+
+impl<'a> Report<'a> {
+    pub fn new(
+        runs: &'a [SuiteRun],
+        errors: &'a [LoadError],
+        total: Duration,
+        timings: &Timings,
+        usage: UsageReport,
+    ) -> Self {
+        Self {
+            version: REPORT_VERSION,
+            summary: Summary::new(
+                runs.iter().flat_map(|run| &run.results),
+                runs.iter().map(|run| run.skipped.len()).sum(),
+                total,
+            ),
+            timings: timings
+                .snapshot()
+                .into_iter()
+                .map(|p| PhaseReport {
+                    phase: p.name,
+                    wall_us: p.wall.as_micros() as u64,
+                    summed_us: p.summed.as_micros() as u64,
+                })
+                .collect(),
+            usage,
+            suites: runs
+                .iter()
+                .map(|run| SuiteReport {
+                    suite: &run.name,
+                    path: run.path.display().to_string(),
+                    summary: Summary::new(run.results.iter(), run.skipped.len(), run.elapsed),
+                    tests: &run.results,
+                    coverage: run.coverage.as_ref(),
+                })
+                .collect(),
+            errors: errors
+                .iter()
+                .map(|error| ErrorReport {
+                    path: error.path.display().to_string(),
+                    message: &error.message,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let value = serde_json::to_value(self).context("Failed to serialize report")?;
+        let content = crate::canonical::to_json_string_pretty(&value);
+        std::fs::write(path, content + "\n")
+            .with_context(|| format!("Failed to write JSON report {}", path.display()))
+    }
+}
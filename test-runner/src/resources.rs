@@ -0,0 +1,124 @@
+/*!
+Named resources that serialize tests under `--jobs`.
+
+A test lists the resources it uses (`resources: [db]`, or the shorthand
+`serial_group: db`). Each resource is a semaphore with a single permit, so
+tests sharing one never overlap while unrelated tests still run in
+parallel. Permits are always taken in name order, so two tests needing the
+same pair of resources can't deadlock.
+*/
+
+use crate::TestCase;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub struct ResourceLocks {
+    locks: HashMap<String, Arc<Semaphore>>,
+}
+
+/// Held resources; released when dropped
+pub struct HeldResources {
+    _permits: Vec<OwnedSemaphorePermit>,
+}
+
+impl ResourceLocks {
+    /// One lock per resource named anywhere in `tests`
+    pub fn for_tests(tests: &[TestCase]) -> Self {
+        let locks = tests
+            .iter()
+            .flat_map(|t| t.resources())
+            .map(|name| (name, Arc::new(Semaphore::new(1))))
+            .collect();
+        Self { locks }
+    }
+
+    /// Take every lock in `names` without waiting, or none of them
+    pub fn try_acquire(&self, names: &[String]) -> Option<HeldResources> {
+        let mut permits = Vec::new();
+        for lock in self.ordered(names) {
+            // On failure the permits taken so far drop with `permits`
+            permits.push(lock.try_acquire_owned().ok()?);
+        }
+        Some(HeldResources { _permits: permits })
+    }
+
+    /// Wait for every lock in `names`
+    pub async fn acquire(&self, names: &[String]) -> HeldResources {
+        let mut permits = Vec::new();
+        for lock in self.ordered(names) {
+            permits.push(
+                lock.acquire_owned()
+                    .await
+                    .expect("resource locks are never closed"),
+            );
+        }
+        HeldResources { _permits: permits }
+    }
+
+    fn ordered(&self, names: &[String]) -> Vec<Arc<Semaphore>> {
+        let mut names: Vec<&String> = names.iter().collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .filter_map(|name| self.locks.get(name).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{self, Mock};
+    use crate::TestCase;
+    use std::time::Duration;
+
+    fn test(name: &str, resource: Option<&str>) -> TestCase {
+        let resources = resource.map_or(String::new(), |r| format!(", resources: [{}]", r));
+        serde_yaml::from_str(&format!("{{name: {0}, method: {0}{1}}}", name, resources)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn tests_sharing_a_resource_never_overlap() {
+        let delay = Duration::from_millis(50);
+        let (python, rust) = (Mock::slow(delay), Mock::slow(delay));
+        let runner = mock::runner(&[&python, &rust]).await;
+        let tests = [
+            test("db_1", Some("db")),
+            test("db_2", Some("db")),
+            test("db_3", Some("db")),
+            test("free_1", None),
+            test("free_2", None),
+            test("free_3", None),
+        ];
+        let results = crate::run_tests(&runner, &tests, None, 4).await;
+        assert!(results.iter().all(|r| r.passed));
+
+        for mock in [&python, &rust] {
+            let invocations = mock.invocations();
+            assert_eq!(invocations.len(), tests.len());
+            let overlaps = |name: &str| {
+                let (_, span) = invocations.iter().find(|(m, _)| m == name).unwrap();
+                invocations
+                    .iter()
+                    .filter(|(m, other)| {
+                        m != name && other.start < span.end && span.start < other.end
+                    })
+                    .map(|(m, _)| m.as_str())
+                    .collect::<Vec<_>>()
+            };
+            for db in ["db_1", "db_2", "db_3"] {
+                let found = overlaps(db);
+                assert!(
+                    found.iter().all(|m| m.starts_with("free")),
+                    "{} ran with {:?}",
+                    db,
+                    found
+                );
+            }
+            for free in ["free_1", "free_2", "free_3"] {
+                assert!(!overlaps(free).is_empty(), "{} ran alone", free);
+            }
+        }
+    }
+}
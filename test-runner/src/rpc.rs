@@ -0,0 +1,536 @@
+/*!
+Calls the runner makes against a single server.
+
+Every call goes through the run's `Usage` so bytes and RPC time are
+accounted the same way whichever server or test shape issued it.
+*/
+
+use crate::compare;
+use crate::context::{CleanupQueue, ContextGuard};
+use crate::transpile_test::transpile_test_service_client::TranspileTestServiceClient;
+use crate::transpile_test::{
+    CreateContextRequest, Encoding, ErrorCode, ExportContextRequest, GetServerInfoRequest,
+    InspectStateRequest, InvokeBatchRequest, InvokeMethodRequest, InvokeMethodResponse,
+    InvokeStreamChunk, ListMethodsRequest, MethodInfo,
+};
+use crate::transport::Auth;
+use crate::usage::{Traffic, Usage};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::time::Duration;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::Channel;
+use tonic::Streaming;
+use tracing::warn;
+use transpile_common::json_depth::{self, DepthError};
+use transpile_common::msgpack;
+
+pub type Client = TranspileTestServiceClient<InterceptedService<Channel, Auth>>;
+
+/// How much longer than a call's timeout the runner waits, so a server that
+/// enforces the timeout itself can say so before the runner gives up
+const SERVER_DEADLINE_GRACE: Duration = Duration::from_millis(500);
+
+/// Names of every method the server registered
+pub async fn list_methods(client: &mut Client) -> anyhow::Result<BTreeSet<String>> {
+    Ok(method_infos(client)
+        .await?
+        .into_iter()
+        .map(|m| m.name)
+        .collect())
+}
+
+/// Every method the server registered, with its declared signature
+pub async fn method_infos(client: &mut Client) -> anyhow::Result<Vec<MethodInfo>> {
+    let response = client
+        .list_methods(ListMethodsRequest {
+            prefix: String::new(),
+        })
+        .await
+        .map_err(|status| anyhow::anyhow!("ListMethods failed: {}", status))?;
+    Ok(response.into_inner().methods)
+}
+
+/// How arguments and results are sent, from `--encoding` or a suite's
+/// `encoding`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadEncoding {
+    /// JSON text, which every server accepts
+    #[default]
+    Json,
+    /// MessagePack, to servers that offer it; JSON to the others
+    Msgpack,
+}
+
+/// The encoding to call the server with: `wanted`, if the server lists it
+/// in `GetServerInfo`, or else JSON
+pub async fn negotiate(client: &mut Client, name: &str, wanted: PayloadEncoding) -> Encoding {
+    let wanted = match wanted {
+        PayloadEncoding::Json => return Encoding::Json,
+        PayloadEncoding::Msgpack => Encoding::Msgpack,
+    };
+    let offered = match client.get_server_info(GetServerInfoRequest {}).await {
+        Ok(resp) => resp
+            .into_inner()
+            .encodings()
+            .any(|encoding| encoding == wanted),
+        Err(status) => {
+            warn!("GetServerInfo failed on {} server: {}", name, status);
+            false
+        }
+    };
+    if !offered {
+        warn!(
+            "{} server doesn't accept {} payloads; sending it JSON",
+            name,
+            wanted.as_str_name()
+        );
+        return Encoding::Json;
+    }
+    wanted
+}
+
+/// A server's client together with the counters its calls update
+pub struct Server<'a> {
+    /// The target's name, for messages
+    pub name: &'a str,
+    pub client: &'a mut Client,
+    pub usage: &'a Usage,
+    pub traffic: &'a Traffic,
+    pub cleanup: &'a CleanupQueue,
+    /// Deepest nesting accepted in responses
+    pub max_json_depth: usize,
+    /// Longest to wait for any one call; unbounded if `None`
+    pub timeout: Option<Duration>,
+    /// How `invoke` and `invoke_batch` send arguments and get results
+    pub encoding: Encoding,
+}
+
+/// Outcome of running a method on one server
+#[derive(Default)]
+pub struct Execution {
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub time_us: Option<i64>,
+    /// The error came from the gRPC call itself rather than the method
+    pub transport_error: bool,
+    /// The call ran past the test's timeout, and the runner abandoned it
+    /// or the server failed it with `TIMEOUT`
+    pub timed_out: bool,
+    /// What kind of failure the server reported; `Unspecified` on success,
+    /// for errors the runner made up, and from servers without error codes
+    pub code: ErrorCode,
+    /// State of the test's context after the call, for tests that check it
+    pub state: Option<Result<serde_json::Value, StateError>>,
+    /// What the method printed during the call, as the server captured it
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Why a context's state couldn't be read
+#[derive(Debug)]
+pub enum StateError {
+    /// The test has no context to inspect
+    NoContext,
+    /// The server answered but couldn't inspect the context, e.g. because
+    /// it no longer exists
+    Rejected(String),
+    /// The call itself failed or timed out
+    Unreachable(String),
+    /// The state isn't JSON the runner accepts
+    Invalid(String),
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::NoContext => write!(f, "the test has no context; it must be stateful"),
+            StateError::Rejected(error) => write!(f, "the server couldn't inspect it: {}", error),
+            StateError::Unreachable(error) => write!(f, "InspectState failed: {}", error),
+            StateError::Invalid(error) => write!(f, "it can't be read: {}", error),
+        }
+    }
+}
+
+impl Execution {
+    /// The server handled the request but reported an error
+    pub fn failed(error: String) -> Self {
+        Self {
+            result: None,
+            error: Some(error),
+            time_us: None,
+            transport_error: false,
+            timed_out: false,
+            code: ErrorCode::Unspecified,
+            state: None,
+            stdout: String::new(),
+            stderr: String::new(),
+        }
+    }
+
+    /// The call didn't finish within the test's timeout
+    pub fn timed_out(timeout: Duration) -> Self {
+        Self {
+            result: None,
+            error: Some(format!("timed out after {}ms", timeout.as_millis())),
+            time_us: None,
+            transport_error: false,
+            timed_out: true,
+            code: ErrorCode::Unspecified,
+            state: None,
+            stdout: String::new(),
+            stderr: String::new(),
+        }
+    }
+
+    /// The request never got a proper response
+    pub fn unreachable(status: tonic::Status) -> Self {
+        Self {
+            result: None,
+            error: Some(status.to_string()),
+            time_us: None,
+            transport_error: true,
+            timed_out: false,
+            code: ErrorCode::Unspecified,
+            state: None,
+            stdout: String::new(),
+            stderr: String::new(),
+        }
+    }
+
+    /// The server doesn't know the method or context the test names, which
+    /// is a mistake in the suite rather than a difference between servers
+    pub fn misconfigured(&self) -> bool {
+        matches!(
+            self.code,
+            ErrorCode::MethodNotFound | ErrorCode::ContextNotFound
+        )
+    }
+}
+
+impl Server<'_> {
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Create a context, failing with the `Execution` to report for the test
+    pub async fn create_context(
+        &mut self,
+        initial_state: String,
+    ) -> Result<ContextGuard, Execution> {
+        self.traffic.sent(initial_state.len());
+        let request = CreateContextRequest { initial_state };
+        let call = self.client.create_context(request);
+        match timed(self.usage, self.timeout, call).await? {
+            Ok(resp) => {
+                let resp = resp.into_inner();
+                if resp.success {
+                    Ok(self.cleanup.guard(self.client, resp.context_id))
+                } else {
+                    Err(Execution::failed(resp.error))
+                }
+            }
+            Err(e) => Err(Execution::unreachable(e)),
+        }
+    }
+
+    /// Invoke `method`. With a timeout, the server is asked to enforce it
+    /// too, and given `SERVER_DEADLINE_GRACE` longer to report that it did.
+    pub async fn invoke(&mut self, context_id: &str, method: &str, args_json: &str) -> Execution {
+        let request = self.request(
+            context_id,
+            method,
+            args_json,
+            self.timeout.map_or(0, |t| t.as_millis() as u64),
+        );
+        self.traffic
+            .sent(request.method_name.len() + request.arguments.len() + request.payload.len());
+        let deadline = self.timeout.map(|t| t + SERVER_DEADLINE_GRACE);
+        let response = match timed(self.usage, deadline, self.client.invoke_method(request)).await {
+            Ok(response) => response,
+            Err(_) => return Execution::timed_out(self.timeout.unwrap_or_default()),
+        };
+
+        match response {
+            Ok(resp) => self.execution(resp.into_inner()),
+            Err(e) => Execution::unreachable(e),
+        }
+    }
+
+    /// Invoke every `(method, arguments)` of `calls` without a context in
+    /// one `InvokeBatch` round trip. The error is the status of the whole
+    /// call, `Unimplemented` from servers without batching.
+    pub async fn invoke_batch(
+        &mut self,
+        calls: &[(&str, &str)],
+    ) -> Result<Vec<Execution>, tonic::Status> {
+        let requests: Vec<InvokeMethodRequest> = calls
+            .iter()
+            .map(|&(method, args_json)| self.request("", method, args_json, 0))
+            .collect();
+        self.traffic.sent(
+            requests
+                .iter()
+                .map(|r| r.method_name.len() + r.arguments.len() + r.payload.len())
+                .sum(),
+        );
+        let request = InvokeBatchRequest { requests };
+        let responses = self
+            .usage
+            .rpc(self.client.invoke_batch(request))
+            .await?
+            .into_inner()
+            .responses;
+        if responses.len() != calls.len() {
+            return Err(tonic::Status::internal(format!(
+                "InvokeBatch returned {} responses for {} requests",
+                responses.len(),
+                calls.len()
+            )));
+        }
+        Ok(responses
+            .into_iter()
+            .map(|resp| self.execution(resp))
+            .collect())
+    }
+
+    /// A request to invoke `method`, with `args_json` in the server's
+    /// encoding
+    fn request(
+        &self,
+        context_id: &str,
+        method: &str,
+        args_json: &str,
+        timeout_ms: u64,
+    ) -> InvokeMethodRequest {
+        let mut request = InvokeMethodRequest {
+            context_id: context_id.to_string(),
+            method_name: method.to_string(),
+            arguments: String::new(),
+            timeout_ms,
+            encoding: Encoding::Json as i32,
+            payload: Vec::new(),
+        };
+        let args = match self.encoding {
+            Encoding::Json => None,
+            Encoding::Msgpack => self
+                .usage
+                .serialize(|| json_depth::from_str(args_json, self.max_json_depth).ok()),
+        };
+        match args {
+            Some(args) => {
+                request.encoding = Encoding::Msgpack as i32;
+                request.payload = self.usage.serialize(|| msgpack::to_vec(&args));
+            }
+            // Arguments that aren't JSON go as they are, for the server to reject
+            None => request.arguments = args_json.to_string(),
+        }
+        request
+    }
+
+    /// What an `InvokeMethodResponse` says happened
+    fn execution(&self, resp: InvokeMethodResponse) -> Execution {
+        self.traffic.received(
+            resp.result.len()
+                + resp.error.len()
+                + resp.payload.len()
+                + resp.stdout.len()
+                + resp.stderr.len(),
+        );
+        if !resp.success {
+            let code = resp.error_code();
+            return Execution {
+                code,
+                timed_out: code == ErrorCode::Timeout,
+                stdout: resp.stdout,
+                stderr: resp.stderr,
+                ..Execution::failed(resp.error)
+            };
+        }
+        // The error is why the response was rejected, if it was
+        let parsed = self.usage.serialize(|| match resp.encoding() {
+            Encoding::Json => {
+                compare::parse_result(&resp.result, self.max_json_depth).map_err(|e| match e {
+                    DepthError::TooDeep { .. } => Some(e.to_string()),
+                    DepthError::Invalid(_) => None,
+                })
+            }
+            Encoding::Msgpack => {
+                msgpack::from_slice(&resp.payload, self.max_json_depth).map_err(|e| match e {
+                    msgpack::DecodeError::TooDeep { .. } => Some(e.to_string()),
+                    msgpack::DecodeError::Invalid(_) => None,
+                })
+            }
+        });
+        let result = match parsed {
+            Ok(value) => Some(value),
+            Err(Some(e)) => return Execution::failed(format!("Response rejected: {}", e)),
+            Err(None) => None,
+        };
+        Execution {
+            result,
+            error: None,
+            time_us: resp.metadata.as_ref().map(|m| m.execution_time_us),
+            transport_error: false,
+            timed_out: false,
+            code: ErrorCode::Unspecified,
+            state: None,
+            stdout: resp.stdout,
+            stderr: resp.stderr,
+        }
+    }
+
+    /// Start `method` streaming its items, failing with the `Execution` to
+    /// report for the test
+    pub async fn invoke_stream(
+        &mut self,
+        context_id: &str,
+        method: &str,
+        args_json: &str,
+    ) -> Result<Streaming<InvokeStreamChunk>, Execution> {
+        // `timeout` bounds the whole stream, which the runner enforces.
+        // Streamed items are always JSON, so the arguments are too
+        let request = InvokeMethodRequest {
+            context_id: context_id.to_string(),
+            method_name: method.to_string(),
+            arguments: args_json.to_string(),
+            timeout_ms: 0,
+            encoding: Encoding::Json as i32,
+            payload: Vec::new(),
+        };
+        self.traffic
+            .sent(request.method_name.len() + request.arguments.len());
+        let call = self.client.invoke_method_stream(request);
+        match timed(self.usage, self.timeout, call).await? {
+            Ok(response) => Ok(response.into_inner()),
+            Err(e) => Err(Execution::unreachable(e)),
+        }
+    }
+
+    /// Current state of a context as JSON text, or why it couldn't be read
+    pub async fn inspect_state(&mut self, context_id: &str) -> Result<String, StateError> {
+        let request = InspectStateRequest {
+            context_id: context_id.to_string(),
+        };
+        let response =
+            match timed(self.usage, self.timeout, self.client.inspect_state(request)).await {
+                Ok(response) => response,
+                Err(timed_out) => {
+                    return Err(StateError::Unreachable(timed_out.error.unwrap_or_default()))
+                }
+            };
+        match response {
+            Ok(resp) => {
+                let resp = resp.into_inner();
+                self.traffic.received(resp.state.len() + resp.error.len());
+                if resp.success {
+                    Ok(resp.state)
+                } else {
+                    Err(StateError::Rejected(resp.error))
+                }
+            }
+            Err(e) => Err(StateError::Unreachable(e.to_string())),
+        }
+    }
+
+    /// Full state of a context from `ExportContext`, parsed like a result
+    pub async fn export_state(
+        &mut self,
+        context_id: &str,
+    ) -> Result<serde_json::Value, StateError> {
+        let request = ExportContextRequest {
+            context_id: context_id.to_string(),
+        };
+        let response = match timed(
+            self.usage,
+            self.timeout,
+            self.client.export_context(request),
+        )
+        .await
+        {
+            Ok(response) => response,
+            Err(timed_out) => {
+                return Err(StateError::Unreachable(timed_out.error.unwrap_or_default()))
+            }
+        };
+        let resp = match response {
+            Ok(resp) => resp.into_inner(),
+            Err(e) => return Err(StateError::Unreachable(e.to_string())),
+        };
+        self.traffic.received(resp.state.len() + resp.error.len());
+        if !resp.success {
+            return Err(StateError::Rejected(resp.error));
+        }
+        self.usage
+            .serialize(|| compare::parse_result(&resp.state, self.max_json_depth))
+            .map_err(|e| StateError::Invalid(e.to_string()))
+    }
+
+    /// Current state of a context, parsed like a result
+    pub async fn read_state(&mut self, context_id: &str) -> Result<serde_json::Value, StateError> {
+        let state = self.inspect_state(context_id).await?;
+        self.usage
+            .serialize(|| compare::parse_result(&state, self.max_json_depth))
+            .map_err(|e| StateError::Invalid(e.to_string()))
+    }
+}
+
+/// Await an RPC under `timeout`, accounting its time as RPC time
+async fn timed<F, T>(
+    usage: &Usage,
+    timeout: Option<Duration>,
+    call: F,
+) -> Result<Result<T, tonic::Status>, Execution>
+where
+    F: Future<Output = Result<T, tonic::Status>>,
+{
+    match timeout {
+        Some(timeout) => usage
+            .rpc(tokio::time::timeout(timeout, call))
+            .await
+            .map_err(|_| Execution::timed_out(timeout)),
+        None => Ok(usage.rpc(call).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{self, Mock};
+    use crate::TestCase;
+    use std::time::{Duration, Instant};
+
+    fn slow_test(expect_error: &str) -> TestCase {
+        let yaml = format!(
+            "{{name: slow, method: add, timeout_ms: 100{}}}",
+            expect_error
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_deadline_the_server_enforces_is_reported_as_a_timeout() {
+        let slow = Mock::slow(Duration::from_secs(60));
+        let mut runner = mock::runner(&[&slow, &slow]).await;
+        let started = Instant::now();
+        let result = runner.run_test(&slow_test("")).await.unwrap();
+        assert!(started.elapsed() < super::SERVER_DEADLINE_GRACE);
+        assert!(!result.passed);
+        assert_eq!(
+            result.error_message.as_deref(),
+            Some("python TimeoutError: exceeded 100ms\nrust TimeoutError: exceeded 100ms")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_server_timeout_can_be_the_expected_error() {
+        let slow = Mock::slow(Duration::from_secs(60));
+        let mut runner = mock::runner(&[&slow, &slow]).await;
+        let test = slow_test(", expect_error: {code: TIMEOUT}");
+        let result = runner.run_test(&test).await.unwrap();
+        assert!(result.passed, "{:?}", result.error_message);
+    }
+}
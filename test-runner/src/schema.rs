@@ -0,0 +1,430 @@
+/*!
+JSON Schema of the `--output-json` report.
+
+The schema is derived from the report's serde types with `schemars`, so a
+field added to a report struct shows up in it without touching this
+module. Every object in it is closed (`additionalProperties: false`), so a
+report with fields the schema doesn't know fails `validate-report`.
+`validate` checks the subset of JSON Schema the derived schema uses.
+
+A test compares a report with every field filled in against the snapshot
+in `tests/fixtures/report.json`. The snapshot carries its `REPORT_VERSION`:
+a report whose shape differs from a snapshot of the same version fails,
+even with `UPDATE_SNAPSHOTS=1`, until the version is bumped.
+*/
+
+use crate::report::{Report, REPORT_VERSION};
+use schemars::generate::SchemaSettings;
+use serde_json::{json, Value as JsonValue};
+
+pub fn report_schema() -> JsonValue {
+    let generator = SchemaSettings::draft2020_12()
+        .for_serialize()
+        .into_generator();
+    let mut schema = generator
+        .into_root_schema_for::<Report<'static>>()
+        .to_value();
+    close_objects(&mut schema);
+    schema["title"] = json!("test-runner report");
+    schema["version"] = json!(REPORT_VERSION);
+    schema
+}
+
+/// Forbid fields the schema doesn't list, in every object it describes
+fn close_objects(schema: &mut JsonValue) {
+    match schema {
+        JsonValue::Object(fields) => {
+            if fields.contains_key("properties") {
+                fields
+                    .entry("additionalProperties")
+                    .or_insert(JsonValue::Bool(false));
+            }
+            fields.values_mut().for_each(close_objects);
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(close_objects),
+        _ => {}
+    }
+}
+
+/// Every violation of `schema` in `value`, as `path: problem` lines
+pub fn validate(value: &JsonValue, schema: &JsonValue) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_at("$", value, schema, schema, &mut errors);
+    errors
+}
+
+/// The schema `schema` refers to with `$ref`, if it's a reference
+fn resolve<'a>(schema: &'a JsonValue, root: &'a JsonValue) -> &'a JsonValue {
+    match schema.get("$ref").and_then(JsonValue::as_str) {
+        Some(reference) => reference
+            .strip_prefix("#/")
+            .map(|pointer| root.pointer(&format!("/{}", pointer)))
+            .unwrap_or(None)
+            .unwrap_or(&JsonValue::Bool(true)),
+        None => schema,
+    }
+}
+
+fn validate_at(
+    path: &str,
+    value: &JsonValue,
+    schema: &JsonValue,
+    root: &JsonValue,
+    errors: &mut Vec<String>,
+) {
+    let schema = resolve(schema, root);
+
+    // A value must match one of the alternatives; report the closest miss
+    if let Some(alternatives) = schema
+        .get("anyOf")
+        .or_else(|| schema.get("oneOf"))
+        .and_then(JsonValue::as_array)
+    {
+        let mut closest: Option<Vec<String>> = None;
+        for alternative in alternatives {
+            let mut found = Vec::new();
+            validate_at(path, value, alternative, root, &mut found);
+            if found.is_empty() {
+                closest = None;
+                break;
+            }
+            if closest.as_ref().is_none_or(|c| found.len() < c.len()) {
+                closest = Some(found);
+            }
+        }
+        errors.extend(closest.unwrap_or_default());
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            JsonValue::String(t) => vec![t.as_str()],
+            JsonValue::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{}: expected {}, found {}",
+                path,
+                allowed.join(" or "),
+                crate::diff::type_name(value)
+            ));
+            return;
+        }
+    }
+
+    let allowed = match (schema.get("const"), schema.get("enum")) {
+        (Some(constant), _) => Some(std::slice::from_ref(constant)),
+        (None, Some(JsonValue::Array(values))) => Some(values.as_slice()),
+        _ => None,
+    };
+    if let Some(allowed) = allowed.filter(|allowed| !allowed.contains(value)) {
+        let allowed: Vec<String> = allowed.iter().map(JsonValue::to_string).collect();
+        errors.push(format!(
+            "{}: expected {}, found {}",
+            path,
+            allowed.join(" or "),
+            value
+        ));
+    }
+
+    if let (Some(minimum), Some(number)) = (
+        schema.get("minimum").and_then(JsonValue::as_f64),
+        value.as_f64(),
+    ) {
+        if number < minimum {
+            errors.push(format!(
+                "{}: {} is below the minimum {}",
+                path, number, minimum
+            ));
+        }
+    }
+
+    if let JsonValue::Object(fields) = value {
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        if let Some(required) = schema.get("required").and_then(|r| r.as_array()) {
+            for field in required.iter().filter_map(|f| f.as_str()) {
+                if !fields.contains_key(field) {
+                    errors.push(format!("{}: missing field '{}'", path, field));
+                }
+            }
+        }
+        for (key, field) in fields {
+            match properties.and_then(|p| p.get(key)) {
+                Some(field_schema) => {
+                    let path = format!("{}.{}", path, key);
+                    validate_at(&path, field, field_schema, root, errors)
+                }
+                None if schema.get("additionalProperties") == Some(&JsonValue::Bool(false)) => {
+                    errors.push(format!("{}: unexpected field '{}'", path, key));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let JsonValue::Array(items) = value {
+        // Tuples list a schema per position, then maybe one for the rest
+        let prefix = schema
+            .get("prefixItems")
+            .and_then(JsonValue::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        for (index, item) in items.iter().enumerate() {
+            let item_schema = prefix.get(index).or_else(|| schema.get("items"));
+            if let Some(item_schema) = item_schema {
+                let path = format!("{}[{}]", path, index);
+                validate_at(&path, item, item_schema, root, errors);
+            }
+        }
+        let length = items.len() as u64;
+        let min = schema.get("minItems").and_then(JsonValue::as_u64);
+        let max = schema.get("maxItems").and_then(JsonValue::as_u64);
+        if min.is_some_and(|min| length < min) || max.is_some_and(|max| length > max) {
+            errors.push(format!("{}: {} items is the wrong length", path, length));
+        }
+    }
+}
+
+fn has_type(value: &JsonValue, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coverage::{Coverage, MissingMethod, TargetMethods};
+    use crate::report::{PhaseReport, Report};
+    use crate::suites::{LoadError, SuiteRun};
+    use crate::timing::Timings;
+    use crate::usage::{TrafficReport, UsageReport};
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/report.json");
+
+    /// A run with every optional part of the report present
+    fn run() -> SuiteRun {
+        let full = json!({
+            "name": "range_squares",
+            "passed": false,
+            "status": "failed",
+            "targets": [
+                {"target": "python", "result": [0, 1], "error": null, "time_us": 12,
+                 "stdout": "out\n", "stderr": "err\n"},
+                {"target": "rust", "result": null, "error": "boom", "time_us": null},
+            ],
+            "error_message": "Streams diverge at item 1",
+            "diverged": ["python", "rust"],
+            "cached": false,
+            "wall_time_us": 40,
+            "transport_error": false,
+            "attempts": 2,
+            "verified_items": 1,
+        });
+        let minimal = json!({"name": "add", "passed": true, "targets": []});
+        SuiteRun {
+            name: "Simple Math".to_string(),
+            path: "test-defs/simple_math.yaml".into(),
+            results: vec![
+                serde_json::from_value(full).unwrap(),
+                serde_json::from_value(minimal).unwrap(),
+            ],
+            skipped: vec![serde_yaml::from_str("{name: skipped, method: add}").unwrap()],
+            elapsed: Duration::from_micros(50),
+            coverage: Some(Coverage {
+                tested: vec!["add".to_string()],
+                untested: vec![TargetMethods {
+                    target: "rust".to_string(),
+                    methods: vec!["multiply".to_string()],
+                }],
+                missing: vec![MissingMethod {
+                    method: "range_squares".to_string(),
+                    missing_from: vec!["python".to_string()],
+                }],
+            }),
+        }
+    }
+
+    fn report() -> JsonValue {
+        let runs = [run()];
+        let errors = [LoadError {
+            path: "test-defs/broken.yaml".into(),
+            message: "expected a mapping".to_string(),
+        }];
+        let usage = UsageReport {
+            peak_rss_bytes: Some(1 << 20),
+            targets: vec![TrafficReport {
+                target: "python".to_string(),
+                bytes_sent: 10,
+                bytes_received: 20,
+            }],
+            serialization_us: 3,
+            rpc_us: 4,
+        };
+        let total = Duration::from_micros(60);
+        let mut report = Report::new(&runs, &errors, total, &Timings::new(), usage);
+        report.timings = vec![PhaseReport {
+            phase: "python".to_string(),
+            wall_us: 30,
+            summed_us: 35,
+        }];
+        serde_json::to_value(&report).unwrap()
+    }
+
+    /// Every object field path `schema` describes, as
+    /// `$.suites[].tests[].name`
+    fn schema_paths(
+        path: &str,
+        schema: &JsonValue,
+        root: &JsonValue,
+        paths: &mut BTreeSet<String>,
+    ) {
+        let schema = resolve(schema, root);
+        if let Some(properties) = schema.get("properties").and_then(JsonValue::as_object) {
+            for (key, field) in properties {
+                let path = format!("{}.{}", path, key);
+                paths.insert(path.clone());
+                schema_paths(&path, field, root, paths);
+            }
+        }
+        if let Some(alternatives) = schema.get("anyOf").and_then(JsonValue::as_array) {
+            for alternative in alternatives {
+                schema_paths(path, alternative, root, paths);
+            }
+        }
+        if let Some(items) = schema.get("items") {
+            schema_paths(&format!("{}[]", path), items, root, paths);
+        }
+    }
+
+    /// Every object field path in `value`, in `schema_paths` form
+    fn value_paths(path: &str, value: &JsonValue, paths: &mut BTreeSet<String>) {
+        match value {
+            JsonValue::Object(fields) => {
+                for (key, field) in fields {
+                    let path = format!("{}.{}", path, key);
+                    paths.insert(path.clone());
+                    value_paths(&path, field, paths);
+                }
+            }
+            JsonValue::Array(items) => {
+                for item in items {
+                    value_paths(&format!("{}[]", path), item, paths);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn report_matches_schema() {
+        assert_eq!(validate(&report(), &report_schema()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn schema_describes_nothing_the_report_lacks() {
+        let (mut described, mut present) = (BTreeSet::new(), BTreeSet::new());
+        let schema = report_schema();
+        schema_paths("$", &schema, &schema, &mut described);
+        value_paths("$", &report(), &mut present);
+        let unused: Vec<&String> = described.difference(&present).collect();
+        assert!(
+            unused.is_empty(),
+            "in the schema but not the report: {:?}",
+            unused
+        );
+    }
+
+    /// Every field path in `value` with the type of its non-null values,
+    /// as `$.suites[].tests[].name: string`
+    fn shape(value: &JsonValue) -> BTreeSet<String> {
+        let mut paths = BTreeSet::new();
+        value_paths("$", value, &mut paths);
+        let mut typed = BTreeSet::new();
+        for path in paths {
+            typed.insert(path.clone());
+            let mut values = Vec::new();
+            values_at(value, &path[1..], &mut values);
+            for value in values.into_iter().filter(|v| !v.is_null()) {
+                typed.insert(format!("{}: {}", path, crate::diff::type_name(value)));
+            }
+        }
+        typed
+    }
+
+    /// The values at a `value_paths` path, relative to `value`
+    fn values_at<'a>(value: &'a JsonValue, path: &str, found: &mut Vec<&'a JsonValue>) {
+        if path.is_empty() {
+            found.push(value);
+        } else if let Some(rest) = path.strip_prefix("[]") {
+            for item in value.as_array().into_iter().flatten() {
+                values_at(item, rest, found);
+            }
+        } else if let Some(rest) = path.strip_prefix('.') {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            if let Some(field) = value.get(&rest[..end]) {
+                values_at(field, &rest[end..], found);
+            }
+        }
+    }
+
+    /// Rerun with UPDATE_SNAPSHOTS=1 to accept a deliberate change. A
+    /// change of shape is only accepted along with a new `REPORT_VERSION`.
+    #[test]
+    fn report_matches_snapshot() {
+        let report = report();
+        let text = crate::canonical::to_json_string_pretty(&report) + "\n";
+        let snapshot: JsonValue =
+            serde_json::from_str(&std::fs::read_to_string(SNAPSHOT).unwrap()).unwrap();
+        if snapshot["version"] == json!(REPORT_VERSION) {
+            let (before, after) = (shape(&snapshot), shape(&report));
+            let removed: Vec<&String> = before.difference(&after).collect();
+            let added: Vec<&String> = after.difference(&before).collect();
+            assert!(
+                removed.is_empty() && added.is_empty(),
+                "the report changed shape without a new REPORT_VERSION ({}); \
+                 removed: {:?}, added: {:?}",
+                REPORT_VERSION,
+                removed,
+                added
+            );
+        }
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(SNAPSHOT, &text).unwrap();
+        }
+        let snapshot = std::fs::read_to_string(SNAPSHOT).unwrap();
+        assert!(
+            text == snapshot,
+            "the report no longer matches {}; rerun with UPDATE_SNAPSHOTS=1 to accept:\n{}",
+            SNAPSHOT,
+            text
+        );
+    }
+
+    #[test]
+    fn violations_name_their_path() {
+        let mut report = report();
+        report["suites"][0]["tests"][0]["attempts"] = json!("2");
+        report["suites"][0]["extra"] = json!(1);
+        report["errors"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("message");
+        assert_eq!(
+            validate(&report, &report_schema()),
+            [
+                "$.errors[0]: missing field 'message'",
+                "$.suites[0]: unexpected field 'extra'",
+                "$.suites[0].tests[0].attempts: expected integer, found string",
+            ]
+        );
+    }
+}
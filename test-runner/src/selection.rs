@@ -0,0 +1,92 @@
+/*!
+Choosing which tests of a suite to run.
+
+`--filter` matches test names: a pattern containing `*` or `?` is a glob
+over the whole name, anything else is a substring. `--tag` may be repeated
+and selects tests carrying any of the given tags, and `--group` likewise
+selects tests whose `serial_group` or `resources` name any of the given
+groups. Every criterion given must match. Tests left out are counted as
+skipped, not dropped silently.
+*/
+
+use crate::TestCase;
+
+#[derive(Default)]
+pub struct Selection {
+    pub filter: Option<String>,
+    pub tags: Vec<String>,
+    pub groups: Vec<String>,
+}
+
+impl Selection {
+    /// Whether any criteria were given, i.e. whether anything can be skipped
+    pub fn is_active(&self) -> bool {
+        self.filter.is_some() || !self.tags.is_empty() || !self.groups.is_empty()
+    }
+
+    pub fn matches(&self, test: &TestCase) -> bool {
+        let name_matches = match &self.filter {
+            Some(pattern) if is_glob(pattern) => glob_match(pattern, &test.name),
+            Some(pattern) => test.name.contains(pattern.as_str()),
+            None => true,
+        };
+        let tag_matches = self.tags.is_empty() || self.tags.iter().any(|t| test.tags.contains(t));
+        let group_matches =
+            self.groups.is_empty() || test.resources().iter().any(|r| self.groups.contains(r));
+        name_matches && tag_matches && group_matches
+    }
+
+    /// Human-readable description for "nothing matched" errors
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(filter) = &self.filter {
+            parts.push(format!("--filter '{}'", filter));
+        }
+        for tag in &self.tags {
+            parts.push(format!("--tag '{}'", tag));
+        }
+        for group in &self.groups {
+            parts.push(format!("--group '{}'", group));
+        }
+        parts.join(" ")
+    }
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// `*` matches any run of characters, `?` exactly one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text index it's currently absorbing up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+            }
+            Some(c) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, absorbed)) => {
+                    p = star + 1;
+                    t = absorbed + 1;
+                    backtrack = Some((star, absorbed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,96 @@
+/*!
+Saving a context's state after a test and starting a test from saved state.
+
+```yaml
+- name: counter_after_many_steps
+  stateful: true
+  steps: [...]
+  snapshot_state: snapshots/counter.json
+
+- name: counter_from_snapshot
+  method: counter_increment
+  stateful: true
+  initial_state_file: snapshots/counter.json
+```
+
+`snapshot_state` writes the reference server's context state, read with
+`ExportContext`, to a file once the test has run, whether or not it passed,
+so a failing stateful scenario can be picked up again without replaying
+every step. `initial_state_file` reads a file as the test's
+`initial_state` when the suite is loaded, so snapshots round-trip through a
+suite. Both paths are relative to the file the test is in. A snapshot that
+can't be taken is a warning; it doesn't change the test's outcome.
+*/
+
+use crate::rpc::Server;
+use crate::TestCase;
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Resolve the test's state file paths against `base`, the directory of
+/// the file it's in, and read `initial_state_file` into `initial_state`
+pub fn resolve(test: &mut TestCase, base: &Path) -> Result<()> {
+    if let Some(path) = &mut test.snapshot_state {
+        *path = base.join(&*path);
+    }
+    let Some(path) = &mut test.initial_state_file else {
+        return Ok(());
+    };
+    *path = base.join(&*path);
+    if test.initial_state.is_some() {
+        anyhow::bail!(
+            "Test '{}' has both initial_state and initial_state_file",
+            test.name
+        );
+    }
+    let content = std::fs::read_to_string(&*path).with_context(|| {
+        format!(
+            "Test '{}': failed to read initial_state_file {}",
+            test.name,
+            path.display()
+        )
+    })?;
+    let state: serde_json::Value = serde_json::from_str(&content)
+        .with_context(|| format!("Test '{}': {} is not valid JSON", test.name, path.display()))?;
+    if !state.is_object() {
+        anyhow::bail!(
+            "Test '{}': {} must hold a JSON object",
+            test.name,
+            path.display()
+        );
+    }
+    test.initial_state = Some(content);
+    Ok(())
+}
+
+/// Export the state of `context_id` on `server` to the test's
+/// `snapshot_state` file, if it has one
+pub async fn save(server: &mut Server<'_>, test: &TestCase, context_id: &str) {
+    let Some(path) = &test.snapshot_state else {
+        return;
+    };
+    let written = match server.export_state(context_id).await {
+        Ok(state) => write(path, &state),
+        Err(e) => Err(anyhow::anyhow!("the state couldn't be exported: {}", e)),
+    };
+    match written {
+        Ok(()) => info!("{}: saved state to {}", test.name, path.display()),
+        Err(e) => warn!(
+            "{}: no snapshot written to {}: {:#}",
+            test.name,
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn write(path: &Path, state: &serde_json::Value) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut json = crate::canonical::to_json_string_pretty(state);
+    json.push('\n');
+    std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+}
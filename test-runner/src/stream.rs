@@ -0,0 +1,623 @@
+/*!
+`stream: true`: tests of methods that yield items, like Python generators.
+
+```yaml
+- name: range_squares_1000
+  method: range_squares
+  arguments: {n: 1000}
+  stream: true
+```
+
+The method is called with `InvokeMethodStream` on every server. Each
+stream is read concurrently by a task of its own into a buffer of `BUFFER`
+items, and the items are compared index by index as they arrive, so a
+large output is never held in full and a fast server can't run far ahead
+of a slow one. The test fails at the first index where the items differ,
+or where one stream ends before another, naming the index; a stream that
+ends with an error fails it too. Once the test is decided, every stream
+still running is cancelled, which stops the method on its server, and the
+result records how many items were verified. `expected`, if given, is the
+list of items every stream must yield. `timeout_ms` bounds the whole
+stream, and a stateful test streams in a context of its own on each server,
+which `snapshot_state` saves like any other test's. `setup` and `teardown`
+run around the stream on each server, in that context if there is one.
+*/
+
+use crate::compare;
+use crate::context::ContextGuard;
+use crate::hooks;
+use crate::rpc::Execution;
+use crate::snapshot;
+use crate::transpile_test::InvokeStreamChunk;
+use crate::usage::Usage;
+use crate::{canonical, Compared, Mismatch, TestCase, TestResult, TestRunner};
+use serde_json::Value as JsonValue;
+use std::future::Future;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tonic::Streaming;
+
+/// Items read ahead of the comparison per stream
+const BUFFER: usize = 64;
+
+/// One server's stream, as far as it has been compared
+#[derive(Default)]
+struct Side {
+    reader: Option<Reader>,
+    context: Option<ContextGuard>,
+    /// Items read so far
+    count: u64,
+    /// How the stream ended, once it has
+    end: Option<End>,
+    /// Why the test's setup failed on this server, which skips the test
+    setup_failure: Option<String>,
+}
+
+struct End {
+    error: Option<String>,
+    time_us: Option<i64>,
+    transport_error: bool,
+}
+
+/// What a reader passes on: the next item, or how the stream ended
+enum Read {
+    Item(JsonValue),
+    End(End),
+}
+
+/// A task reading one stream ahead of the comparison
+struct Reader {
+    reads: mpsc::Receiver<Read>,
+    task: JoinHandle<()>,
+}
+
+/// Where the streams stopped agreeing
+enum Divergence<E> {
+    /// At `index`, stream `ended` had ended while stream `yielded` still
+    /// yielded `item`
+    Ended {
+        index: usize,
+        ended: usize,
+        yielded: usize,
+        item: JsonValue,
+    },
+    /// The check rejected the items at one index
+    Rejected(E),
+}
+
+impl Reader {
+    /// Start `read`, which passes a stream's items to the sender it's given
+    /// until the stream ends or the sender is closed
+    fn start<F, R>(read: F) -> Self
+    where
+        F: FnOnce(mpsc::Sender<Read>) -> R,
+        R: Future<Output = ()> + Send + 'static,
+    {
+        let (sender, reads) = mpsc::channel(BUFFER);
+        let task = tokio::spawn(read(sender));
+        Self { reads, task }
+    }
+}
+
+impl Side {
+    /// Stop reading a stream that hasn't ended, which cancels the call on
+    /// its server
+    fn cancel(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            reader.task.abort();
+        }
+    }
+}
+
+impl End {
+    fn failed(error: String, transport_error: bool) -> Self {
+        Self {
+            error: Some(error),
+            time_us: None,
+            transport_error,
+        }
+    }
+
+    /// A stream that never started, for the reason `execution` gives
+    fn not_started(execution: Execution) -> Self {
+        Self::failed(
+            execution.error.unwrap_or_default(),
+            execution.transport_error,
+        )
+    }
+}
+
+/// Run a `stream: true` test on every target
+pub async fn run(runner: &mut TestRunner, test: &TestCase) -> TestResult {
+    let started = Instant::now();
+    let args_json = runner
+        .usage
+        .serialize(|| canonical::to_json_string(&test.arguments));
+
+    let mut sides = Vec::with_capacity(runner.clients.len());
+    for target in 0..runner.clients.len() {
+        let _timer = runner.timings.start(&runner.names[target]);
+        sides.push(open(runner, target, test, &args_json).await);
+    }
+    let setup_failure = sides.iter().find_map(|side| side.setup_failure.clone());
+    if let Some(message) = setup_failure {
+        tear_down(runner, test, context_ids(&sides)).await;
+        for side in sides {
+            if let Some(context) = side.context {
+                context.cleanup().await;
+            }
+        }
+        return TestResult::skipped(&test.name, message);
+    }
+
+    let mut verified = 0;
+    let compared = compare_streams(runner, test, &mut sides, &mut verified);
+    let outcome = match test.timeout() {
+        Some(timeout) => tokio::time::timeout(timeout, compared)
+            .await
+            .unwrap_or_else(|_| {
+                Err(Mismatch {
+                    message: format!(
+                        "Streams timed out after {}ms, {} items verified",
+                        timeout.as_millis(),
+                        verified
+                    ),
+                    pair: None,
+                })
+            }),
+        None => compared.await,
+    };
+    for side in &mut sides {
+        side.cancel();
+    }
+
+    if let Some(context) = &sides[0].context {
+        let mut reference = runner.server(0).with_timeout(test.timeout());
+        snapshot::save(&mut reference, test, context.id()).await;
+    }
+    tear_down(runner, test, context_ids(&sides)).await;
+
+    let mut executions = Vec::with_capacity(sides.len());
+    for side in sides {
+        if let Some(context) = side.context {
+            context.cleanup().await;
+        }
+        let end = side
+            .end
+            .unwrap_or_else(|| End::failed("stream not read to the end".into(), false));
+        executions.push(Execution {
+            result: Some(JsonValue::String(format!(
+                "<stream of {} items>",
+                side.count
+            ))),
+            error: end.error,
+            time_us: end.time_us,
+            transport_error: end.transport_error,
+            ..Default::default()
+        });
+    }
+    let times = executions.iter().map(|e| e.time_us).collect();
+    let mut result = runner.test_result(test, started, executions, times, outcome);
+    result.verified_items = Some(verified);
+    result
+}
+
+/// The context of each side, if it has one
+fn context_ids(sides: &[Side]) -> Vec<Option<String>> {
+    sides
+        .iter()
+        .map(|side| side.context.as_ref().map(|c| c.id().to_string()))
+        .collect()
+}
+
+/// Run the test's `teardown` on every server, in its side's context, except
+/// where a stateful test got no context
+async fn tear_down(runner: &mut TestRunner, test: &TestCase, contexts: Vec<Option<String>>) {
+    for (target, context_id) in contexts.into_iter().enumerate() {
+        if test.stateful && context_id.is_none() {
+            continue;
+        }
+        let context_id = context_id.unwrap_or_default();
+        let mut server = runner.server(target).with_timeout(test.timeout());
+        hooks::teardown(&mut server, &context_id, &test.teardown, &test.name).await;
+    }
+}
+
+/// Start the stream on `target`, in a fresh context for stateful tests
+async fn open(runner: &mut TestRunner, target: usize, test: &TestCase, args_json: &str) -> Side {
+    let mut server = runner.server(target).with_timeout(test.timeout());
+    let mut side = Side::default();
+    if test.stateful {
+        let initial_state = test.initial_state.clone().unwrap_or_default();
+        match server.create_context(initial_state).await {
+            Ok(context) => side.context = Some(context),
+            Err(execution) => {
+                side.end = Some(End::not_started(execution));
+                return side;
+            }
+        }
+    }
+    let context_id = side.context.as_ref().map(|c| c.id()).unwrap_or_default();
+    if let Err(failure) = hooks::setup(&mut server, context_id, &test.setup).await {
+        side.setup_failure = Some(format!("Setup failed on {}: {}", server.name, failure));
+        return side;
+    }
+    match server
+        .invoke_stream(context_id, &test.method, args_json)
+        .await
+    {
+        Ok(chunks) => {
+            let usage = runner.usage.clone();
+            let target = runner.names[target].clone();
+            let max_depth = runner.options.max_json_depth;
+            side.reader = Some(Reader::start(|sender| {
+                read(chunks, sender, usage, target, max_depth)
+            }));
+        }
+        Err(execution) => side.end = Some(End::not_started(execution)),
+    }
+    side
+}
+
+/// Pass the items of `chunks` to `sender` until the stream ends or the
+/// comparison stops taking them
+async fn read(
+    mut chunks: Streaming<InvokeStreamChunk>,
+    sender: mpsc::Sender<Read>,
+    usage: Usage,
+    target: String,
+    max_depth: usize,
+) {
+    let traffic = usage.traffic(&target);
+    let mut count = 0;
+    loop {
+        let read = match usage.rpc(chunks.message()).await {
+            Ok(Some(chunk)) if chunk.done => {
+                traffic.received(chunk.error.len());
+                Read::End(End {
+                    error: (!chunk.error.is_empty()).then_some(chunk.error),
+                    time_us: chunk.metadata.map(|m| m.execution_time_us),
+                    transport_error: false,
+                })
+            }
+            Ok(Some(chunk)) => {
+                traffic.received(chunk.item.len());
+                match usage.serialize(|| compare::parse_result(&chunk.item, max_depth)) {
+                    Ok(item) => Read::Item(item),
+                    Err(e) => {
+                        let error = format!("item {} rejected: {}", count, e);
+                        Read::End(End::failed(error, false))
+                    }
+                }
+            }
+            Ok(None) => {
+                let error = "stream closed without a final message".to_string();
+                Read::End(End::failed(error, true))
+            }
+            Err(status) => Read::End(End::failed(status.to_string(), true)),
+        };
+        let ended = matches!(read, Read::End(_));
+        if sender.send(read).await.is_err() || ended {
+            return;
+        }
+        count += 1;
+    }
+}
+
+/// Take every side's next item; `None` for sides whose stream has ended
+async fn next_items(sides: &mut [Side]) -> Vec<Option<JsonValue>> {
+    let mut items = Vec::with_capacity(sides.len());
+    for side in sides.iter_mut() {
+        let Some(reader) = side.reader.as_mut().filter(|_| side.end.is_none()) else {
+            items.push(None);
+            continue;
+        };
+        let read =
+            reader.reads.recv().await.unwrap_or_else(|| {
+                Read::End(End::failed("stream reader stopped".to_string(), true))
+            });
+        let item = match read {
+            Read::Item(item) => {
+                side.count += 1;
+                Some(item)
+            }
+            Read::End(end) => {
+                side.end = Some(end);
+                None
+            }
+        };
+        items.push(item);
+    }
+    items
+}
+
+/// Take the streams' items in step until every stream has ended, passing
+/// the items at each index to `check`, and counting in `verified` the items
+/// that every stream yielded alike
+async fn lockstep<E>(
+    sides: &mut [Side],
+    verified: &mut u64,
+    mut check: impl FnMut(usize, &[Option<&JsonValue>]) -> Result<(), E>,
+) -> Result<(), Divergence<E>> {
+    let mut index = 0;
+    loop {
+        let mut items = next_items(sides).await;
+        if items.iter().all(Option::is_none) {
+            return Ok(());
+        }
+        if let Some(ended) = items.iter().position(Option::is_none) {
+            let yielded = items.iter().position(Option::is_some).unwrap_or_default();
+            return Err(Divergence::Ended {
+                index,
+                ended,
+                yielded,
+                item: items.swap_remove(yielded).unwrap_or_default(),
+            });
+        }
+
+        let values: Vec<Option<&JsonValue>> = items.iter().map(Option::as_ref).collect();
+        check(index, &values).map_err(Divergence::Rejected)?;
+        index += 1;
+        *verified = index as u64;
+    }
+}
+
+/// Compare the streams item by item until they end or diverge, counting
+/// the items every stream yielded alike in `verified`
+async fn compare_streams(
+    runner: &TestRunner,
+    test: &TestCase,
+    sides: &mut [Side],
+    verified: &mut u64,
+) -> Result<(), Mismatch> {
+    let failure = |message| Mismatch {
+        message,
+        pair: None,
+    };
+    let expected = match &test.expected {
+        None => None,
+        Some(JsonValue::Array(items)) => Some(items),
+        Some(_) => return Err(failure("`expected` of a stream test must be a list".into())),
+    };
+    let options = test.compare_options();
+    let names = &runner.names;
+
+    let compared = lockstep(sides, verified, |index, values| {
+        let expected_item = match expected {
+            Some(expected) => match expected.get(index) {
+                Some(item) => Some(item),
+                None => {
+                    return Err(failure(format!(
+                        "Streams yielded more than the {} expected items",
+                        expected.len()
+                    )))
+                }
+            },
+            None => None,
+        };
+        runner
+            .compare_values(Compared::Results, values, expected_item, &options)
+            .map_err(|mismatch| Mismatch {
+                message: format!("Streams diverge at item {}: {}", index, mismatch.message),
+                pair: mismatch.pair,
+            })
+    })
+    .await;
+    match compared {
+        Ok(()) => {}
+        Err(Divergence::Rejected(mismatch)) => return Err(mismatch),
+        Err(Divergence::Ended {
+            index,
+            ended,
+            yielded,
+            item,
+        }) => {
+            let why = sides[ended]
+                .end
+                .as_ref()
+                .and_then(|end| end.error.as_deref())
+                .map(|error| format!(" ({})", error))
+                .unwrap_or_default();
+            let message = format!(
+                "Streams diverge at item {}: {} ended after {} items{}, {} yielded {}",
+                index, names[ended], index, why, names[yielded], item
+            );
+            let other = if ended == 0 { yielded } else { ended };
+            return Err(Mismatch {
+                message,
+                pair: Some((names[0].clone(), names[other].clone())),
+            });
+        }
+    }
+
+    for (side, name) in sides.iter().zip(names.iter()) {
+        if let Some(error) = side.end.as_ref().and_then(|end| end.error.as_ref()) {
+            return Err(failure(format!(
+                "{} stream failed after {} items: {}",
+                name, side.count, error
+            )));
+        }
+    }
+    let index = *verified as usize;
+    match expected {
+        Some(expected) if expected.len() != index => Err(failure(format!(
+            "Streams yielded {} items, expected {}",
+            index,
+            expected.len()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Calls, Mock};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const ITEMS: u64 = 100_000;
+
+    fn range(n: u64) -> Vec<JsonValue> {
+        (0..n).map(|i| json!(i)).collect()
+    }
+
+    fn stream_test() -> TestCase {
+        serde_yaml::from_str("{name: range, method: range, stream: true}").unwrap()
+    }
+
+    async fn stream(mocks: &[&Mock]) -> TestResult {
+        let mut runner = mock::runner(mocks).await;
+        run(&mut runner, &stream_test()).await
+    }
+
+    /// Wait for the mock to notice its client went away
+    async fn cancelled(mock: &Mock) -> bool {
+        for _ in 0..100 {
+            if Calls::get(&mock.calls.streams_cancelled) > 0 {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn matching_streams_verify_every_item() {
+        let (python, rust) = (Mock::streaming(range(1000)), Mock::streaming(range(1000)));
+        let result = stream(&[&python, &rust]).await;
+        assert!(result.passed, "{:?}", result.error_message);
+        assert_eq!(result.verified_items, Some(1000));
+    }
+
+    #[tokio::test]
+    async fn divergence_fails_at_its_index_and_cancels_the_streams() {
+        let mut items = range(ITEMS);
+        items[37] = json!("diverged");
+        let (python, rust) = (Mock::streaming(range(ITEMS)), Mock::streaming(items));
+        let result = stream(&[&python, &rust]).await;
+
+        assert!(!result.passed);
+        let message = result.error_message.unwrap();
+        assert!(
+            message.starts_with("Streams diverge at item 37:"),
+            "{}",
+            message
+        );
+        assert_eq!(result.verified_items, Some(37));
+        for mock in [&python, &rust] {
+            assert!(cancelled(mock).await);
+            assert!(Calls::get(&mock.calls.streamed_items) < ITEMS as usize);
+        }
+    }
+
+    #[tokio::test]
+    async fn shorter_stream_fails_where_it_ended() {
+        let (python, rust) = (Mock::streaming(range(ITEMS)), Mock::streaming(range(40)));
+        let result = stream(&[&python, &rust]).await;
+
+        assert!(!result.passed);
+        let message = result.error_message.unwrap();
+        assert!(
+            message.starts_with("Streams diverge at item 40: rust ended after 40 items"),
+            "{}",
+            message
+        );
+        assert_eq!(result.verified_items, Some(40));
+        assert!(cancelled(&python).await);
+    }
+
+    /// A side streaming `items`, counting in `sent` those its reader got
+    /// into the buffer
+    fn streaming(items: Vec<JsonValue>, sent: Arc<AtomicUsize>) -> Side {
+        let reader = Reader::start(|sender| async move {
+            for item in items {
+                if sender.send(Read::Item(item)).await.is_err() {
+                    return;
+                }
+                sent.fetch_add(1, Ordering::SeqCst);
+            }
+            let _ = sender
+                .send(Read::End(End {
+                    error: None,
+                    time_us: None,
+                    transport_error: false,
+                }))
+                .await;
+        });
+        Side {
+            reader: Some(reader),
+            ..Default::default()
+        }
+    }
+
+    fn equal(_: usize, values: &[Option<&JsonValue>]) -> Result<(), ()> {
+        match values.windows(2).all(|pair| pair[0] == pair[1]) {
+            true => Ok(()),
+            false => Err(()),
+        }
+    }
+
+    #[tokio::test]
+    async fn lockstep_verifies_matching_streams() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let mut sides = vec![
+            streaming(range(1000), sent.clone()),
+            streaming(range(1000), sent.clone()),
+        ];
+        let mut verified = 0;
+        assert!(lockstep(&mut sides, &mut verified, equal).await.is_ok());
+        assert_eq!(verified, 1000);
+        assert_eq!(sent.load(Ordering::SeqCst), 2000);
+    }
+
+    #[tokio::test]
+    async fn lockstep_stops_at_a_divergence_before_the_streams_are_read() {
+        let mut items = range(ITEMS);
+        items[37] = json!("diverged");
+        let (python, rust) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let mut sides = vec![
+            streaming(range(ITEMS), python.clone()),
+            streaming(items, rust.clone()),
+        ];
+        let mut verified = 0;
+        let outcome = lockstep(&mut sides, &mut verified, |index, values| {
+            equal(index, values).map_err(|_| index)
+        })
+        .await;
+        for side in &mut sides {
+            side.cancel();
+        }
+
+        assert!(matches!(outcome, Err(Divergence::Rejected(37))));
+        assert_eq!(verified, 37);
+        for sent in [python, rust] {
+            assert!(sent.load(Ordering::SeqCst) <= 38 + BUFFER);
+        }
+    }
+
+    #[tokio::test]
+    async fn lockstep_diverges_where_a_shorter_stream_ended() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let mut sides = vec![
+            streaming(range(ITEMS), sent.clone()),
+            streaming(range(40), sent.clone()),
+        ];
+        let mut verified = 0;
+        let outcome = lockstep(&mut sides, &mut verified, equal).await;
+
+        match outcome {
+            Err(Divergence::Ended {
+                index,
+                ended,
+                yielded,
+                item,
+            }) => assert_eq!((index, ended, yielded, item), (40, 1, 0, json!(40))),
+            _ => panic!("streams should diverge where the shorter one ended"),
+        }
+        assert_eq!(verified, 40);
+    }
+}
@@ -0,0 +1,101 @@
+/*!
+Finding the suite files of a run, and what running each one produced.
+
+Each `--suite` may be a file, a directory searched recursively for `*.yaml`
+and `*.yml`, or a quoted glob such as `'suites/**/math_*.yaml'` (`*` also
+matches across directories). Files are run in sorted path order, each file
+once even if several arguments find it.
+*/
+
+use crate::coverage::Coverage;
+use crate::selection::{glob_match, is_glob};
+use crate::{TestCase, TestResult};
+use anyhow::{Context, Result};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Results of one suite file
+pub struct SuiteRun {
+    pub name: String,
+    pub path: PathBuf,
+    pub results: Vec<TestResult>,
+    /// Tests left out by --filter/--tag
+    pub skipped: Vec<TestCase>,
+    pub elapsed: Duration,
+    /// Methods exercised, with --coverage
+    pub coverage: Option<Coverage>,
+}
+
+/// A suite file that couldn't be loaded; the other suites still run
+pub struct LoadError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+/// Every suite file named by `args`
+pub fn discover(args: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for arg in args {
+        let found = if is_glob(&arg.to_string_lossy()) {
+            glob_files(arg)?
+        } else if arg.is_dir() {
+            let mut found = Vec::new();
+            collect_yaml(arg, &mut found)?;
+            found
+        } else {
+            // Missing files are reported when loading, like unparsable ones
+            vec![arg.clone()]
+        };
+        if found.is_empty() {
+            anyhow::bail!("No suite files found in {}", arg.display());
+        }
+        files.extend(found);
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn collect_yaml(dir: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read suite directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_yaml(&path, found)?;
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yaml" | "yml")
+        ) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Files under the glob's fixed leading directories that match it whole
+fn glob_files(pattern: &Path) -> Result<Vec<PathBuf>> {
+    let base: PathBuf = pattern
+        .components()
+        .take_while(|c| !is_glob(&c.as_os_str().to_string_lossy()))
+        .collect();
+    let base = if base.components().next().is_none() {
+        PathBuf::from(Component::CurDir.as_os_str())
+    } else {
+        base
+    };
+
+    let mut candidates = Vec::new();
+    if base.is_dir() {
+        collect_yaml(&base, &mut candidates)?;
+    }
+    let pattern = pattern.to_string_lossy();
+    let pattern = pattern.strip_prefix("./").unwrap_or(&pattern);
+    Ok(candidates
+        .into_iter()
+        .filter(|path| {
+            let path = path.to_string_lossy();
+            glob_match(pattern, path.strip_prefix("./").unwrap_or(&path))
+        })
+        .collect())
+}
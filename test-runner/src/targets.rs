@@ -0,0 +1,128 @@
+/*!
+The servers a suite runs against.
+
+`servers` maps a target name to the server implementing it, for any number
+of runtimes behind the same proto:
+
+```yaml
+servers:
+  python: {host: localhost, port: 50051}
+  rust: {host: localhost, port: 50052}
+  typescript: {host: localhost, port: 50053}
+reference: python
+```
+
+Every target's outcome is compared with the reference target's, and only the
+reference's result is checked against `expected`. The reference is the
+suite's `reference:` if given, otherwise the first target. Suites written
+for the original fixed python/rust pair are the two-entry case and parse
+unchanged, with `python` as the reference.
+
+`--target NAME` keeps only that server, so an implementation can be worked
+on without the others running; its results are then checked against
+`expected` alone.
+*/
+
+use crate::ServerConfig;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Targets in suite order, except that the reference always comes first
+#[derive(Debug)]
+pub struct Targets {
+    targets: Vec<Target>,
+}
+
+#[derive(Debug)]
+pub struct Target {
+    pub name: String,
+    pub config: ServerConfig,
+}
+
+impl Targets {
+    pub fn new(targets: Vec<Target>) -> Self {
+        Self { targets }
+    }
+
+    /// Move `name` to the front so it becomes the reference
+    pub fn set_reference(&mut self, name: &str) -> Result<(), String> {
+        let index = self
+            .targets
+            .iter()
+            .position(|t| t.name == name)
+            .ok_or_else(|| {
+                format!(
+                    "reference '{}' is not one of the servers ({})",
+                    name,
+                    self.names().join(", ")
+                )
+            })?;
+        let reference = self.targets.remove(index);
+        self.targets.insert(0, reference);
+        Ok(())
+    }
+
+    /// Drop every target but `name`, which becomes the only one run
+    pub fn only(&mut self, name: &str) -> Result<(), String> {
+        if !self.targets.iter().any(|t| t.name == name) {
+            return Err(format!(
+                "target '{}' is not one of the servers ({})",
+                name,
+                self.names().join(", ")
+            ));
+        }
+        self.targets.retain(|t| t.name == name);
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Target> {
+        self.targets.iter()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.targets.iter().map(|t| t.name.clone()).collect()
+    }
+}
+
+impl<'de> Deserialize<'de> for Targets {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TargetsVisitor)
+    }
+}
+
+/// Collects map entries in order, which a `HashMap` would lose
+struct TargetsVisitor;
+
+impl<'de> Visitor<'de> for TargetsVisitor {
+    type Value = Targets;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of target names to servers")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Targets, A::Error> {
+        let mut targets: Vec<Target> = Vec::new();
+        while let Some((name, config)) = map.next_entry::<String, ServerConfig>()? {
+            if targets.iter().any(|t| t.name == name) {
+                return Err(de::Error::custom(format!("duplicate server '{}'", name)));
+            }
+            targets.push(Target { name, config });
+        }
+        if targets.is_empty() {
+            return Err(de::Error::custom("no servers given"));
+        }
+        Ok(Targets { targets })
+    }
+}
+
+impl Serialize for Targets {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.targets.len()))?;
+        for target in &self.targets {
+            map.serialize_entry(&target.name, &target.config)?;
+        }
+        map.end()
+    }
+}
@@ -0,0 +1,221 @@
+/*!
+Per-phase wall-clock accounting for a run.
+
+Phases are timed with scoped guards: `timings.start("connect")` returns a
+timer that records its interval against the phase when dropped. Phases keep
+the order in which they were first recorded so the breakdown reads top to
+bottom in the same order the run happened.
+
+Each phase tracks both the summed time of every interval and the wall-clock
+time covered by their union, which differ once tests run concurrently.
+*/
+
+use colored::*;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type Interval = (Instant, Instant);
+
+/// Accumulated intervals per phase, shared by every timer started from it
+#[derive(Clone, Default)]
+pub struct Timings {
+    phases: Arc<Mutex<Vec<PhaseIntervals>>>,
+}
+
+struct PhaseIntervals {
+    name: String,
+    intervals: Vec<Interval>,
+}
+
+/// Totals for one phase
+pub struct PhaseTiming {
+    pub name: String,
+    /// Sum of every interval, i.e. task time
+    pub summed: Duration,
+    /// Time covered by at least one interval
+    pub wall: Duration,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start timing `phase`; the interval is recorded when the guard drops
+    pub fn start(&self, phase: &str) -> PhaseTimer {
+        PhaseTimer {
+            timings: self.clone(),
+            phase: phase.to_string(),
+            started: Instant::now(),
+        }
+    }
+
+    fn record(&self, phase: &str, started: Instant, finished: Instant) {
+        let mut phases = self.phases.lock().unwrap();
+        match phases.iter_mut().find(|p| p.name == phase) {
+            Some(p) => p.intervals.push((started, finished)),
+            None => phases.push(PhaseIntervals {
+                name: phase.to_string(),
+                intervals: vec![(started, finished)],
+            }),
+        }
+    }
+
+    /// Current totals in first-recorded order
+    pub fn snapshot(&self) -> Vec<PhaseTiming> {
+        self.phases
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| PhaseTiming {
+                name: p.name.clone(),
+                summed: p.intervals.iter().map(|(start, end)| *end - *start).sum(),
+                wall: union_length(&p.intervals),
+            })
+            .collect()
+    }
+}
+
+/// Length of the union of possibly overlapping intervals
+fn union_length(intervals: &[Interval]) -> Duration {
+    let mut sorted = intervals.to_vec();
+    sorted.sort_by_key(|(start, _)| *start);
+
+    let mut total = Duration::ZERO;
+    let mut current: Option<Interval> = None;
+    for (start, end) in sorted {
+        current = match current {
+            Some((cur_start, cur_end)) if start <= cur_end => Some((cur_start, cur_end.max(end))),
+            Some((cur_start, cur_end)) => {
+                total += cur_end - cur_start;
+                Some((start, end))
+            }
+            None => Some((start, end)),
+        };
+    }
+    if let Some((start, end)) = current {
+        total += end - start;
+    }
+    total
+}
+
+/// Guard returned by [`Timings::start`]
+pub struct PhaseTimer {
+    timings: Timings,
+    phase: String,
+    started: Instant,
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        self.timings
+            .record(&self.phase, self.started, Instant::now());
+    }
+}
+
+/// Human-friendly duration: μs below 1ms, ms below 1s, seconds otherwise
+pub fn format_duration(d: Duration) -> String {
+    let us = d.as_micros();
+    if us < 1_000 {
+        format!("{}μs", us)
+    } else if us < 1_000_000 {
+        format!("{:.1}ms", us as f64 / 1_000.0)
+    } else {
+        format!("{:.2}s", d.as_secs_f64())
+    }
+}
+
+/// Print the breakdown; `concurrent` adds summed task time next to wall-clock
+pub fn print_breakdown(total: Duration, timings: &Timings, concurrent: bool) {
+    println!(
+        "{}: {} wall-clock",
+        "Timing".bright_blue().bold(),
+        format_duration(total)
+    );
+
+    let phases = timings.snapshot();
+    let width = phases.iter().map(|p| p.name.len()).max().unwrap_or(0);
+    for phase in phases {
+        if concurrent {
+            println!(
+                "  {:<width$}  {} wall | {} summed",
+                phase.name,
+                format_duration(phase.wall).cyan(),
+                format_duration(phase.summed).cyan(),
+                width = width
+            );
+        } else {
+            println!(
+                "  {:<width$}  {}",
+                phase.name,
+                format_duration(phase.wall).cyan(),
+                width = width
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn overlapping_intervals_count_once_toward_wall() {
+        let base = Instant::now();
+        let timings = Timings::new();
+        // 0-10 and 5-20 overlap; 30-40 stands apart
+        timings.record("execute", base, base + ms(10));
+        timings.record("execute", base + ms(5), base + ms(20));
+        timings.record("execute", base + ms(30), base + ms(40));
+
+        let phases = timings.snapshot();
+        assert_eq!(phases.len(), 1);
+        assert_eq!(phases[0].summed, ms(35));
+        assert_eq!(phases[0].wall, ms(30));
+    }
+
+    #[test]
+    fn union_handles_nested_and_touching_intervals() {
+        let base = Instant::now();
+        let at = |start, end| (base + ms(start), base + ms(end));
+        assert_eq!(union_length(&[]), Duration::ZERO);
+        assert_eq!(union_length(&[at(0, 50), at(10, 20)]), ms(50));
+        assert_eq!(union_length(&[at(10, 20), at(0, 10)]), ms(20));
+    }
+
+    #[test]
+    fn phases_keep_first_recorded_order() {
+        let base = Instant::now();
+        let timings = Timings::new();
+        timings.record("load", base, base + ms(1));
+        timings.record("connect", base, base + ms(2));
+        timings.record("load", base, base + ms(3));
+
+        let names: Vec<String> = timings.snapshot().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, ["load", "connect"]);
+    }
+
+    #[test]
+    fn timer_records_when_dropped() {
+        let timings = Timings::new();
+        let timer = timings.start("connect");
+        assert!(timings.snapshot().is_empty());
+        drop(timer);
+        assert_eq!(timings.snapshot()[0].name, "connect");
+
+        // Clones share the same phases
+        let _ = timings.clone().start("execute");
+        assert_eq!(timings.snapshot().len(), 2);
+    }
+
+    #[test]
+    fn durations_switch_units() {
+        assert_eq!(format_duration(Duration::from_micros(999)), "999μs");
+        assert_eq!(format_duration(Duration::from_micros(1_500)), "1.5ms");
+        assert_eq!(format_duration(ms(2_500)), "2.50s");
+    }
+}
@@ -0,0 +1,161 @@
+/*!
+How the runner reaches a server.
+
+Connections are plaintext HTTP/2 unless the server's config has `tls`, in
+which case they use TLS, verified against `ca_cert` or, without one, the
+system's trusted roots. `client_cert` and `client_key` together add a
+client identity for mutual TLS. An `auth_token` is sent as
+`authorization: Bearer <token>` with every request:
+
+```yaml
+servers:
+  python:
+    host: python.example.com
+    port: 443
+    tls: {ca_cert: certs/ca.pem, domain: python.example.com}
+    auth_token: secret
+```
+
+Certificate files are read and checked before connecting, so a missing
+file or one that isn't PEM is reported with the field that names it rather
+than as a failed connection.
+*/
+
+use crate::rpc::Client;
+use crate::transpile_test::transpile_test_service_client::TranspileTestServiceClient;
+use crate::{ServerConfig, TlsConfig};
+use anyhow::{Context, Result};
+use std::path::Path;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Request, Status};
+
+/// Largest response accepted, matching the servers' limit rather than
+/// tonic's 4MiB default
+const MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Adds the server's bearer token, if it has one, to every request
+#[derive(Clone, Default)]
+pub struct Auth(Option<MetadataValue<Ascii>>);
+
+impl Interceptor for Auth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.0 {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// Everything needed to connect to one server, checked up front
+#[derive(Clone)]
+pub struct Target {
+    endpoint: Endpoint,
+    auth: Auth,
+}
+
+impl Target {
+    /// Validate `config` of the server named `name`
+    pub fn new(name: &str, config: &ServerConfig) -> Result<Self> {
+        let scheme = if config.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let url = format!("{}://{}:{}", scheme, config.host, config.port);
+        let mut endpoint = Endpoint::from_shared(url.clone())
+            .with_context(|| format!("Invalid address for {} server: {}", name, url))?;
+        if let Some(tls) = &config.tls {
+            let tls = tls_config(tls, &config.host)
+                .with_context(|| format!("Invalid `tls` for {} server", name))?;
+            endpoint = endpoint
+                .tls_config(tls)
+                .with_context(|| format!("Invalid `tls` for {} server", name))?;
+        }
+
+        let auth = match &config.auth_token {
+            Some(token) => {
+                let value = format!("Bearer {}", token)
+                    .parse()
+                    .with_context(|| format!("Invalid `auth_token` for {} server", name))?;
+                Auth(Some(value))
+            }
+            None => Auth::default(),
+        };
+        Ok(Self { endpoint, auth })
+    }
+
+    pub fn url(&self) -> String {
+        self.endpoint.uri().to_string()
+    }
+
+    /// Look up the server's host, failing if it doesn't resolve
+    pub async fn resolve(&self) -> Result<()> {
+        let uri = self.endpoint.uri();
+        let host = uri.host().unwrap_or_default();
+        // IPv6 literals keep their brackets in the URI
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or_default();
+        let mut addresses = tokio::net::lookup_host((host, port))
+            .await
+            .with_context(|| format!("could not resolve host `{}`", host))?;
+        if addresses.next().is_none() {
+            anyhow::bail!("could not resolve host `{}`: no addresses", host);
+        }
+        Ok(())
+    }
+
+    pub async fn connect(&self) -> Result<Client, tonic::transport::Error> {
+        let channel = self.endpoint.connect().await?;
+        Ok(
+            TranspileTestServiceClient::with_interceptor(channel, self.auth.clone())
+                .max_decoding_message_size(MAX_MESSAGE_BYTES),
+        )
+    }
+}
+
+fn tls_config(tls: &TlsConfig, host: &str) -> Result<ClientTlsConfig> {
+    let mut config = match &tls.ca_cert {
+        Some(path) => {
+            let pem = read_pem(path, "ca_cert", "CERTIFICATE")?;
+            ClientTlsConfig::new().ca_certificate(Certificate::from_pem(pem))
+        }
+        None => ClientTlsConfig::new().with_native_roots(),
+    };
+    config = config.domain_name(tls.domain.as_deref().unwrap_or(host));
+
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => {
+            let cert = read_pem(cert, "client_cert", "CERTIFICATE")?;
+            let key = read_pem(key, "client_key", "PRIVATE KEY")?;
+            config = config.identity(Identity::from_pem(cert, key));
+        }
+        (Some(_), None) => anyhow::bail!("`client_cert` is set without `client_key`"),
+        (None, Some(_)) => anyhow::bail!("`client_key` is set without `client_cert`"),
+        (None, None) => {}
+    }
+    Ok(config)
+}
+
+/// Contents of the PEM file at `path`, which must hold a `label` block
+fn read_pem(path: &Path, field: &str, label: &str) -> Result<Vec<u8>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("`{}`: cannot read {}", field, path.display()))?;
+    let text = String::from_utf8_lossy(&pem);
+    let end = format!("{}-----", label);
+    let has_block = text
+        .lines()
+        .any(|line| line.starts_with("-----BEGIN ") && line.trim_end().ends_with(&end));
+    if !has_block {
+        anyhow::bail!(
+            "`{}`: {} is not a PEM file with a {} block",
+            field,
+            path.display(),
+            label
+        );
+    }
+    Ok(pem)
+}
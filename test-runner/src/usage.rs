@@ -0,0 +1,244 @@
+/*!
+The runner's own resource usage.
+
+With many concurrent jobs and large payloads the runner can become the
+bottleneck, so it tracks its peak RSS, the bytes of request and response
+strings exchanged with each server, and how long it spent serializing and
+parsing JSON versus awaiting RPCs. Byte counts are taken where requests are
+built and responses consumed, not at the HTTP/2 layer, so they exclude
+protobuf and transport framing.
+*/
+
+use colored::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Shared counters; clones update the same totals
+#[derive(Clone)]
+pub struct Usage {
+    counters: Arc<Counters>,
+}
+
+struct Counters {
+    /// Per target, in target order
+    traffic: Vec<(String, Traffic)>,
+    serialization_ns: AtomicU64,
+    rpc_ns: AtomicU64,
+}
+
+/// Bytes exchanged with one server
+#[derive(Default)]
+pub struct Traffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl Traffic {
+    pub fn sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn report(&self, target: &str) -> TrafficReport {
+        TrafficReport {
+            target: target.to_string(),
+            bytes_sent: self.sent.load(Ordering::Relaxed),
+            bytes_received: self.received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(JsonSchema, Serialize)]
+pub struct UsageReport {
+    /// `None` where the platform doesn't report it
+    pub peak_rss_bytes: Option<u64>,
+    pub targets: Vec<TrafficReport>,
+    pub serialization_us: u64,
+    pub rpc_us: u64,
+}
+
+#[derive(JsonSchema, Serialize)]
+pub struct TrafficReport {
+    pub target: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl Usage {
+    /// Counters for the named targets
+    pub fn new(targets: Vec<String>) -> Self {
+        Self {
+            counters: Arc::new(Counters {
+                traffic: targets
+                    .into_iter()
+                    .map(|target| (target, Traffic::default()))
+                    .collect(),
+                serialization_ns: AtomicU64::default(),
+                rpc_ns: AtomicU64::default(),
+            }),
+        }
+    }
+
+    pub fn traffic(&self, target: &str) -> &Traffic {
+        self.counters
+            .traffic
+            .iter()
+            .find(|(name, _)| name == target)
+            .map(|(_, traffic)| traffic)
+            .expect("traffic is counted for every target")
+    }
+
+    /// Run `f`, counting its time as serialization
+    pub fn serialize<T>(&self, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let value = f();
+        add(&self.counters.serialization_ns, started.elapsed());
+        value
+    }
+
+    /// Await an RPC, counting the time spent waiting for it
+    pub async fn rpc<F: Future>(&self, call: F) -> F::Output {
+        let started = Instant::now();
+        let output = call.await;
+        add(&self.counters.rpc_ns, started.elapsed());
+        output
+    }
+
+    pub fn report(&self) -> UsageReport {
+        UsageReport {
+            peak_rss_bytes: peak_rss_bytes(),
+            targets: self
+                .counters
+                .traffic
+                .iter()
+                .map(|(target, traffic)| traffic.report(target))
+                .collect(),
+            serialization_us: self.counters.serialization_ns.load(Ordering::Relaxed) / 1_000,
+            rpc_us: self.counters.rpc_ns.load(Ordering::Relaxed) / 1_000,
+        }
+    }
+}
+
+fn add(counter: &AtomicU64, elapsed: Duration) {
+    counter.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+fn peak_rss_bytes() -> Option<u64> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage only writes into the struct we pass
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) != 0 {
+            return None;
+        }
+        usage.assume_init()
+    };
+    let max_rss = u64::try_from(usage.ru_maxrss).ok()?;
+    // Linux reports kilobytes, macOS bytes
+    if cfg!(target_os = "macos") {
+        Some(max_rss)
+    } else {
+        Some(max_rss * 1024)
+    }
+}
+
+#[cfg(not(unix))]
+fn peak_rss_bytes() -> Option<u64> {
+    None
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+/// Footer printed after the timing breakdown
+pub fn print_usage(report: &UsageReport) {
+    let rss = report
+        .peak_rss_bytes
+        .map(format_bytes)
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("{}: peak RSS {}", "Runner".bright_blue().bold(), rss.cyan());
+    let width = report
+        .targets
+        .iter()
+        .map(|t| t.target.len())
+        .max()
+        .unwrap_or(0)
+        .max("time".len());
+    for traffic in &report.targets {
+        println!(
+            "  {:<width$}  {} sent | {} received",
+            traffic.target,
+            format_bytes(traffic.bytes_sent).cyan(),
+            format_bytes(traffic.bytes_received).cyan(),
+            width = width
+        );
+    }
+    println!(
+        "  {:<width$}  {} serializing | {} awaiting RPCs",
+        "time",
+        crate::timing::format_duration(Duration::from_micros(report.serialization_us)).cyan(),
+        crate::timing::format_duration(Duration::from_micros(report.rpc_us)).cyan(),
+        width = width
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{self, Mock};
+    use crate::TestCase;
+
+    /// A test sending a string of `len` bytes, which the mock echoes back
+    fn echo(len: usize) -> TestCase {
+        let test = format!(
+            "{{name: echo, method: echo, arguments: {{text: {}}}}}",
+            "x".repeat(len)
+        );
+        serde_yaml::from_str(&test).unwrap()
+    }
+
+    #[tokio::test]
+    async fn traffic_grows_with_payload_size() {
+        let (python, rust) = (Mock::default(), Mock::default());
+        let mut runner = mock::runner(&[&python, &rust]).await;
+        let mut totals = [(0, 0); 2];
+        let mut growth = Vec::new();
+        for len in [10, 100_000] {
+            let result = runner.run_test(&echo(len)).await.unwrap();
+            assert!(result.passed, "{:?}", result.error_message);
+            let report = runner.usage.report();
+            for (traffic, (sent, received)) in report.targets.iter().zip(&mut totals) {
+                let grown = (
+                    traffic.bytes_sent - *sent,
+                    traffic.bytes_received - *received,
+                );
+                assert!(
+                    grown.0 >= len as u64 && grown.1 >= len as u64,
+                    "{:?}",
+                    grown
+                );
+                (*sent, *received) = (traffic.bytes_sent, traffic.bytes_received);
+                growth.push(grown);
+            }
+        }
+        assert!(growth[2].0 > growth[0].0 && growth[2].1 > growth[0].1);
+    }
+}
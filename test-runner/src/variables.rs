@@ -0,0 +1,263 @@
+/*!
+`${VAR}` placeholders in test arguments, hook arguments and `initial_state`.
+
+One test body can then be run with different sizes or inputs:
+
+```yaml
+variables: {N: 1000}
+tests:
+  - name: sum_range
+    method: sum_range
+    arguments: {n: "${N}"}
+```
+
+and `-D N=100000` on the command line. A name is looked up in the `-D`
+definitions (the last one wins), then the suite's `variables`.
+`${env:NAME}` also falls back to the environment variable `NAME`, so a
+definition or suite variable of the same name overrides the environment.
+
+Placeholders are replaced inside string values. A string that is exactly
+one placeholder is replaced by the value parsed as JSON, so `"${N}"` becomes
+the number `100000` rather than the string; a value that isn't JSON stays a
+string. `initial_state` is JSON text and gets plain text replacement. Any
+undefined variable fails the load, listing every test and path that uses one.
+*/
+
+use crate::{canonical, diff, TestCase};
+use anyhow::Result;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+
+/// Where placeholder values come from, highest precedence first
+pub struct Variables<'a> {
+    /// `-D NAME=VALUE` from the command line
+    pub defines: &'a [(String, String)],
+    /// The suite's `variables`
+    pub suite: &'a BTreeMap<String, JsonValue>,
+}
+
+impl Variables<'_> {
+    fn lookup(&self, name: &str) -> Option<String> {
+        match name.strip_prefix("env:") {
+            Some(env_name) => self
+                .defined(env_name)
+                .or_else(|| std::env::var(env_name).ok()),
+            None => self.defined(name),
+        }
+    }
+
+    fn defined(&self, name: &str) -> Option<String> {
+        let define = self.defines.iter().rev().find(|(key, _)| key == name);
+        match define {
+            Some((_, value)) => Some(value.clone()),
+            None => self.suite.get(name).map(|value| match value {
+                JsonValue::String(text) => text.clone(),
+                other => canonical::to_json_string(other),
+            }),
+        }
+    }
+}
+
+/// Parse a `-D NAME=VALUE` argument
+pub fn parse_define(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected NAME=VALUE, got `{}`", arg)),
+    }
+}
+
+/// Replace the placeholders of every test, failing if any is undefined
+pub fn substitute(tests: &mut [TestCase], variables: &Variables) -> Result<()> {
+    let mut undefined = Vec::new();
+    for test in tests.iter_mut() {
+        let mut missing = Vec::new();
+        let path = "arguments".to_string();
+        substitute_value(&mut test.arguments, path, variables, &mut missing);
+        for (index, step) in test.steps.iter_mut().enumerate() {
+            let path = format!("steps[{}].arguments", index);
+            substitute_value(&mut step.arguments, path, variables, &mut missing);
+        }
+        let hooks = [("setup", &mut test.setup), ("teardown", &mut test.teardown)];
+        for (list, hooks) in hooks {
+            for (index, hook) in hooks.iter_mut().enumerate() {
+                let path = format!("{}[{}].arguments", list, index);
+                substitute_value(&mut hook.arguments, path, variables, &mut missing);
+            }
+        }
+        if let Some(state) = &mut test.initial_state {
+            match expand(state, variables) {
+                Ok(expanded) => *state = expanded,
+                Err(names) => {
+                    missing.extend(names.into_iter().map(|name| ("initial_state".into(), name)))
+                }
+            }
+        }
+        for (path, name) in missing {
+            undefined.push(format!("test '{}' at {}: ${{{}}}", test.name, path, name));
+        }
+    }
+    if !undefined.is_empty() {
+        anyhow::bail!(
+            "Undefined variables (set them with -D NAME=VALUE or `variables:`):\n  {}",
+            undefined.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Substitute in every string of `value`, collecting `(path, name)` of
+/// undefined variables
+fn substitute_value(
+    value: &mut JsonValue,
+    path: String,
+    variables: &Variables,
+    missing: &mut Vec<(String, String)>,
+) {
+    match value {
+        JsonValue::String(text) => {
+            let single = is_single_placeholder(text);
+            match expand(text, variables) {
+                Ok(expanded) if single => {
+                    *value = serde_json::from_str(&expanded).unwrap_or(JsonValue::String(expanded));
+                }
+                Ok(expanded) => *text = expanded,
+                Err(names) => missing.extend(names.into_iter().map(|name| (path.clone(), name))),
+            }
+        }
+        JsonValue::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                substitute_value(item, format!("{}[{}]", path, index), variables, missing);
+            }
+        }
+        JsonValue::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let path = format!("{}{}", path, diff::key_segment(key));
+                substitute_value(field, path, variables, missing);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `text` with every `${NAME}` replaced, or the names that aren't defined.
+/// An unterminated `${` is left as it is.
+fn expand(text: &str, variables: &Variables) -> Result<String, Vec<String>> {
+    let mut out = String::with_capacity(text.len());
+    let mut missing = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        out.push_str(&rest[..start]);
+        match variables.lookup(name) {
+            Some(value) => out.push_str(&value),
+            None => missing.push(name.to_string()),
+        }
+        rest = &rest[start + 2 + len + 1..];
+    }
+    out.push_str(rest);
+    if missing.is_empty() {
+        Ok(out)
+    } else {
+        Err(missing)
+    }
+}
+
+fn is_single_placeholder(text: &str) -> bool {
+    text.strip_prefix("${")
+        .and_then(|inner| inner.strip_suffix('}'))
+        .is_some_and(|name| !name.contains('}'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn define(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    /// `argument` with its placeholders replaced
+    fn resolve(
+        defines: &[(String, String)],
+        suite: JsonValue,
+        argument: &str,
+    ) -> Result<JsonValue> {
+        let suite: BTreeMap<String, JsonValue> = serde_json::from_value(suite).unwrap();
+        let mut tests: Vec<TestCase> =
+            vec![serde_yaml::from_str("{name: uses_vars, method: add}").unwrap()];
+        tests[0].arguments = json!({"x": argument});
+        substitute(
+            &mut tests,
+            &Variables {
+                defines,
+                suite: &suite,
+            },
+        )?;
+        Ok(tests[0].arguments["x"].clone())
+    }
+
+    #[test]
+    fn definitions_override_the_suite() {
+        let defines = [define("N", "2")];
+        assert_eq!(
+            resolve(&defines, json!({"N": 1}), "${N}").unwrap(),
+            json!(2)
+        );
+    }
+
+    #[test]
+    fn the_last_definition_wins() {
+        let defines = [define("N", "1"), define("N", "2")];
+        assert_eq!(resolve(&defines, json!({}), "${N}").unwrap(), json!(2));
+    }
+
+    #[test]
+    fn definitions_override_the_environment() {
+        std::env::set_var("TEST_RUNNER_VARIABLES_DEFINED", "env");
+        let defines = [define("TEST_RUNNER_VARIABLES_DEFINED", "cli")];
+        let value = resolve(&defines, json!({}), "${env:TEST_RUNNER_VARIABLES_DEFINED}");
+        assert_eq!(value.unwrap(), json!("cli"));
+    }
+
+    #[test]
+    fn the_suite_overrides_the_environment() {
+        std::env::set_var("TEST_RUNNER_VARIABLES_SUITE", "env");
+        let suite = json!({"TEST_RUNNER_VARIABLES_SUITE": "suite"});
+        let value = resolve(&[], suite, "${env:TEST_RUNNER_VARIABLES_SUITE}");
+        assert_eq!(value.unwrap(), json!("suite"));
+    }
+
+    #[test]
+    fn only_env_placeholders_fall_back_to_the_environment() {
+        std::env::set_var("TEST_RUNNER_VARIABLES_ENV", "env");
+        let value = resolve(&[], json!({}), "${env:TEST_RUNNER_VARIABLES_ENV}");
+        assert_eq!(value.unwrap(), json!("env"));
+        assert!(resolve(&[], json!({}), "${TEST_RUNNER_VARIABLES_ENV}").is_err());
+    }
+
+    #[test]
+    fn only_a_lone_placeholder_is_parsed_as_json() {
+        let defines = [define("N", "2")];
+        assert_eq!(
+            resolve(&defines, json!({}), "n=${N}").unwrap(),
+            json!("n=2")
+        );
+        let suite = json!({"WORD": "two"});
+        assert_eq!(resolve(&[], suite, "${WORD}").unwrap(), json!("two"));
+    }
+
+    #[test]
+    fn undefined_variables_are_listed_with_their_test_and_path() {
+        let error = resolve(&[], json!({}), "${A}-${B}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Undefined variables (set them with -D NAME=VALUE or `variables:`):\n  \
+             test 'uses_vars' at arguments.x: ${A}\n  \
+             test 'uses_vars' at arguments.x: ${B}"
+        );
+    }
+}
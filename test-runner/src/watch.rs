@@ -0,0 +1,236 @@
+/*!
+`--watch`: rerun suites whenever their files change.
+
+After a first run of every suite the runner keeps going, watching each
+suite file, the files it includes and any `--watch-path` directories
+(recursively). Changes are gathered until the files have been quiet for
+`DEBOUNCE`, so an editor's save or a build writing many files triggers one
+rerun. A changed suite or include reruns the suites using it; a change
+under a `--watch-path`, typically an implementation, reruns every suite.
+
+Connections are kept between runs, except that they are re-established,
+and servers the runner started are restarted, when a `--watch-path` changed
+or a run hit a transport error: the server is probably being restarted or
+rebuilt at the same time. Each run ends with a one-line summary, and the
+runner exits on Ctrl-C with the status of the last run.
+*/
+
+use crate::connections::Connections;
+use crate::output;
+use crate::suites::{LoadError, SuiteRun};
+use crate::timing::Timings;
+use crate::usage::Usage;
+use crate::{loader, plan, run_tests, runner_options, selection, Args, TestRunner};
+use anyhow::{Context, Result};
+use colored::*;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Quiet time after the last change before rerunning
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// A suite file and the files it includes, canonicalized
+struct Watched {
+    path: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+/// Run `suite_paths`, then rerun them on changes until Ctrl-C
+pub async fn run(args: &Args, suite_paths: Vec<PathBuf>) -> Result<()> {
+    let watch_paths: Vec<PathBuf> = args
+        .watch_path
+        .iter()
+        .map(|path| {
+            path.canonicalize()
+                .with_context(|| format!("Cannot watch {}", path.display()))
+        })
+        .collect::<Result<_>>()?;
+    let mut suites: Vec<Watched> = suite_paths
+        .into_iter()
+        .map(|path| Watched {
+            files: canonical(&path).into_iter().collect(),
+            path,
+        })
+        .collect();
+
+    let (sender, mut changes) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                let _ = sender.send(event.paths);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Watching files failed: {}", e),
+        })
+        .context("Failed to watch files")?;
+    for path in &watch_paths {
+        watcher
+            .watch(path, RecursiveMode::Recursive)
+            .with_context(|| format!("Cannot watch {}", path.display()))?;
+    }
+
+    let mut connections = Connections::new(Duration::from_secs(args.connect_timeout));
+    let mut watched_dirs: Vec<PathBuf> = Vec::new();
+    let mut affected: Vec<usize> = (0..suites.len()).collect();
+    loop {
+        let passed = run_suites(args, &mut suites, &affected, &mut connections).await;
+
+        // Editors replace files rather than write them in place, which
+        // ends a watch on the file itself, so directories are watched
+        for suite in &suites {
+            for dir in suite.files.iter().filter_map(|file| file.parent()) {
+                if !watched_dirs.iter().any(|d| d == dir) {
+                    watcher
+                        .watch(dir, RecursiveMode::NonRecursive)
+                        .with_context(|| format!("Cannot watch {}", dir.display()))?;
+                    watched_dirs.push(dir.to_path_buf());
+                }
+            }
+        }
+        println!("{}", "Watching for changes (Ctrl-C to quit)".dimmed());
+
+        // Wait for a relevant change, then for the files to settle
+        let mut changed: Vec<PathBuf> = Vec::new();
+        loop {
+            tokio::select! {
+                paths = changes.recv() => {
+                    let Some(paths) = paths else {
+                        anyhow::bail!("Stopped receiving file changes");
+                    };
+                    changed.extend(paths);
+                }
+                _ = tokio::time::sleep(DEBOUNCE), if !changed.is_empty() => {
+                    affected = affected_suites(&suites, &watch_paths, &changed);
+                    if !affected.is_empty() {
+                        break;
+                    }
+                    changed.clear();
+                }
+                _ = tokio::signal::ctrl_c() => {
+                    drop(connections);
+                    std::process::exit(if passed { 0 } else { 1 });
+                }
+            }
+        }
+        let under_watch_path = |path: &PathBuf| watch_paths.iter().any(|dir| path.starts_with(dir));
+        if changed.iter().any(under_watch_path) {
+            connections.reset();
+        }
+        let names: Vec<String> = changed
+            .iter()
+            .filter(|path| under_watch_path(path) || suites.iter().any(|s| s.files.contains(path)))
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        println!("\n{} {}", "Changed:".bright_blue().bold(), names.join(", "));
+    }
+}
+
+/// Run the suites at `indices`, printing as usual and then one line of
+/// counts; true if every test passed
+async fn run_suites(
+    args: &Args,
+    suites: &mut [Watched],
+    indices: &[usize],
+    connections: &mut Connections,
+) -> bool {
+    let started = Instant::now();
+    let selection = selection::Selection {
+        filter: args.filter.clone(),
+        tags: args.tag.clone(),
+        groups: args.group.clone(),
+    };
+    let mut reporter = output::reporter(args.format, args.quiet, args.target.clone());
+    let mut runs = Vec::new();
+    let mut errors = Vec::new();
+    for &index in indices {
+        let watched = &mut suites[index];
+        match run_suite(args, &watched.path, &selection, connections, &mut *reporter).await {
+            Ok((run, files)) => {
+                watched.files = files;
+                if run.results.iter().any(|r| r.transport_error) {
+                    connections.reset();
+                }
+                runs.push(run);
+            }
+            Err(e) => {
+                errors.push(LoadError {
+                    path: watched.path.clone(),
+                    message: format!("{:#}", e),
+                });
+            }
+        }
+    }
+    reporter.total(&runs, &errors);
+    // The terse reporters already end with this line
+    if matches!(args.format, output::Format::Full) && !args.quiet {
+        println!(
+            "{} in {:.1}s",
+            output::summary(&runs, &errors, &args.target),
+            started.elapsed().as_secs_f64()
+        );
+    }
+    errors.is_empty() && !runs.iter().flat_map(|run| &run.results).any(|r| r.failed())
+}
+
+/// Run one suite, returning its results and the files it's read from
+async fn run_suite(
+    args: &Args,
+    path: &Path,
+    selection: &selection::Selection,
+    connections: &mut Connections,
+    reporter: &mut dyn output::Reporter,
+) -> Result<(SuiteRun, Vec<PathBuf>)> {
+    let started = Instant::now();
+    let mut suite = loader::load_suite(path, args.max_json_depth, &args.define)?;
+    let files = canonical(path)
+        .into_iter()
+        .chain(suite.included.iter().cloned())
+        .collect();
+    let (selected, skipped) = plan(&mut suite, selection, args.target.as_deref())?;
+    let runner = TestRunner::new(
+        &suite.servers,
+        connections,
+        Timings::new(),
+        Usage::new(suite.servers.names()),
+        runner_options(args).for_suite(&suite),
+    )
+    .await
+    .inspect_err(|_| {
+        // A server that can't be reached may be restarting
+        connections.reset();
+    })?;
+    let results = run_tests(&runner, &selected, None, args.jobs).await;
+    reporter.suite(&suite.name, &results, skipped.len());
+    let run = SuiteRun {
+        name: suite.name,
+        path: path.to_path_buf(),
+        results,
+        skipped,
+        elapsed: started.elapsed(),
+        coverage: None,
+    };
+    Ok((run, files))
+}
+
+/// Indices of the suites to rerun after `changed`
+fn affected_suites(suites: &[Watched], watch_paths: &[PathBuf], changed: &[PathBuf]) -> Vec<usize> {
+    if changed
+        .iter()
+        .any(|path| watch_paths.iter().any(|dir| path.starts_with(dir)))
+    {
+        return (0..suites.len()).collect();
+    }
+    (0..suites.len())
+        .filter(|&i| changed.iter().any(|path| suites[i].files.contains(path)))
+        .collect()
+}
+
+fn canonical(path: &Path) -> Option<PathBuf> {
+    path.canonicalize().ok()
+}
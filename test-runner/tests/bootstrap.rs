@@ -0,0 +1,58 @@
+//! `test-runner bootstrap` end to end: builds the Rust server in ../rust,
+//! starts it, runs the smoke suite and stops it again.
+
+use std::process::{Command, Output};
+
+fn bootstrap(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_test-runner"))
+        .arg("bootstrap")
+        .args(args)
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("test-runner runs")
+}
+
+#[test]
+fn bootstrap_builds_starts_tests_and_stops_the_server() {
+    let output = bootstrap(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let checks = [
+        "✓ built Rust server",
+        "✓ server listening on port",
+        "✓ protocol version",
+        "✓ all registration sources finished",
+        "✓ smoke test echo_round_trip",
+        "✓ smoke test add",
+        "✓ smoke test multiply",
+        "✓ smoke test counter_after_setup",
+        "✓ call in flight at SIGTERM completed",
+        "✓ server exited cleanly after SIGTERM",
+        "✓ stopped server on port",
+        "Bootstrap complete: ready to run suites",
+    ];
+    for check in checks {
+        assert!(
+            stdout.contains(check),
+            "missing '{}' in:\n{}",
+            check,
+            stdout
+        );
+    }
+    assert!(!stdout.contains('✗'), "{}", stdout);
+}
+
+#[test]
+fn bootstrap_fails_on_a_missing_server_binary() {
+    let output = bootstrap(&["--server-bin", "/nonexistent/test-server"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains('✗'), "{}", stdout);
+    assert!(!stdout.contains("Bootstrap complete"), "{}", stdout);
+}